@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use atoma_service::mock::{router, MockInferenceConfig};
+use axum::http::StatusCode;
+use clap::Parser;
+use tokio::net::TcpListener;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// Command line arguments for the mock inference service
+#[derive(Parser)]
+struct Args {
+    /// Address the mock inference service binds to
+    #[arg(short, long, default_value = "127.0.0.1:8000")]
+    bind_address: String,
+
+    /// Delay before responding to each request, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+
+    /// Delay between the chunks of streamed responses, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    chunk_interval_ms: u64,
+
+    /// Number of tokens generated by chat completions, unless `max_tokens` is lower
+    #[arg(long, default_value_t = 16)]
+    num_completion_tokens: u64,
+
+    /// Number of dimensions of the generated embeddings
+    #[arg(long, default_value_t = 16)]
+    embedding_dimensions: usize,
+
+    /// Status code of the error returned to every inference request
+    #[arg(long)]
+    error_status: Option<u16>,
+
+    /// Number of chunks after which streamed responses are aborted
+    #[arg(long)]
+    disconnect_after_chunks: Option<usize>,
+
+    /// Number of chunks after which a malformed chunk is sent in streamed responses
+    #[arg(long)]
+    malformed_chunk_after: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+    let args = Args::parse();
+    let error_status = args
+        .error_status
+        .map(StatusCode::from_u16)
+        .transpose()
+        .context("Invalid error status")?;
+    let config = MockInferenceConfig {
+        latency: Duration::from_millis(args.latency_ms),
+        chunk_interval: Duration::from_millis(args.chunk_interval_ms),
+        num_completion_tokens: args.num_completion_tokens,
+        embedding_dimensions: args.embedding_dimensions,
+        error_status,
+        disconnect_after_chunks: args.disconnect_after_chunks,
+        malformed_chunk_after: args.malformed_chunk_after,
+    };
+
+    let listener = TcpListener::bind(&args.bind_address)
+        .await
+        .context("Failed to bind the mock inference service")?;
+    info!(
+        "Mock inference service listening on {}, with configuration: {config:?}",
+        args.bind_address
+    );
+    axum::serve(listener, router(config))
+        .await
+        .context("Mock inference service failed")
+}
@@ -8,17 +8,25 @@ use anyhow::{Context, Result};
 use atoma_confidential::AtomaConfidentialComputeService;
 use atoma_daemon::{AtomaDaemonConfig, DaemonState};
 use atoma_service::{
-    config::AtomaServiceConfig,
+    admission::AdmissionController,
+    backends::ModelBackends,
+    chat_template::ChatTemplate,
+    config::{AtomaServiceConfig, ModelMetadata, ZkLoginConfig, DEFAULT_EPOCH_REFRESH_INTERVAL},
     proxy::{config::ProxyConfig, register_on_proxy},
+    rate_limit::RateLimiters,
+    replay::ReplayGuard,
     server::AppState,
 };
 use atoma_state::{config::AtomaStateManagerConfig, AtomaState, AtomaStateManager};
 use atoma_sui::{client::AtomaSuiClient, AtomaSuiConfig, SuiEventSubscriber};
-use atoma_utils::spawn_with_shutdown;
+use atoma_utils::{signature::ZkLoginVerifier, spawn_with_shutdown};
 use clap::Parser;
 use dotenv::dotenv;
 use futures::future::try_join_all;
-use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use hf_hub::{
+    api::sync::{ApiBuilder, ApiRepo},
+    Repo, RepoType,
+};
 use sui_keys::keystore::FileBasedKeystore;
 use sui_sdk::{types::base_types::ObjectID, wallet_context::WalletContext};
 use tokenizers::Tokenizer;
@@ -47,6 +55,16 @@ const LOGS: &str = "./logs";
 const NODE_LOG_FILE: &str = "atoma-node.log";
 /// The log file name for the daemon service.
 const DAEMON_LOG_FILE: &str = "atoma-daemon.log";
+/// The keys of a model's `config.json` that can hold its context length, in order of precedence
+const CONTEXT_LENGTH_KEYS: [&str; 5] = [
+    "max_position_embeddings",
+    "n_positions",
+    "max_seq_len",
+    "seq_length",
+    "max_sequence_length",
+];
+/// The key of the text model's configuration, in `config.json` of multimodal models
+const TEXT_CONFIG_KEY: &str = "text_config";
 
 /// Command line arguments for the Atoma node
 #[derive(Parser)]
@@ -98,6 +116,8 @@ impl Config {
 ///
 /// This function concurrently fetches tokenizer configurations for multiple models from HuggingFace's
 /// repository and initializes them. Each tokenizer is wrapped in an Arc for safe sharing across threads.
+/// The chat template and the context length of each model are fetched from the same repository,
+/// if available (see [`load_chat_template`] and [`load_context_length`]).
 ///
 /// # Arguments
 ///
@@ -106,7 +126,9 @@ impl Config {
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing a vector of Arc-wrapped tokenizers on success, or an error if:
+/// Returns a `Result` containing a vector of Arc-wrapped tokenizers, a vector of the optional
+/// chat template of each model, and a vector of the optional context length of each model,
+/// on success, or an error if:
 /// - Failed to fetch tokenizer configuration from HuggingFace
 /// - Failed to parse the tokenizer JSON
 /// - Any other network or parsing errors occur
@@ -121,7 +143,8 @@ impl Config {
 ///     let models = vec!["facebook/opt-125m".to_string()];
 ///     let revisions = vec!["main".to_string()];
 ///     
-///     let tokenizers = initialize_tokenizers(&models, &revisions).await?;
+///     let (tokenizers, chat_templates, context_lengths) =
+///         initialize_tokenizers(&models, &revisions).await?;
 ///     Ok(())
 /// }
 /// ```
@@ -130,7 +153,11 @@ async fn initialize_tokenizers(
     models: &[String],
     revisions: &[String],
     hf_token: String,
-) -> Result<Vec<Arc<Tokenizer>>> {
+) -> Result<(
+    Vec<Arc<Tokenizer>>,
+    Vec<Option<ChatTemplate>>,
+    Vec<Option<i64>>,
+)> {
     let api = ApiBuilder::new()
         .with_progress(true)
         .with_token(Some(hf_token))
@@ -151,19 +178,216 @@ async fn initialize_tokenizers(
                     .get("tokenizer.json")
                     .expect("Failed to get tokenizer.json");
 
-                Tokenizer::from_file(tokenizer_filename)
+                let tokenizer = Tokenizer::from_file(tokenizer_filename)
                     .map_err(|e| {
                         anyhow::anyhow!(format!(
                             "Failed to parse tokenizer for model {}, with error: {}",
                             model, e
                         ))
                     })
-                    .map(Arc::new)
+                    .map(Arc::new)?;
+
+                Ok::<_, anyhow::Error>((
+                    tokenizer,
+                    (
+                        load_chat_template(&repo, model),
+                        load_context_length(&repo, model),
+                    ),
+                ))
             }
         })
         .collect();
 
-    try_join_all(fetch_futures).await
+    let (tokenizers, (chat_templates, context_lengths)) =
+        try_join_all(fetch_futures).await?.into_iter().unzip();
+    Ok((tokenizers, chat_templates, context_lengths))
+}
+
+/// Loads the chat template of a model from its HuggingFace repository.
+///
+/// The template is read from the repository's `chat_template.jinja` file, if any, or from
+/// the `chat_template` entry of its `tokenizer_config.json` otherwise. Models without a (valid)
+/// chat template are still served, but their prompt tokens are estimated per message, instead
+/// of being counted exactly, so a warning is logged.
+///
+/// # Arguments
+///
+/// * `repo` - The HuggingFace repository of the model
+/// * `model` - The name of the model, for logging purposes
+///
+/// # Returns
+///
+/// Returns the model's chat template, or `None` if it has none or it could not be loaded.
+fn load_chat_template(repo: &ApiRepo, model: &str) -> Option<ChatTemplate> {
+    let tokenizer_config = repo
+        .get("tokenizer_config.json")
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or(serde_json::Value::Null);
+    let chat_template_file = repo
+        .get("chat_template.jinja")
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok());
+    match ChatTemplate::from_hf_files(&tokenizer_config, chat_template_file) {
+        Ok(Some(chat_template)) => Some(chat_template),
+        Ok(None) => {
+            warn!(
+                target = "atoma-node-service",
+                event = "chat_template_not_found",
+                "No chat template found for model {model}, prompt tokens will be estimated per message"
+            );
+            None
+        }
+        Err(e) => {
+            warn!(
+                target = "atoma-node-service",
+                event = "chat_template_invalid",
+                "Failed to compile chat template for model {model}, prompt tokens will be estimated per message, with error: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// Loads the context length of a model from the `config.json` of its HuggingFace repository.
+///
+/// The context length is read from the first of [`CONTEXT_LENGTH_KEYS`] found in the
+/// configuration, or in its `text_config` section, for multimodal models.
+///
+/// # Arguments
+///
+/// * `repo` - The HuggingFace repository of the model
+/// * `model` - The name of the model, for logging purposes
+///
+/// # Returns
+///
+/// Returns the model's context length, or `None` if it could not be found.
+fn load_context_length(repo: &ApiRepo, model: &str) -> Option<i64> {
+    let model_config: serde_json::Value = repo
+        .get("config.json")
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or(serde_json::Value::Null);
+    let context_length = [Some(&model_config), model_config.get(TEXT_CONFIG_KEY)]
+        .into_iter()
+        .flatten()
+        .find_map(|config| {
+            CONTEXT_LENGTH_KEYS
+                .iter()
+                .find_map(|key| config.get(key).and_then(|value| value.as_i64()))
+        });
+    if context_length.is_none() {
+        warn!(
+            target = "atoma-node-service",
+            event = "context_length_not_found",
+            "No context length found for model {model}, configure it in `model_metadata` to check that requests fit in the model's context window"
+        );
+    }
+    context_length
+}
+
+/// Resolves the token limits of each deployed model.
+///
+/// The limits configured in `model_metadata` take precedence, while models with no configured
+/// context length fall back to the one found in their `config.json`.
+///
+/// # Arguments
+///
+/// * `models` - The names of the deployed models
+/// * `model_metadata` - The configured token limits, for some of the models
+/// * `context_lengths` - The context length found in the `config.json` of each model, if any
+///
+/// # Returns
+///
+/// Returns the token limits of each model, in the same order as `models`.
+fn resolve_model_metadata(
+    models: &[String],
+    model_metadata: Vec<ModelMetadata>,
+    context_lengths: Vec<Option<i64>>,
+) -> Vec<ModelMetadata> {
+    for metadata in model_metadata
+        .iter()
+        .filter(|metadata| !models.contains(&metadata.model))
+    {
+        warn!(
+            target = "atoma-node-service",
+            event = "model_metadata_unknown_model",
+            "Ignoring model metadata for model {}, as it is not in the list of deployed models",
+            metadata.model
+        );
+    }
+    models
+        .iter()
+        .zip(context_lengths)
+        .map(|(model, context_length)| {
+            let mut metadata = model_metadata
+                .iter()
+                .find(|metadata| &metadata.model == model)
+                .cloned()
+                .unwrap_or_else(|| ModelMetadata {
+                    model: model.clone(),
+                    ..Default::default()
+                });
+            metadata.context_length = metadata.context_length.or(context_length);
+            metadata
+        })
+        .collect()
+}
+
+/// Loads the verifier of zkLogin signatures, from the configured cached JWKs.
+fn load_zklogin_verifier(zklogin_config: &ZkLoginConfig) -> Result<Arc<ZkLoginVerifier>> {
+    let jwks_json = std::fs::read(&zklogin_config.jwks_path).context(format!(
+        "Failed to read zkLogin cached JWKs from {}",
+        zklogin_config.jwks_path
+    ))?;
+    let zklogin_verifier =
+        ZkLoginVerifier::from_jwks_json(&jwks_json).context("Invalid zkLogin cached JWKs")?;
+    Ok(Arc::new(zklogin_verifier))
+}
+
+/// Periodically refreshes the current Sui epoch, for the validity of deprecated tasks and,
+/// if zkLogin signatures are accepted, for their verifier.
+///
+/// Failures to fetch the epoch are logged and retried on the next refresh, as deprecated
+/// tasks and zkLogin signatures are only rejected until the epoch is first known. The epoch
+/// is refreshed every `epoch_refresh_interval` of the zkLogin configuration, if any.
+async fn refresh_current_epoch(
+    client: Arc<RwLock<AtomaSuiClient>>,
+    current_epoch_sender: watch::Sender<Option<u64>>,
+    zklogin_verifier: Option<Arc<ZkLoginVerifier>>,
+    zklogin_config: Option<ZkLoginConfig>,
+    mut shutdown_receiver: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(
+        zklogin_config.map_or(DEFAULT_EPOCH_REFRESH_INTERVAL, |zklogin_config| {
+            zklogin_config.epoch_refresh_interval
+        }),
+    );
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match client.read().await.get_current_epoch().await {
+                    Ok(epoch) => {
+                        current_epoch_sender.send_replace(Some(epoch));
+                        if let Some(zklogin_verifier) = &zklogin_verifier {
+                            zklogin_verifier.set_current_epoch(epoch);
+                        }
+                    }
+                    Err(e) => warn!(
+                        target = "atoma-node-service",
+                        event = "epoch_refresh_error",
+                        error = %e,
+                        "Failed to refresh the current epoch"
+                    ),
+                }
+            }
+            _ = shutdown_receiver.changed() => {
+                return Ok(());
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -257,6 +481,7 @@ async fn main() -> Result<()> {
 
     let (compute_shared_secret_sender, _compute_shared_secret_receiver) =
         tokio::sync::mpsc::unbounded_channel();
+    let (tee_attestation_sender, tee_attestation_receiver) = tokio::sync::watch::channel(false);
 
     let confidential_compute_service_handle = spawn_with_shutdown(
         AtomaConfidentialComputeService::start_confidential_compute_service(
@@ -265,6 +490,7 @@ async fn main() -> Result<()> {
             _app_state_decryption_receiver,
             _app_state_encryption_receiver,
             _compute_shared_secret_receiver,
+            tee_attestation_sender,
             shutdown_receiver.clone(),
         ),
         shutdown_sender.clone(),
@@ -313,10 +539,49 @@ async fn main() -> Result<()> {
         shutdown_sender.clone(),
     );
 
+    let backends = Arc::new(
+        ModelBackends::from_config(&config.service)
+            .context("Invalid inference backends configuration")?,
+    );
+    let admission = AdmissionController::from_config(&config.service)
+        .context("Invalid admission control configuration")?;
+    let rate_limiters = RateLimiters::from_config(&config.service.rate_limits)
+        .context("Invalid rate limits configuration")?;
+    let replay_guard = ReplayGuard::from_config(&config.service.replay_protection)
+        .context("Invalid replay protection configuration")?;
+    let zklogin_verifier = config
+        .service
+        .zklogin
+        .as_ref()
+        .map(load_zklogin_verifier)
+        .transpose()?;
+    let (current_epoch_sender, current_epoch_receiver) = watch::channel(None);
+    let epoch_refresh_handle = spawn_with_shutdown(
+        refresh_current_epoch(
+            client.clone(),
+            current_epoch_sender,
+            zklogin_verifier.clone(),
+            config.service.zklogin.clone(),
+            shutdown_receiver.clone(),
+        ),
+        shutdown_sender.clone(),
+    );
+    let backend_health_checks_handle = spawn_with_shutdown(
+        backends
+            .clone()
+            .run_health_checks(shutdown_receiver.clone()),
+        shutdown_sender.clone(),
+    );
+
     let hf_token = std::env::var(HF_TOKEN)
         .context(format!("Variable {} not set in the .env file", HF_TOKEN))?;
-    let tokenizers =
+    let (tokenizers, chat_templates, context_lengths) =
         initialize_tokenizers(&config.service.models, &config.service.revisions, hf_token).await?;
+    let model_metadata = resolve_model_metadata(
+        &config.service.models,
+        config.service.model_metadata,
+        context_lengths,
+    );
 
     let app_state = AppState {
         state_manager_sender,
@@ -324,20 +589,28 @@ async fn main() -> Result<()> {
         decryption_sender: app_state_decryption_sender,
         encryption_sender: app_state_encryption_sender,
         compute_shared_secret_sender,
+        tee_attestation_receiver,
+        current_epoch_receiver,
         tokenizers: Arc::new(tokenizers),
         models: Arc::new(config.service.models),
-        chat_completions_service_url: config
-            .service
-            .chat_completions_service_url
-            .context("Chat completions service URL not configured")?,
-        embeddings_service_url: config
-            .service
-            .embeddings_service_url
-            .context("Embeddings service URL not configured")?,
-        image_generations_service_url: config
-            .service
-            .image_generations_service_url
-            .context("Image generations service URL not configured")?,
+        num_tokens_per_image: config.service.num_tokens_per_image,
+        usage_reconciliation: config.service.usage_reconciliation,
+        task_roles: config.service.task_roles,
+        chat_templates: Arc::new(chat_templates),
+        model_metadata: Arc::new(model_metadata),
+        node_small_ids: Arc::new(
+            config
+                .daemon
+                .node_badges
+                .iter()
+                .map(|(_, node_small_id)| *node_small_id as i64)
+                .collect(),
+        ),
+        backends,
+        admission: Arc::new(admission),
+        rate_limiters: Arc::new(rate_limiters),
+        replay_guard: Arc::new(replay_guard),
+        zklogin_verifier,
         keystore: Arc::new(keystore),
         address_index,
     };
@@ -413,6 +686,8 @@ async fn main() -> Result<()> {
         server_result,
         daemon_result,
         confidential_compute_service_result,
+        backend_health_checks_result,
+        epoch_refresh_result,
         _,
     ) = try_join!(
         subscriber_handle,
@@ -420,6 +695,8 @@ async fn main() -> Result<()> {
         service_handle,
         daemon_handle,
         confidential_compute_service_handle,
+        backend_health_checks_handle,
+        epoch_refresh_handle,
         ctrl_c
     )?;
     handle_tasks_results(
@@ -428,6 +705,8 @@ async fn main() -> Result<()> {
         server_result,
         daemon_result,
         confidential_compute_service_result,
+        backend_health_checks_result,
+        epoch_refresh_result,
     )?;
 
     info!(
@@ -516,6 +795,11 @@ fn setup_logging<P: AsRef<Path>>(log_dir: P) -> Result<(WorkerGuard, WorkerGuard
 /// * `subscriber_result` - The result of the subscriber task, which may contain an error.
 /// * `state_manager_result` - The result of the state manager task, which may contain an error.
 /// * `server_result` - The result of the server task, which may contain an error.
+/// * `daemon_result` - The result of the daemon task, which may contain an error.
+/// * `confidential_compute_service_result` - The result of the confidential compute service task,
+///   which may contain an error.
+/// * `backend_health_checks_result` - The result of the backend health checks task, which may
+///   contain an error.
 ///
 /// # Returns
 ///
@@ -530,6 +814,8 @@ fn handle_tasks_results(
     server_result: Result<()>,
     daemon_result: Result<()>,
     confidential_compute_service_result: Result<()>,
+    backend_health_checks_result: Result<()>,
+    epoch_refresh_result: Result<()>,
 ) -> Result<()> {
     let result_handler = |result: Result<()>, message: &str| {
         if let Err(e) = result {
@@ -551,5 +837,10 @@ fn handle_tasks_results(
         confidential_compute_service_result,
         "Confidential compute service terminated abruptly",
     )?;
+    result_handler(
+        backend_health_checks_result,
+        "Backend health checks terminated abruptly",
+    )?;
+    result_handler(epoch_refresh_result, "Epoch refresh terminated abruptly")?;
     Ok(())
 }
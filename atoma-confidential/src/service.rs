@@ -16,7 +16,7 @@ use atoma_sui::{client::AtomaSuiClientError, events::AtomaEvent};
 use atoma_utils::constants::NONCE_SIZE;
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::{mpsc::UnboundedReceiver, oneshot, RwLock};
+use tokio::sync::{mpsc::UnboundedReceiver, oneshot, watch, RwLock};
 use tracing::instrument;
 use x25519_dalek::PublicKey;
 
@@ -64,6 +64,9 @@ pub struct AtomaConfidentialComputeService {
     service_encryption_receiver: UnboundedReceiver<ServiceEncryptionRequest>,
     /// Channel receiver for incoming Atoma service requests for shared secret computation
     service_shared_secret_receiver: UnboundedReceiver<ServiceSharedSecretRequest>,
+    /// Channel sender notifying the Atoma service whether the node's current public key
+    /// is backed by a TEE remote attestation, successfully submitted to the Sui blockchain
+    tee_attestation_sender: watch::Sender<bool>,
     /// Signal receiver for coordinating graceful shutdown of the service
     shutdown_signal: tokio::sync::watch::Receiver<bool>,
 }
@@ -76,6 +79,7 @@ impl AtomaConfidentialComputeService {
         service_decryption_receiver: UnboundedReceiver<ServiceDecryptionRequest>,
         service_encryption_receiver: UnboundedReceiver<ServiceEncryptionRequest>,
         service_shared_secret_receiver: UnboundedReceiver<ServiceSharedSecretRequest>,
+        tee_attestation_sender: watch::Sender<bool>,
         shutdown_signal: tokio::sync::watch::Receiver<bool>,
     ) -> Result<Self> {
         let key_manager = X25519KeyPairManager::new()?;
@@ -87,6 +91,7 @@ impl AtomaConfidentialComputeService {
             service_decryption_receiver,
             service_encryption_receiver,
             service_shared_secret_receiver,
+            tee_attestation_sender,
             shutdown_signal,
         })
     }
@@ -104,6 +109,7 @@ impl AtomaConfidentialComputeService {
     /// * `service_decryption_receiver` - Channel receiver for decryption requests
     /// * `service_encryption_receiver` - Channel receiver for encryption requests
     /// * `service_shared_secret_receiver` - Channel receiver for shared secret computation requests
+    /// * `tee_attestation_sender` - Watch channel sender for the node's TEE attestation status
     /// * `shutdown_signal` - Watch channel receiver for coordinating service shutdown
     ///
     /// # Returns
@@ -121,6 +127,7 @@ impl AtomaConfidentialComputeService {
         service_decryption_receiver: UnboundedReceiver<ServiceDecryptionRequest>,
         service_encryption_receiver: UnboundedReceiver<ServiceEncryptionRequest>,
         service_shared_secret_receiver: UnboundedReceiver<ServiceSharedSecretRequest>,
+        tee_attestation_sender: watch::Sender<bool>,
         shutdown_signal: tokio::sync::watch::Receiver<bool>,
     ) -> Result<()> {
         let mut service = Self::new(
@@ -129,6 +136,7 @@ impl AtomaConfidentialComputeService {
             service_decryption_receiver,
             service_encryption_receiver,
             service_shared_secret_receiver,
+            tee_attestation_sender,
             shutdown_signal,
         )?;
 
@@ -242,6 +250,10 @@ impl AtomaConfidentialComputeService {
     /// 3. Retrieves the public key associated with the rotated keys
     /// 4. Submits the attestation to the Sui blockchain with the quote and public key
     ///
+    /// The TEE attestation status is reset once the keys are rotated, and only set again once
+    /// the attestation for the new public key is successfully submitted. Without the `tdx`
+    /// feature, no attestation is ever submitted, so the status is never set.
+    ///
     /// # Returns
     /// - `Ok(())` if the attestation was successfully submitted
     /// - `Err(AtomaConfidentialComputeError)` if any step fails, including key rotation or Sui client errors
@@ -253,6 +265,8 @@ impl AtomaConfidentialComputeService {
     #[instrument(level = "debug", skip_all)]
     async fn submit_node_key_rotation_tdx_attestation(&mut self) -> Result<()> {
         self.key_manager.rotate_keys();
+        // NOTE: The new public key is not attested until the attestation is submitted
+        self.tee_attestation_sender.send_replace(false);
         #[cfg(feature = "tdx")]
         {
             let public_key = self.key_manager.get_public_key();
@@ -280,6 +294,7 @@ impl AtomaConfidentialComputeService {
                         "Submitted node key rotation attestation successfully"
                     );
                     self.key_rotation_counter = Some(key_rotation_counter);
+                    self.tee_attestation_sender.send_replace(true);
                     Ok(())
                 }
                 Err(e) => {
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+use crate::{
+    config::{AtomaServiceConfig, ModelAdmissionConfig},
+    handlers::prometheus::{
+        ADMISSION_IN_FLIGHT_REQUESTS, ADMISSION_QUEUE_DEPTH, ADMISSION_QUEUE_WAIT_TIME,
+        ADMISSION_REJECTED_REQUESTS,
+    },
+};
+
+/// The rejection reason reported when a model's admission queue is full
+const QUEUE_FULL_REASON: &str = "queue_full";
+
+/// The rejection reason reported when a request times out in a model's admission queue
+const QUEUE_TIMEOUT_REASON: &str = "queue_timeout";
+
+/// The admission queue of a model, bounding the number of requests in flight to its
+/// inference backend.
+///
+/// In-flight slots are handed out through a fair semaphore, so that queued requests are
+/// admitted in the order they arrived.
+#[derive(Debug)]
+struct AdmissionQueue {
+    /// Name of the model, for metrics and errors
+    model: String,
+    /// Slots of the requests in flight to the model's inference backend
+    in_flight_slots: Arc<Semaphore>,
+    /// Number of requests currently waiting for a slot
+    queue_depth: AtomicUsize,
+    /// Maximum number of requests waiting for a slot
+    max_queue_depth: usize,
+    /// Maximum duration a request waits for a slot
+    queue_timeout: Duration,
+}
+
+/// Tracks a request waiting in an admission queue.
+///
+/// The request is counted in the queue depth until this guard is dropped, including when
+/// the request is cancelled while waiting (e.g. because the client disconnected).
+struct QueuedRequest<'a>(&'a AdmissionQueue);
+
+impl<'a> QueuedRequest<'a> {
+    /// Enqueues a request, unless the queue is already full
+    fn new(queue: &'a AdmissionQueue) -> Option<Self> {
+        if queue.queue_depth.fetch_add(1, Ordering::Relaxed) >= queue.max_queue_depth {
+            queue.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        ADMISSION_QUEUE_DEPTH
+            .with_label_values(&[queue.model.as_str()])
+            .inc();
+        Some(Self(queue))
+    }
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        self.0.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        ADMISSION_QUEUE_DEPTH
+            .with_label_values(&[self.0.model.as_str()])
+            .dec();
+    }
+}
+
+impl AdmissionQueue {
+    fn new(config: &ModelAdmissionConfig) -> Self {
+        Self {
+            model: config.model.clone(),
+            in_flight_slots: Arc::new(Semaphore::new(config.max_in_flight_requests)),
+            queue_depth: AtomicUsize::new(0),
+            max_queue_depth: config.max_queue_depth,
+            queue_timeout: config.queue_timeout,
+        }
+    }
+
+    /// Admits a request to the model's inference backend, waiting in the queue if all
+    /// in-flight slots are taken.
+    async fn admit(&self) -> Result<AdmissionPermit, AdmissionError> {
+        let start = Instant::now();
+        let slot = match self.in_flight_slots.clone().try_acquire_owned() {
+            Ok(slot) => {
+                ADMISSION_QUEUE_WAIT_TIME
+                    .with_label_values(&[self.model.as_str()])
+                    .observe(0.0);
+                slot
+            }
+            Err(_) => {
+                let Some(_queued_request) = QueuedRequest::new(self) else {
+                    return Err(self.reject(QUEUE_FULL_REASON));
+                };
+                let slot = tokio::time::timeout(
+                    self.queue_timeout,
+                    self.in_flight_slots.clone().acquire_owned(),
+                )
+                .await;
+                ADMISSION_QUEUE_WAIT_TIME
+                    .with_label_values(&[self.model.as_str()])
+                    .observe(start.elapsed().as_secs_f64());
+                match slot {
+                    Ok(slot) => slot.expect("Admission queue semaphores are never closed"),
+                    Err(_) => return Err(self.reject(QUEUE_TIMEOUT_REASON)),
+                }
+            }
+        };
+        ADMISSION_IN_FLIGHT_REQUESTS
+            .with_label_values(&[self.model.as_str()])
+            .inc();
+        Ok(AdmissionPermit {
+            slot: Some((self.model.clone(), slot)),
+        })
+    }
+
+    /// Records the rejection of a request, and returns the corresponding error
+    fn reject(&self, reason: &str) -> AdmissionError {
+        ADMISSION_REJECTED_REQUESTS
+            .with_label_values(&[self.model.as_str(), reason])
+            .inc();
+        warn!(
+            target = "atoma-service",
+            event = "admission_rejected",
+            model = self.model,
+            reason,
+            "Rejecting request, as the model's inference backend is saturated"
+        );
+        let model = self.model.clone();
+        // NOTE: Clients are asked to retry once a request at the head of the queue would
+        // have timed out, which is when the queue is guaranteed to have made progress
+        let retry_after = self.queue_timeout;
+        if reason == QUEUE_FULL_REASON {
+            AdmissionError::QueueFull { model, retry_after }
+        } else {
+            AdmissionError::QueueTimeout { model, retry_after }
+        }
+    }
+}
+
+/// Permit of a request admitted to its model's inference backend.
+///
+/// The request holds its model's in-flight slot until this permit is dropped, so the permit
+/// must live for as long as the inference backend serves the request, including while its
+/// response is streamed.
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    /// The model of the request and its in-flight slot, if the model's requests are limited
+    slot: Option<(String, OwnedSemaphorePermit)>,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        if let Some((model, _)) = &self.slot {
+            ADMISSION_IN_FLIGHT_REQUESTS
+                .with_label_values(&[model.as_str()])
+                .dec();
+        }
+    }
+}
+
+/// Admission control of the models deployed by the Atoma Service.
+///
+/// Each model with an entry in `admission_control` has its own admission queue, so that a
+/// saturated model never delays requests to other models. Requests to models without an
+/// entry are always admitted.
+#[derive(Debug, Default)]
+pub struct AdmissionController {
+    /// The admission queues of the limited models, keyed by model name
+    queues: HashMap<String, AdmissionQueue>,
+}
+
+impl AdmissionController {
+    /// Creates the admission queues of the deployed models, from the service configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if admission control is configured for a model that is not in
+    /// `models`, more than once for the same model, or without any in-flight slot.
+    pub fn from_config(config: &AtomaServiceConfig) -> Result<Self, AdmissionError> {
+        let mut queues = HashMap::with_capacity(config.admission_control.len());
+        for admission in &config.admission_control {
+            if !config.models.contains(&admission.model) {
+                return Err(AdmissionError::UnknownModel(admission.model.clone()));
+            }
+            if admission.max_in_flight_requests == 0 {
+                return Err(AdmissionError::NoInFlightRequests(admission.model.clone()));
+            }
+            if queues
+                .insert(admission.model.clone(), AdmissionQueue::new(admission))
+                .is_some()
+            {
+                return Err(AdmissionError::DuplicateModel(admission.model.clone()));
+            }
+        }
+        Ok(Self { queues })
+    }
+
+    /// Admits a request for `model` to its inference backend.
+    ///
+    /// # Returns
+    ///
+    /// Returns the permit of the admitted request, to be held until the inference backend has
+    /// served it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model's admission queue is full, or if the request times out
+    /// waiting in it.
+    pub async fn admit(&self, model: &str) -> Result<AdmissionPermit, AdmissionError> {
+        match self.queues.get(model) {
+            Some(queue) => queue.admit().await,
+            None => Ok(AdmissionPermit { slot: None }),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AdmissionError {
+    #[error(
+        "Admission control configured for model {0}, which is not in the list of deployed models"
+    )]
+    UnknownModel(String),
+    #[error("Admission control configured more than once for model {0}")]
+    DuplicateModel(String),
+    #[error("Admission control of model {0} must allow at least one request in flight")]
+    NoInFlightRequests(String),
+    #[error("Too many requests queued for model {model}, retry after {retry_after:?}")]
+    QueueFull {
+        model: String,
+        retry_after: Duration,
+    },
+    #[error(
+        "Request for model {model} timed out in the admission queue, retry after {retry_after:?}"
+    )]
+    QueueTimeout {
+        model: String,
+        retry_after: Duration,
+    },
+}
+
+impl AdmissionError {
+    /// Returns the duration after which a rejected request can be retried, if the error
+    /// is a rejection
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::QueueFull { retry_after, .. } | Self::QueueTimeout { retry_after, .. } => {
+                Some(*retry_after)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(max_in_flight_requests: usize, max_queue_depth: usize) -> AdmissionController {
+        let config = ModelAdmissionConfig {
+            model: "llama".to_string(),
+            max_in_flight_requests,
+            max_queue_depth,
+            queue_timeout: Duration::from_millis(100),
+        };
+        AdmissionController {
+            queues: HashMap::from([(config.model.clone(), AdmissionQueue::new(&config))]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admit_unlimited_model() {
+        let controller = controller(1, 0);
+        let _permit = controller.admit("llama").await.unwrap();
+        for _ in 0..10 {
+            assert!(controller.admit("e5").await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admit_rejects_when_queue_is_full() {
+        let controller = controller(1, 0);
+        let permit = controller.admit("llama").await.unwrap();
+        assert!(matches!(
+            controller.admit("llama").await,
+            Err(AdmissionError::QueueFull { retry_after, .. }) if retry_after == Duration::from_millis(100)
+        ));
+
+        // NOTE: Releasing the permit frees the in-flight slot
+        drop(permit);
+        assert!(controller.admit("llama").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_admit_times_out_in_queue() {
+        let controller = controller(1, 1);
+        let _permit = controller.admit("llama").await.unwrap();
+        assert!(matches!(
+            controller.admit("llama").await,
+            Err(AdmissionError::QueueTimeout { .. })
+        ));
+        assert_eq!(
+            controller.queues["llama"]
+                .queue_depth
+                .load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admit_queued_request_once_slot_is_released() {
+        let controller = Arc::new(controller(1, 1));
+        let permit = controller.admit("llama").await.unwrap();
+        let queued = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.admit("llama").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            controller.admit("llama").await,
+            Err(AdmissionError::QueueFull { .. })
+        ));
+        drop(permit);
+        assert!(queued.await.unwrap().is_ok());
+    }
+}
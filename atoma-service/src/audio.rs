@@ -0,0 +1,154 @@
+/// Byte rate assumed for audio files whose duration cannot be read from their headers, in
+/// bytes per second.
+///
+/// This is the byte rate of 16 kbit/s audio, a lower bound for the bitrate of compressed
+/// speech (e.g. MP3, Opus or AAC), so that the estimated duration of such files is an upper
+/// bound of their actual duration.
+pub const MIN_AUDIO_BYTE_RATE: u64 = 2_000;
+
+/// Highest byte rate accepted from the headers of WAV files, in bytes per second.
+///
+/// This is the byte rate of 192 kHz stereo audio with 32-bit samples. As the headers are
+/// sent by the client, the duration of an audio file is never estimated below its size at
+/// this byte rate.
+pub const MAX_AUDIO_BYTE_RATE: u64 = 1_536_000;
+
+/// Returns the duration of an audio file, in seconds.
+///
+/// The duration of uncompressed WAV files is read from their headers, if consistent. Other
+/// formats are assumed to be encoded at [`MIN_AUDIO_BYTE_RATE`], which overestimates their
+/// duration, so that the compute units reserved for transcribing them are never too few.
+/// In any case, the duration is at least the size of the file at [`MAX_AUDIO_BYTE_RATE`].
+pub fn estimate_audio_duration(data: &[u8]) -> f64 {
+    let min_duration = data.len() as f64 / MAX_AUDIO_BYTE_RATE as f64;
+    wav_duration(data)
+        .unwrap_or(data.len() as f64 / MIN_AUDIO_BYTE_RATE as f64)
+        .max(min_duration)
+}
+
+/// Returns the number of compute units charged for transcribing audio of the given duration,
+/// in seconds, which is one compute unit per (started) second of audio, and at least one.
+pub fn audio_duration_compute_units(duration: f64) -> i64 {
+    duration.ceil().max(1.0) as i64
+}
+
+/// Returns the duration of a WAV file, in seconds, from the sample rate and block alignment
+/// of its `fmt ` chunk and the size of its `data` chunk, or `None` if the file is not a valid
+/// WAV file.
+///
+/// The byte rate of the `fmt ` chunk must be the product of its sample rate and block
+/// alignment, and at most [`MAX_AUDIO_BYTE_RATE`], for the file to be valid.
+fn wav_duration(data: &[u8]) -> Option<f64> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let read_u16 = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let fmt_byte_rate = |chunk_start: usize| {
+        let sample_rate = read_u32(chunk_start + 4)?;
+        let byte_rate = read_u32(chunk_start + 8)?;
+        let block_align = read_u16(chunk_start + 12)?;
+        sample_rate
+            .checked_mul(block_align)
+            .filter(|rate| *rate > 0 && *rate == byte_rate && *rate as u64 <= MAX_AUDIO_BYTE_RATE)
+    };
+    let mut byte_rate = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let chunk_id = &data[offset..offset + 4];
+        let chunk_size = read_u32(offset + 4)?;
+        let chunk_start = offset + 8;
+        match chunk_id {
+            b"fmt " => byte_rate = fmt_byte_rate(chunk_start),
+            b"data" => {
+                // NOTE: Streamed WAV files may have a placeholder data size, so the size
+                // is bounded by the number of bytes actually present
+                let data_size = chunk_size.min(data.len() - chunk_start);
+                return byte_rate.map(|byte_rate| data_size as f64 / byte_rate as f64);
+            }
+            _ => {}
+        }
+        // NOTE: Chunks are padded to an even number of bytes
+        offset = chunk_start
+            .checked_add(chunk_size)?
+            .checked_add(chunk_size % 2)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(byte_rate: u32, num_data_bytes: usize) -> Vec<u8> {
+        wav_with_format(byte_rate / 2, byte_rate, 2, num_data_bytes)
+    }
+
+    fn wav_with_format(
+        sample_rate: u32,
+        byte_rate: u32,
+        block_align: u16,
+        num_data_bytes: usize,
+    ) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\0\0\0\x01\0\x01\0");
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(b"\x10\0");
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(num_data_bytes as u32).to_le_bytes());
+        wav.extend(std::iter::repeat(0).take(num_data_bytes));
+        wav
+    }
+
+    #[test]
+    fn test_estimate_audio_duration() {
+        assert_eq!(estimate_audio_duration(&wav(32_000, 80_000)), 2.5);
+        // NOTE: Compressed audio is assumed to have the lowest bitrate
+        assert_eq!(estimate_audio_duration(&[0xFF; 5_000]), 2.5);
+        // NOTE: A WAV file with a truncated data chunk lasts as long as its data
+        let mut truncated = wav(32_000, 16_000);
+        let data_size_offset = truncated.len() - 16_000 - 4;
+        truncated[data_size_offset..data_size_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(estimate_audio_duration(&truncated), 0.5);
+    }
+
+    #[test]
+    fn test_estimate_audio_duration_with_untrusted_headers() {
+        let num_data_bytes = 64_000;
+        let size_based_duration = |wav: &[u8]| wav.len() as f64 / MIN_AUDIO_BYTE_RATE as f64;
+        // NOTE: A byte rate inconsistent with the sample rate and block alignment is ignored
+        let inconsistent = wav_with_format(16_000, u32::MAX, 2, num_data_bytes);
+        assert_eq!(
+            estimate_audio_duration(&inconsistent),
+            size_based_duration(&inconsistent)
+        );
+        let overflowing = wav_with_format(u32::MAX, u32::MAX, 1, num_data_bytes);
+        assert_eq!(
+            estimate_audio_duration(&overflowing),
+            size_based_duration(&overflowing)
+        );
+        // NOTE: Consistent headers above the highest byte rate are ignored as well
+        let too_fast = wav_with_format(1_000_000, 4_000_000, 4, num_data_bytes);
+        assert_eq!(
+            estimate_audio_duration(&too_fast),
+            size_based_duration(&too_fast)
+        );
+        let fastest = wav_with_format(192_000, MAX_AUDIO_BYTE_RATE as u32, 8, num_data_bytes);
+        assert!(
+            estimate_audio_duration(&fastest) >= fastest.len() as f64 / MAX_AUDIO_BYTE_RATE as f64
+        );
+        assert_eq!(
+            estimate_audio_duration(&wav_with_format(16_000, 32_000, 2, num_data_bytes)),
+            2.0
+        );
+    }
+}
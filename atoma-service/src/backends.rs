@@ -0,0 +1,884 @@
+pub mod adapters;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use futures::{future::join_all, Stream, StreamExt};
+use reqwest::{Client, Response, StatusCode, Url};
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::{sync::watch, time::MissedTickBehavior};
+use tracing::{error, info, instrument, trace, warn};
+
+use crate::{
+    config::{AtomaServiceConfig, BackendHealthConfig, BackendKind},
+    middleware::RequestType,
+};
+
+pub use adapters::InferenceBackend;
+
+/// A replica of an inference service, e.g. one of several vLLM containers serving the same model.
+#[derive(Debug)]
+struct Replica {
+    /// Base URL of the replica, without a trailing slash
+    url: String,
+    /// Number of requests currently being served by the replica
+    outstanding_requests: AtomicUsize,
+    /// Number of consecutive failed requests to the replica
+    consecutive_failures: AtomicU32,
+    /// Whether the last active health probe of the replica succeeded
+    is_healthy: AtomicBool,
+    /// The instant until which the replica is ejected from its pool, after failing too many
+    /// consecutive requests
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    /// Creates a new replica, assumed healthy until probed
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            outstanding_requests: AtomicUsize::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            is_healthy: AtomicBool::new(true),
+            ejected_until: Mutex::new(None),
+        }
+    }
+
+    /// Returns whether the replica is currently ejected from its pool
+    fn is_ejected(&self) -> bool {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .is_some_and(|ejected_until| Instant::now() < ejected_until)
+    }
+
+    /// Returns whether the replica can be selected to serve requests
+    fn is_available(&self) -> bool {
+        self.is_healthy.load(Ordering::Relaxed) && !self.is_ejected()
+    }
+
+    /// Records a successful request to the replica
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Records a failed request to the replica, ejecting it from its pool once it has failed
+    /// `max_consecutive_failures` requests in a row
+    fn record_failure(&self, health: &BackendHealthConfig) {
+        let consecutive_failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if consecutive_failures >= health.max_consecutive_failures {
+            warn!(
+                target = "atoma-service",
+                event = "backend_replica_ejected",
+                url = self.url,
+                consecutive_failures,
+                "Ejecting inference service replica for {:?}, after {consecutive_failures} consecutive failures",
+                health.ejection_duration
+            );
+            *self.ejected_until.lock().unwrap() = Some(Instant::now() + health.ejection_duration);
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the result of an active health probe of the replica. A successful probe
+    /// reinstates the replica, if it was ejected.
+    fn record_probe(&self, is_healthy: bool) {
+        if self.is_healthy.swap(is_healthy, Ordering::Relaxed) != is_healthy {
+            info!(
+                target = "atoma-service",
+                event = "backend_replica_health_changed",
+                url = self.url,
+                is_healthy,
+                "Inference service replica health changed"
+            );
+        }
+        if is_healthy {
+            *self.ejected_until.lock().unwrap() = None;
+        }
+    }
+}
+
+/// The body of a request to an inference service
+#[derive(Clone, Copy, Debug)]
+pub enum RequestBody<'a> {
+    /// A JSON body
+    Json(&'a Value),
+    /// A body of another content type, forwarded as is (e.g. a `multipart/form-data` body)
+    Raw {
+        /// The body bytes
+        body: &'a Bytes,
+        /// The value of the `Content-Type` header of the body
+        content_type: &'a str,
+    },
+}
+
+/// A response of an inference service whose body is not necessarily JSON (e.g. audio)
+#[derive(Debug)]
+pub struct RawResponse {
+    /// The value of the `Content-Type` header of the response, if any
+    pub content_type: Option<String>,
+    /// The response body bytes
+    pub body: Bytes,
+}
+
+/// Tracks a request being served by a replica, for least-outstanding-requests balancing.
+///
+/// The request is counted as outstanding until this guard is dropped.
+struct OutstandingRequest(Arc<Replica>);
+
+impl OutstandingRequest {
+    fn new(replica: Arc<Replica>) -> Self {
+        replica.outstanding_requests.fetch_add(1, Ordering::Relaxed);
+        Self(replica)
+    }
+}
+
+impl Drop for OutstandingRequest {
+    fn drop(&mut self) {
+        self.0.outstanding_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A pool of replicas of an inference service, serving requests for one or more models.
+///
+/// Requests are balanced across replicas by least outstanding requests, among the replicas
+/// that passed their last health probe and are not ejected. If no replica is available, all
+/// replicas are tried, so that stale health information never makes the pool unusable.
+#[derive(Debug)]
+pub struct ModelBackend {
+    /// Name of the backend, for health reporting
+    name: String,
+    /// Inference engine of the replicas
+    kind: BackendKind,
+    /// Replicas of the inference service
+    replicas: Vec<Arc<Replica>>,
+    /// Offset of the first replica considered by the next selection, to spread requests
+    /// across equally loaded replicas
+    next_replica: AtomicUsize,
+    /// HTTP client for requests to the inference service, with the backend's connect timeout
+    client: Client,
+    /// Maximum duration of a request to the inference service
+    request_timeout: Option<Duration>,
+    /// Health checking configuration of the replicas
+    health: BackendHealthConfig,
+}
+
+impl ModelBackend {
+    /// Creates a new pool of replicas of an inference service.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend, usually the model it serves
+    /// * `kind` - The inference engine of the replicas
+    /// * `urls` - The base URLs of the replicas of the inference service
+    /// * `connect_timeout` - Maximum duration to establish a connection to the inference service
+    /// * `request_timeout` - Maximum duration of a request to the inference service
+    /// * `health` - Health checking configuration of the replicas
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no replicas, a URL is invalid, or if the HTTP client
+    /// cannot be built.
+    pub fn new(
+        name: &str,
+        kind: BackendKind,
+        urls: &[String],
+        connect_timeout: Option<Duration>,
+        request_timeout: Option<Duration>,
+        health: BackendHealthConfig,
+    ) -> Result<Self, ModelBackendError> {
+        if urls.is_empty() {
+            return Err(ModelBackendError::NoReplicas(name.to_string()));
+        }
+        for url in urls {
+            Url::parse(url).map_err(|e| ModelBackendError::InvalidUrl {
+                model: name.to_string(),
+                url: url.to_string(),
+                error: e.to_string(),
+            })?;
+        }
+        let mut client_builder = Client::builder();
+        if let Some(connect_timeout) = connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            replicas: urls.iter().map(|url| Arc::new(Replica::new(url))).collect(),
+            next_replica: AtomicUsize::new(0),
+            client: client_builder.build()?,
+            request_timeout,
+            health,
+        })
+    }
+
+    /// Returns the name of the backend
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the adapter for the inference engine of the backend
+    pub fn adapter(&self) -> &'static dyn InferenceBackend {
+        adapters::adapter(self.kind)
+    }
+
+    /// Selects the replica to serve the next request, among the replicas not yet attempted
+    /// for it.
+    ///
+    /// Returns the index of the available replica with the least outstanding requests, or of
+    /// any replica with the least outstanding requests, if none is available.
+    fn select_replica(&self, attempted: &[usize]) -> Option<usize> {
+        let num_replicas = self.replicas.len();
+        let offset = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        let candidates = (0..num_replicas)
+            .map(|i| (offset + i) % num_replicas)
+            .filter(|index| !attempted.contains(index));
+        let least_outstanding = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates.min_by_key(|index| {
+                self.replicas[*index]
+                    .outstanding_requests
+                    .load(Ordering::Relaxed)
+            })
+        };
+        least_outstanding(
+            &mut candidates
+                .clone()
+                .filter(|index| self.replicas[*index].is_available()),
+        )
+        .or_else(|| least_outstanding(&mut candidates.clone()))
+    }
+
+    /// Sends a POST request with `body` to `path`, failing over to another replica when a
+    /// replica fails to respond.
+    ///
+    /// Only requests for which the replica returned no response at all (e.g. on connection
+    /// errors or timeouts) are retried, as the inference service cannot have started streaming
+    /// a response for them. Server errors count as replica failures, but are returned as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the request on the inference service
+    /// * `body` - The body of the request
+    /// * `is_streaming` - Whether the response is streamed, in which case the request timeout
+    ///   only bounds the time until the response starts
+    ///
+    /// # Returns
+    ///
+    /// Returns the response, along with the guard tracking the request as outstanding on
+    /// the replica that served it.
+    async fn send(
+        &self,
+        path: &str,
+        body: RequestBody<'_>,
+        is_streaming: bool,
+    ) -> Result<(Response, OutstandingRequest), ModelBackendError> {
+        let mut attempted = Vec::with_capacity(self.replicas.len());
+        let mut last_error = None;
+        while let Some(index) = self.select_replica(&attempted) {
+            attempted.push(index);
+            let replica = &self.replicas[index];
+            let outstanding_request = OutstandingRequest::new(replica.clone());
+            let request = self.client.post(format!("{}{}", replica.url, path));
+            let request = match body {
+                RequestBody::Json(payload) => request.json(payload),
+                RequestBody::Raw { body, content_type } => request
+                    .header(reqwest::header::CONTENT_TYPE, content_type)
+                    .body(body.clone()),
+            };
+            let result = match self.request_timeout {
+                Some(request_timeout) if is_streaming => {
+                    tokio::time::timeout(request_timeout, request.send())
+                        .await
+                        .map_err(|_| ModelBackendError::Timeout(request_timeout))
+                        .and_then(|result| result.map_err(ModelBackendError::from))
+                }
+                Some(request_timeout) => request
+                    .timeout(request_timeout)
+                    .send()
+                    .await
+                    .map_err(ModelBackendError::from),
+                None => request.send().await.map_err(ModelBackendError::from),
+            };
+            match result {
+                Ok(response) => {
+                    if response.status().is_server_error() {
+                        replica.record_failure(&self.health);
+                    } else {
+                        replica.record_success();
+                    }
+                    return Ok((response, outstanding_request));
+                }
+                Err(e) => {
+                    replica.record_failure(&self.health);
+                    warn!(
+                        target = "atoma-service",
+                        event = "backend_replica_request_failed",
+                        backend = self.name,
+                        url = replica.url,
+                        error = %e,
+                        "Request to inference service replica failed, failing over to another replica"
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| ModelBackendError::NoReplicas(self.name.clone())))
+    }
+
+    /// Sends a POST request with a JSON `payload` to `path` on the inference service, and
+    /// returns the JSON body of its response.
+    ///
+    /// The request is bounded by the backend's request timeout, and fails over to another
+    /// replica if a replica fails to respond.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no replica responds, or if the response body is not valid JSON.
+    pub async fn post_json(&self, path: &str, payload: &Value) -> Result<Value, ModelBackendError> {
+        let (response, _outstanding_request) =
+            self.send(path, RequestBody::Json(payload), false).await?;
+        Ok(response.json::<Value>().await?)
+    }
+
+    /// Sends a POST request with `body` to `path` on the inference service, and returns its
+    /// response body as is, along with its content type.
+    ///
+    /// This is used for requests or responses that are not JSON, such as audio transcriptions
+    /// of `multipart/form-data` bodies, or generated speech. The request is bounded by the
+    /// backend's request timeout, and fails over to another replica if a replica fails to
+    /// respond.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no replica responds, or if the response has an error status.
+    pub async fn post_raw(
+        &self,
+        path: &str,
+        body: RequestBody<'_>,
+    ) -> Result<RawResponse, ModelBackendError> {
+        let (response, _outstanding_request) = self.send(path, body, false).await?;
+        if !response.status().is_success() {
+            return Err(ModelBackendError::ErrorStatus(response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(str::to_string);
+        Ok(RawResponse {
+            content_type,
+            body: response.bytes().await?,
+        })
+    }
+
+    /// Sends a POST request with a JSON `payload` to `path` on the inference service, for a
+    /// streamed response.
+    ///
+    /// As streams can last for much longer than regular responses, the backend's request timeout
+    /// only bounds the time until the response starts, not the time to fully receive it. The
+    /// request fails over to another replica if a replica fails to respond, and counts as
+    /// outstanding on the replica serving it until the returned stream is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no replica responds, or if the response has an error status.
+    pub async fn post_streaming(
+        &self,
+        path: &str,
+        payload: &Value,
+    ) -> Result<impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static, ModelBackendError>
+    {
+        let (response, outstanding_request) =
+            self.send(path, RequestBody::Json(payload), true).await?;
+        if !response.status().is_success() {
+            return Err(ModelBackendError::ErrorStatus(response.status()));
+        }
+        Ok(response.bytes_stream().map(move |chunk| {
+            let _outstanding_request = &outstanding_request;
+            chunk
+        }))
+    }
+
+    /// Actively probes the health endpoint of a replica, and records the result
+    async fn probe(&self, replica: &Replica) {
+        let is_healthy = self
+            .client
+            .get(format!("{}{}", replica.url, self.health.health_check_path))
+            .timeout(self.health.health_check_timeout)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success());
+        trace!(
+            target = "atoma-service",
+            event = "backend_replica_probed",
+            backend = self.name,
+            url = replica.url,
+            is_healthy,
+        );
+        replica.record_probe(is_healthy);
+    }
+
+    /// Returns the current health of the backend and of each of its replicas
+    pub fn health(&self) -> BackendHealth {
+        let replicas = self
+            .replicas
+            .iter()
+            .map(|replica| ReplicaHealth {
+                url: replica.url.clone(),
+                is_healthy: replica.is_healthy.load(Ordering::Relaxed),
+                is_ejected: replica.is_ejected(),
+                outstanding_requests: replica.outstanding_requests.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+        BackendHealth {
+            name: self.name.clone(),
+            is_available: self.replicas.iter().any(|replica| replica.is_available()),
+            replicas,
+        }
+    }
+}
+
+/// Health of an inference backend, as reported by the `/health` endpoint
+#[derive(Debug, Serialize)]
+pub struct BackendHealth {
+    /// Name of the backend
+    pub name: String,
+    /// Whether any replica of the backend is available to serve requests
+    pub is_available: bool,
+    /// Health of each replica of the backend
+    pub replicas: Vec<ReplicaHealth>,
+}
+
+/// Health of a replica of an inference backend
+#[derive(Debug, Serialize)]
+pub struct ReplicaHealth {
+    /// Base URL of the replica
+    pub url: String,
+    /// Whether the last active health probe of the replica succeeded
+    pub is_healthy: bool,
+    /// Whether the replica is ejected, after failing too many consecutive requests
+    pub is_ejected: bool,
+    /// Number of requests currently being served by the replica
+    pub outstanding_requests: usize,
+}
+
+/// The inference backends of the models deployed by the Atoma Service.
+///
+/// Each model is served by the backend configured for it in `backends`, if any, and by the
+/// service URL of the requested endpoint otherwise (e.g. `chat_completions_service_url`).
+#[derive(Debug, Default)]
+pub struct ModelBackends {
+    /// The backends configured for specific models, keyed by model name
+    backends: HashMap<String, ModelBackend>,
+    /// The backend for chat completions of models without a configured backend
+    chat_completions: Option<ModelBackend>,
+    /// The backend for embeddings of models without a configured backend
+    embeddings: Option<ModelBackend>,
+    /// The backend for image generations of models without a configured backend
+    image_generations: Option<ModelBackend>,
+    /// The backend for audio transcriptions of models without a configured backend
+    audio_transcriptions: Option<ModelBackend>,
+    /// The backend for speech generations of models without a configured backend
+    speech: Option<ModelBackend>,
+    /// Health checking configuration of the backends
+    health: BackendHealthConfig,
+}
+
+impl ModelBackends {
+    /// Creates the inference backends of the deployed models, from the service configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - A backend is configured for a model that is not in `models`, or more than once for
+    ///   the same model
+    /// - A backend is configured without any replica URL
+    /// - A model in `models` has no configured backend, and no service URL is configured
+    /// - A URL is invalid
+    pub fn from_config(config: &AtomaServiceConfig) -> Result<Self, ModelBackendError> {
+        let mut backends = HashMap::with_capacity(config.backends.len());
+        for backend in &config.backends {
+            if !config.models.contains(&backend.model) {
+                return Err(ModelBackendError::UnknownModel(backend.model.clone()));
+            }
+            let model_backend = ModelBackend::new(
+                &backend.model,
+                backend.kind,
+                &backend.urls,
+                backend.connect_timeout,
+                backend.request_timeout,
+                config.backend_health.clone(),
+            )?;
+            if backends
+                .insert(backend.model.clone(), model_backend)
+                .is_some()
+            {
+                return Err(ModelBackendError::DuplicateModel(backend.model.clone()));
+            }
+        }
+
+        let service_backend = |name: &str, url: &Option<String>| {
+            url.as_ref()
+                .map(|url| {
+                    // NOTE: Service URLs are assumed to be served by vLLM
+                    ModelBackend::new(
+                        name,
+                        BackendKind::default(),
+                        std::slice::from_ref(url),
+                        None,
+                        None,
+                        config.backend_health.clone(),
+                    )
+                })
+                .transpose()
+        };
+        let model_backends = Self {
+            backends,
+            chat_completions: service_backend(
+                "chat_completions_service",
+                &config.chat_completions_service_url,
+            )?,
+            embeddings: service_backend("embeddings_service", &config.embeddings_service_url)?,
+            image_generations: service_backend(
+                "image_generations_service",
+                &config.image_generations_service_url,
+            )?,
+            audio_transcriptions: service_backend(
+                "audio_transcriptions_service",
+                &config.audio_transcriptions_service_url,
+            )?,
+            speech: service_backend("speech_service", &config.speech_service_url)?,
+            health: config.backend_health.clone(),
+        };
+
+        // NOTE: The endpoint a model is served on is only known from its tasks, so models
+        // without a configured backend only require a service URL for any endpoint
+        let has_service_backend = model_backends.chat_completions.is_some()
+            || model_backends.embeddings.is_some()
+            || model_backends.image_generations.is_some()
+            || model_backends.audio_transcriptions.is_some()
+            || model_backends.speech.is_some();
+        if !has_service_backend {
+            if let Some(model) = config
+                .models
+                .iter()
+                .find(|model| !model_backends.backends.contains_key(*model))
+            {
+                return Err(ModelBackendError::MissingBackend(model.clone()));
+            }
+        }
+
+        Ok(model_backends)
+    }
+
+    /// Returns the inference backend serving `request_type` requests for `model`, if any
+    pub fn get(&self, model: &str, request_type: &RequestType) -> Option<&ModelBackend> {
+        self.backends.get(model).or_else(|| match request_type {
+            RequestType::ChatCompletions | RequestType::Completions => {
+                self.chat_completions.as_ref()
+            }
+            // NOTE: Rerank (cross-encoder) models are served by embeddings services, such as TEI
+            RequestType::Embeddings | RequestType::Rerank => self.embeddings.as_ref(),
+            RequestType::ImageGenerations => self.image_generations.as_ref(),
+            RequestType::AudioTranscriptions => self.audio_transcriptions.as_ref(),
+            RequestType::Speech => self.speech.as_ref(),
+            RequestType::NonInference => None,
+        })
+    }
+
+    /// Returns an iterator over all the inference backends
+    fn iter(&self) -> impl Iterator<Item = &ModelBackend> {
+        self.backends.values().chain(
+            [
+                &self.chat_completions,
+                &self.embeddings,
+                &self.image_generations,
+                &self.audio_transcriptions,
+                &self.speech,
+            ]
+            .into_iter()
+            .flatten(),
+        )
+    }
+
+    /// Returns the current health of each inference backend, sorted by name
+    pub fn health(&self) -> Vec<BackendHealth> {
+        let mut health = self.iter().map(ModelBackend::health).collect::<Vec<_>>();
+        health.sort_by(|a, b| a.name.cmp(&b.name));
+        health
+    }
+
+    /// Actively probes the health endpoint of every replica, at the configured interval, until
+    /// a shutdown signal is received.
+    ///
+    /// # Arguments
+    ///
+    /// * `shutdown_signal` - Receiver for the node's shutdown signal
+    #[instrument(level = "info", skip_all)]
+    pub async fn run_health_checks(
+        self: Arc<Self>,
+        mut shutdown_signal: watch::Receiver<bool>,
+    ) -> Result<(), ModelBackendError> {
+        let mut interval = tokio::time::interval(self.health.health_check_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    join_all(self.iter().flat_map(|backend| {
+                        backend
+                            .replicas
+                            .iter()
+                            .map(move |replica| backend.probe(replica))
+                    }))
+                    .await;
+                }
+                shutdown_signal_changed = shutdown_signal.changed() => {
+                    match shutdown_signal_changed {
+                        Ok(()) => {
+                            if *shutdown_signal.borrow() {
+                                trace!(
+                                    target = "atoma-service",
+                                    event = "shutdown_signal",
+                                    "Shutdown signal received, stopping backend health checks"
+                                );
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            error!(
+                                target = "atoma-service",
+                                event = "shutdown_signal_error",
+                                error = %e,
+                                "Shutdown signal channel closed"
+                            );
+                            // NOTE: We want to break here as well, since no one can signal shutdown anymore
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ModelBackendError {
+    #[error("Invalid URL {url} for the backend of model {model}: {error}")]
+    InvalidUrl {
+        model: String,
+        url: String,
+        error: String,
+    },
+    #[error("Backend configured for model {0}, which is not in the list of deployed models")]
+    UnknownModel(String),
+    #[error("More than one backend configured for model {0}")]
+    DuplicateModel(String),
+    #[error("No backend configured for model {0}, and no service URL configured")]
+    MissingBackend(String),
+    #[error("No replica configured for backend {0}")]
+    NoReplicas(String),
+    #[error("Failed to send request to the inference service: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("Inference service did not respond within {0:?}")]
+    Timeout(Duration),
+    #[error("Inference service returned error status {0}")]
+    ErrorStatus(StatusCode),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelBackendConfig;
+    use axum::{routing::post, Json, Router};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    fn config(
+        backends: Vec<ModelBackendConfig>,
+        chat_completions_url: Option<&str>,
+    ) -> AtomaServiceConfig {
+        AtomaServiceConfig {
+            chat_completions_service_url: chat_completions_url.map(str::to_string),
+            embeddings_service_url: None,
+            image_generations_service_url: None,
+            audio_transcriptions_service_url: None,
+            speech_service_url: None,
+            backends,
+            backend_health: BackendHealthConfig::default(),
+            admission_control: vec![],
+            rate_limits: Default::default(),
+            replay_protection: Default::default(),
+            zklogin: None,
+            models: vec!["llama".to_string(), "e5".to_string()],
+            revisions: vec!["main".to_string(), "main".to_string()],
+            num_tokens_per_image: 1_024,
+            usage_reconciliation: Default::default(),
+            model_metadata: vec![],
+            task_roles: Default::default(),
+            service_bind_address: "0.0.0.0:3000".to_string(),
+        }
+    }
+
+    fn backend(model: &str, urls: &[&str]) -> ModelBackendConfig {
+        ModelBackendConfig {
+            model: model.to_string(),
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            connect_timeout: None,
+            request_timeout: Some(Duration::from_secs(30)),
+            kind: BackendKind::default(),
+        }
+    }
+
+    /// Returns the URL of a local port with nothing listening on it
+    async fn closed_port_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    /// Spawns a local inference service replica answering every request with `response`
+    async fn spawn_replica(response: Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route(
+            "/v1/embeddings",
+            post(move || {
+                let response = response.clone();
+                async move { Json(response) }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[test]
+    fn test_model_backends_routing() {
+        let backends = ModelBackends::from_config(&config(
+            vec![backend("e5", &["http://tei:80/"])],
+            Some("http://vllm:8000"),
+        ))
+        .unwrap();
+        assert_eq!(
+            backends.get("e5", &RequestType::Embeddings).unwrap().name(),
+            "e5"
+        );
+        assert_eq!(
+            backends
+                .get("llama", &RequestType::ChatCompletions)
+                .unwrap()
+                .name(),
+            "chat_completions_service"
+        );
+        assert!(backends.get("llama", &RequestType::Embeddings).is_none());
+    }
+
+    #[test]
+    fn test_model_backends_validation() {
+        assert!(matches!(
+            ModelBackends::from_config(&config(vec![backend("mistral", &["http://vllm:8000"])], None)),
+            Err(ModelBackendError::UnknownModel(model)) if model == "mistral"
+        ));
+        assert!(matches!(
+            ModelBackends::from_config(&config(
+                vec![backend("e5", &["http://tei:80"]), backend("e5", &["http://tei:81"])],
+                None
+            )),
+            Err(ModelBackendError::DuplicateModel(model)) if model == "e5"
+        ));
+        assert!(matches!(
+            ModelBackends::from_config(&config(vec![backend("e5", &["http://tei:80"])], None)),
+            Err(ModelBackendError::MissingBackend(model)) if model == "llama"
+        ));
+        assert!(matches!(
+            ModelBackends::from_config(&config(vec![backend("e5", &["tei"])], Some("http://vllm:8000"))),
+            Err(ModelBackendError::InvalidUrl { model, .. }) if model == "e5"
+        ));
+        assert!(matches!(
+            ModelBackends::from_config(&config(vec![backend("e5", &[])], Some("http://vllm:8000"))),
+            Err(ModelBackendError::NoReplicas(model)) if model == "e5"
+        ));
+    }
+
+    #[test]
+    fn test_select_replica_least_outstanding_requests() {
+        let urls = ["http://a:80", "http://b:80", "http://c:80"].map(str::to_string);
+        let backend = ModelBackend::new(
+            "e5",
+            BackendKind::default(),
+            &urls,
+            None,
+            None,
+            BackendHealthConfig::default(),
+        )
+        .unwrap();
+        let _a = OutstandingRequest::new(backend.replicas[0].clone());
+        let _b = OutstandingRequest::new(backend.replicas[1].clone());
+        assert_eq!(backend.select_replica(&[]), Some(2));
+        assert_eq!(backend.select_replica(&[2]).map(|i| i < 2), Some(true));
+        assert_eq!(backend.select_replica(&[0, 1, 2]), None);
+
+        // NOTE: Unavailable replicas are only selected when no replica is available
+        backend.replicas[2].record_probe(false);
+        assert_ne!(backend.select_replica(&[]), Some(2));
+        assert_eq!(backend.select_replica(&[0, 1]), Some(2));
+    }
+
+    #[test]
+    fn test_replica_ejection() {
+        let health = BackendHealthConfig {
+            max_consecutive_failures: 2,
+            ..Default::default()
+        };
+        let replica = Replica::new("http://a:80");
+        replica.record_failure(&health);
+        replica.record_success();
+        replica.record_failure(&health);
+        assert!(replica.is_available());
+        replica.record_failure(&health);
+        assert!(replica.is_ejected());
+        assert!(!replica.is_available());
+
+        // NOTE: A successful health probe reinstates the replica
+        replica.record_probe(true);
+        assert!(replica.is_available());
+    }
+
+    #[tokio::test]
+    async fn test_post_json_fails_over_to_another_replica() {
+        let response = json!({"data": [{"embedding": [0.1, 0.2]}]});
+        let urls = [
+            closed_port_url().await,
+            spawn_replica(response.clone()).await,
+        ];
+        let health = BackendHealthConfig {
+            max_consecutive_failures: 1,
+            ..Default::default()
+        };
+        let backend =
+            ModelBackend::new("e5", BackendKind::default(), &urls, None, None, health).unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                backend
+                    .post_json("/v1/embeddings", &json!({"input": "Hello"}))
+                    .await
+                    .unwrap(),
+                response
+            );
+        }
+        let health = backend.health();
+        assert!(health.is_available);
+        assert!(health.replicas[0].is_ejected);
+        assert!(!health.replicas[1].is_ejected);
+        assert_eq!(health.replicas[1].outstanding_requests, 0);
+    }
+}
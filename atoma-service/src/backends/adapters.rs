@@ -0,0 +1,218 @@
+use serde_json::{json, Value};
+
+use crate::{config::BackendKind, handlers::USAGE_KEY};
+
+/// The key for the stream options of a request
+const STREAM_OPTIONS_KEY: &str = "stream_options";
+
+/// The key for the choices of a response or chunk
+const CHOICES_KEY: &str = "choices";
+
+/// The key for the finish reason of a choice
+const FINISH_REASON_KEY: &str = "finish_reason";
+
+/// The key for the timings reported by llama.cpp instead of the usage
+const TIMINGS_KEY: &str = "timings";
+
+/// The keys identifying a chunk, copied to the usage chunks split from it
+const CHUNK_ID_KEYS: [&str; 4] = ["id", "object", "created", "model"];
+
+/// An adapter for the API of an inference engine.
+///
+/// Inference engines all expose OpenAI-compatible endpoints, but differ in how they report
+/// usage. Adapters normalize requests to, and responses from, an inference engine, so that
+/// handlers and the [`Streamer`](crate::streamer::Streamer) only deal with vLLM's behavior:
+/// non-streaming responses report their `usage`, and streams end with a chunk without choices
+/// reporting the usage of the request.
+pub trait InferenceBackend: Send + Sync {
+    /// Prepares the body of a streaming request, so that the inference engine reports the
+    /// usage of the request at the end of the stream.
+    fn prepare_streaming_request(&self, payload: &mut Value) {
+        payload[STREAM_OPTIONS_KEY] = json!({
+            "include_usage": true
+        });
+    }
+
+    /// Returns the usage reported in a response or chunk, in the OpenAI format, if any
+    fn usage(&self, body: &Value) -> Option<Value> {
+        body.get(USAGE_KEY)
+            .filter(|usage| usage.is_object())
+            .cloned()
+    }
+
+    /// Normalizes the body of a non-streaming response, so that it reports its `usage`
+    fn normalize_response(&self, response: &mut Value) {
+        if let Some(usage) = self.usage(response) {
+            response[USAGE_KEY] = usage;
+        }
+    }
+
+    /// Normalizes a chunk of a streamed response into the chunks a vLLM stream would contain
+    fn normalize_chunk(&self, chunk: Value) -> Vec<Value> {
+        vec![chunk]
+    }
+}
+
+/// Adapter for vLLM, which needs no normalization.
+#[derive(Debug)]
+pub struct VllmBackend;
+
+impl InferenceBackend for VllmBackend {}
+
+/// Adapter for SGLang, whose OpenAI-compatible API reports usage the same way as vLLM.
+#[derive(Debug)]
+pub struct SglangBackend;
+
+impl InferenceBackend for SglangBackend {}
+
+/// Adapter for Hugging Face Text Generation Inference (TGI).
+///
+/// TGI reports the usage of a stream on the chunk carrying the finish reason, instead of on a
+/// separate chunk without choices.
+#[derive(Debug)]
+pub struct TgiBackend;
+
+impl InferenceBackend for TgiBackend {
+    fn normalize_chunk(&self, chunk: Value) -> Vec<Value> {
+        let usage = self.usage(&chunk);
+        split_final_chunk(chunk, usage)
+    }
+}
+
+/// Adapter for the llama.cpp server.
+///
+/// The llama.cpp server may report its `timings` instead of the usage, both on non-streaming
+/// responses and on the chunk carrying the finish reason of a stream.
+#[derive(Debug)]
+pub struct LlamaCppBackend;
+
+impl InferenceBackend for LlamaCppBackend {
+    fn usage(&self, body: &Value) -> Option<Value> {
+        if let Some(usage) = body.get(USAGE_KEY).filter(|usage| usage.is_object()) {
+            return Some(usage.clone());
+        }
+        let timings = body.get(TIMINGS_KEY)?;
+        let count = |key: &str| timings.get(key).and_then(Value::as_i64);
+        // NOTE: `prompt_n` only counts the prompt tokens that were not cached, `cache_n` the
+        // ones reused from the prompt cache
+        let prompt_tokens = count("prompt_n")? + count("cache_n").unwrap_or(0);
+        let completion_tokens = count("predicted_n")?;
+        Some(json!({
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }))
+    }
+
+    fn normalize_chunk(&self, chunk: Value) -> Vec<Value> {
+        let usage = self.usage(&chunk);
+        split_final_chunk(chunk, usage)
+    }
+}
+
+/// Returns the adapter for an inference engine
+pub fn adapter(kind: BackendKind) -> &'static dyn InferenceBackend {
+    match kind {
+        BackendKind::Vllm => &VllmBackend,
+        BackendKind::Tgi => &TgiBackend,
+        BackendKind::Sglang => &SglangBackend,
+        BackendKind::LlamaCpp => &LlamaCppBackend,
+    }
+}
+
+/// Sets the `usage` of a chunk, and if the chunk carries a finish reason, follows it with a
+/// chunk without choices reporting the same usage, which is what ends vLLM streams.
+///
+/// The usage is kept on the chunk carrying the finish reason, as vLLM does when reporting
+/// usage on every chunk, so that it is known even if the client disconnects right after it.
+fn split_final_chunk(mut chunk: Value, usage: Option<Value>) -> Vec<Value> {
+    let Some(usage) = usage else {
+        return vec![chunk];
+    };
+    chunk[USAGE_KEY] = usage.clone();
+    let is_final = chunk
+        .get(CHOICES_KEY)
+        .and_then(Value::as_array)
+        .is_some_and(|choices| {
+            choices
+                .iter()
+                .any(|choice| choice.get(FINISH_REASON_KEY).is_some_and(Value::is_string))
+        });
+    if !is_final {
+        return vec![chunk];
+    }
+    let mut usage_chunk = json!({
+        CHOICES_KEY: [],
+        USAGE_KEY: usage
+    });
+    for key in CHUNK_ID_KEYS {
+        if let Some(value) = chunk.get(key) {
+            usage_chunk[key] = value.clone();
+        }
+    }
+    vec![chunk, usage_chunk]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "model": "llama",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": finish_reason}]
+        })
+    }
+
+    #[test]
+    fn test_tgi_splits_usage_from_final_chunk() {
+        let usage = json!({"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7});
+        let mut final_chunk = chunk(Some("stop"));
+        final_chunk[USAGE_KEY] = usage.clone();
+
+        let chunks = TgiBackend.normalize_chunk(final_chunk.clone());
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], final_chunk);
+        assert_eq!(
+            chunks[1],
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "model": "llama",
+                "choices": [],
+                "usage": usage
+            })
+        );
+        // NOTE: Chunks without usage, or without a finish reason, are left as is
+        assert_eq!(TgiBackend.normalize_chunk(chunk(Some("stop"))).len(), 1);
+        let mut content_chunk = chunk(None);
+        content_chunk[USAGE_KEY] = usage;
+        assert_eq!(TgiBackend.normalize_chunk(content_chunk).len(), 1);
+    }
+
+    #[test]
+    fn test_llama_cpp_reports_timings_as_usage() {
+        let timings = json!({"cache_n": 3, "prompt_n": 9, "predicted_n": 4, "predicted_ms": 80.5});
+        let expected_usage =
+            json!({"prompt_tokens": 12, "completion_tokens": 4, "total_tokens": 16});
+
+        let mut response = json!({"choices": [], "timings": timings});
+        LlamaCppBackend.normalize_response(&mut response);
+        assert_eq!(response[USAGE_KEY], expected_usage);
+
+        let mut final_chunk = chunk(Some("length"));
+        final_chunk[TIMINGS_KEY] = timings;
+        let chunks = LlamaCppBackend.normalize_chunk(final_chunk);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1][CHOICES_KEY], json!([]));
+        assert_eq!(chunks[1][USAGE_KEY], expected_usage);
+
+        // NOTE: The reported usage takes precedence over the timings
+        let usage = json!({"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2});
+        let mut response = json!({"usage": usage, "timings": {"prompt_n": 9, "predicted_n": 4}});
+        LlamaCppBackend.normalize_response(&mut response);
+        assert_eq!(response[USAGE_KEY], usage);
+    }
+}
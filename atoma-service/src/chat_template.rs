@@ -0,0 +1,304 @@
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde_json::Value;
+
+/// The name under which the chat template is registered in its environment
+const CHAT_TEMPLATE_NAME: &str = "chat_template";
+
+/// The name of the default chat template, for models shipping several named templates
+const DEFAULT_CHAT_TEMPLATE_NAME: &str = "default";
+
+/// The key for the chat template in a model's `tokenizer_config.json`
+const CHAT_TEMPLATE_KEY: &str = "chat_template";
+
+/// The key for the beginning of sequence token in a model's `tokenizer_config.json`
+const BOS_TOKEN_KEY: &str = "bos_token";
+
+/// The key for the end of sequence token in a model's `tokenizer_config.json`
+const EOS_TOKEN_KEY: &str = "eos_token";
+
+/// The key for the content of a special token, when given as an object
+const SPECIAL_TOKEN_CONTENT_KEY: &str = "content";
+
+/// The key for the tool calls of a message
+const TOOL_CALLS: &str = "tool_calls";
+
+/// The key for the function of a tool call
+const FUNCTION: &str = "function";
+
+/// The key for the arguments of a tool call function
+const FUNCTION_ARGUMENTS: &str = "arguments";
+
+/// A model's Jinja chat template, as published in its HuggingFace repository.
+///
+/// The template turns a conversation into the exact prompt that the inference service
+/// feeds to the model, so that the number of prompt tokens can be counted before the
+/// request is forwarded. Templates are rendered the same way `transformers` does, that is,
+/// with `trim_blocks` and `lstrip_blocks` enabled, Python-style string and dict methods,
+/// and a `raise_exception` function.
+pub struct ChatTemplate {
+    /// The environment holding the compiled template
+    environment: Environment<'static>,
+    /// The beginning of sequence token, if any
+    bos_token: Option<String>,
+    /// The end of sequence token, if any
+    eos_token: Option<String>,
+}
+
+impl ChatTemplate {
+    /// Compiles a new chat template.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The Jinja source of the chat template
+    /// * `bos_token` - The beginning of sequence token, exposed to the template as `bos_token`
+    /// * `eos_token` - The end of sequence token, exposed to the template as `eos_token`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template has a syntax error.
+    pub fn new(
+        template: String,
+        bos_token: Option<String>,
+        eos_token: Option<String>,
+    ) -> Result<Self, Error> {
+        let mut environment = Environment::new();
+        environment.set_trim_blocks(true);
+        environment.set_lstrip_blocks(true);
+        environment
+            .set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        minijinja_contrib::add_to_environment(&mut environment);
+        environment.add_function("raise_exception", raise_exception);
+        environment.add_template_owned(CHAT_TEMPLATE_NAME, template)?;
+        Ok(Self {
+            environment,
+            bos_token,
+            eos_token,
+        })
+    }
+
+    /// Creates a chat template from the files of a model's HuggingFace repository.
+    ///
+    /// The template is taken from the standalone `chat_template.jinja` file, if the repository
+    /// has one, and from the `chat_template` entry of `tokenizer_config.json` otherwise. The
+    /// latter can either be a single template, or a list of named templates, in which case the
+    /// `default` one is used. Special tokens are always read from `tokenizer_config.json`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokenizer_config` - The contents of the model's `tokenizer_config.json`
+    /// * `chat_template_file` - The contents of the model's `chat_template.jinja`, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` if the model has no chat template.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template has a syntax error.
+    pub fn from_hf_files(
+        tokenizer_config: &Value,
+        chat_template_file: Option<String>,
+    ) -> Result<Option<Self>, Error> {
+        let template = match (chat_template_file, tokenizer_config.get(CHAT_TEMPLATE_KEY)) {
+            (Some(template), _) => template,
+            (None, Some(Value::String(template))) => template.clone(),
+            (None, Some(Value::Array(templates))) => {
+                let Some(template) = templates
+                    .iter()
+                    .find(|t| {
+                        t.get("name").and_then(|n| n.as_str()) == Some(DEFAULT_CHAT_TEMPLATE_NAME)
+                    })
+                    .and_then(|t| t.get("template"))
+                    .and_then(|t| t.as_str())
+                else {
+                    return Ok(None);
+                };
+                template.to_string()
+            }
+            _ => return Ok(None),
+        };
+        let special_token = |key: &str| match tokenizer_config.get(key) {
+            Some(Value::String(token)) => Some(token.clone()),
+            Some(Value::Object(token)) => token
+                .get(SPECIAL_TOKEN_CONTENT_KEY)
+                .and_then(|content| content.as_str())
+                .map(str::to_string),
+            _ => None,
+        };
+        Self::new(
+            template,
+            special_token(BOS_TOKEN_KEY),
+            special_token(EOS_TOKEN_KEY),
+        )
+        .map(Some)
+    }
+
+    /// Renders the prompt for a conversation, ending with the generation prompt for the
+    /// assistant's reply, as inference services do for chat completion requests.
+    ///
+    /// Messages are expected to have plain string (or `null`) contents. Tool call arguments
+    /// given as JSON encoded strings are decoded first, as templates expect them as objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The messages of the conversation
+    /// * `tools` - The tool definitions of the request, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if rendering fails, including when the template itself raises an
+    /// exception (e.g. for conversations whose roles do not alternate).
+    pub fn render(&self, messages: &[Value], tools: Option<&Value>) -> Result<String, Error> {
+        let messages = messages
+            .iter()
+            .cloned()
+            .map(decode_tool_call_arguments)
+            .collect::<Vec<_>>();
+        self.environment
+            .get_template(CHAT_TEMPLATE_NAME)?
+            .render(context! {
+                messages => messages,
+                tools => tools,
+                bos_token => self.bos_token,
+                eos_token => self.eos_token,
+                add_generation_prompt => true,
+            })
+    }
+}
+
+/// Decodes the JSON encoded arguments of a message's tool calls
+fn decode_tool_call_arguments(mut message: Value) -> Value {
+    if let Some(tool_calls) = message
+        .get_mut(TOOL_CALLS)
+        .and_then(|tool_calls| tool_calls.as_array_mut())
+    {
+        for arguments in tool_calls
+            .iter_mut()
+            .filter_map(|tool_call| tool_call.get_mut(FUNCTION)?.get_mut(FUNCTION_ARGUMENTS))
+        {
+            if let Some(decoded) = arguments
+                .as_str()
+                .and_then(|a| serde_json::from_str::<Value>(a).ok())
+            {
+                *arguments = decoded;
+            }
+        }
+    }
+    message
+}
+
+/// Implements the `raise_exception` function that HuggingFace chat templates use to
+/// reject invalid conversations
+fn raise_exception(message: String) -> Result<String, Error> {
+    Err(Error::new(ErrorKind::InvalidOperation, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A ChatML chat template, as used by many open models
+    const CHATML_TEMPLATE: &str = "{% for message in messages %}{{'<|im_start|>' + message['role'] + '\n' + message['content'] + '<|im_end|>' + '\n'}}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\n' }}{% endif %}";
+
+    /// A template in the style of Llama's, exercising `bos_token`, Python string methods,
+    /// `raise_exception`, tools and tool calls
+    const LLAMA_STYLE_TEMPLATE: &str = r#"{{- bos_token }}
+{%- if tools is not none %}
+Tools: {{ tools | tojson }}
+{%- endif %}
+{%- for message in messages %}
+    {%- if message['role'] not in ['system', 'user', 'assistant', 'tool'] %}
+        {{- raise_exception('Unsupported role: ' + message['role']) }}
+    {%- endif %}
+    {%- if message.tool_calls %}
+        {%- set tool_call = message.tool_calls[0].function %}
+<|{{ message['role'] }}|>{{ tool_call.name }}({{ tool_call.arguments.city }})<|eot|>
+    {%- else %}
+<|{{ message['role'] }}|>{{ message['content'].strip() }}<|eot|>
+    {%- endif %}
+{%- endfor %}
+{%- if add_generation_prompt %}
+<|assistant|>
+{%- endif %}"#;
+
+    #[test]
+    fn test_render_chatml_template() {
+        let template = ChatTemplate::new(CHATML_TEMPLATE.to_string(), None, None).unwrap();
+        let messages = vec![
+            json!({"role": "system", "content": "You are helpful."}),
+            json!({"role": "user", "content": "Hello"}),
+        ];
+        assert_eq!(
+            template.render(&messages, None).unwrap(),
+            "<|im_start|>system\nYou are helpful.<|im_end|>\n<|im_start|>user\nHello<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_render_llama_style_template() {
+        let template = ChatTemplate::new(
+            LLAMA_STYLE_TEMPLATE.to_string(),
+            Some("<|begin|>".to_string()),
+            None,
+        )
+        .unwrap();
+        let messages = vec![
+            json!({"role": "user", "content": "  Weather in Lisbon?  "}),
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\": \"Lisbon\"}"}
+                }]
+            }),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}),
+        ];
+        let tools = json!([{"type": "function", "function": {"name": "get_weather"}}]);
+        assert_eq!(
+            template.render(&messages, Some(&tools)).unwrap(),
+            "<|begin|>Tools: [{\"function\":{\"name\":\"get_weather\"},\"type\":\"function\"}]<|user|>Weather in Lisbon?<|eot|><|assistant|>get_weather(Lisbon)<|eot|><|tool|>Sunny<|eot|><|assistant|>"
+        );
+    }
+
+    #[test]
+    fn test_render_raises_exception() {
+        let template = ChatTemplate::new(LLAMA_STYLE_TEMPLATE.to_string(), None, None).unwrap();
+        let messages = vec![json!({"role": "narrator", "content": "Once upon a time"})];
+        let error = template.render(&messages, None).unwrap_err();
+        assert!(error.to_string().contains("Unsupported role: narrator"));
+    }
+
+    #[test]
+    fn test_from_hf_files() {
+        let tokenizer_config = json!({
+            "bos_token": {"content": "<s>", "lstrip": false},
+            "eos_token": "</s>",
+            "chat_template": [
+                {"name": "tool_use", "template": "tools"},
+                {"name": "default", "template": "{{ bos_token }}{{ messages[0]['content'] }}{{ eos_token }}"}
+            ]
+        });
+        let template = ChatTemplate::from_hf_files(&tokenizer_config, None)
+            .unwrap()
+            .expect("Chat template should be found");
+        let messages = vec![json!({"role": "user", "content": "Hi"})];
+        assert_eq!(template.render(&messages, None).unwrap(), "<s>Hi</s>");
+
+        // NOTE: A standalone `chat_template.jinja` file takes precedence
+        let template =
+            ChatTemplate::from_hf_files(&tokenizer_config, Some("{{ eos_token }}".to_string()))
+                .unwrap()
+                .expect("Chat template should be found");
+        assert_eq!(template.render(&messages, None).unwrap(), "</s>");
+
+        assert!(
+            ChatTemplate::from_hf_files(&json!({"eos_token": "</s>"}), None)
+                .unwrap()
+                .is_none()
+        );
+        assert!(ChatTemplate::from_hf_files(&json!({"chat_template": "{% if %}"}), None).is_err());
+    }
+}
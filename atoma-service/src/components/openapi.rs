@@ -2,10 +2,19 @@ use axum::Router;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+use crate::handlers::audio::{
+    AudioTranscriptionsOpenApi, ConfidentialAudioTranscriptionsOpenApi, ConfidentialSpeechOpenApi,
+    SpeechOpenApi, AUDIO_TRANSCRIPTIONS_PATH, CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH,
+    CONFIDENTIAL_SPEECH_PATH, SPEECH_PATH,
+};
 use crate::handlers::chat_completions::{
     ChatCompletionsOpenApi, ConfidentialChatCompletionsOpenApi, CHAT_COMPLETIONS_PATH,
     CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
 };
+use crate::handlers::completions::{
+    CompletionsOpenApi, ConfidentialCompletionsOpenApi, COMPLETIONS_PATH,
+    CONFIDENTIAL_COMPLETIONS_PATH,
+};
 use crate::handlers::embeddings::{
     ConfidentialEmbeddingsOpenApi, EmbeddingsOpenApi, CONFIDENTIAL_EMBEDDINGS_PATH, EMBEDDINGS_PATH,
 };
@@ -13,6 +22,12 @@ use crate::handlers::image_generations::{
     ConfidentialImageGenerationsOpenApi, ImageGenerationsOpenApi,
     CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_GENERATIONS_PATH,
 };
+use crate::handlers::models::{ModelsOpenApi, MODELS_PATH};
+use crate::handlers::quote::{QuoteOpenApi, QUOTE_PATH};
+use crate::handlers::rerank::{
+    ConfidentialRerankOpenApi, RerankOpenApi, CONFIDENTIAL_RERANK_PATH, RERANK_PATH,
+};
+use crate::handlers::stacks::{StacksOpenApi, STACKS_PATH};
 use crate::server::{HealthOpenApi, MetricsOpenApi, HEALTH_PATH, METRICS_PATH};
 
 pub fn openapi_routes() -> Router {
@@ -21,22 +36,42 @@ pub fn openapi_routes() -> Router {
         nest(
             (path = HEALTH_PATH, api = HealthOpenApi),
             (path = METRICS_PATH, api = MetricsOpenApi),
+            (path = MODELS_PATH, api = ModelsOpenApi),
+            (path = QUOTE_PATH, api = QuoteOpenApi),
+            (path = STACKS_PATH, api = StacksOpenApi),
             (path = CHAT_COMPLETIONS_PATH, api = ChatCompletionsOpenApi),
+            (path = COMPLETIONS_PATH, api = CompletionsOpenApi),
             (path = EMBEDDINGS_PATH, api = EmbeddingsOpenApi),
+            (path = RERANK_PATH, api = RerankOpenApi),
             (path = IMAGE_GENERATIONS_PATH, api = ImageGenerationsOpenApi),
+            (path = AUDIO_TRANSCRIPTIONS_PATH, api = AudioTranscriptionsOpenApi),
+            (path = SPEECH_PATH, api = SpeechOpenApi),
             (path = CONFIDENTIAL_IMAGE_GENERATIONS_PATH, api = ConfidentialImageGenerationsOpenApi),
             (path = CONFIDENTIAL_EMBEDDINGS_PATH, api = ConfidentialEmbeddingsOpenApi),
+            (path = CONFIDENTIAL_RERANK_PATH, api = ConfidentialRerankOpenApi),
             (path = CONFIDENTIAL_CHAT_COMPLETIONS_PATH, api = ConfidentialChatCompletionsOpenApi),
+            (path = CONFIDENTIAL_COMPLETIONS_PATH, api = ConfidentialCompletionsOpenApi),
+            (path = CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH, api = ConfidentialAudioTranscriptionsOpenApi),
+            (path = CONFIDENTIAL_SPEECH_PATH, api = ConfidentialSpeechOpenApi),
         ),
         tags(
             (name = "health", description = "Health check"),
             (name = "metrics", description = "Metrics"),
+            (name = "models", description = "Models"),
+            (name = "quote", description = "Price quotes"),
+            (name = "stacks", description = "Stack usage"),
             (name = "chat", description = "Chat completions"),
+            (name = "completions", description = "Text completions"),
             (name = "embeddings", description = "Embeddings"),
+            (name = "rerank", description = "Rerank"),
             (name = "images", description = "Image generations"),
+            (name = "audio", description = "Audio transcriptions and speech"),
             (name = "confidential-images", description = "Confidential image generations"),
             (name = "confidential-embeddings", description = "Confidential embeddings"),
+            (name = "confidential-rerank", description = "Confidential rerank"),
             (name = "confidential-chat", description = "Confidential chat completions"),
+            (name = "confidential-completions", description = "Confidential text completions"),
+            (name = "confidential-audio", description = "Confidential audio transcriptions and speech"),
         ),
         servers(
             (url = "http://localhost:8080"),
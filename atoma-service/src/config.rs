@@ -1,8 +1,45 @@
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
 use config::{Config, File};
 use serde::Deserialize;
 
+/// Default number of input tokens charged for each image in a chat completion request
+const DEFAULT_NUM_TOKENS_PER_IMAGE: i64 = 1_024;
+
+/// Default interval between active health probes of the inference backends' replicas
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default timeout of active health probes of the inference backends' replicas
+const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default path of the health endpoint of inference services (as exposed by vLLM and TEI)
+const DEFAULT_HEALTH_CHECK_PATH: &str = "/health";
+
+/// Default number of consecutive failed requests after which a replica is ejected
+const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+/// Default duration for which a failing replica is ejected
+const DEFAULT_EJECTION_DURATION: Duration = Duration::from_secs(30);
+
+/// Default maximum age of the timestamp of a signed request
+const DEFAULT_FRESHNESS_WINDOW: Duration = Duration::from_secs(300);
+
+/// Default maximum number of signed requests remembered in memory, in front of the database
+const DEFAULT_MAX_CACHED_SIGNATURES: usize = 100_000;
+
+/// Default role of chat completions tasks
+const DEFAULT_CHAT_COMPLETIONS_TASK_ROLE: i64 = 0;
+
+/// Default role of embeddings tasks
+const DEFAULT_EMBEDDINGS_TASK_ROLE: i64 = 1;
+
+/// Default role of image generations tasks
+const DEFAULT_IMAGE_GENERATIONS_TASK_ROLE: i64 = 2;
+
+/// Default interval between refreshes of the current Sui epoch, for deprecated tasks and
+/// zkLogin signatures
+pub const DEFAULT_EPOCH_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Configuration for the Atoma Service.
 ///
 /// This struct holds the configuration options for the Atoma Service,
@@ -12,21 +49,84 @@ pub struct AtomaServiceConfig {
     /// URL for the chat completions service.
     ///
     /// This is an optional field that, if provided, specifies the endpoint
-    /// for the chat completions service used by the Atoma Service.
+    /// for the chat completions service used by the Atoma Service, for
+    /// models without an entry in `backends`.
     pub chat_completions_service_url: Option<String>,
 
     /// URL for the embeddings service.
     ///
     /// This is an optional field that, if provided, specifies the endpoint
-    /// for the embeddings service used by the Atoma Service.
+    /// for the embeddings service used by the Atoma Service, for models
+    /// without an entry in `backends`.
     pub embeddings_service_url: Option<String>,
 
     /// URL for the image generations service.
     ///
     /// This is an optional field that, if provided, specifies the endpoint
-    /// for the image generations service used by the Atoma Service.
+    /// for the image generations service used by the Atoma Service, for
+    /// models without an entry in `backends`.
     pub image_generations_service_url: Option<String>,
 
+    /// URL for the audio transcriptions service.
+    ///
+    /// This is an optional field that, if provided, specifies the endpoint
+    /// for the audio transcriptions service (e.g. a Whisper server) used by
+    /// the Atoma Service, for models without an entry in `backends`.
+    pub audio_transcriptions_service_url: Option<String>,
+
+    /// URL for the speech service.
+    ///
+    /// This is an optional field that, if provided, specifies the endpoint
+    /// for the text-to-speech service used by the Atoma Service, for models
+    /// without an entry in `backends`.
+    pub speech_service_url: Option<String>,
+
+    /// Inference backends of the deployed models.
+    ///
+    /// This field maps models in `models` to the replicas of the inference service serving
+    /// them (e.g. several vLLM or TEI containers), along with the timeouts of requests to it.
+    /// Models without an entry are served by the service URL of the requested endpoint.
+    #[serde(default)]
+    pub backends: Vec<ModelBackendConfig>,
+
+    /// Health checking of the inference backends' replicas.
+    ///
+    /// This field specifies how replicas are actively probed, and when they are ejected
+    /// from their pool after failing requests.
+    #[serde(default)]
+    pub backend_health: BackendHealthConfig,
+
+    /// Admission control of the deployed models.
+    ///
+    /// This field contains an optional entry for each model in `models`, bounding the number
+    /// of requests in flight to the model's inference backend, and queueing the excess ones.
+    /// Models without an entry are not limited.
+    #[serde(default)]
+    pub admission_control: Vec<ModelAdmissionConfig>,
+
+    /// Rate limits of the requests of each Sui address, and on each stack.
+    ///
+    /// This field bounds the requests per second, concurrent requests and tokens per minute
+    /// that a single client can consume from the node. Requests exceeding any limit are
+    /// rejected with a `429 Too Many Requests` status code.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+
+    /// Replay protection of signed requests.
+    ///
+    /// This field specifies how long a signed timestamp remains fresh, and whether requests
+    /// must carry one. Signed requests carrying a timestamp are rejected if they were already
+    /// served, even across restarts of the node.
+    #[serde(default)]
+    pub replay_protection: ReplayProtectionConfig,
+
+    /// Verification of zkLogin signatures.
+    ///
+    /// This field points to the cached JWKs of the OpenID providers against which zkLogin
+    /// signatures are verified. If not set, requests with zkLogin signatures are rejected.
+    #[serde(default)]
+    pub zklogin: Option<ZkLoginConfig>,
+
     /// List of model names.
     ///
     /// This field contains a list of model names that are deployed by the Atoma Service,
@@ -39,12 +139,297 @@ pub struct AtomaServiceConfig {
     /// model that is currently supported by the Atoma Service.
     pub revisions: Vec<String>,
 
+    /// Number of input tokens charged for each image in a chat completion request.
+    ///
+    /// This field specifies the fixed cost, in tokens, used to estimate the compute units of
+    /// each `image_url` content part, for vision models. It should be at least the number of
+    /// tokens the deployed models use to encode a single image.
+    #[serde(default = "default_num_tokens_per_image")]
+    pub num_tokens_per_image: i64,
+
+    /// Reconciliation of the token usage reported by the inference services.
+    ///
+    /// This field specifies which token counts are charged to stacks for chat completion and
+    /// completion requests: the ones reported by the inference service, the ones counted by
+    /// the node with the model's tokenizer, or the maximum of both.
+    #[serde(default)]
+    pub usage_reconciliation: UsageReconciliationPolicy,
+
+    /// Token limits of the deployed models.
+    ///
+    /// This field contains an optional entry for each model in `models`, specifying its
+    /// context length and the bounds on the number of tokens a chat completion request can
+    /// ask it to generate. Models without an entry, or with no configured context length,
+    /// fall back to the context length in the model's `config.json`.
+    #[serde(default)]
+    pub model_metadata: Vec<ModelMetadata>,
+
+    /// Roles of the tasks serving each type of inference request.
+    ///
+    /// This field maps each inference endpoint to the role of the tasks whose stacks it
+    /// serves, so that a stack bought for one endpoint cannot be drained by requests to
+    /// another one. Endpoints without a role only check the model of the stack's task.
+    #[serde(default)]
+    pub task_roles: TaskRolesConfig,
+
     /// Bind address for the Atoma Service.
     ///
     /// This field specifies the address and port on which the Atoma Service will bind.
     pub service_bind_address: String,
 }
 
+/// Inference backend of a model deployed by the Atoma Service.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModelBackendConfig {
+    /// Name of the model, as listed in `models`
+    pub model: String,
+
+    /// Base URLs of the replicas of the inference service serving the model
+    pub urls: Vec<String>,
+
+    /// Maximum duration to establish a connection to the inference service
+    pub connect_timeout: Option<Duration>,
+
+    /// Maximum duration of a request to the inference service. For streamed
+    /// responses, it only bounds the time until the stream starts.
+    pub request_timeout: Option<Duration>,
+
+    /// Inference engine of the inference service, which determines how requests, streamed
+    /// chunks and usage reports are normalized
+    #[serde(default)]
+    pub kind: BackendKind,
+}
+
+/// Roles of the tasks serving each type of inference request, as set when the tasks are
+/// registered on-chain.
+///
+/// Chat completions (and completions), embeddings and image generations tasks default to the
+/// roles 0, 1 and 2. Audio transcriptions, speech and rerank tasks have no default role, and
+/// must be set to the roles of the tasks registered for their models.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TaskRolesConfig {
+    /// Role of the tasks serving chat completions and completions requests
+    pub chat_completions: Option<i64>,
+    /// Role of the tasks serving embeddings requests
+    pub embeddings: Option<i64>,
+    /// Role of the tasks serving image generations requests
+    pub image_generations: Option<i64>,
+    /// Role of the tasks serving audio transcriptions requests
+    pub audio_transcriptions: Option<i64>,
+    /// Role of the tasks serving speech requests
+    pub speech: Option<i64>,
+    /// Role of the tasks serving rerank requests
+    pub rerank: Option<i64>,
+}
+
+impl Default for TaskRolesConfig {
+    fn default() -> Self {
+        Self {
+            chat_completions: Some(DEFAULT_CHAT_COMPLETIONS_TASK_ROLE),
+            embeddings: Some(DEFAULT_EMBEDDINGS_TASK_ROLE),
+            image_generations: Some(DEFAULT_IMAGE_GENERATIONS_TASK_ROLE),
+            audio_transcriptions: None,
+            speech: None,
+            rerank: None,
+        }
+    }
+}
+
+/// Policy for the token usage charged to stacks, when the usage reported by an inference
+/// service differs from the one counted by the node.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageReconciliationPolicy {
+    /// Charge the token counts reported by the inference service
+    #[default]
+    Backend,
+    /// Charge the token counts of the node, which are the estimated number of prompt tokens
+    /// and the number of tokens of the generated text
+    Local,
+    /// Charge the maximum of the reported and local counts, for input and output tokens
+    Max,
+}
+
+/// Inference engine of an inference backend.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// vLLM, whose OpenAI-compatible API is used as the reference for all engines
+    #[default]
+    Vllm,
+    /// Hugging Face Text Generation Inference
+    Tgi,
+    /// SGLang
+    Sglang,
+    /// The llama.cpp server
+    LlamaCpp,
+}
+
+/// Health checking configuration of the inference backends' replicas.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BackendHealthConfig {
+    /// Interval between active health probes of each replica
+    pub health_check_interval: Duration,
+
+    /// Maximum duration of an active health probe
+    pub health_check_timeout: Duration,
+
+    /// Path of the health endpoint of the inference services
+    pub health_check_path: String,
+
+    /// Number of consecutive failed requests after which a replica is ejected from its pool
+    pub max_consecutive_failures: u32,
+
+    /// Duration for which a failing replica is ejected from its pool, unless a health probe
+    /// succeeds before
+    pub ejection_duration: Duration,
+}
+
+impl Default for BackendHealthConfig {
+    fn default() -> Self {
+        Self {
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            health_check_timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            health_check_path: DEFAULT_HEALTH_CHECK_PATH.to_string(),
+            max_consecutive_failures: DEFAULT_MAX_CONSECUTIVE_FAILURES,
+            ejection_duration: DEFAULT_EJECTION_DURATION,
+        }
+    }
+}
+
+/// Admission control configuration of a model deployed by the Atoma Service.
+///
+/// Requests beyond `max_in_flight_requests` wait in a FIFO queue for a request in flight to
+/// complete. Requests arriving when the queue is full, or waiting for longer than
+/// `queue_timeout`, are rejected with a `503 Service Unavailable` status code.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModelAdmissionConfig {
+    /// Name of the model, as listed in `models`
+    pub model: String,
+
+    /// Maximum number of requests in flight to the model's inference backend
+    pub max_in_flight_requests: usize,
+
+    /// Maximum number of requests waiting for a request in flight to complete
+    pub max_queue_depth: usize,
+
+    /// Maximum duration a request waits in the queue before being rejected
+    pub queue_timeout: Duration,
+}
+
+/// Rate limits of the requests to the Atoma Service.
+///
+/// Both scopes are enforced independently, so a request must be within the limits of its
+/// Sui address and of its stack to be served. Scopes without limits are not limited.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RateLimitConfig {
+    /// Limits of the requests signed by each Sui address
+    pub per_address: Option<RateLimits>,
+
+    /// Limits of the requests on each stack
+    pub per_stack: Option<RateLimits>,
+}
+
+/// Limits of the requests of a single rate limiting key (a Sui address or a stack).
+///
+/// Requests per second and tokens per minute are enforced with token buckets, allowing
+/// bursts of up to one second of requests, and one minute of tokens.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RateLimits {
+    /// Maximum number of requests per second
+    pub requests_per_second: Option<u64>,
+
+    /// Maximum number of requests served at once, including streamed responses
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Maximum number of tokens per minute, as estimated when reserving the compute units
+    /// of each request
+    pub tokens_per_minute: Option<u64>,
+}
+
+/// Replay protection configuration of the signed requests to the Atoma Service.
+///
+/// Clients opt in by signing a timestamp, optionally along with a nonce, with their request,
+/// either as the `X-Request-Timestamp` and `X-Request-Nonce` headers, or as the
+/// `request_timestamp` and `request_nonce` fields of the request body.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReplayProtectionConfig {
+    /// Whether signed requests without a timestamp are rejected
+    pub require_timestamp: bool,
+
+    /// Maximum difference between a request's timestamp and the node's clock, in either
+    /// direction. Replays are detected for as long as a request is fresh.
+    pub freshness_window: Duration,
+
+    /// Maximum number of seen requests remembered in memory, in front of the database
+    pub max_cached_signatures: usize,
+}
+
+impl Default for ReplayProtectionConfig {
+    fn default() -> Self {
+        Self {
+            require_timestamp: false,
+            freshness_window: DEFAULT_FRESHNESS_WINDOW,
+            max_cached_signatures: DEFAULT_MAX_CACHED_SIGNATURES,
+        }
+    }
+}
+
+/// Verification configuration of zkLogin signatures.
+///
+/// zkLogin signatures are verified offline, against JWKs cached in a JSON file, and the
+/// current Sui epoch, which is periodically fetched from the Sui RPC node.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ZkLoginConfig {
+    /// Path to the JSON file of the cached JWKs, a list of objects with a `jwk_id` (with the
+    /// `iss` and `kid` of the key) and a `jwk` (with its `kty`, `e`, `n` and `alg`)
+    pub jwks_path: String,
+
+    /// Interval between refreshes of the current Sui epoch
+    #[serde(default = "default_epoch_refresh_interval")]
+    pub epoch_refresh_interval: Duration,
+}
+
+/// Token limits of a model deployed by the Atoma Service.
+///
+/// These limits are enforced on chat completion requests before compute units are reserved
+/// for them, so that requests the model cannot serve are rejected upfront.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModelMetadata {
+    /// Name of the model, as listed in `models`
+    pub model: String,
+
+    /// Maximum number of tokens, prompt and completion combined, the model can process
+    pub context_length: Option<i64>,
+
+    /// Number of completion tokens used for requests that do not specify `max_tokens`
+    /// (or `max_completion_tokens`). If not set, requests without it can generate up to the
+    /// remaining context length.
+    pub default_max_tokens: Option<i64>,
+
+    /// Maximum number of completion tokens a single request can ask for
+    pub max_tokens: Option<i64>,
+
+    /// Whether requests asking for more completion tokens than allowed, either by `max_tokens`
+    /// or by the remaining context length, are clamped to the allowed amount instead of
+    /// being rejected
+    #[serde(default)]
+    pub clamp_max_tokens: bool,
+}
+
+/// Returns the default number of input tokens charged for each image
+fn default_num_tokens_per_image() -> i64 {
+    DEFAULT_NUM_TOKENS_PER_IMAGE
+}
+
+/// Returns the default interval between refreshes of the current Sui epoch
+fn default_epoch_refresh_interval() -> Duration {
+    DEFAULT_EPOCH_REFRESH_INTERVAL
+}
+
 impl AtomaServiceConfig {
     /// Creates a new `AtomaServiceConfig` instance from a configuration file.
     ///
@@ -1,11 +1,14 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::rate_limit::RateLimitStatus;
+
 /// Response structure for API errors
 ///
 /// This struct is used to provide a consistent error response format across the API.
@@ -27,6 +30,12 @@ pub struct ErrorDetails {
     pub code: String,
     /// A human-readable error message describing what went wrong
     pub message: String,
+    /// The OpenAI-style error type (e.g., "invalid_request_error"), for errors on request parameters
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    /// The request parameter that caused the error, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
 }
 
 /// Represents all possible errors that can occur within the Atoma service
@@ -63,6 +72,39 @@ pub enum AtomaServiceError {
         endpoint: String,
     },
 
+    /// Error returned when a request parameter has a value the model does not accept,
+    /// e.g. a `max_tokens` above the model's maximum number of completion tokens
+    #[error("Invalid value for parameter {param}: {message}")]
+    InvalidParameter {
+        /// Description of why the parameter value is invalid
+        message: String,
+        /// The name of the invalid parameter
+        param: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the prompt and the requested completion tokens do not fit
+    /// in the model's context window
+    #[error("Context length exceeded: {message}")]
+    ContextLengthExceeded {
+        /// Description of the context length overflow
+        message: String,
+        /// The name of the parameter to reduce, either the messages or the completion tokens
+        param: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the requested model is not served by the node
+    #[error("Model not found: {model}")]
+    ModelNotFound {
+        /// The requested model
+        model: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
     /// Error returned when the underlying ML model encounters an error
     #[error("Model error: {model_error}")]
     ModelError {
@@ -81,6 +123,41 @@ pub enum AtomaServiceError {
         endpoint: String,
     },
 
+    /// Error returned when the request does not meet the security level required by the stack's task,
+    /// e.g. a confidential compute stack used through a non-confidential endpoint
+    #[error("Insufficient security level: {message}")]
+    InsufficientSecurityLevel {
+        /// Description of the security level mismatch
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the request exceeds the rate limits of its Sui address or stack
+    #[error("Rate limit exceeded: {message}")]
+    RateLimitExceeded {
+        /// Description of the exceeded limit
+        message: String,
+        /// The duration after which the client can retry the request
+        retry_after: Duration,
+        /// The status of the request's rate limits, reported in the response headers
+        rate_limit_status: RateLimitStatus,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the inference backend of the requested model is saturated, and
+    /// the request could not be admitted to it
+    #[error("Service unavailable: {message}")]
+    ServiceUnavailable {
+        /// Description of why the request could not be admitted
+        message: String,
+        /// The duration after which the client can retry the request
+        retry_after: Duration,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
     /// Error returned for unexpected internal server errors
     #[error("Internal server error: {message}")]
     InternalError {
@@ -104,16 +181,28 @@ impl AtomaServiceError {
     /// - `"MISSING_HEADER"` for missing required headers
     /// - `"INVALID_HEADER"` for invalid header values
     /// - `"INVALID_BODY"` for malformed request bodies
+    /// - `"invalid_value"` for request parameters the model does not accept
+    /// - `"context_length_exceeded"` for requests not fitting in the model's context window
+    /// - `"model_not_found"` for models not served by the node
     /// - `"MODEL_ERROR"` for ML model errors
     /// - `"AUTH_ERROR"` for authentication failures
+    /// - `"INSUFFICIENT_SECURITY_LEVEL"` for requests not meeting the task's security level
+    /// - `"RATE_LIMIT_EXCEEDED"` for requests exceeding the rate limits of their address or stack
+    /// - `"SERVICE_UNAVAILABLE"` for requests rejected by a saturated inference backend
     /// - `"INTERNAL_ERROR"` for unexpected server errors
     fn error_code(&self) -> &'static str {
         match self {
             Self::MissingHeader { .. } => "MISSING_HEADER",
             Self::InvalidHeader { .. } => "INVALID_HEADER",
             Self::InvalidBody { .. } => "INVALID_BODY",
+            Self::InvalidParameter { .. } => "invalid_value",
+            Self::ContextLengthExceeded { .. } => "context_length_exceeded",
+            Self::ModelNotFound { .. } => "model_not_found",
             Self::ModelError { .. } => "MODEL_ERROR",
             Self::AuthError { .. } => "AUTH_ERROR",
+            Self::InsufficientSecurityLevel { .. } => "INSUFFICIENT_SECURITY_LEVEL",
+            Self::RateLimitExceeded { .. } => "RATE_LIMIT_EXCEEDED",
+            Self::ServiceUnavailable { .. } => "SERVICE_UNAVAILABLE",
             Self::InternalError { .. } => "INTERNAL_ERROR",
         }
     }
@@ -130,16 +219,35 @@ impl AtomaServiceError {
     /// - For missing headers: Specifies which header is missing
     /// - For invalid headers: A generic invalid header message
     /// - For invalid body: Includes the specific validation error
+    /// - For invalid parameters and context length overflows: The full message, as in OpenAI's API
+    /// - For models not found: The requested model, as in OpenAI's API
     /// - For model errors: Includes the model-specific error message
     /// - For auth errors: A generic authentication failure message
+    /// - For insufficient security level: Includes the required security level
+    /// - For rate limit errors: Includes the exceeded limit
+    /// - For service unavailability: Includes why the request could not be admitted
     /// - For internal errors: A generic server error message
     fn client_message(&self) -> String {
         match self {
             Self::MissingHeader { header, .. } => format!("Missing required header: {}", header),
             Self::InvalidHeader { .. } => "Invalid header value provided".to_string(),
             Self::InvalidBody { message, .. } => format!("Invalid request body: {}", message),
+            Self::InvalidParameter { message, .. }
+            | Self::ContextLengthExceeded { message, .. } => message.clone(),
+            Self::ModelNotFound { model, .. } => {
+                format!("The model `{}` does not exist", model)
+            }
             Self::ModelError { model_error, .. } => format!("Model error: {}", model_error),
             Self::AuthError { .. } => "Authentication failed".to_string(),
+            Self::InsufficientSecurityLevel { message, .. } => {
+                format!("Insufficient security level: {}", message)
+            }
+            Self::RateLimitExceeded { message, .. } => {
+                format!("Rate limit exceeded: {}", message)
+            }
+            Self::ServiceUnavailable { message, .. } => {
+                format!("Service unavailable: {}", message)
+            }
             Self::InternalError { .. } => "Internal server error occurred".to_string(),
         }
     }
@@ -147,9 +255,14 @@ impl AtomaServiceError {
     /// Returns the HTTP status code associated with this error
     ///
     /// Maps each error variant to an appropriate HTTP status code:
-    /// - `400 Bad Request` for invalid inputs (missing/invalid headers, invalid body, model errors)
+    /// - `400 Bad Request` for invalid inputs (missing/invalid headers, invalid body or parameters,
+    ///   context length overflows, model errors)
     /// - `401 Unauthorized` for authentication failures
+    /// - `403 Forbidden` for requests not meeting the task's security level
+    /// - `404 Not Found` for models not served by the node
+    /// - `429 Too Many Requests` for requests exceeding the rate limits of their address or stack
     /// - `500 Internal Server Error` for unexpected server errors
+    /// - `503 Service Unavailable` for requests rejected by a saturated inference backend
     ///
     /// # Returns
     ///
@@ -159,9 +272,52 @@ impl AtomaServiceError {
             Self::MissingHeader { .. }
             | Self::InvalidHeader { .. }
             | Self::InvalidBody { .. }
+            | Self::InvalidParameter { .. }
+            | Self::ContextLengthExceeded { .. }
             | Self::ModelError { .. } => StatusCode::BAD_REQUEST,
             Self::AuthError { .. } => StatusCode::UNAUTHORIZED,
+            Self::InsufficientSecurityLevel { .. } => StatusCode::FORBIDDEN,
+            Self::ModelNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::RateLimitExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
             Self::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Returns the number of seconds after which the request can be retried, if any
+    ///
+    /// The duration is rounded up to whole seconds, as required by the `Retry-After` header.
+    fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            Self::RateLimitExceeded { retry_after, .. }
+            | Self::ServiceUnavailable { retry_after, .. } => {
+                Some((retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)).max(1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the OpenAI-style error type, for errors on request parameters
+    ///
+    /// Errors mirroring the ones of OpenAI's API are reported with the `invalid_request_error`
+    /// type, so that OpenAI-compatible clients can handle them as they would for OpenAI.
+    fn error_type(&self) -> Option<&'static str> {
+        match self {
+            Self::InvalidParameter { .. }
+            | Self::ContextLengthExceeded { .. }
+            | Self::ModelNotFound { .. } => Some("invalid_request_error"),
+            _ => None,
+        }
+    }
+
+    /// Returns the request parameter that caused the error, if any
+    fn param(&self) -> Option<&str> {
+        match self {
+            Self::InvalidParameter { param, .. } | Self::ContextLengthExceeded { param, .. } => {
+                Some(param)
+            }
+            Self::ModelNotFound { .. } => Some("model"),
+            _ => None,
         }
     }
 
@@ -178,8 +334,14 @@ impl AtomaServiceError {
             Self::MissingHeader { endpoint, .. } => endpoint.clone(),
             Self::InvalidHeader { endpoint, .. } => endpoint.clone(),
             Self::InvalidBody { endpoint, .. } => endpoint.clone(),
+            Self::InvalidParameter { endpoint, .. } => endpoint.clone(),
+            Self::ContextLengthExceeded { endpoint, .. } => endpoint.clone(),
+            Self::ModelNotFound { endpoint, .. } => endpoint.clone(),
             Self::ModelError { endpoint, .. } => endpoint.clone(),
             Self::AuthError { endpoint, .. } => endpoint.clone(),
+            Self::InsufficientSecurityLevel { endpoint, .. } => endpoint.clone(),
+            Self::RateLimitExceeded { endpoint, .. } => endpoint.clone(),
+            Self::ServiceUnavailable { endpoint, .. } => endpoint.clone(),
             Self::InternalError { endpoint, .. } => endpoint.clone(),
         }
     }
@@ -196,16 +358,38 @@ impl AtomaServiceError {
     /// - For missing headers: The name of the missing header
     /// - For invalid headers: The specific validation error
     /// - For invalid body: The detailed validation message
+    /// - For invalid parameters: The parameter and why its value is invalid
+    /// - For context length overflows: The detailed overflow message
+    /// - For models not found: The requested model
     /// - For model errors: The complete model error message
     /// - For auth errors: The specific authentication failure reason
+    /// - For insufficient security level: The security level mismatch
+    /// - For rate limit errors: The exceeded limit
+    /// - For service unavailability: Why the request could not be admitted
     /// - For internal errors: The detailed internal error message
     fn message(&self) -> String {
         match self {
             Self::MissingHeader { header, .. } => format!("Missing required header: {}", header),
             Self::InvalidHeader { message, .. } => format!("Invalid header value: {}", message),
             Self::InvalidBody { message, .. } => format!("Invalid request body: {}", message),
+            Self::InvalidParameter { message, param, .. } => {
+                format!("Invalid value for parameter {}: {}", param, message)
+            }
+            Self::ContextLengthExceeded { message, .. } => {
+                format!("Context length exceeded: {}", message)
+            }
+            Self::ModelNotFound { model, .. } => format!("Model not found: {}", model),
             Self::ModelError { model_error, .. } => format!("Model error: {}", model_error),
             Self::AuthError { auth_error, .. } => format!("Authentication error: {}", auth_error),
+            Self::InsufficientSecurityLevel { message, .. } => {
+                format!("Insufficient security level: {}", message)
+            }
+            Self::RateLimitExceeded { message, .. } => {
+                format!("Rate limit exceeded: {}", message)
+            }
+            Self::ServiceUnavailable { message, .. } => {
+                format!("Service unavailable: {}", message)
+            }
             Self::InternalError { message, .. } => format!("Internal server error: {}", message),
         }
     }
@@ -223,8 +407,22 @@ impl IntoResponse for AtomaServiceError {
             error: ErrorDetails {
                 code: self.error_code().to_string(),
                 message: self.client_message(),
+                error_type: self.error_type().map(str::to_string),
+                param: self.param().map(str::to_string),
             },
         };
-        (self.status_code(), Json(error_response)).into_response()
+        let mut response = (self.status_code(), Json(error_response)).into_response();
+        if let Some(retry_after_secs) = self.retry_after_secs() {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after_secs.into());
+        }
+        if let Self::RateLimitExceeded {
+            rate_limit_status, ..
+        } = &self
+        {
+            rate_limit_status.insert_headers(response.headers_mut());
+        }
+        response
     }
 }
@@ -0,0 +1,619 @@
+use crate::{
+    audio::audio_duration_compute_units,
+    backends::{RawResponse, RequestBody},
+    error::AtomaServiceError,
+    handlers::{
+        admit_inference_request, encrypt_response_body,
+        handle_confidential_compute_encryption_response, inference_backend,
+        prometheus::{
+            AUDIO_TRANSCRIPTIONS_LATENCY_METRICS, AUDIO_TRANSCRIPTIONS_NUM_REQUESTS,
+            SPEECH_LATENCY_METRICS, SPEECH_NUM_REQUESTS,
+        },
+        sign_raw_response_and_update_stack_hash, sign_response_and_update_stack_hash,
+        update_stack_num_compute_units, RESPONSE_HASH_KEY, SIGNATURE_KEY,
+    },
+    middleware::{EncryptionMetadata, RequestMetadata, RequestType},
+    multipart::{form_data_boundary, parse_form_data},
+    server::AppState,
+    types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
+};
+use atoma_utils::constants::{PAYLOAD_HASH_SIZE, RESPONSE_HASH, RESPONSE_SIGNATURE};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+/// The path for confidential audio transcriptions requests
+pub const CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH: &str = "/v1/confidential/audio/transcriptions";
+
+/// The path for audio transcriptions requests
+pub const AUDIO_TRANSCRIPTIONS_PATH: &str = "/v1/audio/transcriptions";
+
+/// The path for confidential speech requests
+pub const CONFIDENTIAL_SPEECH_PATH: &str = "/v1/confidential/audio/speech";
+
+/// The path for speech requests
+pub const SPEECH_PATH: &str = "/v1/audio/speech";
+
+/// Body size limit of audio transcriptions requests, whose audio files can be up to 25MB,
+/// as in OpenAI's API, along with the other form fields
+pub const MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE: usize = 26 * 1024 * 1024;
+
+/// The key for the model parameter in the request body
+const MODEL_KEY: &str = "model";
+
+/// The key for the audio duration, in seconds, in `verbose_json` transcription responses
+const DURATION_KEY: &str = "duration";
+
+/// OpenAPI documentation structure for the audio transcriptions endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the audio transcriptions API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(audio_transcriptions_handler),
+    components(schemas(AudioTranscriptionsRequest, AudioTranscriptionsResponse))
+)]
+pub(crate) struct AudioTranscriptionsOpenApi;
+
+/// Create transcription
+///
+/// Transcribes an audio file, sent as a `multipart/form-data` body, by forwarding it to the
+/// audio transcriptions service (e.g. a Whisper server) serving the requested model.
+///
+/// The request is charged one compute unit per second of audio. JSON responses are signed
+/// like the responses of the other endpoints, while text responses (the `text`, `srt` and
+/// `vtt` response formats) are returned as is, with their hash and signature in the
+/// `X-Response-Hash` and `X-Response-Signature` headers.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The audio transcriptions service request fails
+/// - Response parsing or signing fails
+///
+/// Returns a `AtomaServiceError::ServiceUnavailable` if the model's admission queue is full,
+/// or if the request timed out waiting in it.
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "audio",
+    request_body(content = AudioTranscriptionsRequest, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Audio transcribed successfully", body = AudioTranscriptionsResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, headers, body),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn audio_transcriptions_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_audio_transcriptions_request(request_metadata, &state, &headers, body).await
+}
+
+/// OpenAPI documentation structure for the confidential audio transcriptions endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the confidential audio
+/// transcriptions API, whose requests encrypt a `multipart/form-data` body, and whose
+/// responses are encrypted for the client.
+#[derive(OpenApi)]
+#[openapi(
+    paths(confidential_audio_transcriptions_handler),
+    components(schemas(ConfidentialComputeRequest, ConfidentialComputeResponse))
+)]
+pub(crate) struct ConfidentialAudioTranscriptionsOpenApi;
+
+/// Create confidential transcription
+///
+/// Transcribes an audio file in a confidential computing context. The request's ciphertext
+/// encrypts the `multipart/form-data` body of a transcription request, whose content type
+/// (with its boundary) is sent as the request's `plaintext_content_type`. The response is
+/// encrypted so that only the client can decrypt it.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if:
+/// - The audio transcriptions service request fails
+/// - Response encryption fails
+/// - State manager updates fail
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "confidential-audio",
+    request_body = ConfidentialComputeRequest,
+    responses(
+        (status = OK, description = "Confidential audio transcribed successfully", body = ConfidentialComputeResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, headers, body),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn confidential_audio_transcriptions_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_audio_transcriptions_request(request_metadata, &state, &headers, body).await
+}
+
+/// OpenAPI documentation structure for the speech endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the speech API, including
+/// its request schema. It uses the `utoipa` framework to generate the API documentation.
+#[derive(OpenApi)]
+#[openapi(paths(speech_handler), components(schemas(SpeechRequest)))]
+pub(crate) struct SpeechOpenApi;
+
+/// Create speech
+///
+/// Generates audio from the input text, by forwarding the request to the speech service
+/// serving the requested model. The request is charged one compute unit per character of
+/// its input.
+///
+/// The generated audio is returned as is, with its hash and signature in the
+/// `X-Response-Hash` and `X-Response-Signature` headers.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The speech service request fails
+/// - Response signing fails
+///
+/// Returns a `AtomaServiceError::ServiceUnavailable` if the model's admission queue is full,
+/// or if the request timed out waiting in it.
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "audio",
+    request_body = SpeechRequest,
+    responses(
+        (status = OK, description = "Speech generated successfully", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn speech_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_speech_request(request_metadata, &state, payload).await
+}
+
+/// OpenAPI documentation structure for the confidential speech endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the confidential speech API,
+/// whose generated audio is encrypted for the client.
+#[derive(OpenApi)]
+#[openapi(
+    paths(confidential_speech_handler),
+    components(schemas(ConfidentialComputeRequest, ConfidentialComputeResponse))
+)]
+pub(crate) struct ConfidentialSpeechOpenApi;
+
+/// Create confidential speech
+///
+/// Generates audio from the input text in a confidential computing context. The generated
+/// audio is encrypted so that only the client can decrypt it, and returned along with its
+/// hash and signature.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if:
+/// - The speech service request fails
+/// - Response encryption fails
+/// - State manager updates fail
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "confidential-audio",
+    request_body = ConfidentialComputeRequest,
+    responses(
+        (status = OK, description = "Confidential speech generated successfully", body = ConfidentialComputeResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn confidential_speech_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_speech_request(request_metadata, &state, payload).await
+}
+
+/// Serves an audio transcription request, plain or confidential, releasing the compute units
+/// reserved on the stack if it fails.
+async fn handle_audio_transcriptions_request(
+    request_metadata: RequestMetadata,
+    state: &AppState,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        session_grant_id,
+        payload_hash,
+        client_encryption_metadata,
+        endpoint_path: endpoint,
+        request_type: _,
+        json_canonicalization,
+    } = request_metadata;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        event = "audio-transcriptions-handler",
+        "Received audio transcriptions request, with payload hash: {payload_hash:?}"
+    );
+
+    // NOTE: The form was already parsed by the `verify_stack_permissions` middleware, so
+    // only the model is extracted from it here
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    let model = form_data_boundary(content_type)
+        .and_then(|boundary| {
+            parse_form_data(&body, &boundary)
+                .ok()?
+                .into_iter()
+                .find(|field| field.name == MODEL_KEY)
+                .and_then(|field| field.text().map(str::to_string))
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    AUDIO_TRANSCRIPTIONS_NUM_REQUESTS
+        .with_label_values(&[model.as_str()])
+        .inc();
+    let timer = AUDIO_TRANSCRIPTIONS_LATENCY_METRICS
+        .with_label_values(&[model.as_str()])
+        .start_timer();
+
+    let result: Result<Response<Body>, AtomaServiceError> = async {
+        let payload = json!({ MODEL_KEY: model });
+        let response = {
+            let _admission_permit = admit_inference_request(state, &payload, &endpoint).await?;
+            inference_backend(state, &payload, RequestType::AudioTranscriptions, &endpoint)?
+                .post_raw(
+                    AUDIO_TRANSCRIPTIONS_PATH,
+                    RequestBody::Raw {
+                        body: &body,
+                        content_type,
+                    },
+                )
+                .await
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!(
+                        "Error sending request to audio transcriptions service: {}",
+                        e
+                    ),
+                    endpoint: endpoint.clone(),
+                })?
+        };
+
+        let is_json = response
+            .content_type
+            .as_deref()
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+        if !is_json {
+            return serve_raw_response(
+                state,
+                response,
+                payload_hash,
+                stack_small_id,
+                estimated_total_compute_units,
+                client_encryption_metadata,
+                &endpoint,
+            )
+            .await;
+        }
+
+        let mut response_body = serde_json::from_slice::<Value>(&response.body).map_err(|e| {
+            AtomaServiceError::InternalError {
+                message: format!("Error parsing audio transcriptions response: {}", e),
+                endpoint: endpoint.clone(),
+            }
+        })?;
+        // NOTE: The duration reported by `verbose_json` responses is exact, while the one
+        // estimated by the middleware is an upper bound for compressed audio formats
+        let total_compute_units =
+            response_body
+                .get(DURATION_KEY)
+                .and_then(Value::as_f64)
+                .map(|duration| {
+                    audio_duration_compute_units(duration).min(estimated_total_compute_units)
+                });
+        sign_response_and_update_stack_hash(
+            &mut response_body,
+            payload_hash,
+            json_canonicalization,
+            state,
+            stack_small_id,
+            total_compute_units.unwrap_or(estimated_total_compute_units),
+            endpoint.clone(),
+        )
+        .await?;
+        let response_body = handle_confidential_compute_encryption_response(
+            state,
+            response_body,
+            client_encryption_metadata,
+            endpoint.clone(),
+        )
+        .await?;
+        if let Some(total_compute_units) = total_compute_units {
+            update_stack_num_compute_units(
+                &state.state_manager_sender,
+                stack_small_id,
+                session_grant_id,
+                estimated_total_compute_units,
+                total_compute_units,
+                &endpoint,
+            )?;
+        }
+        Ok(Json(response_body).into_response())
+    }
+    .await;
+
+    match result {
+        Ok(response) => {
+            timer.observe_duration();
+            Ok(response)
+        }
+        Err(e) => release_compute_units_on_error(
+            state,
+            stack_small_id,
+            session_grant_id,
+            estimated_total_compute_units,
+            &endpoint,
+            "audio transcriptions",
+            e,
+        ),
+    }
+}
+
+/// Serves a speech request, plain or confidential, releasing the compute units reserved on
+/// the stack if it fails.
+async fn handle_speech_request(
+    request_metadata: RequestMetadata,
+    state: &AppState,
+    payload: Value,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        session_grant_id,
+        payload_hash,
+        client_encryption_metadata,
+        endpoint_path: endpoint,
+        ..
+    } = request_metadata;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        event = "speech-handler",
+        "Received speech request, with payload hash: {payload_hash:?}"
+    );
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown");
+    SPEECH_NUM_REQUESTS.with_label_values(&[model]).inc();
+    let timer = SPEECH_LATENCY_METRICS
+        .with_label_values(&[model])
+        .start_timer();
+
+    let result: Result<Response<Body>, AtomaServiceError> = async {
+        let response = {
+            let _admission_permit = admit_inference_request(state, &payload, &endpoint).await?;
+            inference_backend(state, &payload, RequestType::Speech, &endpoint)?
+                .post_raw(SPEECH_PATH, RequestBody::Json(&payload))
+                .await
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!("Error sending request to speech service: {}", e),
+                    endpoint: endpoint.clone(),
+                })?
+        };
+        serve_raw_response(
+            state,
+            response,
+            payload_hash,
+            stack_small_id,
+            estimated_total_compute_units,
+            client_encryption_metadata,
+            &endpoint,
+        )
+        .await
+    }
+    .await;
+
+    match result {
+        Ok(response) => {
+            timer.observe_duration();
+            Ok(response)
+        }
+        Err(e) => release_compute_units_on_error(
+            state,
+            stack_small_id,
+            session_grant_id,
+            estimated_total_compute_units,
+            &endpoint,
+            "speech",
+            e,
+        ),
+    }
+}
+
+/// Signs a response of the inference service whose body is not JSON, and serves it.
+///
+/// Plain responses are returned as is, with their content type, and with their hash and
+/// signature in the `X-Response-Hash` and `X-Response-Signature` headers. Confidential
+/// responses are encrypted, and returned as a JSON object with the `nonce` and `ciphertext`
+/// of the response body, along with its `response_hash` and `signature`.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if signing or encrypting the response fails.
+async fn serve_raw_response(
+    state: &AppState,
+    response: RawResponse,
+    payload_hash: [u8; 32],
+    stack_small_id: i64,
+    num_compute_units: i64,
+    client_encryption_metadata: Option<EncryptionMetadata>,
+    endpoint: &str,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let (response_hash, signature) = sign_raw_response_and_update_stack_hash(
+        &response.body,
+        payload_hash,
+        state,
+        stack_small_id,
+        num_compute_units,
+        endpoint.to_string(),
+    )?;
+    let response_hash = STANDARD.encode(response_hash);
+    if let Some(EncryptionMetadata {
+        proxy_x25519_public_key,
+        salt,
+    }) = client_encryption_metadata
+    {
+        let mut response_body = encrypt_response_body(
+            state,
+            response.body.to_vec(),
+            salt,
+            proxy_x25519_public_key,
+            endpoint,
+        )
+        .await?;
+        response_body[RESPONSE_HASH_KEY] = json!(response_hash);
+        response_body[SIGNATURE_KEY] = json!(signature);
+        return Ok(Json(response_body).into_response());
+    }
+    let mut builder = Response::builder()
+        .header(RESPONSE_HASH, response_hash)
+        .header(RESPONSE_SIGNATURE, signature);
+    if let Some(content_type) = response.content_type {
+        builder = builder.header(CONTENT_TYPE, content_type);
+    }
+    builder
+        .body(Body::from(response.body))
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Error building response: {}", e),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Releases the compute units reserved on the stack for a request that failed, as the
+/// inference service did not generate a proper response for it, so that the stack is not
+/// penalized for the request, nor its session key grant, if any, consumed.
+fn release_compute_units_on_error(
+    state: &AppState,
+    stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
+    estimated_total_compute_units: i64,
+    endpoint: &str,
+    request_kind: &str,
+    error: AtomaServiceError,
+) -> Result<Response<Body>, AtomaServiceError> {
+    update_stack_num_compute_units(
+        &state.state_manager_sender,
+        stack_small_id,
+        session_grant_id,
+        estimated_total_compute_units,
+        0,
+        endpoint,
+    )?;
+    // NOTE: Rejections by admission control are returned as is, so that clients
+    // know when to retry the request
+    if let AtomaServiceError::ServiceUnavailable { .. } = error {
+        return Err(error);
+    }
+    Err(AtomaServiceError::InternalError {
+        message: format!("Error handling {request_kind} response: {}", error),
+        endpoint: endpoint.to_string(),
+    })
+}
+
+/// The `multipart/form-data` body of an audio transcriptions request.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AudioTranscriptionsRequest {
+    /// The audio file to transcribe, in one of these formats: flac, mp3, mp4, mpeg, mpga,
+    /// m4a, ogg, wav, or webm.
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+    /// ID of the model to use.
+    model: String,
+    /// The language of the input audio, in ISO-639-1 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    /// An optional text to guide the model's style or continue a previous audio segment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prompt: Option<String>,
+    /// The format of the output, in one of these options: json, text, srt, verbose_json, or vtt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_format: Option<String>,
+    /// The sampling temperature, between 0 and 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+/// The JSON response of an audio transcriptions request.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AudioTranscriptionsResponse {
+    /// The transcribed text.
+    pub text: String,
+    /// The duration of the input audio, in seconds, for `verbose_json` responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// The language of the input audio, for `verbose_json` responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// The body of a speech request.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SpeechRequest {
+    /// ID of the model to use.
+    model: String,
+    /// The text to generate audio for.
+    input: String,
+    /// The voice to use when generating the audio.
+    voice: String,
+    /// The format of the audio, in one of these options: mp3, opus, aac, flac, wav, or pcm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_format: Option<String>,
+    /// The speed of the generated audio, between 0.25 and 4.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    speed: Option<f32>,
+}
@@ -1,6 +1,9 @@
 use crate::{
-    handlers::{sign_response_and_update_stack_hash, update_stack_num_compute_units},
-    middleware::EncryptionMetadata,
+    handlers::{
+        admit_inference_request, inference_backend, sign_response_and_update_stack_hash,
+        update_stack_num_compute_units,
+    },
+    middleware::{EncryptionMetadata, RequestType},
     server::AppState,
     streamer::{Streamer, StreamingEncryptionMetadata},
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
@@ -8,15 +11,15 @@ use crate::{
 use atoma_confidential::types::{
     ConfidentialComputeSharedSecretRequest, ConfidentialComputeSharedSecretResponse,
 };
-use atoma_utils::constants::PAYLOAD_HASH_SIZE;
+use atoma_utils::{constants::PAYLOAD_HASH_SIZE, hashing::JsonCanonicalization};
 use axum::{
     body::Body,
     extract::State,
     response::{IntoResponse, Response, Sse},
     Extension, Json,
 };
-use reqwest::Client;
-use serde_json::{json, Value};
+use futures::StreamExt;
+use serde_json::Value;
 use tracing::{info, instrument};
 use utoipa::OpenApi;
 
@@ -43,6 +46,9 @@ const MODEL_KEY: &str = "model";
 /// The key for the stream parameter in the request body
 const STREAM_KEY: &str = "stream";
 
+/// The key for the max tokens parameter in the request body
+const MAX_TOKENS_KEY: &str = "max_tokens";
+
 /// OpenAPI documentation structure for the chat completions endpoint.
 ///
 /// This struct defines the OpenAPI (Swagger) documentation for the chat completions API,
@@ -119,6 +125,9 @@ pub(crate) struct ChatCompletionsOpenApi;
 /// - Response parsing fails
 /// - Response signing fails
 /// - Token usage update fails
+///
+/// Returns a `AtomaServiceError::ServiceUnavailable` if the model's admission queue is full,
+/// or if the request timed out waiting in it.
 #[utoipa::path(
     post,
     path = "",
@@ -126,7 +135,8 @@ pub(crate) struct ChatCompletionsOpenApi;
     request_body = ChatCompletionsRequest,
     responses(
         (status = OK, description = "Chat completion successful", body = ChatCompletionsResponse),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -142,8 +152,10 @@ pub async fn chat_completions_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
+        json_canonicalization,
         ..
     } = request_metadata;
     info!(
@@ -164,7 +176,9 @@ pub async fn chat_completions_handler(
         &state,
         endpoint.clone(),
         payload_hash,
+        json_canonicalization,
         stack_small_id,
+        session_grant_id,
         is_stream,
         payload,
         estimated_total_compute_units,
@@ -181,10 +195,16 @@ pub async fn chat_completions_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
             )?;
+            // NOTE: Rejections by admission control are returned as is, so that clients
+            // know when to retry the request
+            if let AtomaServiceError::ServiceUnavailable { .. } = e {
+                return Err(e);
+            }
             return Err(AtomaServiceError::InternalError {
                 message: format!("Error handling chat completions response: {}", e),
                 endpoint: request_metadata.endpoint_path.clone(),
@@ -279,7 +299,8 @@ pub(crate) struct ConfidentialChatCompletionsOpenApi;
     request_body = ConfidentialComputeRequest,
     responses(
         (status = OK, description = "Confidential chat completion successful", body = ConfidentialComputeResponse),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -295,8 +316,10 @@ pub async fn confidential_chat_completions_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
+        json_canonicalization,
         ..
     } = request_metadata;
     info!(
@@ -317,7 +340,9 @@ pub async fn confidential_chat_completions_handler(
         &state,
         endpoint.clone(),
         payload_hash,
+        json_canonicalization,
         stack_small_id,
+        session_grant_id,
         is_stream,
         payload,
         estimated_total_compute_units,
@@ -334,10 +359,16 @@ pub async fn confidential_chat_completions_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
             )?;
+            // NOTE: Rejections by admission control are returned as is, so that clients
+            // know when to retry the request
+            if let AtomaServiceError::ServiceUnavailable { .. } = e {
+                return Err(e);
+            }
             return Err(AtomaServiceError::InternalError {
                 message: format!("Error handling chat completions response: {}", e),
                 endpoint: request_metadata.endpoint_path.clone(),
@@ -357,7 +388,9 @@ pub async fn confidential_chat_completions_handler(
 /// * `state` - Application state containing service configuration and connections
 /// * `endpoint` - The API endpoint path where the request was received
 /// * `payload_hash` - BLAKE2b hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the response body
 /// * `stack_small_id` - Unique identifier for the stack making the request
+/// * `session_grant_id` - Identifier of the session key grant the request is charged to, if any
 /// * `is_stream` - Boolean flag indicating whether this is a streaming request
 /// * `payload` - The JSON payload containing the chat completion request
 /// * `estimated_total_compute_units` - Estimated compute units for the request
@@ -382,7 +415,9 @@ pub async fn confidential_chat_completions_handler(
 ///     state,
 ///     "/v1/chat/completions".to_string(),
 ///     payload_hash,
+///     JsonCanonicalization::SerdeJson,
 ///     stack_id,
+///     None,
 ///     false, // non-streaming
 ///     payload,
 ///     estimated_units,
@@ -404,7 +439,9 @@ async fn handle_response(
     state: &AppState,
     endpoint: String,
     payload_hash: [u8; PAYLOAD_HASH_SIZE],
+    json_canonicalization: JsonCanonicalization,
     stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     is_stream: bool,
     payload: Value,
     estimated_total_compute_units: i64,
@@ -415,8 +452,10 @@ async fn handle_response(
             state,
             payload,
             stack_small_id,
+            session_grant_id,
             estimated_total_compute_units,
             payload_hash,
+            json_canonicalization,
             client_encryption_metadata,
             endpoint,
         )
@@ -435,8 +474,10 @@ async fn handle_response(
             state,
             payload,
             stack_small_id,
+            session_grant_id,
             estimated_total_compute_units,
             payload_hash,
+            json_canonicalization,
             streaming_encryption_metadata,
             endpoint,
         )
@@ -462,8 +503,10 @@ async fn handle_response(
 /// * `state` - Application state containing service configuration and keystore
 /// * `payload` - The JSON payload containing the chat completion request
 /// * `stack_small_id` - Unique identifier for the stack making the request
+/// * `session_grant_id` - Identifier of the session key grant the request is charged to, if any
 /// * `estimated_total_compute_units` - Estimated compute units count for the request
 /// * `payload_hash` - BLAKE2b hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the response body
 /// * `client_encryption_metadata` - The client encryption metadata for the request
 /// * `endpoint` - The endpoint where the request was made
 ///
@@ -504,12 +547,15 @@ async fn handle_response(
         payload_hash
     )
 )]
+#[allow(clippy::too_many_arguments)]
 async fn handle_non_streaming_response(
     state: &AppState,
     payload: Value,
     stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     estimated_total_compute_units: i64,
     payload_hash: [u8; PAYLOAD_HASH_SIZE],
+    json_canonicalization: JsonCanonicalization,
     client_encryption_metadata: Option<EncryptionMetadata>,
     endpoint: String,
 ) -> Result<Response<Body>, AtomaServiceError> {
@@ -531,15 +577,20 @@ async fn handle_non_streaming_response(
     )
     .await?;
 
-    let total_compute_units = utils::extract_total_num_tokens(&response_body, model);
+    let estimated_input_tokens =
+        utils::estimated_input_tokens(&payload, estimated_total_compute_units);
+    let total_compute_units =
+        utils::extract_total_num_tokens(state, &response_body, model, estimated_input_tokens);
 
     utils::serve_non_streaming_response(
         state,
         response_body,
         stack_small_id,
+        session_grant_id,
         estimated_total_compute_units,
         total_compute_units,
         payload_hash,
+        json_canonicalization,
         client_encryption_metadata,
         endpoint,
         timer,
@@ -560,8 +611,10 @@ async fn handle_non_streaming_response(
 /// * `state` - Application state containing service configuration and connections
 /// * `payload` - The JSON payload containing the chat completion request
 /// * `stack_small_id` - Unique identifier for the stack making the request
+/// * `session_grant_id` - Identifier of the session key grant the request is charged to, if any
 /// * `estimated_total_compute_units` - Estimated compute units count for the request
 /// * `payload_hash` - BLAKE2b hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the accumulated response
 /// * `streaming_encryption_metadata` - The client encryption metadata for the streaming request
 /// * `endpoint` - The endpoint where the request was made
 ///
@@ -575,6 +628,9 @@ async fn handle_non_streaming_response(
 /// - The inference service request fails
 /// - The inference service returns a non-success status code
 ///
+/// Returns `AtomaServiceError::ServiceUnavailable` if the request could not be admitted to the
+/// model's inference backend.
+///
 /// # Example Response Stream
 ///
 /// The SSE stream will emit events in the following format:
@@ -594,21 +650,24 @@ async fn handle_non_streaming_response(
         payload_hash
     )
 )]
+#[allow(clippy::too_many_arguments)]
 async fn handle_streaming_response(
     state: &AppState,
     mut payload: Value,
     stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     estimated_total_compute_units: i64,
     payload_hash: [u8; 32],
+    json_canonicalization: JsonCanonicalization,
     streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
     endpoint: String,
 ) -> Result<Response<Body>, AtomaServiceError> {
-    // NOTE: If streaming is requested, add the include_usage option to the payload
-    // so that the atoma node state manager can be updated with the total number of tokens
-    // that were processed for this request.
-    payload["stream_options"] = json!({
-        "include_usage": true
-    });
+    // NOTE: If streaming is requested, the inference backend is asked to report the usage
+    // at the end of the stream, so that the atoma node state manager can be updated with the
+    // total number of tokens that were processed for this request.
+    let backend = inference_backend(state, &payload, RequestType::ChatCompletions, &endpoint)?;
+    let adapter = backend.adapter();
+    adapter.prepare_streaming_request(&mut payload);
 
     let model = payload
         .get(MODEL_KEY)
@@ -621,14 +680,9 @@ async fn handle_streaming_response(
         .with_label_values(&[model])
         .start_timer();
 
-    let client = Client::new();
-    let response = client
-        .post(format!(
-            "{}{}",
-            state.chat_completions_service_url, CHAT_COMPLETIONS_PATH
-        ))
-        .json(&payload)
-        .send()
+    let admission_permit = admit_inference_request(state, &payload, &endpoint).await?;
+    let stream = backend
+        .post_streaming(CHAT_COMPLETIONS_PATH, &payload)
         .await
         .map_err(|e| {
             AtomaServiceError::InternalError {
@@ -641,26 +695,36 @@ async fn handle_streaming_response(
                 endpoint: endpoint.clone(),
             }
         })?;
+    // NOTE: The request keeps its model's admission slot until the stream is dropped
+    let stream = stream.map(move |chunk| {
+        let _admission_permit = &admission_permit;
+        chunk
+    });
 
-    if !response.status().is_success() {
-        return Err(AtomaServiceError::InternalError {
-            message: "Inference service returned error".to_string(),
-            endpoint,
-        });
-    }
-
-    let stream = response.bytes_stream();
+    let estimated_input_tokens =
+        utils::estimated_input_tokens(&payload, estimated_total_compute_units);
+    let tokenizer = state
+        .models
+        .iter()
+        .position(|m| m == model)
+        .map(|index| state.tokenizers[index].clone());
 
     // Create the SSE stream
     let stream = Sse::new(Streamer::new(
         stream,
         state.state_manager_sender.clone(),
         stack_small_id,
+        session_grant_id,
         estimated_total_compute_units,
         payload_hash,
+        json_canonicalization,
         state.keystore.clone(),
         state.address_index,
         model.to_string(),
+        tokenizer,
+        estimated_input_tokens,
+        adapter,
+        state.usage_reconciliation,
         streaming_encryption_metadata,
         endpoint,
         timer,
@@ -699,6 +763,10 @@ pub struct ChatCompletionsRequest {
     /// An upper bound for the number of tokens that can be generated for a completion,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
+    /// An upper bound for the number of tokens that can be generated for a completion, superseding `max_tokens`.
+    /// If neither is set, the model's default is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u32>,
     /// How many chat completion choices to generate for each input message.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     n: Option<usize>,
@@ -1027,6 +1095,11 @@ pub(crate) mod utils {
     use atoma_utils::constants::PAYLOAD_HASH_SIZE;
     use prometheus::HistogramTimer;
 
+    use crate::{
+        handlers::USAGE_KEY,
+        usage::{count_tokens, reconcile_usage, response_texts, TokenUsage},
+    };
+
     use super::*;
 
     /// Retrieves encryption metadata for streaming chat completions when confidential compute is enabled.
@@ -1130,13 +1203,14 @@ pub(crate) mod utils {
     /// Sends a chat completion request to the inference service and parses the response.
     ///
     /// This function handles the HTTP communication with the inference service by:
-    /// 1. Creating a new HTTP client
-    /// 2. Sending the request with the provided payload
+    /// 1. Selecting the inference backend of the requested model
+    /// 2. Sending the request with the provided payload to one of the backend's replicas,
+    ///    failing over to another replica if it does not respond
     /// 3. Parsing the JSON response
     ///
     /// # Arguments
     ///
-    /// * `state` - Application state containing service configuration including the inference service URL
+    /// * `state` - Application state containing the inference backends of the deployed models
     /// * `payload` - The JSON payload containing the chat completion request parameters
     /// * `stack_small_id` - Unique identifier for the stack making the request
     /// * `payload_hash` - BLAKE2b hash of the original request payload
@@ -1154,6 +1228,9 @@ pub(crate) mod utils {
     /// - The HTTP request to the inference service fails
     /// - The response body cannot be parsed as valid JSON
     ///
+    /// Returns `AtomaServiceError::ServiceUnavailable` if the request could not be admitted to
+    /// the model's inference backend.
+    ///
     /// # Instrumentation
     ///
     /// This function is instrumented with info-level tracing that includes:
@@ -1187,14 +1264,10 @@ pub(crate) mod utils {
         payload_hash: [u8; PAYLOAD_HASH_SIZE],
         endpoint: &str,
     ) -> Result<Value, AtomaServiceError> {
-        let client = Client::new();
-        let response = client
-        .post(format!(
-            "{}{}",
-            state.chat_completions_service_url, CHAT_COMPLETIONS_PATH
-        ))
-        .json(&payload)
-        .send()
+        let _admission_permit = admit_inference_request(state, payload, endpoint).await?;
+        let backend = inference_backend(state, payload, RequestType::ChatCompletions, endpoint)?;
+        let mut response_body = backend
+        .post_json(CHAT_COMPLETIONS_PATH, payload)
         .await
         .map_err(|e| {
             AtomaServiceError::InternalError {
@@ -1207,32 +1280,40 @@ pub(crate) mod utils {
                 endpoint: endpoint.to_string(),
             }
         })?;
-        response.json::<Value>().await.map_err(|e| {
-            AtomaServiceError::InternalError {
-                message: format!(
-                    "Error reading response body, for request with payload hash: {:?}, and stack small id: {}, with error: {}",
-                    payload_hash,
-                    stack_small_id,
-                    e
-                ),
-                // NOTE: We don't know the number of tokens processed for this request,
-                // as the returned output is invalid JSON. For this reason, we set it to 0.
-                endpoint: endpoint.to_string(),
-            }
-        })
+        backend.adapter().normalize_response(&mut response_body);
+        Ok(response_body)
+    }
+
+    /// Returns the estimated number of input tokens of a chat completion request.
+    ///
+    /// The estimated compute units for a chat completion request are the estimated number
+    /// of input tokens plus `max_tokens`, see `utils::calculate_chat_completion_compute_units`
+    pub(crate) fn estimated_input_tokens(
+        payload: &Value,
+        estimated_total_compute_units: i64,
+    ) -> i64 {
+        payload
+            .get(MAX_TOKENS_KEY)
+            .and_then(|max_tokens| max_tokens.as_i64())
+            .map_or(estimated_total_compute_units, |max_tokens| {
+                (estimated_total_compute_units - max_tokens).max(0)
+            })
     }
 
     /// Extracts and tracks token usage metrics from a chat completion response.
     ///
     /// This function processes the "usage" field of a chat completion response to:
     /// 1. Extract prompt and completion token counts
-    /// 2. Record token usage metrics for monitoring
-    /// 3. Calculate total compute units used
+    /// 2. Reconcile them with the locally counted tokens, according to the node's policy
+    /// 3. Record token usage metrics for monitoring
+    /// 4. Calculate total compute units used
     ///
     /// # Arguments
     ///
+    /// * `state` - Application state containing the models' tokenizers and the reconciliation policy
     /// * `response_body` - The JSON response body from the chat completion API containing usage statistics
     /// * `model` - The name of the model used for the completion (e.g., "gpt-4", "gpt-3.5-turbo")
+    /// * `estimated_input_tokens` - The number of input tokens counted locally for the request
     ///
     /// # Returns
     ///
@@ -1266,28 +1347,43 @@ pub(crate) mod utils {
     ///         "completion_tokens": 20
     ///     }
     /// });
-    /// let total = extract_total_num_tokens(&response_body, "gpt-4");
+    /// let total = extract_total_num_tokens(&state, &response_body, "gpt-4", 10);
     /// assert_eq!(total, 30);
     /// ```
-    pub(crate) fn extract_total_num_tokens(response_body: &Value, model: &str) -> i64 {
-        let mut total_compute_units = 0;
-        if let Some(usage) = response_body.get("usage") {
-            if let Some(prompt_tokens) = usage.get("prompt_tokens") {
-                let prompt_tokens = prompt_tokens.as_u64().unwrap_or(0);
-                CHAT_COMPLETIONS_INPUT_TOKENS_METRICS
-                    .with_label_values(&[model])
-                    .inc_by(prompt_tokens as f64);
-                total_compute_units += prompt_tokens;
-            }
-            if let Some(completion_tokens) = usage.get("completion_tokens") {
-                let completion_tokens = completion_tokens.as_u64().unwrap_or(0);
-                CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS
-                    .with_label_values(&[model])
-                    .inc_by(completion_tokens as f64);
-                total_compute_units += completion_tokens;
-            }
-        }
-        total_compute_units as i64
+    pub(crate) fn extract_total_num_tokens(
+        state: &AppState,
+        response_body: &Value,
+        model: &str,
+        estimated_input_tokens: i64,
+    ) -> i64 {
+        let reported_usage = response_body
+            .get(USAGE_KEY)
+            .and_then(TokenUsage::from_usage)
+            .unwrap_or_default();
+        let local_usage = state
+            .models
+            .iter()
+            .position(|m| m == model)
+            .and_then(|index| {
+                count_tokens(&state.tokenizers[index], &response_texts(response_body))
+            })
+            .map(|output_tokens| TokenUsage {
+                input_tokens: estimated_input_tokens,
+                output_tokens,
+            });
+        let usage = reconcile_usage(
+            state.usage_reconciliation,
+            model,
+            reported_usage,
+            local_usage,
+        );
+        CHAT_COMPLETIONS_INPUT_TOKENS_METRICS
+            .with_label_values(&[model])
+            .inc_by(usage.input_tokens as f64);
+        CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS
+            .with_label_values(&[model])
+            .inc_by(usage.output_tokens as f64);
+        usage.total()
     }
 
     /// Processes and serves a non-streaming chat completion response by handling signature verification,
@@ -1306,9 +1402,11 @@ pub(crate) mod utils {
     /// * `state` - Application state containing service configuration and connections
     /// * `response_body` - The JSON response body from the inference service
     /// * `stack_small_id` - Unique identifier for the stack making the request
+    /// * `session_grant_id` - Identifier of the session key grant the request is charged to, if any
     /// * `estimated_total_compute_units` - Initially estimated compute units for the request
     /// * `total_compute_units` - Actual compute units used by the request
     /// * `payload_hash` - BLAKE2b hash of the original request payload
+    /// * `json_canonicalization` - JSON canonicalization used to hash the response body
     /// * `client_encryption_metadata` - Optional encryption metadata for confidential compute
     /// * `endpoint` - The API endpoint path where the request was received
     /// * `timer` - Prometheus histogram timer for tracking response latency
@@ -1332,9 +1430,11 @@ pub(crate) mod utils {
     ///     &state,
     ///     response_body,
     ///     stack_id,
+    ///     None,
     ///     estimated_units,
     ///     actual_units,
     ///     payload_hash,
+    ///     JsonCanonicalization::SerdeJson,
     ///     encryption_metadata,
     ///     "/v1/chat/completions".to_string(),
     ///     timer
@@ -1357,9 +1457,11 @@ pub(crate) mod utils {
         state: &AppState,
         mut response_body: Value,
         stack_small_id: i64,
+        session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
         estimated_total_compute_units: i64,
         total_compute_units: i64,
         payload_hash: [u8; PAYLOAD_HASH_SIZE],
+        json_canonicalization: JsonCanonicalization,
         client_encryption_metadata: Option<EncryptionMetadata>,
         endpoint: String,
         timer: HistogramTimer,
@@ -1378,8 +1480,10 @@ pub(crate) mod utils {
         if let Err(e) = sign_response_and_update_stack_hash(
             &mut response_body,
             payload_hash,
+            json_canonicalization,
             state,
             stack_small_id,
+            total_compute_units,
             endpoint.clone(),
         )
         .await
@@ -1430,6 +1534,7 @@ pub(crate) mod utils {
         update_stack_num_compute_units(
             &state.state_manager_sender,
             stack_small_id,
+            session_grant_id,
             estimated_total_compute_units,
             total_compute_units,
             &endpoint,
@@ -0,0 +1,582 @@
+use crate::{
+    handlers::{
+        admit_inference_request,
+        chat_completions::{utils as chat_utils, StopCondition},
+        inference_backend, update_stack_num_compute_units,
+    },
+    middleware::{EncryptionMetadata, RequestType},
+    server::AppState,
+    streamer::{Streamer, StreamingEncryptionMetadata},
+    types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
+};
+use atoma_utils::{constants::PAYLOAD_HASH_SIZE, hashing::JsonCanonicalization};
+use axum::{
+    body::Body,
+    extract::State,
+    response::{IntoResponse, Response, Sse},
+    Extension, Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, time::Duration};
+use tracing::{info, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{error::AtomaServiceError, handlers::prometheus::*, middleware::RequestMetadata};
+
+/// The path for confidential completions requests
+pub const CONFIDENTIAL_COMPLETIONS_PATH: &str = "/v1/confidential/completions";
+
+/// The path for completions requests
+pub const COMPLETIONS_PATH: &str = "/v1/completions";
+
+/// The keep-alive interval in seconds
+const STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS: u64 = 15;
+
+/// The key for the model parameter in the request body
+const MODEL_KEY: &str = "model";
+
+/// The key for the stream parameter in the request body
+const STREAM_KEY: &str = "stream";
+
+/// The key for the max tokens parameter in the request body
+const MAX_TOKENS_KEY: &str = "max_tokens";
+
+/// The key for the prompt parameter in the request body
+const PROMPT_KEY: &str = "prompt";
+
+/// OpenAPI documentation structure for the completions endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the (legacy) completions API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(completions_handler),
+    components(schemas(
+        CompletionsRequest,
+        CompletionsPrompt,
+        CompletionsResponse,
+        CompletionChoice,
+        CompletionUsage
+    ))
+)]
+pub(crate) struct CompletionsOpenApi;
+
+/// Create completion
+///
+/// This handler serves the legacy completions API, whose prompts are raw strings or token
+/// arrays, as used by code completion plugins and evaluation harnesses. It performs the
+/// same operations as the chat completions handler:
+/// 1. Forwards the completion request to the inference service of the model's chat tasks
+/// 2. Signs the response using the node's keystore
+/// 3. Tracks token usage for the stack
+///
+/// # Arguments
+///
+/// * `request_metadata` - Stack ID and estimated compute units count from middleware
+/// * `state` - Application state containing the inference client and keystore
+/// * `payload` - The completion request body
+///
+/// # Returns
+///
+/// Returns a JSON response containing the inference service's response and a cryptographic
+/// signature of it, or an SSE stream of completion chunks if `stream` is set.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The inference service request fails
+/// - Response parsing fails
+/// - Response signing fails
+/// - Token usage update fails
+///
+/// Returns a `AtomaServiceError::ServiceUnavailable` if the model's admission queue is full,
+/// or if the request timed out waiting in it.
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "completions",
+    request_body = CompletionsRequest,
+    responses(
+        (status = OK, description = "Completion successful", body = CompletionsResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn completions_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_completions_request(request_metadata, &state, payload).await
+}
+
+/// OpenAPI documentation structure for the confidential completions endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the confidential completions
+/// API, which provides an encrypted variant of the standard completions endpoint. It uses the
+/// `utoipa` framework to generate the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(confidential_completions_handler),
+    components(schemas(ConfidentialComputeRequest, ConfidentialComputeResponse))
+)]
+pub(crate) struct ConfidentialCompletionsOpenApi;
+
+/// Create confidential completion
+///
+/// Handles completion requests in a confidential computing context, where the request was
+/// decrypted by the confidential compute middleware, and the response is encrypted so that
+/// only the client can decrypt it. Both streaming and non-streaming responses are supported.
+///
+/// # Arguments
+/// * `request_metadata` - Extension containing the request context, including the client's
+///   encryption metadata
+/// * `state` - Application state containing service connections and configuration
+/// * `payload` - The decrypted completion request body
+///
+/// # Returns
+/// Returns the encrypted completion response, or an SSE stream of encrypted chunks.
+///
+/// # Errors
+/// Returns `AtomaServiceError::InternalError` if:
+/// - The inference service request fails
+/// - Response encryption fails
+/// - State manager updates fail
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "confidential-completions",
+    request_body = ConfidentialComputeRequest,
+    responses(
+        (status = OK, description = "Confidential completion successful", body = ConfidentialComputeResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn confidential_completions_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>, AtomaServiceError> {
+    handle_completions_request(request_metadata, &state, payload).await
+}
+
+/// Serves a completion request, plain or confidential, releasing the compute units reserved
+/// on the stack if it fails.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::ServiceUnavailable` if the request could not be admitted to
+/// the model's inference backend, and `AtomaServiceError::InternalError` otherwise.
+async fn handle_completions_request(
+    request_metadata: RequestMetadata,
+    state: &AppState,
+    payload: Value,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        session_grant_id,
+        payload_hash,
+        client_encryption_metadata,
+        json_canonicalization,
+        endpoint_path: endpoint,
+        ..
+    } = request_metadata;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        event = "completions-handler",
+        "Received completions request, with payload hash: {payload_hash:?}"
+    );
+
+    let is_stream = payload
+        .get(STREAM_KEY)
+        .and_then(|s| s.as_bool())
+        .unwrap_or_default();
+
+    let result = if is_stream {
+        match chat_utils::get_streaming_encryption_metadata(
+            state,
+            client_encryption_metadata,
+            payload_hash,
+            stack_small_id,
+            &endpoint,
+        )
+        .await
+        {
+            Ok(streaming_encryption_metadata) => {
+                handle_streaming_response(
+                    state,
+                    payload,
+                    stack_small_id,
+                    session_grant_id,
+                    estimated_total_compute_units,
+                    payload_hash,
+                    json_canonicalization,
+                    streaming_encryption_metadata,
+                    endpoint.clone(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        }
+    } else {
+        handle_non_streaming_response(
+            state,
+            payload,
+            stack_small_id,
+            session_grant_id,
+            estimated_total_compute_units,
+            payload_hash,
+            json_canonicalization,
+            client_encryption_metadata,
+            endpoint.clone(),
+        )
+        .await
+    };
+
+    result.or_else(|e| {
+        // NOTE: The inference service failed to generate a proper response, so the compute
+        // units reserved for the request are released, and the stack is not penalized
+        update_stack_num_compute_units(
+            &state.state_manager_sender,
+            stack_small_id,
+            session_grant_id,
+            estimated_total_compute_units,
+            0,
+            &endpoint,
+        )?;
+        // NOTE: Rejections by admission control are returned as is, so that clients
+        // know when to retry the request
+        if let AtomaServiceError::ServiceUnavailable { .. } = e {
+            return Err(e);
+        }
+        Err(AtomaServiceError::InternalError {
+            message: format!("Error handling completions response: {}", e),
+            endpoint,
+        })
+    })
+}
+
+/// Handles non-streaming completion requests, by forwarding them to the inference service,
+/// then signing the response, updating the stack's total hash and, as a final step, its
+/// compute units, exactly as for chat completions.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if the inference service request fails, or if
+/// signing, encrypting or recording the response fails.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        path = COMPLETIONS_PATH,
+        completion_type = "non-streaming",
+        stack_small_id,
+        estimated_total_compute_units,
+        payload_hash
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn handle_non_streaming_response(
+    state: &AppState,
+    payload: Value,
+    stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
+    estimated_total_compute_units: i64,
+    payload_hash: [u8; PAYLOAD_HASH_SIZE],
+    json_canonicalization: JsonCanonicalization,
+    client_encryption_metadata: Option<EncryptionMetadata>,
+    endpoint: String,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown");
+    COMPLETIONS_NUM_REQUESTS.with_label_values(&[model]).inc();
+    let timer = COMPLETIONS_LATENCY_METRICS
+        .with_label_values(&[model])
+        .start_timer();
+
+    let response_body = {
+        let _admission_permit = admit_inference_request(state, &payload, &endpoint).await?;
+        let backend = inference_backend(state, &payload, RequestType::Completions, &endpoint)?;
+        let mut response_body = backend
+            .post_json(COMPLETIONS_PATH, &payload)
+            .await
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!(
+                    "Error sending request to inference service, for request with payload hash: {:?}, and stack small id: {}, with error: {}",
+                    payload_hash, stack_small_id, e
+                ),
+                endpoint: endpoint.clone(),
+            })?;
+        backend.adapter().normalize_response(&mut response_body);
+        response_body
+    };
+
+    let total_compute_units = chat_utils::extract_total_num_tokens(
+        state,
+        &response_body,
+        model,
+        estimated_input_tokens(&payload, estimated_total_compute_units),
+    );
+
+    chat_utils::serve_non_streaming_response(
+        state,
+        response_body,
+        stack_small_id,
+        session_grant_id,
+        estimated_total_compute_units,
+        total_compute_units,
+        payload_hash,
+        json_canonicalization,
+        client_encryption_metadata,
+        endpoint,
+        timer,
+    )
+    .await
+}
+
+/// Handles streaming completion requests by establishing a Server-Sent Events (SSE)
+/// connection, whose chunks are accumulated, signed and accounted for by a [`Streamer`],
+/// exactly as for chat completions.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if the inference service request fails, and
+/// `AtomaServiceError::ServiceUnavailable` if the request could not be admitted to the
+/// model's inference backend.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        path = COMPLETIONS_PATH,
+        completion_type = "streaming",
+        stack_small_id,
+        estimated_total_compute_units,
+        payload_hash
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn handle_streaming_response(
+    state: &AppState,
+    mut payload: Value,
+    stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
+    estimated_total_compute_units: i64,
+    payload_hash: [u8; PAYLOAD_HASH_SIZE],
+    json_canonicalization: JsonCanonicalization,
+    streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
+    endpoint: String,
+) -> Result<Response<Body>, AtomaServiceError> {
+    // NOTE: The usage of the request is reported in the final chunk of the stream, so that
+    // the stack can be charged for the number of tokens that were actually processed
+    let backend = inference_backend(state, &payload, RequestType::Completions, &endpoint)?;
+    let adapter = backend.adapter();
+    adapter.prepare_streaming_request(&mut payload);
+
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown");
+    COMPLETIONS_NUM_REQUESTS.with_label_values(&[model]).inc();
+    let timer = CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN
+        .with_label_values(&[model])
+        .start_timer();
+
+    let admission_permit = admit_inference_request(state, &payload, &endpoint).await?;
+    let stream = backend
+        .post_streaming(COMPLETIONS_PATH, &payload)
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!(
+                "Error sending request to inference service, for request with payload hash: {:?}, and stack small id: {}, with error: {}",
+                payload_hash, stack_small_id, e
+            ),
+            endpoint: endpoint.clone(),
+        })?;
+    // NOTE: The request keeps its model's admission slot until the stream is dropped
+    let stream = stream.map(move |chunk| {
+        let _admission_permit = &admission_permit;
+        chunk
+    });
+
+    let estimated_input_tokens = estimated_input_tokens(&payload, estimated_total_compute_units);
+    let tokenizer = state
+        .models
+        .iter()
+        .position(|m| m == model)
+        .map(|index| state.tokenizers[index].clone());
+
+    let stream = Sse::new(Streamer::new(
+        stream,
+        state.state_manager_sender.clone(),
+        stack_small_id,
+        session_grant_id,
+        estimated_total_compute_units,
+        payload_hash,
+        json_canonicalization,
+        state.keystore.clone(),
+        state.address_index,
+        model.to_string(),
+        tokenizer,
+        estimated_input_tokens,
+        adapter,
+        state.usage_reconciliation,
+        streaming_encryption_metadata,
+        endpoint,
+        timer,
+    ))
+    .keep_alive(
+        axum::response::sse::KeepAlive::new()
+            .interval(Duration::from_secs(STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS))
+            .text("keep-alive"),
+    );
+
+    Ok(stream.into_response())
+}
+
+/// Returns the estimated number of input tokens of a completion request.
+///
+/// The estimated compute units of a completion request are the number of tokens of its
+/// prompts, plus `max_tokens` for each of them, see `utils::calculate_completion_compute_units`
+fn estimated_input_tokens(payload: &Value, estimated_total_compute_units: i64) -> i64 {
+    let num_prompts = match payload.get(PROMPT_KEY) {
+        Some(Value::Array(prompts)) if prompts.iter().all(|p| !p.is_number()) => {
+            prompts.len() as i64
+        }
+        _ => 1,
+    };
+    payload
+        .get(MAX_TOKENS_KEY)
+        .and_then(|max_tokens| max_tokens.as_i64())
+        .map_or(estimated_total_compute_units, |max_tokens| {
+            (estimated_total_compute_units - num_prompts * max_tokens).max(0)
+        })
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename(serialize = "requestBody", deserialize = "RequestBody"))]
+pub struct CompletionsRequest {
+    /// ID of the model to use.
+    model: String,
+    /// The prompt(s) to generate completions for, encoded as a string, array of strings,
+    /// array of tokens, or array of token arrays.
+    prompt: CompletionsPrompt,
+    /// Generates `best_of` completions server-side and returns the best one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    best_of: Option<usize>,
+    /// Echo back the prompt in addition to the completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    echo: Option<bool>,
+    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    /// Modify the likelihood of specified tokens appearing in the completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<String, f32>>,
+    /// Include the log probabilities on the `logprobs` most likely output tokens, as well as the chosen tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    logprobs: Option<u32>,
+    /// The maximum number of tokens that can be generated in each completion. Defaults to the
+    /// model's default, or to 16 tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    /// How many completions to generate for each prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<usize>,
+    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    /// A seed to use for random number generation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// Up to 4 sequences where the API will stop generating further tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop: Option<StopCondition>,
+    /// If set, the server will stream the results as they come in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    /// The suffix that comes after a completion of inserted text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    suffix: Option<String>,
+    /// What sampling temperature to use, between 0 and 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    /// An alternative to sampling with temperature, called nucleus sampling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    /// A unique identifier representing your end-user, which can help the system to monitor and detect abuse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+}
+
+/// The prompt(s) of a completion request.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum CompletionsPrompt {
+    /// A single text prompt.
+    String(String),
+    /// Multiple text prompts, each getting its own completion.
+    StringArray(Vec<String>),
+    /// A single prompt, as token IDs.
+    Tokens(Vec<u32>),
+    /// Multiple prompts, as token IDs, each getting its own completion.
+    TokensArray(Vec<Vec<u32>>),
+}
+
+/// Response structure returned by the completions API.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CompletionsResponse {
+    /// Unique identifier for the completion.
+    pub id: String,
+    /// The object type, always "text_completion".
+    pub object: String,
+    /// Unix timestamp (in seconds) of when the completion was created.
+    pub created: u64,
+    /// The model used for the completion.
+    pub model: String,
+    /// Array of completion choices, one per prompt and per `n`.
+    pub choices: Vec<CompletionChoice>,
+    /// Statistics about token usage for this completion.
+    pub usage: CompletionUsage,
+}
+
+/// Represents a single completion choice returned by the completions API.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CompletionChoice {
+    /// The index of this choice in the array of choices.
+    pub index: u32,
+    /// The generated text.
+    pub text: String,
+    /// Log probabilities of the output tokens, if requested.
+    pub logprobs: Option<Value>,
+    /// Why the model stopped generating tokens, either "stop" or "length".
+    pub finish_reason: Option<String>,
+}
+
+/// Represents the token usage statistics for a completion request.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CompletionUsage {
+    /// The number of tokens of the prompt(s).
+    pub prompt_tokens: u32,
+    /// The number of tokens of the completion(s).
+    pub completion_tokens: u32,
+    /// The total number of tokens used (prompt_tokens + completion_tokens).
+    pub total_tokens: u32,
+}
@@ -1,17 +1,18 @@
 use crate::{
     error::AtomaServiceError,
     handlers::{
-        handle_confidential_compute_encryption_response,
+        admit_inference_request, handle_confidential_compute_encryption_response,
+        inference_backend,
         prometheus::{TEXT_EMBEDDINGS_LATENCY_METRICS, TEXT_EMBEDDINGS_NUM_REQUESTS},
         sign_response_and_update_stack_hash, update_stack_num_compute_units,
     },
-    middleware::{EncryptionMetadata, RequestMetadata},
+    middleware::{EncryptionMetadata, RequestMetadata, RequestType},
     server::AppState,
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
 };
+use atoma_utils::hashing::JsonCanonicalization;
 use axum::{extract::State, Extension, Json};
 use prometheus::HistogramTimer;
-use reqwest::Client;
 use serde_json::Value;
 use tracing::{info, instrument};
 use utoipa::OpenApi;
@@ -60,7 +61,8 @@ pub(crate) struct EmbeddingsOpenApi;
     request_body = Value,
     responses(
         (status = OK, description = "Embeddings generated successfully", body = Value),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -81,10 +83,12 @@ pub async fn embeddings_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
         request_type: _,
+        json_canonicalization,
     } = request_metadata;
 
     TEXT_EMBEDDINGS_NUM_REQUESTS
@@ -100,6 +104,7 @@ pub async fn embeddings_handler(
         stack_small_id,
         estimated_total_compute_units,
         payload_hash,
+        json_canonicalization,
         client_encryption_metadata,
         &endpoint,
         timer,
@@ -111,6 +116,7 @@ pub async fn embeddings_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
@@ -169,7 +175,8 @@ pub(crate) struct ConfidentialEmbeddingsOpenApi;
     request_body = ConfidentialComputeRequest,
     responses(
         (status = OK, description = "Confidential embeddings generated successfully", body = ConfidentialComputeResponse),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -190,10 +197,12 @@ pub async fn confidential_embeddings_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
         request_type: _,
+        json_canonicalization,
     } = request_metadata;
 
     TEXT_EMBEDDINGS_NUM_REQUESTS
@@ -209,6 +218,7 @@ pub async fn confidential_embeddings_handler(
         stack_small_id,
         estimated_total_compute_units,
         payload_hash,
+        json_canonicalization,
         client_encryption_metadata,
         &endpoint,
         timer,
@@ -220,6 +230,7 @@ pub async fn confidential_embeddings_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
@@ -246,6 +257,7 @@ pub async fn confidential_embeddings_handler(
 /// * `stack_small_id` - Unique identifier for the stack making the request
 /// * `estimated_total_compute_units` - Expected computational cost of the request
 /// * `payload_hash` - 32-byte hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the response body
 /// * `client_encryption_metadata` - Optional encryption details for confidential compute
 /// * `endpoint` - The API endpoint path being called
 /// * `timer` - Prometheus histogram timer for tracking request duration
@@ -273,6 +285,7 @@ pub async fn confidential_embeddings_handler(
 ///     stack_id,
 ///     compute_units,
 ///     payload_hash,
+///     JsonCanonicalization::Jcs,
 ///     encryption_metadata,
 ///     "/v1/embeddings",
 ///     metrics_timer
@@ -290,38 +303,28 @@ async fn handle_embeddings_response(
     stack_small_id: i64,
     estimated_total_compute_units: i64,
     payload_hash: [u8; 32],
+    json_canonicalization: JsonCanonicalization,
     client_encryption_metadata: Option<EncryptionMetadata>,
     endpoint: &str,
     timer: HistogramTimer,
 ) -> Result<Json<Value>, AtomaServiceError> {
-    let client = Client::new();
-    let response = client
-        .post(format!(
-            "{}{}",
-            state.embeddings_service_url, EMBEDDINGS_PATH
-        ))
-        .json(&payload)
-        .send()
+    let _admission_permit = admit_inference_request(state, payload, endpoint).await?;
+    let mut response_body = inference_backend(state, payload, RequestType::Embeddings, endpoint)?
+        .post_json(EMBEDDINGS_PATH, payload)
         .await
         .map_err(|e| AtomaServiceError::InternalError {
             message: format!("Error sending request to embeddings service: {}", e),
             endpoint: endpoint.to_string(),
         })?;
-    let mut response_body =
-        response
-            .json::<Value>()
-            .await
-            .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error reading response body: {}", e),
-                endpoint: endpoint.to_string(),
-            })?;
 
     // Sign the response and update the stack hash
     if let Err(e) = sign_response_and_update_stack_hash(
         &mut response_body,
         payload_hash,
+        json_canonicalization,
         state,
         stack_small_id,
+        estimated_total_compute_units,
         endpoint.to_string(),
     )
     .await
@@ -1,16 +1,17 @@
 use crate::{
     error::AtomaServiceError,
     handlers::{
+        admit_inference_request, inference_backend,
         prometheus::{IMAGE_GEN_LATENCY_METRICS, IMAGE_GEN_NUM_REQUESTS},
         update_stack_num_compute_units,
     },
-    middleware::{EncryptionMetadata, RequestMetadata},
+    middleware::{EncryptionMetadata, RequestMetadata, RequestType},
     server::AppState,
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
 };
+use atoma_utils::hashing::JsonCanonicalization;
 use axum::{extract::State, Extension, Json};
 use prometheus::HistogramTimer;
-use reqwest::Client;
 use serde_json::Value;
 use tracing::{info, instrument};
 use utoipa::OpenApi;
@@ -61,7 +62,8 @@ pub(crate) struct ImageGenerationsOpenApi;
     request_body = Value,
     responses(
         (status = OK, description = "Images generated successfully", body = Value),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -88,16 +90,19 @@ pub async fn image_generations_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
         request_type: _,
+        json_canonicalization,
     } = request_metadata;
 
     match handle_image_generations_response(
         &state,
         payload,
         payload_hash,
+        json_canonicalization,
         stack_small_id,
         estimated_total_compute_units,
         client_encryption_metadata,
@@ -111,10 +116,16 @@ pub async fn image_generations_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
             )?;
+            // NOTE: Rejections by admission control are returned as is, so that clients
+            // know when to retry the request
+            if let AtomaServiceError::ServiceUnavailable { .. } = e {
+                return Err(e);
+            }
             Err(AtomaServiceError::InternalError {
                 message: format!("Error handling image generations response: {}", e),
                 endpoint: endpoint.to_string(),
@@ -170,7 +181,8 @@ pub(crate) struct ConfidentialImageGenerationsOpenApi;
     request_body = ConfidentialComputeRequest,
     responses(
         (status = OK, description = "Confidential images generated successfully", body = ConfidentialComputeResponse),
-        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
     )
 )]
 #[instrument(
@@ -197,16 +209,19 @@ pub async fn confidential_image_generations_handler(
     let RequestMetadata {
         stack_small_id,
         estimated_total_compute_units,
+        session_grant_id,
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
         request_type: _,
+        json_canonicalization,
     } = request_metadata;
 
     match handle_image_generations_response(
         &state,
         payload,
         payload_hash,
+        json_canonicalization,
         stack_small_id,
         estimated_total_compute_units,
         client_encryption_metadata,
@@ -220,10 +235,16 @@ pub async fn confidential_image_generations_handler(
             update_stack_num_compute_units(
                 &state.state_manager_sender,
                 stack_small_id,
+                session_grant_id,
                 estimated_total_compute_units,
                 0,
                 &endpoint,
             )?;
+            // NOTE: Rejections by admission control are returned as is, so that clients
+            // know when to retry the request
+            if let AtomaServiceError::ServiceUnavailable { .. } = e {
+                return Err(e);
+            }
             Err(AtomaServiceError::InternalError {
                 message: format!("Error handling image generations response: {}", e),
                 endpoint: endpoint.to_string(),
@@ -245,6 +266,7 @@ pub async fn confidential_image_generations_handler(
 /// * `state` - Application state containing service URLs and other shared resources
 /// * `payload` - The JSON payload containing the image generation request parameters
 /// * `payload_hash` - A 32-byte hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the response body
 /// * `stack_small_id` - Identifier for the current stack
 /// * `estimated_total_compute_units` - Expected computational cost of the operation
 /// * `client_encryption_metadata` - Optional encryption metadata for confidential compute
@@ -274,31 +296,20 @@ async fn handle_image_generations_response(
     state: &AppState,
     payload: Value,
     payload_hash: [u8; 32],
+    json_canonicalization: JsonCanonicalization,
     stack_small_id: i64,
     estimated_total_compute_units: i64,
     client_encryption_metadata: Option<EncryptionMetadata>,
     endpoint: &str,
     timer: HistogramTimer,
 ) -> Result<Json<Value>, AtomaServiceError> {
-    let client = Client::new();
-    let response = client
-        .post(format!(
-            "{}{}",
-            state.image_generations_service_url, IMAGE_GENERATIONS_PATH
-        ))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| AtomaServiceError::InternalError {
-            message: format!("Error sending request to image generations service: {}", e),
-            endpoint: endpoint.to_string(),
-        })?;
+    let _admission_permit = admit_inference_request(state, &payload, endpoint).await?;
     let mut response_body =
-        response
-            .json::<Value>()
+        inference_backend(state, &payload, RequestType::ImageGenerations, endpoint)?
+            .post_json(IMAGE_GENERATIONS_PATH, &payload)
             .await
             .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error reading response body: {}", e),
+                message: format!("Error sending request to image generations service: {}", e),
                 endpoint: endpoint.to_string(),
             })?;
 
@@ -306,8 +317,10 @@ async fn handle_image_generations_response(
     if let Err(e) = sign_response_and_update_stack_hash(
         &mut response_body,
         payload_hash,
+        json_canonicalization,
         state,
         stack_small_id,
+        estimated_total_compute_units,
         endpoint.to_string(),
     )
     .await
@@ -1,28 +1,43 @@
+pub(crate) mod audio;
 pub(crate) mod chat_completions;
+pub(crate) mod completions;
 pub(crate) mod embeddings;
 pub(crate) mod image_generations;
+pub(crate) mod models;
 pub(crate) mod prometheus;
+pub(crate) mod quote;
+pub(crate) mod rerank;
+pub(crate) mod stacks;
+
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use atoma_confidential::types::{
-    ConfidentialComputeEncryptionRequest, ConfidentialComputeEncryptionResponse,
+    ConfidentialComputeEncryptionRequest, ConfidentialComputeEncryptionResponse, DH_PUBLIC_KEY_SIZE,
+};
+use atoma_utils::{
+    constants::{PAYLOAD_HASH_SIZE, SALT_SIZE},
+    hashing::{blake2b_hash, JsonCanonicalization},
 };
-use atoma_utils::hashing::blake2b_hash;
 use base64::{engine::general_purpose::STANDARD, Engine};
 use flume::Sender;
-use image_generations::CONFIDENTIAL_IMAGE_GENERATIONS_PATH;
 use serde_json::{json, Value};
 use tracing::{info, instrument};
 
 use crate::{
+    admission::AdmissionPermit,
+    backends::ModelBackend,
     error::AtomaServiceError,
-    middleware::EncryptionMetadata,
+    middleware::{EncryptionMetadata, RequestType},
     server::{utils, AppState},
 };
-use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_state::types::{AtomaAtomaStateManagerEvent, StackReceipt};
 
 /// Key for the ciphertext in the response body
 const CIPHERTEXT_KEY: &str = "ciphertext";
 
+/// Key for the model in the request body
+const MODEL_KEY: &str = "model";
+
 /// Key for the nonce in the response body
 const NONCE_KEY: &str = "nonce";
 
@@ -41,8 +56,11 @@ pub const USAGE_KEY: &str = "usage";
 ///
 /// * `response_body` - Mutable reference to the response JSON
 /// * `payload_hash` - Hash of the original request payload
+/// * `json_canonicalization` - JSON canonicalization used to hash the response body
 /// * `state` - Application state containing keystore and state manager
 /// * `stack_small_id` - Identifier for the current stack
+/// * `num_compute_units` - Number of compute units charged for the response, recorded in its
+///   receipt
 ///
 /// # Returns
 ///
@@ -55,22 +73,106 @@ pub const USAGE_KEY: &str = "usage";
 async fn sign_response_and_update_stack_hash(
     response_body: &mut Value,
     payload_hash: [u8; 32],
+    json_canonicalization: JsonCanonicalization,
     state: &AppState,
     stack_small_id: i64,
+    num_compute_units: i64,
     endpoint: String,
 ) -> Result<(), AtomaServiceError> {
+    let response_hash = sign_response(response_body, json_canonicalization, state, &endpoint)?;
+
+    update_stack_total_hash(
+        state,
+        stack_small_id,
+        payload_hash,
+        response_hash,
+        num_compute_units,
+        endpoint,
+    )
+}
+
+/// Signs a JSON response body, and adds the base64 encoded signature and response hash to it
+///
+/// # Returns
+///
+/// Returns the hash of the response body, before the signature was added
+pub(crate) fn sign_response(
+    response_body: &mut Value,
+    json_canonicalization: JsonCanonicalization,
+    state: &AppState,
+    endpoint: &str,
+) -> Result<[u8; 32], AtomaServiceError> {
     // Sign the response body byte content and add the base64 encoded signature to the response body
+    let (response_hash, signature) = utils::sign_response_body(
+        response_body,
+        json_canonicalization,
+        &state.keystore,
+        state.address_index,
+    )
+    .map_err(|e| AtomaServiceError::InternalError {
+        message: format!("Error signing response body: {}", e),
+        endpoint: endpoint.to_string(),
+    })?;
+    response_body[SIGNATURE_KEY] = json!(signature);
+    response_body[RESPONSE_HASH_KEY] = json!(STANDARD.encode(response_hash));
+    Ok(response_hash)
+}
+
+/// Signs a response body that is not JSON (e.g. generated audio) and updates the stack hash
+/// state, as `sign_response_and_update_stack_hash` does for JSON responses.
+///
+/// # Arguments
+///
+/// * `response_body` - The bytes of the response body
+/// * `payload_hash` - Hash of the original request payload
+/// * `state` - Application state containing keystore and state manager
+/// * `stack_small_id` - Identifier for the current stack
+/// * `num_compute_units` - Number of compute units charged for the response
+///
+/// # Returns
+///
+/// Returns the Blake2b hash of the response body, and the base64-encoded signature of it.
+#[instrument(
+    level = "info",
+    skip(response_body, state),
+    fields(event = "sign-raw-response-and-update-stack-hash",)
+)]
+fn sign_raw_response_and_update_stack_hash(
+    response_body: &[u8],
+    payload_hash: [u8; 32],
+    state: &AppState,
+    stack_small_id: i64,
+    num_compute_units: i64,
+    endpoint: String,
+) -> Result<([u8; 32], String), AtomaServiceError> {
     let (response_hash, signature) =
-        utils::sign_response_body(response_body, &state.keystore, state.address_index).map_err(
+        utils::sign_response_bytes(response_body, &state.keystore, state.address_index).map_err(
             |e| AtomaServiceError::InternalError {
                 message: format!("Error signing response body: {}", e),
                 endpoint: endpoint.clone(),
             },
         )?;
-    response_body[SIGNATURE_KEY] = json!(signature);
-    response_body[RESPONSE_HASH_KEY] = json!(STANDARD.encode(response_hash));
+    update_stack_total_hash(
+        state,
+        stack_small_id,
+        payload_hash,
+        response_hash,
+        num_compute_units,
+        endpoint,
+    )?;
+    Ok((response_hash, signature))
+}
 
-    // Update the stack total hash
+/// Updates the total hash of a stack with the hashes of a request and of its response, and
+/// records the receipt of the response
+fn update_stack_total_hash(
+    state: &AppState,
+    stack_small_id: i64,
+    payload_hash: [u8; 32],
+    response_hash: [u8; 32],
+    num_compute_units: i64,
+    endpoint: String,
+) -> Result<(), AtomaServiceError> {
     let total_hash = blake2b_hash(&[payload_hash, response_hash].concat());
     let total_hash_bytes: [u8; 32] = total_hash
         .as_slice()
@@ -88,8 +190,44 @@ async fn sign_response_and_update_stack_hash(
             message: format!("Error updating stack total hash: {}", e),
             endpoint: endpoint.clone(),
         })?;
+    record_stack_receipt(
+        &state.state_manager_sender,
+        stack_small_id,
+        payload_hash,
+        response_hash,
+        num_compute_units,
+        &endpoint,
+    )
+}
 
-    Ok(())
+/// Records the receipt of a response served for a stack, so that the stack's owner can audit
+/// the compute units charged for each of its requests
+pub(crate) fn record_stack_receipt(
+    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    stack_small_id: i64,
+    payload_hash: [u8; 32],
+    response_hash: [u8; 32],
+    num_compute_units: i64,
+    endpoint: &str,
+) -> Result<(), AtomaServiceError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default();
+    state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::RecordStackReceipt {
+            receipt: StackReceipt {
+                stack_small_id,
+                payload_hash: payload_hash.to_vec(),
+                response_hash: response_hash.to_vec(),
+                num_compute_units,
+                timestamp,
+            },
+        })
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Error recording stack receipt: {}", e),
+            endpoint: endpoint.to_string(),
+        })
 }
 
 /// Handles the encryption of response data for confidential compute requests
@@ -164,8 +302,14 @@ pub(crate) async fn handle_confidential_compute_encryption_response(
                 .map(|obj| obj.remove(RESPONSE_HASH_KEY));
         }
 
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let usage = if endpoint != CONFIDENTIAL_IMAGE_GENERATIONS_PATH {
+        // NOTE: Image generations and audio transcriptions do not report token usage, nor do
+        // all rerank services (e.g. TEI)
+        let usage = if !matches!(
+            endpoint.as_str(),
+            image_generations::CONFIDENTIAL_IMAGE_GENERATIONS_PATH
+                | audio::CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH
+                | rerank::CONFIDENTIAL_RERANK_PATH
+        ) {
             Some(
                 response_body
                     .get(USAGE_KEY)
@@ -177,67 +321,95 @@ pub(crate) async fn handle_confidential_compute_encryption_response(
         } else {
             None
         };
-        state
-            .encryption_sender
-            .send((
-                ConfidentialComputeEncryptionRequest {
-                    plaintext: response_body.to_string().as_bytes().to_vec(),
-                    salt,
-                    proxy_x25519_public_key,
-                },
-                sender,
-            ))
-            .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error sending encryption request: {}", e),
-                endpoint: endpoint.clone(),
-            })?;
-        let result = receiver
-            .await
-            .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error receiving encryption response: {}", e),
-                endpoint: endpoint.clone(),
-            })?;
-        match result {
-            Ok(ConfidentialComputeEncryptionResponse { ciphertext, nonce }) => {
-                let nonce = STANDARD.encode(nonce);
-                let ciphertext = STANDARD.encode(ciphertext);
-                let mut response_body = json!({
-                    NONCE_KEY: nonce,
-                    CIPHERTEXT_KEY: ciphertext
-                });
-                if let Some(response_hash) = response_hash {
-                    response_body[RESPONSE_HASH_KEY] = response_hash;
-                }
-                if let Some(signature) = signature {
-                    response_body[SIGNATURE_KEY] = signature;
-                }
-                if let Some(usage) = usage {
-                    response_body[USAGE_KEY] = usage.clone();
-                }
-                Ok(response_body)
-            }
-            Err(e) => {
-                return Err(AtomaServiceError::InternalError {
-                    message: format!("Failed to encrypt confidential compute response: {:?}", e),
-                    endpoint: endpoint.clone(),
-                })
-            }
+        let mut encrypted_response_body = encrypt_response_body(
+            state,
+            response_body.to_string().into_bytes(),
+            salt,
+            proxy_x25519_public_key,
+            &endpoint,
+        )
+        .await?;
+        if let Some(response_hash) = response_hash {
+            encrypted_response_body[RESPONSE_HASH_KEY] = response_hash;
+        }
+        if let Some(signature) = signature {
+            encrypted_response_body[SIGNATURE_KEY] = signature;
         }
+        if let Some(usage) = usage {
+            encrypted_response_body[USAGE_KEY] = usage.clone();
+        }
+        Ok(encrypted_response_body)
     } else {
         Ok(response_body)
     }
 }
 
+/// Encrypts a response body for the client of a confidential compute request, with the
+/// shared secret derived from its proxy X25519 public key and salt.
+///
+/// # Returns
+///
+/// Returns a JSON object with the base64-encoded `nonce` and `ciphertext` of the response
+/// body.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if the encryption request cannot be sent to, or
+/// fails in, the confidential compute service.
+pub(crate) async fn encrypt_response_body(
+    state: &AppState,
+    plaintext: Vec<u8>,
+    salt: [u8; SALT_SIZE],
+    proxy_x25519_public_key: [u8; DH_PUBLIC_KEY_SIZE],
+    endpoint: &str,
+) -> Result<Value, AtomaServiceError> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    state
+        .encryption_sender
+        .send((
+            ConfidentialComputeEncryptionRequest {
+                plaintext,
+                salt,
+                proxy_x25519_public_key,
+            },
+            sender,
+        ))
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Error sending encryption request: {}", e),
+            endpoint: endpoint.to_string(),
+        })?;
+    let result = receiver
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Error receiving encryption response: {}", e),
+            endpoint: endpoint.to_string(),
+        })?;
+    match result {
+        Ok(ConfidentialComputeEncryptionResponse { ciphertext, nonce }) => Ok(json!({
+            NONCE_KEY: STANDARD.encode(nonce),
+            CIPHERTEXT_KEY: STANDARD.encode(ciphertext)
+        })),
+        Err(e) => Err(AtomaServiceError::InternalError {
+            message: format!("Failed to encrypt confidential compute response: {:?}", e),
+            endpoint: endpoint.to_string(),
+        }),
+    }
+}
+
 /// Updates the compute units used by a stack in the state manager.
 ///
 /// This function sends an update event to the state manager to record compute unit usage for a stack.
 /// It's typically used when handling errors to ensure the estimated compute units are still tracked,
 /// even if the actual computation failed.
 ///
+/// If the request was signed with a session key, the compute units reserved on its grant are
+/// reconciled in the same way, so that a failed request does not consume the grant.
+///
 /// # Arguments
 ///
 /// * `state` - Application state containing the state manager channel
 /// * `stack_small_id` - Unique identifier for the stack being updated
+/// * `session_grant_id` - Identifier of the session key grant the request was charged to, if any
 /// * `estimated_total_compute_units` - The estimated number of compute units that would have been used
 /// * `endpoint` - The API endpoint path where the request was received
 ///
@@ -271,6 +443,7 @@ pub(crate) async fn handle_confidential_compute_encryption_response(
 ///     update_stack_num_compute_units(
 ///         state,
 ///         stack_id,
+///         None,
 ///         100, // estimated units
 ///         "/v1/chat/completions"
 ///     ).await?;
@@ -291,6 +464,7 @@ pub(crate) async fn handle_confidential_compute_encryption_response(
 pub(crate) fn update_stack_num_compute_units(
     state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
     stack_small_id: i64,
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     estimated_total_compute_units: i64,
     total_compute_units: i64,
     endpoint: &str,
@@ -304,5 +478,97 @@ pub(crate) fn update_stack_num_compute_units(
         .map_err(|e| AtomaServiceError::InternalError {
             message: format!("Error sending update stack num compute units event: {}", e,),
             endpoint: endpoint.to_string(),
+        })?;
+    if let Some(grant_id) = session_grant_id {
+        state_manager_sender
+            .send(
+                AtomaAtomaStateManagerEvent::UpdateSessionGrantComputeUnits {
+                    grant_id,
+                    estimated_total_compute_units,
+                    total_compute_units,
+                },
+            )
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Error sending update session grant compute units event: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+    }
+    Ok(())
+}
+
+/// Returns the inference backend serving a request, based on the requested model.
+///
+/// # Arguments
+///
+/// * `state` - The application state, holding the inference backends of the deployed models
+/// * `payload` - The request body, containing the requested model
+/// * `request_type` - The type of the request, to select the service URL of models without
+///   a configured backend
+/// * `endpoint` - The endpoint that the request was made to
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if no inference backend serves the requested model,
+/// as supported models are checked beforehand by the `verify_stack_permissions` middleware.
+pub(crate) fn inference_backend<'a>(
+    state: &'a AppState,
+    payload: &Value,
+    request_type: RequestType,
+    endpoint: &str,
+) -> Result<&'a ModelBackend, AtomaServiceError> {
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|model| model.as_str())
+        .unwrap_or_default();
+    state
+        .backends
+        .get(model, &request_type)
+        .ok_or_else(|| AtomaServiceError::InternalError {
+            message: format!("No inference backend configured for model {model}"),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Admits a request to the inference backend of the requested model, waiting in the model's
+/// admission queue if the backend already serves as many requests as it is allowed to.
+///
+/// # Arguments
+///
+/// * `state` - The application state, holding the admission queues of the deployed models
+/// * `payload` - The request body, containing the requested model
+/// * `endpoint` - The endpoint that the request was made to
+///
+/// # Returns
+///
+/// Returns the permit of the admitted request, which must be held until the inference backend
+/// has served it (including until its response is fully streamed).
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::ServiceUnavailable` if the model's admission queue is full, or if
+/// the request timed out waiting in it.
+pub(crate) async fn admit_inference_request(
+    state: &AppState,
+    payload: &Value,
+    endpoint: &str,
+) -> Result<AdmissionPermit, AtomaServiceError> {
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|model| model.as_str())
+        .unwrap_or_default();
+    state
+        .admission
+        .admit(model)
+        .await
+        .map_err(|e| match e.retry_after() {
+            Some(retry_after) => AtomaServiceError::ServiceUnavailable {
+                message: e.to_string(),
+                retry_after,
+                endpoint: endpoint.to_string(),
+            },
+            None => AtomaServiceError::InternalError {
+                message: format!("Failed to admit request: {e}"),
+                endpoint: endpoint.to_string(),
+            },
         })
 }
@@ -0,0 +1,226 @@
+use std::collections::BTreeSet;
+
+use atoma_state::types::{AtomaAtomaStateManagerEvent, Task};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    error::AtomaServiceError,
+    handlers::{
+        audio::{AUDIO_TRANSCRIPTIONS_PATH, SPEECH_PATH},
+        chat_completions::CHAT_COMPLETIONS_PATH,
+        completions::COMPLETIONS_PATH,
+        embeddings::EMBEDDINGS_PATH,
+        image_generations::IMAGE_GENERATIONS_PATH,
+        rerank::RERANK_PATH,
+    },
+    middleware::RequestType,
+    server::AppState,
+};
+
+/// The path for the models endpoint
+pub const MODELS_PATH: &str = "/v1/models";
+
+/// The path for retrieving a single model, whose id may contain slashes
+pub const MODEL_PATH: &str = "/v1/models/*model";
+
+/// The owner reported for models whose id has no organization prefix
+const DEFAULT_OWNER: &str = "atoma";
+
+/// A model served by the node, in OpenAI's format, extended with Atoma-specific fields
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct ModelObject {
+    /// The model identifier, as used in the `model` field of requests
+    pub id: String,
+    /// The object type, always `model`
+    pub object: String,
+    /// Unix time, in seconds, at which the model was created. Always `0`, as it is not known
+    /// to the node
+    pub created: i64,
+    /// The organization owning the model, taken from its id prefix
+    pub owned_by: String,
+    /// Maximum number of tokens, prompt and completion combined, the model can process
+    pub context_length: Option<i64>,
+    /// Paths of the endpoints through which the model is served, depending on the roles
+    /// of the tasks the node is subscribed to for it
+    pub endpoints: Vec<String>,
+    /// Whether the node holds a valid TEE attestation, so that the model can be served for
+    /// stacks requiring confidential compute, through the `/v1/confidential` endpoints
+    pub confidential_compute: bool,
+    /// Small IDs of the non-deprecated tasks the node is subscribed to for the model
+    pub task_small_ids: Vec<i64>,
+}
+
+/// A list of models, in OpenAI's format
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct ModelList {
+    /// The object type, always `list`
+    pub object: String,
+    /// The models served by the node
+    pub data: Vec<ModelObject>,
+}
+
+/// OpenAPI documentation structure for the models endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the models API,
+/// including all response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(models_handler, model_handler),
+    components(schemas(ModelList, ModelObject))
+)]
+pub(crate) struct ModelsOpenApi;
+
+/// List models
+///
+/// Lists the models served by the node, along with their context length, the endpoints
+/// serving them, whether confidential compute is available, and the tasks the node is
+/// subscribed to for each of them.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if the node's subscribed tasks cannot be
+/// retrieved from the state manager.
+#[utoipa::path(
+    get,
+    path = "",
+    tag = "models",
+    responses(
+        (status = OK, description = "Models served by the node", body = ModelList),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn models_handler(
+    State(state): State<AppState>,
+) -> Result<Json<ModelList>, AtomaServiceError> {
+    let tasks = get_subscribed_tasks(&state, MODELS_PATH).await?;
+    let data = state
+        .models
+        .iter()
+        .map(|model| model_object(&state, model, &tasks))
+        .collect();
+    Ok(Json(ModelList {
+        object: "list".to_string(),
+        data,
+    }))
+}
+
+/// Retrieve model
+///
+/// Retrieves a model served by the node, with the same fields as when listing models.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::ModelNotFound` if the node does not serve the model, or a
+/// `AtomaServiceError::InternalError` if the node's subscribed tasks cannot be retrieved.
+#[utoipa::path(
+    get,
+    path = "/{model}",
+    tag = "models",
+    params(("model" = String, Path, description = "The model identifier")),
+    responses(
+        (status = OK, description = "Model served by the node", body = ModelObject),
+        (status = NOT_FOUND, description = "Model not served by the node"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip(state))]
+pub async fn model_handler(
+    State(state): State<AppState>,
+    Path(model): Path<String>,
+) -> Result<Json<ModelObject>, AtomaServiceError> {
+    let endpoint = format!("{MODELS_PATH}/{model}");
+    if !state.models.contains(&model) {
+        return Err(AtomaServiceError::ModelNotFound { model, endpoint });
+    }
+    let tasks = get_subscribed_tasks(&state, &endpoint).await?;
+    Ok(Json(model_object(&state, &model, &tasks)))
+}
+
+/// Retrieves the non-deprecated tasks the node is subscribed to, across all of its badges
+async fn get_subscribed_tasks(
+    state: &AppState,
+    endpoint: &str,
+) -> Result<Vec<Task>, AtomaServiceError> {
+    let mut tasks = Vec::new();
+    for &node_small_id in state.node_small_ids.iter() {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::GetSubscribedTasks {
+                node_small_id,
+                result_sender,
+            })
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Failed to get subscribed tasks: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        let node_tasks = result_receiver
+            .await
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Failed to receive subscribed tasks, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Failed to get subscribed tasks, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        tasks.extend(node_tasks.into_iter().filter(|task| !task.is_deprecated));
+    }
+    Ok(tasks)
+}
+
+/// Builds the model object of a model served by the node, from the tasks it is subscribed to
+fn model_object(state: &AppState, model: &str, tasks: &[Task]) -> ModelObject {
+    let model_tasks = tasks
+        .iter()
+        .filter(|task| task.model_name.as_deref() == Some(model));
+    let task_small_ids = model_tasks
+        .clone()
+        .map(|task| task.task_small_id)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let endpoints = [
+        (RequestType::ChatCompletions, CHAT_COMPLETIONS_PATH),
+        (RequestType::Completions, COMPLETIONS_PATH),
+        (RequestType::Embeddings, EMBEDDINGS_PATH),
+        (RequestType::Rerank, RERANK_PATH),
+        (RequestType::ImageGenerations, IMAGE_GENERATIONS_PATH),
+        (RequestType::AudioTranscriptions, AUDIO_TRANSCRIPTIONS_PATH),
+        (RequestType::Speech, SPEECH_PATH),
+    ]
+    .into_iter()
+    .filter(|(request_type, _)| {
+        model_tasks
+            .clone()
+            .any(|task| request_type.task_role(&state.task_roles) == Some(task.role))
+    })
+    .map(|(_, path)| path.to_string())
+    .collect();
+    ModelObject {
+        id: model.to_string(),
+        object: "model".to_string(),
+        created: 0,
+        owned_by: model
+            .split_once('/')
+            .map_or(DEFAULT_OWNER, |(owner, _)| owner)
+            .to_string(),
+        context_length: state
+            .model_metadata
+            .iter()
+            .find(|metadata| metadata.model == model)
+            .and_then(|metadata| metadata.context_length),
+        endpoints,
+        confidential_compute: *state.tee_attestation_receiver.borrow(),
+        task_small_ids,
+    }
+}
@@ -1,5 +1,8 @@
 use once_cell::sync::Lazy;
-use prometheus::{register_counter_vec, register_histogram_vec, CounterVec, HistogramVec};
+use prometheus::{
+    register_counter_vec, register_histogram_vec, register_int_gauge_vec, CounterVec, HistogramVec,
+    IntGaugeVec,
+};
 
 const LATENCY_HISTOGRAM_BUCKETS: [f64; 15] = [
     0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
@@ -25,6 +28,26 @@ pub static CHAT_COMPLETIONS_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Counter metric that tracks the total number of (legacy) completion requests.
+///
+/// This metric counts the number of incoming requests for text completions,
+/// broken down by model type. Their tokens are tracked along with the ones
+/// of chat completions, as both are served by the same models.
+///
+/// # Metric Details
+/// - Name: `atoma_completions_num_requests`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static COMPLETIONS_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "atoma_completions_num_requests",
+        "The number of incoming requests for completions tasks",
+        &["model"]
+    )
+    .unwrap()
+});
+
 /// Counter metric that tracks the total number of image generation requests.
 ///
 /// This metric counts the number of incoming requests for image generations,
@@ -45,6 +68,44 @@ pub static IMAGE_GEN_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Counter metric that tracks the total number of audio transcription requests.
+///
+/// This metric counts the number of incoming requests for audio transcriptions,
+/// broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_audio_transcriptions_num_requests`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static AUDIO_TRANSCRIPTIONS_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "atoma_audio_transcriptions_num_requests",
+        "The number of incoming requests for audio transcriptions tasks",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Counter metric that tracks the total number of speech generation requests.
+///
+/// This metric counts the number of incoming requests for text-to-speech,
+/// broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_speech_num_requests`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static SPEECH_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "atoma_speech_num_requests",
+        "The number of incoming requests for speech tasks",
+        &["model"]
+    )
+    .unwrap()
+});
+
 /// Counter metric that tracks the total number of text embedding requests.
 ///
 /// This metric counts the number of incoming requests for text embeddings,
@@ -65,6 +126,25 @@ pub static TEXT_EMBEDDINGS_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Counter metric that tracks the total number of rerank requests.
+///
+/// This metric counts the number of incoming requests for reranking documents,
+/// broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_rerank_num_requests`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static RERANK_NUM_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "atoma_rerank_num_requests",
+        "The number of incoming requests for rerank tasks",
+        &["model"]
+    )
+    .unwrap()
+});
+
 /// Histogram metric that tracks the latency of chat completion token generation.
 ///
 /// This metric measures the time taken to generate each token during chat completions,
@@ -87,6 +167,27 @@ pub static CHAT_COMPLETIONS_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Histogram metric that tracks the latency of (legacy) completion requests.
+///
+/// This metric measures the time taken to generate non-streaming text completions,
+/// broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_completions_latency`
+/// - Type: Histogram
+/// - Labels: `model`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static COMPLETIONS_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_completions_latency",
+        "The latency of completion generation in seconds",
+        &["model"],
+        LATENCY_HISTOGRAM_BUCKETS.to_vec(),
+    )
+    .unwrap()
+});
+
 /// Histogram metric that tracks the latency of image generation requests.
 ///
 /// This metric measures the time taken to generate images, broken down by model type.
@@ -109,6 +210,46 @@ pub static IMAGE_GEN_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Histogram metric that tracks the latency of audio transcription requests.
+///
+/// This metric measures the time taken to transcribe audio files, broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_audio_transcriptions_latency`
+/// - Type: Histogram
+/// - Labels: `model`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static AUDIO_TRANSCRIPTIONS_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_audio_transcriptions_latency",
+        "The latency of audio transcription in seconds",
+        &["model"],
+        LATENCY_HISTOGRAM_BUCKETS.to_vec(),
+    )
+    .unwrap()
+});
+
+/// Histogram metric that tracks the latency of speech generation requests.
+///
+/// This metric measures the time taken to generate speech audio, broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_speech_latency`
+/// - Type: Histogram
+/// - Labels: `model`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static SPEECH_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_speech_latency",
+        "The latency of speech generation in seconds",
+        &["model"],
+        LATENCY_HISTOGRAM_BUCKETS.to_vec(),
+    )
+    .unwrap()
+});
+
 /// Histogram metric that tracks the latency of text embedding requests.
 ///
 /// This metric measures the time taken to generate text embeddings, broken down by model type.
@@ -131,6 +272,26 @@ pub static TEXT_EMBEDDINGS_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Histogram metric that tracks the latency of rerank requests.
+///
+/// This metric measures the time taken to rerank documents, broken down by model type.
+///
+/// # Metric Details
+/// - Name: `atoma_rerank_latency`
+/// - Type: Histogram
+/// - Labels: `model`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static RERANK_LATENCY_METRICS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_rerank_latency",
+        "The latency of reranking in seconds",
+        &["model"],
+        LATENCY_HISTOGRAM_BUCKETS.to_vec(),
+    )
+    .unwrap()
+});
+
 /// Histogram metric that tracks the total time spent in the decoding phase of chat completions.
 ///
 /// This metric measures the complete duration of the decoding phase for chat completions,
@@ -216,3 +377,111 @@ pub static CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS: Lazy<CounterVec> = Lazy::new(
     )
     .unwrap()
 });
+
+/// Gauge metric that tracks the number of requests waiting in the admission queue of each model.
+///
+/// This metric measures how many requests are waiting for a request in flight to the model's
+/// inference backend to complete, broken down by model type. A queue that is persistently
+/// non-empty indicates that the model's backend is saturated.
+///
+/// # Metric Details
+/// - Name: `atoma_admission_queue_depth`
+/// - Type: Gauge
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static ADMISSION_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "atoma_admission_queue_depth",
+        "The number of requests waiting in the admission queue",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Gauge metric that tracks the number of admitted requests in flight to each model's backend.
+///
+/// This metric counts the requests currently being served by the model's inference backend,
+/// broken down by model type. It is bounded by the model's configured `max_in_flight_requests`.
+///
+/// # Metric Details
+/// - Name: `atoma_admission_in_flight_requests`
+/// - Type: Gauge
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static ADMISSION_IN_FLIGHT_REQUESTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "atoma_admission_in_flight_requests",
+        "The number of admitted requests in flight to the inference backend",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Histogram metric that tracks the time requests wait in the admission queue of each model.
+///
+/// This metric measures the time between a request arriving and it being admitted to the
+/// model's inference backend, or rejected after timing out, broken down by model type.
+/// Requests admitted right away are recorded with a zero wait time.
+///
+/// # Metric Details
+/// - Name: `atoma_admission_queue_wait_time`
+/// - Type: Histogram
+/// - Labels: `model`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static ADMISSION_QUEUE_WAIT_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_admission_queue_wait_time",
+        "Time spent waiting in the admission queue in seconds",
+        &["model"],
+        LATENCY_HISTOGRAM_BUCKETS.to_vec(),
+    )
+    .unwrap()
+});
+
+/// Histogram metric that tracks the discrepancy of the token usage reported by the inference
+/// services from the one counted by the node.
+///
+/// This metric records, for each chat completion and completion request whose tokens can be
+/// counted locally, the difference between the reported and local number of tokens, relative
+/// to the local one, broken down by model type and by token type, either `input` or `output`.
+/// Positive values mean that the inference service reports more tokens than the node counts.
+///
+/// # Metric Details
+/// - Name: `atoma_usage_discrepancy_ratio`
+/// - Type: Histogram
+/// - Labels:
+///   - `model`: The model the request was for
+///   - `token_type`: Whether input or output tokens are compared
+/// - Unit: ratio
+/// - Buckets: [-1.0, -0.5, -0.25, -0.1, -0.05, -0.01, 0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0]
+pub static USAGE_DISCREPANCY_RATIO: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "atoma_usage_discrepancy_ratio",
+        "The discrepancy of the reported number of tokens from the locally counted one, relative to the latter",
+        &["model", "token_type"],
+        vec![-1.0, -0.5, -0.25, -0.1, -0.05, -0.01, 0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0],
+    )
+    .unwrap()
+});
+
+/// Counter metric that tracks the number of requests rejected by admission control.
+///
+/// This metric counts the requests rejected with a `503 Service Unavailable` status code,
+/// broken down by model type and by reason, either `queue_full` or `queue_timeout`.
+///
+/// # Metric Details
+/// - Name: `atoma_admission_rejected_requests`
+/// - Type: Counter
+/// - Labels:
+///   - `model`: The model the request was for
+///   - `reason`: Why the request was rejected
+/// - Unit: requests (count)
+pub static ADMISSION_REJECTED_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "atoma_admission_rejected_requests",
+        "The number of requests rejected by admission control",
+        &["model", "reason"]
+    )
+    .unwrap()
+});
@@ -0,0 +1,175 @@
+use axum::{extract::State, http::HeaderMap, Extension, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    error::AtomaServiceError,
+    middleware::{utils as middleware_utils, RequestMetadata, RequestType},
+    server::AppState,
+};
+
+use super::{sign_response, stacks::get_stack};
+
+/// The path for the quote endpoint
+pub const QUOTE_PATH: &str = "/v1/quote";
+
+/// Number of compute units the price of a stack is quoted for
+const PRICE_COMPUTE_UNITS: i128 = 1_000_000;
+
+/// A request for the price of an inference request, without serving it
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuoteRequest {
+    /// Path of the endpoint the request would be sent to, e.g. `/v1/chat/completions`
+    pub endpoint: String,
+    /// Body of the request, as it would be sent to the endpoint. Audio transcriptions requests
+    /// are quoted from the `duration` of their `file`, in seconds, instead of the file itself
+    #[schema(value_type = Object)]
+    pub request: Value,
+}
+
+/// The price of an inference request on a stack, signed by the node along with a
+/// `response_hash`, as inference responses are
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct QuoteResponse {
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// The model of the request
+    pub model: String,
+    /// Number of compute units the request would reserve on the stack
+    pub estimated_compute_units: i64,
+    /// Number of compute units of the stack that are neither used nor reserved
+    pub remaining_compute_units: i64,
+    /// Price of one million compute units of the stack, in the smallest unit of USDC
+    pub price_per_one_million_compute_units: i64,
+    /// Price of the estimated compute units, in the smallest unit of USDC, rounded up
+    pub estimated_price: i64,
+}
+
+/// OpenAPI documentation structure for the quote endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the quote API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(paths(quote_handler), components(schemas(QuoteRequest, QuoteResponse)))]
+pub(crate) struct QuoteOpenApi;
+
+/// Quote request
+///
+/// Returns the number of compute units an inference request would reserve on a stack, and
+/// their price, computed as when the request is served, but without reserving any compute
+/// units of the stack.
+///
+/// The request must be signed by the owner of the stack, or by a session key granted by the
+/// owner, and carry the stack small id header, as inference requests do.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InvalidBody` if the endpoint cannot be quoted, or if the
+/// request is invalid for the endpoint.
+///
+/// Returns a `AtomaServiceError::AuthError` if the stack does not exist, or is not owned by
+/// the signer.
+///
+/// Returns a `AtomaServiceError::InternalError` if the stack cannot be retrieved from the
+/// state manager, or if the response cannot be signed.
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "quote",
+    request_body = QuoteRequest,
+    responses(
+        (status = OK, description = "Quote of the request", body = QuoteResponse),
+        (status = BAD_REQUEST, description = "Invalid request"),
+        (status = UNAUTHORIZED, description = "Stack not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn quote_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, AtomaServiceError> {
+    let endpoint = QUOTE_PATH.to_string();
+    let QuoteRequest {
+        endpoint: quoted_endpoint,
+        mut request,
+    } = serde_json::from_value(payload).map_err(|e| AtomaServiceError::InvalidBody {
+        message: format!("Failed to parse quote request, with error: {e}"),
+        endpoint: endpoint.clone(),
+    })?;
+    let request_type = RequestType::from_path(&quoted_endpoint);
+    if request_type == RequestType::NonInference {
+        return Err(AtomaServiceError::InvalidBody {
+            message: format!("Endpoint {quoted_endpoint} cannot be quoted"),
+            endpoint,
+        });
+    }
+    let (stack_small_id, sui_address, _) =
+        middleware_utils::stack_request_signer(&state, &headers, &endpoint)?;
+    let (model, estimated_compute_units) = middleware_utils::calculate_request_compute_units(
+        &mut request,
+        &request_type,
+        &state,
+        &endpoint,
+    )?;
+
+    let stack = get_stack(&state, stack_small_id, &endpoint)
+        .await?
+        .filter(|stack| stack.owner_address == sui_address.to_string())
+        .ok_or_else(|| AtomaServiceError::AuthError {
+            auth_error: format!("Stack {stack_small_id} not found"),
+            endpoint: endpoint.clone(),
+        })?;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        event = "quote-handler",
+        "Quoted {estimated_compute_units} compute units for {quoted_endpoint} on stack {stack_small_id}"
+    );
+    let quote = QuoteResponse {
+        stack_small_id,
+        model,
+        estimated_compute_units,
+        remaining_compute_units: stack.num_compute_units - stack.already_computed_units,
+        price_per_one_million_compute_units: stack.price_per_one_million_compute_units,
+        estimated_price: estimated_price(
+            estimated_compute_units,
+            stack.price_per_one_million_compute_units,
+        ),
+    };
+
+    let mut response_body = json!(quote);
+    sign_response(
+        &mut response_body,
+        request_metadata.json_canonicalization,
+        &state,
+        &endpoint,
+    )?;
+    Ok(Json(response_body))
+}
+
+/// Returns the price of a number of compute units, in the smallest unit of USDC, rounded up
+fn estimated_price(num_compute_units: i64, price_per_one_million_compute_units: i64) -> i64 {
+    let price = i128::from(num_compute_units) * i128::from(price_per_one_million_compute_units);
+    i64::try_from((price + PRICE_COMPUTE_UNITS - 1) / PRICE_COMPUTE_UNITS).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimated_price() {
+        assert_eq!(estimated_price(1_000_000, 250), 250);
+        assert_eq!(estimated_price(2_000, 500_000), 1_000);
+        // NOTE: Fractions of the smallest unit of USDC are rounded up
+        assert_eq!(estimated_price(1, 250), 1);
+        assert_eq!(estimated_price(0, 250), 0);
+        assert_eq!(estimated_price(i64::MAX, i64::MAX), i64::MAX);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    error::AtomaServiceError,
+    handlers::{
+        admit_inference_request, handle_confidential_compute_encryption_response,
+        inference_backend,
+        prometheus::{RERANK_LATENCY_METRICS, RERANK_NUM_REQUESTS},
+        sign_response_and_update_stack_hash, update_stack_num_compute_units,
+    },
+    middleware::{EncryptionMetadata, RequestMetadata, RequestType},
+    server::AppState,
+    types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
+};
+use atoma_utils::hashing::JsonCanonicalization;
+use axum::{extract::State, Extension, Json};
+use prometheus::HistogramTimer;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+/// The path for confidential rerank requests
+pub const CONFIDENTIAL_RERANK_PATH: &str = "/v1/confidential/rerank";
+
+/// The path for rerank requests
+pub const RERANK_PATH: &str = "/v1/rerank";
+
+/// The path of the rerank endpoint of the inference service, as exposed by both TEI and vLLM
+const RERANK_SERVICE_PATH: &str = "/rerank";
+
+/// The key for the model parameter in the request body
+const MODEL_KEY: &str = "model";
+
+/// The key for the reranked documents in the response body
+const RESULTS_KEY: &str = "results";
+
+/// OpenAPI documentation structure for the rerank endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the rerank API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(rerank_handler),
+    components(schemas(RerankRequest, RerankDocument, RerankResponse, RerankResult))
+)]
+pub(crate) struct RerankOpenApi;
+
+/// Rerank documents
+///
+/// This handler forwards the request to the rerank endpoint of the embeddings service (which
+/// serves cross-encoder models), and returns the relevance score of each document to the query.
+///
+/// Inference services returning the results as a bare array (e.g. TEI) have them wrapped in
+/// the `results` field of the response, so that all responses can be signed.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing service URLs
+/// * `payload` - The rerank request body
+///
+/// # Returns
+///
+/// Returns the JSON response from the rerank service
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The rerank service request fails
+/// - Response parsing fails
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "rerank",
+    request_body = RerankRequest,
+    responses(
+        (status = OK, description = "Documents reranked successfully", body = RerankResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn rerank_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, AtomaServiceError> {
+    info!("Received rerank request, with payload: {payload}");
+    handle_rerank_request(request_metadata, &state, payload).await
+}
+
+/// OpenAPI documentation structure for the confidential rerank endpoint.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the confidential rerank API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(confidential_rerank_handler),
+    components(schemas(ConfidentialComputeRequest, ConfidentialComputeResponse))
+)]
+pub(crate) struct ConfidentialRerankOpenApi;
+
+/// Handler for confidential rerank requests
+///
+/// This endpoint processes rerank requests with additional confidential computing guarantees.
+/// It forwards the request to the rerank service and returns an encrypted response that can
+/// only be decrypted by the client.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if:
+/// * The rerank service request fails
+/// * Response processing or encryption fails
+/// * Stack compute unit updates fail
+///
+/// # Example Request
+///
+/// ```json
+/// {
+///     "model": "BAAI/bge-reranker-v2-m3",
+///     "query": "What is the capital of France?",
+///     "documents": ["Paris is the capital of France.", "Berlin is in Germany."]
+/// }
+/// ```
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "confidential-rerank",
+    request_body = ConfidentialComputeRequest,
+    responses(
+        (status = OK, description = "Confidential documents reranked successfully", body = ConfidentialComputeResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error"),
+        (status = SERVICE_UNAVAILABLE, description = "Model is overloaded, retry after the `Retry-After` delay")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = request_metadata.endpoint_path)
+)]
+pub async fn confidential_rerank_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, AtomaServiceError> {
+    info!("Received confidential rerank request");
+    handle_rerank_request(request_metadata, &state, payload).await
+}
+
+/// Serves a rerank request, plain or confidential, releasing the compute units reserved on
+/// the stack if it fails.
+async fn handle_rerank_request(
+    request_metadata: RequestMetadata,
+    state: &AppState,
+    payload: Value,
+) -> Result<Json<Value>, AtomaServiceError> {
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown");
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        session_grant_id,
+        payload_hash,
+        client_encryption_metadata,
+        endpoint_path: endpoint,
+        request_type: _,
+        json_canonicalization,
+    } = request_metadata;
+
+    RERANK_NUM_REQUESTS.with_label_values(&[model]).inc();
+    let timer = RERANK_LATENCY_METRICS
+        .with_label_values(&[model])
+        .start_timer();
+
+    match handle_rerank_response(
+        state,
+        &payload,
+        stack_small_id,
+        estimated_total_compute_units,
+        payload_hash,
+        json_canonicalization,
+        client_encryption_metadata,
+        &endpoint,
+        timer,
+    )
+    .await
+    {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            update_stack_num_compute_units(
+                &state.state_manager_sender,
+                stack_small_id,
+                session_grant_id,
+                estimated_total_compute_units,
+                0,
+                &endpoint,
+            )?;
+            Err(e)
+        }
+    }
+}
+
+/// Forwards a rerank request to the inference service, then signs its response and encrypts
+/// it for confidential requests.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InternalError` if:
+/// * Network request to the rerank service fails
+/// * Response signing or stack hash updates fail
+/// * Confidential compute encryption processing fails
+#[instrument(
+    level = "info",
+    skip(state, payload),
+    fields(path = endpoint)
+)]
+#[allow(clippy::too_many_arguments)]
+async fn handle_rerank_response(
+    state: &AppState,
+    payload: &Value,
+    stack_small_id: i64,
+    estimated_total_compute_units: i64,
+    payload_hash: [u8; 32],
+    json_canonicalization: JsonCanonicalization,
+    client_encryption_metadata: Option<EncryptionMetadata>,
+    endpoint: &str,
+    timer: HistogramTimer,
+) -> Result<Json<Value>, AtomaServiceError> {
+    let _admission_permit = admit_inference_request(state, payload, endpoint).await?;
+    let mut response_body = inference_backend(state, payload, RequestType::Rerank, endpoint)?
+        .post_json(RERANK_SERVICE_PATH, payload)
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Error sending request to rerank service: {}", e),
+            endpoint: endpoint.to_string(),
+        })?;
+    if response_body.is_array() {
+        response_body = json!({ RESULTS_KEY: response_body });
+    }
+
+    // Sign the response and update the stack hash
+    if let Err(e) = sign_response_and_update_stack_hash(
+        &mut response_body,
+        payload_hash,
+        json_canonicalization,
+        state,
+        stack_small_id,
+        estimated_total_compute_units,
+        endpoint.to_string(),
+    )
+    .await
+    {
+        return Err(AtomaServiceError::InternalError {
+            message: format!("Error signing response and updating stack hash: {}", e),
+            endpoint: endpoint.to_string(),
+        });
+    }
+
+    match handle_confidential_compute_encryption_response(
+        state,
+        response_body,
+        client_encryption_metadata,
+        endpoint.to_string(),
+    )
+    .await
+    {
+        Ok(response_body) => {
+            // Stop the timer before returning the valid response
+            timer.observe_duration();
+            Ok(Json(response_body))
+        }
+        Err(e) => Err(AtomaServiceError::InternalError {
+            message: format!(
+                "Error handling confidential compute encryption response: {}",
+                e
+            ),
+            endpoint: endpoint.to_string(),
+        }),
+    }
+}
+
+/// The body of a rerank request.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RerankRequest {
+    /// ID of the model to use.
+    model: String,
+    /// The query to rank the documents against.
+    query: String,
+    /// The documents to rank. TEI's `texts` field is accepted as well.
+    #[serde(alias = "texts")]
+    documents: Vec<RerankDocument>,
+    /// The number of most relevant documents to return.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top_n: Option<u32>,
+    /// Whether to return the documents along with their scores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    return_documents: Option<bool>,
+}
+
+/// A document to rank, either as a string or as an object with a `text` field.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum RerankDocument {
+    Text(String),
+    Object { text: String },
+}
+
+/// The response of a rerank request.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RerankResponse {
+    /// The documents, from the most to the least relevant to the query.
+    pub results: Vec<RerankResult>,
+    /// The token usage of the request, if reported by the inference service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Value>,
+}
+
+/// The relevance of a document to the query.
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RerankResult {
+    /// The index of the document in the request.
+    pub index: usize,
+    /// The relevance score of the document.
+    #[serde(alias = "score")]
+    pub relevance_score: f64,
+}
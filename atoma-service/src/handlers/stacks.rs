@@ -0,0 +1,313 @@
+use atoma_state::types::{AtomaAtomaStateManagerEvent, Stack, StackReceipt, StackSettlementTicket};
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tracing::{info, instrument};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::AtomaServiceError,
+    middleware::{utils as middleware_utils, RequestMetadata},
+    server::AppState,
+};
+
+use super::sign_response;
+
+/// The path for the stacks endpoints
+pub const STACKS_PATH: &str = "/v1/stacks";
+
+/// The path for the stack usage endpoint
+pub const STACK_USAGE_PATH: &str = "/v1/stacks/:stack_small_id/usage";
+
+/// Number of receipts returned per page, unless the request sets a `limit`
+const DEFAULT_RECEIPTS_LIMIT: i64 = 100;
+
+/// Maximum number of receipts returned per page
+const MAX_RECEIPTS_LIMIT: i64 = 1_000;
+
+/// Maximum number of receipts that can be skipped, so that the offset of the next page
+/// (and the extra receipt fetched to find it) never overflows an `i64`
+const MAX_RECEIPTS_OFFSET: i64 = i64::MAX - MAX_RECEIPTS_LIMIT - 1;
+
+/// Pagination of the receipts of a stack usage request
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StackUsageQuery {
+    /// Maximum number of receipts to return, between 1 and 1000 (defaults to 100)
+    pub limit: Option<i64>,
+    /// Number of receipts to skip, between 0 and `i64::MAX - 1001` (defaults to 0)
+    pub offset: Option<i64>,
+}
+
+/// The settlement state of a stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StackSettlementState {
+    /// The stack can be used for inference requests
+    Active,
+    /// The stack is being settled, and no longer accepts inference requests
+    InSettlePeriod,
+    /// The settlement of the stack is disputed by an attestation node
+    InDispute,
+    /// The dispute on the settlement of the stack was resolved
+    Settled,
+    /// The node claimed the funds of the stack
+    Claimed,
+}
+
+impl StackSettlementState {
+    /// Returns the settlement state of a stack, from its settlement ticket, if any
+    fn new(stack: &Stack, settlement_ticket: Option<&StackSettlementTicket>) -> Self {
+        match settlement_ticket {
+            Some(ticket) if ticket.is_claimed => Self::Claimed,
+            Some(ticket) if ticket.dispute_settled_at_epoch.is_some() => Self::Settled,
+            Some(ticket) if ticket.is_in_dispute => Self::InDispute,
+            Some(_) => Self::InSettlePeriod,
+            None if stack.in_settle_period => Self::InSettlePeriod,
+            None => Self::Active,
+        }
+    }
+}
+
+/// The receipt of a response served for a stack
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct StackUsageReceipt {
+    /// Base64 encoded Blake2b hash of the request payload
+    pub payload_hash: String,
+    /// Base64 encoded Blake2b hash of the response, as signed by the node
+    pub response_hash: String,
+    /// Number of compute units charged for the response
+    pub num_compute_units: i64,
+    /// Unix timestamp, in seconds, at which the response was served
+    pub timestamp: i64,
+}
+
+impl From<StackReceipt> for StackUsageReceipt {
+    fn from(receipt: StackReceipt) -> Self {
+        Self {
+            payload_hash: STANDARD.encode(receipt.payload_hash),
+            response_hash: STANDARD.encode(receipt.response_hash),
+            num_compute_units: receipt.num_compute_units,
+            timestamp: receipt.timestamp,
+        }
+    }
+}
+
+/// The usage of a stack, signed by the node along with a `response_hash`, as inference
+/// responses are
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct StackUsageResponse {
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// Total number of compute units of the stack
+    pub num_compute_units: i64,
+    /// Number of compute units of the stack already used or reserved
+    pub already_computed_units: i64,
+    /// Number of responses served for the stack
+    pub num_total_messages: i64,
+    /// Settlement state of the stack
+    pub settlement_state: StackSettlementState,
+    /// Receipts of the responses served for the stack, in the order they were served
+    pub receipts: Vec<StackUsageReceipt>,
+    /// Offset of the next page of receipts, if any
+    pub next_offset: Option<i64>,
+}
+
+/// OpenAPI documentation structure for the stacks endpoints.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the stacks API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(stack_usage_handler),
+    components(schemas(StackUsageResponse, StackUsageReceipt, StackSettlementState))
+)]
+pub(crate) struct StacksOpenApi;
+
+/// Stack usage
+///
+/// Returns the compute units used by a stack, its settlement state, and a page of the
+/// receipts of the responses served for it, so that the stack's owner can audit what it is
+/// charged for each request.
+///
+/// The request must be signed by the owner of the stack: session keys are not accepted. As
+/// the request has no body, its signature covers its path and query.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::AuthError` if the stack does not exist, or is not owned by
+/// the signer.
+///
+/// Returns a `AtomaServiceError::InternalError` if the stack cannot be retrieved from the
+/// state manager, or if the response cannot be signed.
+#[utoipa::path(
+    get,
+    path = "/{stack_small_id}/usage",
+    tag = "stacks",
+    params(
+        ("stack_small_id" = i64, Path, description = "Unique small integer identifier for the stack"),
+        StackUsageQuery
+    ),
+    responses(
+        (status = OK, description = "Usage of the stack", body = StackUsageResponse),
+        (status = BAD_REQUEST, description = "Invalid request"),
+        (status = UNAUTHORIZED, description = "Stack not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn stack_usage_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    Path(stack_small_id): Path<i64>,
+    Query(query): Query<StackUsageQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AtomaServiceError> {
+    let endpoint = STACK_USAGE_PATH.to_string();
+    let sui_address = middleware_utils::request_signer(&headers, &endpoint)?;
+    let stack = get_stack(&state, stack_small_id, &endpoint)
+        .await?
+        .filter(|stack| stack.owner_address == sui_address.to_string())
+        .ok_or_else(|| AtomaServiceError::AuthError {
+            auth_error: format!("Stack {stack_small_id} not found"),
+            endpoint: endpoint.clone(),
+        })?;
+    let settlement_ticket = get_stack_settlement_ticket(&state, stack_small_id, &endpoint).await?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_RECEIPTS_LIMIT)
+        .clamp(1, MAX_RECEIPTS_LIMIT);
+    let offset = query
+        .offset
+        .unwrap_or_default()
+        .clamp(0, MAX_RECEIPTS_OFFSET);
+    // NOTE: One more receipt than the limit is fetched, to know if there is a next page
+    let mut receipts =
+        get_stack_receipts(&state, stack_small_id, limit + 1, offset, &endpoint).await?;
+    let next_offset = (receipts.len() as i64 > limit).then_some(offset.saturating_add(limit));
+    receipts.truncate(limit as usize);
+    info!(
+        target = "atoma-service",
+        level = "info",
+        event = "stack-usage-handler",
+        "Returning {} receipts of stack {stack_small_id}",
+        receipts.len()
+    );
+
+    let usage = StackUsageResponse {
+        stack_small_id,
+        num_compute_units: stack.num_compute_units,
+        already_computed_units: stack.already_computed_units,
+        num_total_messages: stack.num_total_messages,
+        settlement_state: StackSettlementState::new(&stack, settlement_ticket.as_ref()),
+        receipts: receipts.into_iter().map(StackUsageReceipt::from).collect(),
+        next_offset,
+    };
+    let mut response_body = json!(usage);
+    sign_response(
+        &mut response_body,
+        request_metadata.json_canonicalization,
+        &state,
+        &endpoint,
+    )?;
+    Ok(Json(response_body))
+}
+
+/// Retrieves a stack from the state manager, without reserving any of its compute units
+pub(crate) async fn get_stack(
+    state: &AppState,
+    stack_small_id: i64,
+    endpoint: &str,
+) -> Result<Option<Stack>, AtomaServiceError> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    state
+        .state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::GetStack {
+            stack_small_id,
+            result_sender,
+        })
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to send get stack event: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to receive stack: {e}"),
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to get stack: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Retrieves the settlement ticket of a stack from the state manager, if it is being settled
+async fn get_stack_settlement_ticket(
+    state: &AppState,
+    stack_small_id: i64,
+    endpoint: &str,
+) -> Result<Option<StackSettlementTicket>, AtomaServiceError> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    state
+        .state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::GetStackSettlementTicket {
+            stack_small_id,
+            result_sender,
+        })
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to send get stack settlement ticket event: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to receive stack settlement ticket: {e}"),
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to get stack settlement ticket: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Retrieves a page of the receipts of a stack from the state manager
+async fn get_stack_receipts(
+    state: &AppState,
+    stack_small_id: i64,
+    limit: i64,
+    offset: i64,
+    endpoint: &str,
+) -> Result<Vec<StackReceipt>, AtomaServiceError> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    state
+        .state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::GetStackReceipts {
+            stack_small_id,
+            limit,
+            offset,
+            result_sender,
+        })
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to send get stack receipts event: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to receive stack receipts: {e}"),
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to get stack receipts: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+}
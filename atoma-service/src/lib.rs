@@ -2,14 +2,24 @@
 //! and supports multiple signature schemes (including ed25519, secp256k1, and secp256r1,
 //! matching SUI's supported cryptography primitives).
 
+pub mod admission;
+pub mod audio;
+pub mod backends;
+pub mod chat_template;
 pub(crate) mod components;
 pub mod config;
 pub mod error;
 pub(crate) mod handlers;
 pub mod middleware;
+pub mod mock;
+pub mod multipart;
 pub mod proxy;
+pub mod rate_limit;
+pub mod replay;
 pub mod server;
+pub mod session;
 pub mod streamer;
 #[cfg(test)]
 mod tests;
 pub mod types;
+pub mod usage;
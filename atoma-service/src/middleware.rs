@@ -1,35 +1,47 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 
 use crate::{
+    audio::{audio_duration_compute_units, estimate_audio_duration},
+    config::TaskRolesConfig,
     error::AtomaServiceError,
     handlers::{
-        chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
-        image_generations::IMAGE_GENERATIONS_PATH,
+        audio::{
+            AUDIO_TRANSCRIPTIONS_PATH, CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH,
+            CONFIDENTIAL_SPEECH_PATH, MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE, SPEECH_PATH,
+        },
+        chat_completions::{CHAT_COMPLETIONS_PATH, CONFIDENTIAL_CHAT_COMPLETIONS_PATH},
+        completions::{COMPLETIONS_PATH, CONFIDENTIAL_COMPLETIONS_PATH},
+        embeddings::{CONFIDENTIAL_EMBEDDINGS_PATH, EMBEDDINGS_PATH},
+        image_generations::{CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_GENERATIONS_PATH},
+        rerank::{CONFIDENTIAL_RERANK_PATH, RERANK_PATH},
+        stacks::get_stack,
+        update_stack_num_compute_units,
     },
+    multipart::{form_data_boundary, parse_form_data},
+    rate_limit::RateLimitError,
+    replay::{signed_request_digest, ReplayError, RequestFreshness, SignedRequest},
     server::AppState,
+    session::{SessionGrant, SessionGrantError},
     types::ConfidentialComputeRequest,
 };
 use atoma_confidential::types::{ConfidentialComputeDecryptionRequest, DH_PUBLIC_KEY_SIZE};
-use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_state::types::{AtomaAtomaStateManagerEvent, Task};
 use atoma_utils::{
     constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE, SALT_SIZE},
-    hashing::blake2b_hash,
-    verify_signature,
+    hashing::{blake2b_hash, JsonCanonicalization},
+    signature::ZkLoginVerifier,
+    signer_address, verify_signature,
 };
 use axum::{
     body::Body,
     extract::State,
-    http::{HeaderValue, Request},
+    http::{header::CONTENT_TYPE, HeaderValue, Method, Request},
     middleware::Next,
     response::Response,
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
-use serde_json::Value;
-use sui_sdk::types::{
-    base_types::SuiAddress,
-    crypto::{PublicKey, Signature, SuiSignature},
-    digests::TransactionDigest,
-};
+use serde_json::{json, Value};
+use sui_sdk::types::{base_types::SuiAddress, digests::TransactionDigest};
 use tokio::sync::oneshot;
 use tracing::instrument;
 
@@ -42,18 +54,96 @@ const MODEL: &str = "model";
 /// The key for the max tokens in the request body
 const MAX_TOKENS: &str = "max_tokens";
 
+/// The key for the max completion tokens in the request body, superseding `max_tokens`
+const MAX_COMPLETION_TOKENS: &str = "max_completion_tokens";
+
+/// The key for the signed timestamp in the request body
+const REQUEST_TIMESTAMP: &str = "request_timestamp";
+
+/// The key for the signed nonce in the request body
+const REQUEST_NONCE: &str = "request_nonce";
+
 /// The key for the messages in the request body
 const MESSAGES: &str = "messages";
 
+/// The key for the content of a message in the request body
+const CONTENT: &str = "content";
+
+/// The key for the type of a message content part in the request body
+const CONTENT_PART_TYPE: &str = "type";
+
+/// The type of text message content parts
+const TEXT_CONTENT_PART_TYPE: &str = "text";
+
+/// The type of image message content parts
+const IMAGE_URL_CONTENT_PART_TYPE: &str = "image_url";
+
+/// The key for the text of a text content part in the request body
+const TEXT: &str = "text";
+
+/// The key for the image url of an image content part in the request body
+const IMAGE_URL: &str = "image_url";
+
+/// The key for the tool calls of a message in the request body
+const TOOL_CALLS: &str = "tool_calls";
+
+/// The key for the function of a tool call in the request body
+const FUNCTION: &str = "function";
+
+/// The key for the name of a tool call function in the request body
+const FUNCTION_NAME: &str = "name";
+
+/// The key for the arguments of a tool call function in the request body
+const FUNCTION_ARGUMENTS: &str = "arguments";
+
+/// The key for the tool definitions in the request body
+const TOOLS: &str = "tools";
+
 /// The key for the input tokens in the request body
 const INPUT: &str = "input";
 
+/// The key for the prompt of a completion request in the request body
+const PROMPT: &str = "prompt";
+
+/// The key for the query in the rerank request body
+const QUERY: &str = "query";
+
+/// The key for the documents in the rerank request body
+const DOCUMENTS: &str = "documents";
+
+/// The key for the documents in TEI's rerank request body
+const TEXTS: &str = "texts";
+
+/// The key for the suffix of a completion request in the request body
+const SUFFIX: &str = "suffix";
+
+/// The key for the number of completions returned for each prompt of a completion request
+const COMPLETIONS_N: &str = "n";
+
+/// The key for the number of completions generated for each prompt of a completion request,
+/// of which the best `n` are returned
+const BEST_OF: &str = "best_of";
+
+/// Number of completion tokens of completion requests that do not specify `max_tokens`, if
+/// the model has no default, as in OpenAI's API
+const DEFAULT_COMPLETIONS_MAX_TOKENS: i64 = 16;
+
 /// The key for the image size in the request body
 const IMAGE_SIZE: &str = "size";
 
 /// The key for the number of images in the request body
 const IMAGE_N: &str = "n";
 
+/// The key for the audio file in the (multipart form) body of audio transcriptions requests
+const AUDIO_FILE: &str = "file";
+
+/// The key for the duration, in seconds, of the audio files of multipart form bodies, as
+/// estimated by the middleware
+const AUDIO_DURATION: &str = "duration";
+
+/// The task security level requiring confidential compute (trusted hardware), as registered on the Atoma contract
+const CONFIDENTIAL_COMPUTE_SECURITY_LEVEL: i64 = 2;
+
 /// Metadata for confidential compute decryption requests
 pub struct DecryptionMetadata {
     /// The plaintext body
@@ -82,6 +172,8 @@ pub struct RequestMetadata {
     pub stack_small_id: i64,
     /// The estimated total number of compute units
     pub estimated_total_compute_units: i64,
+    /// The identifier of the session key grant the compute units are reserved on, if any
+    pub session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     /// The payload hash
     pub payload_hash: [u8; 32],
     /// The type of request
@@ -90,6 +182,8 @@ pub struct RequestMetadata {
     pub client_encryption_metadata: Option<EncryptionMetadata>,
     /// endpoint path
     pub endpoint_path: String,
+    /// The JSON canonicalization used to hash the request and response bodies
+    pub json_canonicalization: JsonCanonicalization,
 }
 
 /// The type of request
@@ -99,11 +193,53 @@ pub struct RequestMetadata {
 pub enum RequestType {
     #[default]
     ChatCompletions,
+    Completions,
     Embeddings,
+    Rerank,
     ImageGenerations,
+    AudioTranscriptions,
+    Speech,
     NonInference,
 }
 
+impl RequestType {
+    /// Returns the type of the requests to the given endpoint path, either plaintext or
+    /// confidential
+    pub fn from_path(path: &str) -> Self {
+        match path {
+            CHAT_COMPLETIONS_PATH | CONFIDENTIAL_CHAT_COMPLETIONS_PATH => Self::ChatCompletions,
+            COMPLETIONS_PATH | CONFIDENTIAL_COMPLETIONS_PATH => Self::Completions,
+            EMBEDDINGS_PATH | CONFIDENTIAL_EMBEDDINGS_PATH => Self::Embeddings,
+            RERANK_PATH | CONFIDENTIAL_RERANK_PATH => Self::Rerank,
+            IMAGE_GENERATIONS_PATH | CONFIDENTIAL_IMAGE_GENERATIONS_PATH => Self::ImageGenerations,
+            AUDIO_TRANSCRIPTIONS_PATH | CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH => {
+                Self::AudioTranscriptions
+            }
+            SPEECH_PATH | CONFIDENTIAL_SPEECH_PATH => Self::Speech,
+            _ => Self::NonInference,
+        }
+    }
+
+    /// Returns the role of the tasks that can serve this type of request, as configured in
+    /// `task_roles`
+    ///
+    /// # Returns
+    /// Returns `None` for non-inference requests, which are not tied to any task, and for
+    /// requests whose task role is not configured
+    pub fn task_role(&self, task_roles: &TaskRolesConfig) -> Option<i64> {
+        match self {
+            // NOTE: Text completions are served by the same models as chat completions
+            Self::ChatCompletions | Self::Completions => task_roles.chat_completions,
+            Self::Embeddings => task_roles.embeddings,
+            Self::Rerank => task_roles.rerank,
+            Self::ImageGenerations => task_roles.image_generations,
+            Self::AudioTranscriptions => task_roles.audio_transcriptions,
+            Self::Speech => task_roles.speech,
+            Self::NonInference => None,
+        }
+    }
+}
+
 impl RequestMetadata {
     /// Create a new `RequestMetadata` with the given stack info
     pub fn with_stack_info(
@@ -116,16 +252,32 @@ impl RequestMetadata {
         self
     }
 
+    /// Create a new `RequestMetadata` with the given session key grant, whose compute units
+    /// must be reconciled along with the stack's
+    pub fn with_session_grant_id(mut self, session_grant_id: [u8; PAYLOAD_HASH_SIZE]) -> Self {
+        self.session_grant_id = Some(session_grant_id);
+        self
+    }
+
     /// Create a new `RequestMetadata` with the given payload hash
     pub fn with_payload_hash(mut self, payload_hash: [u8; PAYLOAD_HASH_SIZE]) -> Self {
         self.payload_hash = payload_hash;
         self
     }
 
+    /// Create a new `RequestMetadata` with the given JSON canonicalization
+    pub fn with_json_canonicalization(
+        mut self,
+        json_canonicalization: JsonCanonicalization,
+    ) -> Self {
+        self.json_canonicalization = json_canonicalization;
+        self
+    }
+
     /// Sets the request type for this metadata instance
     ///
     /// # Arguments
-    /// * `request_type` - The type of request (ChatCompletions, Embeddings, ImageGenerations, or NonInference)
+    /// * `request_type` - The type of request (ChatCompletions, Completions, Embeddings, ImageGenerations, or NonInference)
     ///
     /// # Returns
     /// Returns self with the updated request type for method chaining
@@ -208,19 +360,44 @@ impl RequestMetadata {
 ///   max_tokens, stop sequences).
 /// - Other fields as required by the OpenAI API specification.
 ///
+/// The request body may also contain the optional `request_timestamp` (Unix time, in seconds)
+/// and `request_nonce` fields, which are covered by its signature and used for replay
+/// protection. They are removed from the body before it is forwarded.
+///
 /// # Headers
 /// The middleware expects the following custom headers:
-/// - `X-Signature`: The signature of the request body, base64 encoded.
+/// - `X-Signature`: The Sui signature of the request body, base64 encoded. Single key,
+///   multisig and zkLogin signatures are supported, the latter only if the request carries
+///   a `ZkLoginVerifier` extension (see [`atoma_utils::verify_signature`]).
+///
+/// The request body is hashed from its compact `serde_json` serialization, unless the optional
+/// `X-Json-Canonicalization` header is set to `jcs`, in which case it is hashed from its
+/// RFC 8785 canonicalization, as are the response bodies.
+///
+/// `multipart/form-data` bodies (e.g. of audio transcriptions requests) are hashed from their
+/// raw bytes instead, and their timestamp and nonce can only be sent as headers.
+///
+/// `GET` requests have no body, so their path and query (e.g. `/v1/stacks/1/usage?limit=10`)
+/// are hashed instead, and their timestamp and nonce can only be sent as headers.
+///
+/// Alternatively to the body fields, the timestamp and nonce can be sent as the optional
+/// `X-Request-Timestamp` and `X-Request-Nonce` headers. The signature must then cover them,
+/// by signing the digest computed by [`signed_request_digest`] instead of the body hash.
 ///
 /// # Extensions
 /// This middleware adds or updates a `RequestMetadata` extension to the request containing:
 /// - `payload_hash`: The 32-byte Blake2b hash of the request body
+/// - `json_canonicalization`: The JSON canonicalization of the request and response hashes
+///
+/// It also adds a `SignedRequest` extension, identifying the request for replay protection.
 ///
 /// # Errors
 /// Returns a `BAD_REQUEST` status code if:
 /// - Required headers are missing or cannot be parsed.
 /// - The signature or public key cannot be decoded.
 /// - The request body exceeds the maximum size limit.
+/// - The timestamp or nonce is malformed, or sent both as headers and body fields.
+/// - The JSON canonicalization is not supported.
 ///
 /// Returns an `UNAUTHORIZED` status code if:
 /// - The signature verification fails.
@@ -254,37 +431,143 @@ pub async fn signature_verification_middleware(
             message: format!("Failed to convert signature to string, with error: {e}"),
             endpoint: endpoint.clone(),
         })?;
-    let body_bytes = axum::body::to_bytes(req_body, MAX_BODY_SIZE)
+    let body_bytes = axum::body::to_bytes(req_body, utils::max_body_size(&endpoint))
         .await
         .map_err(|e| AtomaServiceError::InvalidBody {
             message: format!("Failed to convert body to bytes, with error: {e}"),
             endpoint: endpoint.clone(),
         })?;
-    let body_json: Value =
-        serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to parse body as JSON, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_blake2b_hash = blake2b_hash(body_json.to_string().as_bytes());
+    let json_canonicalization =
+        utils::json_canonicalization_from_headers(&req_parts.headers, &endpoint)?;
+    let (mut body_json, body_blake2b_hash) = if req_parts.method == Method::GET {
+        let path_and_query = req_parts
+            .uri
+            .path_and_query()
+            .map_or(endpoint.as_str(), |path_and_query| path_and_query.as_str());
+        (None, blake2b_hash(path_and_query.as_bytes()))
+    } else if utils::form_data_boundary_from_headers(&req_parts.headers).is_some() {
+        (None, blake2b_hash(&body_bytes))
+    } else {
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
+                message: format!("Failed to parse body as JSON, with error: {e}"),
+                endpoint: endpoint.clone(),
+            })?;
+        let body_blake2b_hash = json_canonicalization.hash(&body_json);
+        (Some(body_json), body_blake2b_hash)
+    };
     let body_blake2b_hash_bytes: [u8; 32] = body_blake2b_hash
         .as_slice()
         .try_into()
         .expect("Invalid Blake2b hash length");
-    verify_signature(base64_signature, &body_blake2b_hash_bytes).map_err(|e| {
-        AtomaServiceError::AuthError {
-            auth_error: format!("Failed to verify signature, with error: {e}"),
-            endpoint,
+
+    let header_freshness = utils::request_freshness_from_headers(&req_parts.headers, &endpoint)?;
+    let body_freshness = match body_json.as_mut() {
+        Some(body_json) => utils::take_request_freshness_from_body(body_json, &endpoint)?,
+        None => RequestFreshness::default(),
+    };
+    // NOTE: The body fields were removed from `body_json`, which is forwarded instead
+    let forward_body_json = !body_freshness.is_empty();
+    let (freshness, signed_digest) = match (header_freshness.is_empty(), body_freshness.is_empty())
+    {
+        (false, false) => {
+            return Err(AtomaServiceError::InvalidBody {
+                message: "Request timestamp and nonce must be sent either as headers or as body fields, not both".to_string(),
+                endpoint,
+            });
         }
+        (false, true) => {
+            let signed_digest = signed_request_digest(&body_blake2b_hash_bytes, &header_freshness);
+            (header_freshness, signed_digest)
+        }
+        (true, _) => (body_freshness, body_blake2b_hash_bytes),
+    };
+    let zklogin_verifier = req_parts.extensions.get::<Arc<ZkLoginVerifier>>();
+    let signer = verify_signature(
+        base64_signature,
+        &signed_digest,
+        zklogin_verifier.map(Arc::as_ref),
+    )
+    .map_err(|e| AtomaServiceError::AuthError {
+        auth_error: format!("Failed to verify signature, with error: {e}"),
+        endpoint: endpoint.clone(),
     })?;
+    req_parts.extensions.insert(SignedRequest::new(
+        signer.as_ref(),
+        &signed_digest,
+        freshness,
+    ));
     let request_metadata = req_parts
         .extensions
         .get::<RequestMetadata>()
         .cloned()
         .unwrap_or_default()
-        .with_payload_hash(body_blake2b_hash_bytes);
+        .with_payload_hash(body_blake2b_hash_bytes)
+        .with_json_canonicalization(json_canonicalization);
     req_parts.extensions.insert(request_metadata);
-    let req = Request::from_parts(req_parts, Body::from(body_bytes));
+    let body = match body_json {
+        Some(body_json) if forward_body_json => Body::from(body_json.to_string()),
+        _ => Body::from(body_bytes),
+    };
+    let req = Request::from_parts(req_parts, body);
+
+    Ok(next.run(req).await)
+}
 
+/// Middleware rejecting replayed and stale signed requests.
+///
+/// This middleware must run after `signature_verification_middleware`, which identifies
+/// the signed request and extracts its timestamp and nonce. It checks that the timestamp,
+/// if any, is within the configured freshness window of the node's clock, and that the
+/// request was not already served. Requests carrying neither a timestamp nor a nonce are
+/// let through, unless timestamps are required by the configuration, while requests
+/// carrying a nonce without a timestamp are rejected.
+///
+/// Seen requests are recorded in the state manager's database, so that replays are
+/// rejected across restarts of the node.
+///
+/// # Errors
+/// Returns an `UNAUTHORIZED` status code if:
+/// - The timestamp is missing while required or along with a nonce, or outside of the
+///   freshness window.
+/// - The request was already served.
+///
+/// Returns an `INTERNAL_SERVER_ERROR` status code if:
+/// - The request was not verified by `signature_verification_middleware`.
+/// - The request cannot be recorded as seen.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        endpoint = %req.uri().path(),
+    )
+)]
+pub async fn replay_protection_middleware(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, AtomaServiceError> {
+    let endpoint = req.uri().path().to_string();
+    let signed_request = req.extensions().get::<SignedRequest>().ok_or_else(|| {
+        AtomaServiceError::InternalError {
+            message: "Signed request not found, its signature must be verified first".to_string(),
+            endpoint: endpoint.clone(),
+        }
+    })?;
+    state
+        .replay_guard
+        .check(signed_request, &state.state_manager_sender)
+        .await
+        .map_err(|e| match e {
+            ReplayError::StateManager(_) => AtomaServiceError::InternalError {
+                message: e.to_string(),
+                endpoint,
+            },
+            _ => AtomaServiceError::AuthError {
+                auth_error: e.to_string(),
+                endpoint,
+            },
+        })?;
     Ok(next.run(req).await)
 }
 
@@ -294,21 +577,42 @@ pub async fn signature_verification_middleware(
 /// is authorized to use the specified model and has sufficient compute units available.
 ///
 /// # Steps:
-/// 1. Extracts and validates the public key and stack ID from request headers.
+/// 1. Extracts and validates the public key and stack ID from request headers. Requests
+///    signed by a session key are served on behalf of the stack's owner, once the session
+///    grant they carry is verified.
 /// 2. Parses the request body to extract the model and messages.
 /// 3. Verifies that the requested model is supported.
-/// 4. Calculates the total number of compute units required for the request.
-/// 5. Checks if the user has an available stack with sufficient compute units.
+/// 4. Calculates the total number of compute units required for the request. For chat
+///    completions, this resolves the number of completion tokens against the model's
+///    context length and `max_tokens` bounds, rejecting or clamping requests exceeding them.
+/// 5. Checks that the request is within the rate limits of its Sui address and stack.
+/// 6. Checks that the task of the user's stack is not deprecated (or is still valid in the
+///    current epoch), and that its model and role match the requested model and endpoint.
+/// 7. Reserves the compute units of the request on the stack, if it has enough of them.
+/// 8. For requests signed by a session key, reserves the compute units on the session
+///    grant, within its compute unit cap.
 ///
 /// # Headers
 /// The middleware expects the following custom headers:
 /// - `X-Stack-Small-Id`: The ID of the stack being used for this request.
+/// - `X-Session-Grant` (optional): The base64-encoded JSON grant, signed by the stack's
+///   owner, allowing the session key that signed the request to use the stack.
+///
+/// Responses carry `x-ratelimit-*` headers with the remaining requests and tokens of the
+/// most restrictive rate limits, if any are configured.
 ///
 /// # Request Body
 /// The body should be a JSON object containing:
 /// - `model`: The name of the AI model to be used.
 /// - `messages`: An array of message objects, each containing a "content" field.
-/// - `max_tokens`: The maximum number of tokens for the AI's response.
+/// - `max_tokens` (or `max_completion_tokens`): The maximum number of tokens for the AI's
+///   response. If missing, the model's default is used.
+///
+/// Audio transcriptions requests have a `multipart/form-data` body instead, whose text fields
+/// and audio file are read as the fields of a JSON object (see `utils::form_data_to_json`).
+///
+/// Chat completion requests are forwarded with the resolved `max_tokens`, so that the
+/// inference service never generates more tokens than compute units were reserved for.
 ///
 /// # Extensions
 /// This middleware adds a `RequestMetadata` extension to the request containing:
@@ -322,10 +626,22 @@ pub async fn signature_verification_middleware(
 /// - Required headers are missing or invalid.
 /// - The request body is invalid or missing required fields.
 /// - The requested model is not supported.
+/// - The requested completion tokens exceed the model's maximum, or do not fit in its
+///   context window along with the prompt (with OpenAI-style error bodies).
+///
+/// Returns a `TOO_MANY_REQUESTS` status code, with a `Retry-After` header, if the request
+/// exceeds the rate limits of its Sui address or stack.
 ///
 /// Returns an `UNAUTHORIZED` status code if:
 /// - There's no available stack with sufficient compute units.
 /// - Fetching available stacks fails.
+/// - The stack's task is deprecated past its `valid_until_epoch`, or serves a different
+///   model or endpoint.
+/// - The session grant is invalid, expired, issued for another stack or session key, or
+///   its compute unit cap would be exceeded.
+///
+/// Rejected requests are forgotten by the replay protection, so that they can be retried
+/// with the same signature, timestamp and nonce.
 ///
 /// # Security Note
 /// This middleware is crucial for ensuring that users only consume resources they're
@@ -341,129 +657,68 @@ pub async fn verify_stack_permissions(
     state: State<AppState>,
     req: Request<Body>,
     next: Next,
+) -> Result<Response, AtomaServiceError> {
+    // NOTE: Requests rejected before being served are forgotten by the replay protection, so
+    // that they can be retried with the same signature (e.g. after a `429 Too Many Requests`)
+    let signed_request = req.extensions().get::<SignedRequest>().cloned();
+    let result = admit_request(State(state.0.clone()), req, next).await;
+    if let (Err(_), Some(signed_request)) = (&result, signed_request) {
+        state
+            .replay_guard
+            .release(&signed_request, &state.state_manager_sender);
+    }
+    result
+}
+
+/// Verifies the stack permissions of a request, and reserves its compute units, before
+/// serving it (see `verify_stack_permissions`).
+///
+/// Errors are only returned for requests that were not served.
+async fn admit_request(
+    state: State<AppState>,
+    req: Request<Body>,
+    next: Next,
 ) -> Result<Response, AtomaServiceError> {
     let (mut req_parts, req_body) = req.into_parts();
     let endpoint = req_parts.uri.path().to_string();
 
-    // Get request path to determine type
-    let request_type = match req_parts.uri.path() {
-        CHAT_COMPLETIONS_PATH => RequestType::ChatCompletions,
-        EMBEDDINGS_PATH => RequestType::Embeddings,
-        IMAGE_GENERATIONS_PATH => RequestType::ImageGenerations,
-        _ => RequestType::NonInference,
-    };
-
-    let base64_signature = req_parts
-        .headers
-        .get(atoma_utils::constants::SIGNATURE)
-        .ok_or_else(|| AtomaServiceError::MissingHeader {
-            header: atoma_utils::constants::SIGNATURE.to_string(),
-            endpoint: endpoint.clone(),
-        })?
-        .to_str()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Failed to convert signature to string, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let signature =
-        Signature::from_str(base64_signature).map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Failed to parse signature, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let public_key_bytes = signature.public_key_bytes();
-    let public_key =
-        PublicKey::try_from_bytes(signature.scheme(), public_key_bytes).map_err(|e| {
-            AtomaServiceError::InvalidHeader {
-                message: format!("Failed to extract public key from bytes, with error: {e}"),
-                endpoint: endpoint.clone(),
-            }
-        })?;
-    let sui_address = SuiAddress::from(&public_key);
-    let stack_small_id = req_parts
-        .headers
-        .get(atoma_utils::constants::STACK_SMALL_ID)
-        .ok_or_else(|| AtomaServiceError::MissingHeader {
-            header: atoma_utils::constants::STACK_SMALL_ID.to_string(),
-            endpoint: endpoint.clone(),
-        })?;
-    let stack_small_id = stack_small_id
-        .to_str()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Stack small ID cannot be converted to a string, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?
-        .parse::<i64>()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Stack small ID is not a valid integer, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_bytes = axum::body::to_bytes(req_body, MAX_BODY_SIZE)
+    let request_type = RequestType::from_path(&endpoint);
+    // NOTE: The compute unit cap of the session grant, if any, is enforced below
+    let (stack_small_id, sui_address, session_grant) =
+        utils::stack_request_signer(&state, &req_parts.headers, &endpoint)?;
+    let body_bytes = axum::body::to_bytes(req_body, utils::max_body_size(&endpoint))
         .await
         .map_err(|e| AtomaServiceError::InvalidBody {
             message: format!("Failed to convert body to bytes, with error: {e}"),
             endpoint: endpoint.clone(),
         })?;
-    let body_json: Value =
-        serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to parse body as JSON, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let model = body_json
-        .get(MODEL)
-        .ok_or_else(|| AtomaServiceError::InvalidBody {
-            message: "Model not found in body".to_string(),
-            endpoint: endpoint.clone(),
-        })?
-        .as_str()
-        .ok_or_else(|| AtomaServiceError::InvalidBody {
-            message: "Model is not a string".to_string(),
-            endpoint: endpoint.clone(),
-        })?;
-    if !state.models.contains(&model.to_string()) {
-        return Err(AtomaServiceError::InvalidBody {
-            message: format!("Model not supported, supported models: {:?}", state.models),
-            endpoint: endpoint.clone(),
-        });
-    }
+    let mut body_json: Value = match utils::form_data_boundary_from_headers(&req_parts.headers) {
+        Some(boundary) => utils::form_data_to_json(&body_bytes, &boundary, &endpoint)?,
+        None => {
+            serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
+                message: format!("Failed to parse body as JSON, with error: {e}"),
+                endpoint: endpoint.clone(),
+            })?
+        }
+    };
+    let (model, total_num_compute_units) =
+        utils::calculate_request_compute_units(&mut body_json, &request_type, &state, &endpoint)?;
 
-    let total_num_compute_units = utils::calculate_compute_units(
-        &body_json,
-        request_type.clone(),
-        &state,
-        model,
-        endpoint.clone(),
-    )?;
+    // NOTE: Rate limits are enforced before compute units are reserved on the stack, so that
+    // rejected requests never need to release them
+    let rate_limit_permit = state
+        .rate_limiters
+        .acquire(&sui_address.to_string(), total_num_compute_units)
+        .map_err(|e| utils::rate_limit_error(e, &endpoint))?;
 
-    let (result_sender, result_receiver) = oneshot::channel();
-    state
-        .state_manager_sender
-        .send(
-            AtomaAtomaStateManagerEvent::GetAvailableStackWithComputeUnits {
-                stack_small_id,
-                sui_address: sui_address.to_string(),
-                total_num_compute_units,
-                result_sender,
-            },
-        )
-        .map_err(|err| AtomaServiceError::InternalError {
-            message: format!("Failed to get available stacks: {}", err),
-            endpoint: endpoint.clone(),
-        })?;
-    let available_stack = result_receiver
-        .await
-        .map_err(|e| AtomaServiceError::AuthError {
-            auth_error: format!(
-                "Failed to get available stack with enough compute units, with error: {e}"
-            ),
-            endpoint: endpoint.clone(),
-        })?
-        .map_err(|err| AtomaServiceError::AuthError {
-            auth_error: format!(
-                "Failed to get available stack with enough compute units, with error: {err}"
-            ),
-            endpoint: endpoint.clone(),
-        })?;
-    if available_stack.is_none() {
+    // NOTE: The stack's task is checked before compute units are reserved on the stack, so
+    // that requests its task cannot serve never need to release them
+    let stack = get_stack(&state, stack_small_id, &endpoint)
+        .await?
+        .filter(|stack| stack.owner_address == sui_address.to_string());
+    let task_small_id = if let Some(stack) = &stack {
+        stack.task_small_id
+    } else {
         let tx_digest_str = req_parts
             .headers
             .get(atoma_utils::constants::TX_DIGEST)
@@ -477,13 +732,14 @@ pub async fn verify_stack_permissions(
                 endpoint: endpoint.clone(),
             })?;
         let tx_digest = TransactionDigest::from_str(tx_digest_str).unwrap();
-        let (tx_stack_small_id, compute_units) = utils::request_blockchain_for_stack(
-            &state,
-            tx_digest,
-            total_num_compute_units,
-            endpoint.clone(),
-        )
-        .await?;
+        let (tx_stack_small_id, compute_units, tx_task_small_id) =
+            utils::request_blockchain_for_stack(
+                &state,
+                tx_digest,
+                total_num_compute_units,
+                endpoint.clone(),
+            )
+            .await?;
 
         // NOTE: We need to check that the stack small id matches the one in the request
         // otherwise, the user is requesting for a different stack, which is invalid. We
@@ -498,8 +754,91 @@ pub async fn verify_stack_permissions(
                 endpoint,
             });
         }
+        tx_task_small_id as i64
+    };
+    utils::verify_stack_task(&state, task_small_id, &model, &request_type, &endpoint)
+        .await
+        .and_then(|task| utils::verify_task_security_level(&state, &task, &endpoint))?;
+    // NOTE: The stack's limits are only enforced once the signer is known to be allowed to use
+    // it, so that requests on stacks of other owners never consume their limits
+    let rate_limit_permit = state
+        .rate_limiters
+        .acquire_stack(rate_limit_permit, stack_small_id, total_num_compute_units)
+        .map_err(|e| utils::rate_limit_error(e, &endpoint))?;
+
+    if stack.is_some() {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(
+                AtomaAtomaStateManagerEvent::GetAvailableStackWithComputeUnits {
+                    stack_small_id,
+                    sui_address: sui_address.to_string(),
+                    total_num_compute_units,
+                    result_sender,
+                },
+            )
+            .map_err(|err| AtomaServiceError::InternalError {
+                message: format!("Failed to get available stacks: {}", err),
+                endpoint: endpoint.clone(),
+            })?;
+        let available_stack = result_receiver
+            .await
+            .map_err(|e| AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Failed to get available stack with enough compute units, with error: {e}"
+                ),
+                endpoint: endpoint.clone(),
+            })?
+            .map_err(|err| AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Failed to get available stack with enough compute units, with error: {err}"
+                ),
+                endpoint: endpoint.clone(),
+            })?;
+        if available_stack.is_none() {
+            return Err(AtomaServiceError::AuthError {
+                auth_error: "No available stack with enough compute units".to_string(),
+                endpoint,
+            });
+        }
     }
-    let request_metadata = req_parts
+    if let Some(session_grant) = &session_grant {
+        if let Err(e) = session_grant
+            .reserve_compute_units(total_num_compute_units, &state.state_manager_sender)
+            .await
+        {
+            update_stack_num_compute_units(
+                &state.state_manager_sender,
+                stack_small_id,
+                None,
+                total_num_compute_units,
+                0,
+                &endpoint,
+            )?;
+            return Err(match e {
+                SessionGrantError::StateManager(message) => AtomaServiceError::InternalError {
+                    message: format!("Failed to reserve session grant compute units: {message}"),
+                    endpoint,
+                },
+                e => AtomaServiceError::AuthError {
+                    auth_error: e.to_string(),
+                    endpoint,
+                },
+            });
+        }
+    }
+    // NOTE: Chat completion and completion requests carry the `max_tokens` resolved when
+    // calculating their compute units, which must be forwarded to the inference service
+    let body = if matches!(
+        request_type,
+        RequestType::ChatCompletions | RequestType::Completions
+    ) {
+        Body::from(body_json.to_string())
+    } else {
+        Body::from(body_bytes)
+    };
+    let mut request_metadata = req_parts
         .extensions
         .get::<RequestMetadata>()
         .cloned()
@@ -507,9 +846,12 @@ pub async fn verify_stack_permissions(
         .with_stack_info(stack_small_id, total_num_compute_units)
         .with_request_type(request_type)
         .with_endpoint_path(req_parts.uri.path().to_string());
+    if let Some(session_grant) = &session_grant {
+        request_metadata = request_metadata.with_session_grant_id(session_grant.grant_id);
+    }
     req_parts.extensions.insert(request_metadata);
-    let req = Request::from_parts(req_parts, Body::from(body_bytes));
-    Ok(next.run(req).await)
+    let req = Request::from_parts(req_parts, body);
+    Ok(rate_limit_permit.bind(next.run(req).await))
 }
 
 /// Middleware for handling confidential compute requests by decrypting encrypted payloads.
@@ -527,6 +869,18 @@ pub async fn verify_stack_permissions(
 /// - `X-Nonce`: Base string containing the nonce used in encryption
 /// - `X-Node-X25519-PublicKey`: Base64-encoded public key (32 bytes) for key exchange
 ///
+/// The optional `X-Json-Canonicalization` header selects the JSON canonicalization of the
+/// response hashes, as for plaintext requests.
+///
+/// The signature of the request covers its plaintext body hash, or, if the request carries
+/// the optional `X-Request-Timestamp` and `X-Request-Nonce` headers, the digest of the hash
+/// and these headers, as for plaintext requests. The middleware adds a `SignedRequest`
+/// extension, identifying the request for replay protection.
+///
+/// Plaintexts that are not JSON (e.g. the `multipart/form-data` bodies of audio
+/// transcriptions requests) set the `plaintext_content_type` of the request, which is
+/// forwarded as the `Content-Type` of the decrypted request.
+///
 /// # Request Flow
 /// 1. Extracts and validates required headers
 /// 2. Decodes the Diffie-Hellman public key from base64
@@ -566,7 +920,7 @@ pub async fn confidential_compute_middleware(
     let (mut req_parts, req_body) = req.into_parts();
 
     let endpoint = req_parts.uri.path().to_string();
-    let body_bytes = axum::body::to_bytes(req_body, MAX_BODY_SIZE)
+    let body_bytes = axum::body::to_bytes(req_body, utils::max_body_size(&endpoint))
         .await
         .map_err(|e| AtomaServiceError::InvalidBody {
             message: format!("Failed to convert body to bytes, with error: {e}"),
@@ -600,7 +954,27 @@ pub async fn confidential_compute_middleware(
         }
     })?;
 
-    utils::verify_plaintext_body_hash(&plaintext_body_hash_bytes, &req_parts.headers, &endpoint)?;
+    // NOTE: The timestamp and nonce of confidential requests can only be sent as headers, as
+    // their body is encrypted
+    let freshness = utils::request_freshness_from_headers(&req_parts.headers, &endpoint)?;
+    let signed_digest = if freshness.is_empty() {
+        plaintext_body_hash_bytes
+    } else {
+        signed_request_digest(&plaintext_body_hash_bytes, &freshness)
+    };
+    let signer = utils::verify_plaintext_body_hash(
+        &signed_digest,
+        &req_parts.headers,
+        state.zklogin_verifier.as_deref(),
+        &endpoint,
+    )?;
+    req_parts.extensions.insert(SignedRequest::new(
+        signer.as_ref(),
+        &signed_digest,
+        freshness,
+    ));
+    let json_canonicalization =
+        utils::json_canonicalization_from_headers(&req_parts.headers, &endpoint)?;
 
     match utils::decrypt_confidential_compute_request(
         &state,
@@ -624,7 +998,8 @@ pub async fn confidential_compute_middleware(
             req_parts.extensions.insert(
                 request_metadata
                     .with_client_encryption_metadata(client_dh_public_key_bytes, salt_bytes)
-                    .with_payload_hash(plaintext_body_hash_bytes),
+                    .with_payload_hash(plaintext_body_hash_bytes)
+                    .with_json_canonicalization(json_canonicalization),
             );
             let stack_small_id = confidential_compute_request.stack_small_id;
             req_parts.headers.insert(
@@ -638,6 +1013,21 @@ pub async fn confidential_compute_middleware(
                     }
                 })?,
             );
+            // NOTE: Non-JSON plaintexts (e.g. multipart form bodies) are forwarded with their
+            // content type, as the request itself is a JSON envelope
+            if let Some(plaintext_content_type) =
+                &confidential_compute_request.plaintext_content_type
+            {
+                req_parts.headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_str(plaintext_content_type).map_err(|e| {
+                        AtomaServiceError::InvalidBody {
+                            message: format!("Invalid plaintext content type, with error: {e}"),
+                            endpoint: endpoint.clone(),
+                        }
+                    })?,
+                );
+            }
             let req = Request::from_parts(req_parts, body);
             Ok(next.run(req).await)
         }
@@ -650,6 +1040,10 @@ pub async fn confidential_compute_middleware(
 
 pub(crate) mod utils {
     use hyper::HeaderMap;
+    use tokenizers::Tokenizer;
+    use tracing::warn;
+
+    use crate::{chat_template::ChatTemplate, config::ModelMetadata};
 
     use super::*;
 
@@ -665,7 +1059,7 @@ pub(crate) mod utils {
     /// * `endpoint` - The endpoint that the request was made to
     ///
     /// # Returns
-    /// * `Ok(u64)` - The number of compute units if found
+    /// * `Ok((u64, u64, u64))` - The stack small id, number of compute units and task small id, if found
     /// * `Err(AtomaServiceError)` - If the request fails, returns one of:
     ///   - `AtomaServiceError::InternalError` if channel communication fails
     ///   - `AtomaServiceError::AuthError` if no compute units are found for the transaction
@@ -685,7 +1079,7 @@ pub(crate) mod utils {
         tx_digest: TransactionDigest,
         estimated_compute_units: i64,
         endpoint: String,
-    ) -> Result<(u64, u64), AtomaServiceError> {
+    ) -> Result<(u64, u64, u64), AtomaServiceError> {
         let (result_sender, result_receiver) = oneshot::channel();
         state
             .stack_retrieve_sender
@@ -700,8 +1094,8 @@ pub(crate) mod utils {
                 message: "Failed to receive compute units".to_string(),
                 endpoint: endpoint.clone(),
             })?;
-        if let (Some(stack_small_id), Some(compute_units)) = result {
-            Ok((stack_small_id, compute_units))
+        if let (Some(stack_small_id), Some(compute_units), Some(task_small_id)) = result {
+            Ok((stack_small_id, compute_units, task_small_id))
         } else {
             Err(AtomaServiceError::AuthError {
                 auth_error: "No compute units found for transaction".to_string(),
@@ -710,156 +1104,1073 @@ pub(crate) mod utils {
         }
     }
 
-    /// Calculates the total number of compute units required for a request based on its type and content.
+    /// Verifies that a stack's task can serve the incoming request.
+    ///
+    /// A stack is bought for a specific task, which is tied to a single model and role
+    /// (e.g. chat completions, embeddings or image generations). This function retrieves
+    /// the task from the state manager and checks that:
+    /// - The task is not deprecated, or the current Sui epoch has not passed its
+    ///   `valid_until_epoch`
+    /// - The task's model matches the requested model
+    /// - The task's role matches the request type, for inference requests
+    ///
+    /// Otherwise, a stack bought for one model or endpoint could be drained by requests
+    /// to a different model or endpoint.
     ///
     /// # Arguments
-    /// * `body_json` - The parsed JSON body of the request containing model-specific parameters
-    /// * `request_type` - The type of request (ChatCompletions, Embeddings, ImageGenerations, or NonInference)
-    /// * `state` - Application state containing model configurations and tokenizers
-    /// * `model` - The name of the AI model being used
+    /// * `state` - Application state containing the state manager channel
+    /// * `task_small_id` - The small id of the stack's task
+    /// * `model` - The model requested in the body of the request
+    /// * `request_type` - The type of the request, derived from the endpoint path
     /// * `endpoint` - The endpoint that the request was made to
     ///
     /// # Returns
-    /// * `Ok(i64)` - The total number of compute units required
-    /// * `Err(AtomaServiceError)` - If there's an error calculating the units, returns an appropriate HTTP status code
-    ///
-    /// # Compute Unit Calculation
-    /// The calculation varies by request type:
-    /// - ChatCompletions: Based on input tokens + max output tokens
-    /// - Embeddings: Based on input text length
-    /// - ImageGenerations: Based on image dimensions and quantity
-    /// - NonInference: Returns 0 (no compute units required)
+    /// * `Ok(Task)` - The stack's task, if it can serve the request
+    /// * `Err(AtomaServiceError)` - If the request fails, returns one of:
+    ///   - `AtomaServiceError::InternalError` if channel communication fails
+    ///   - `AtomaServiceError::AuthError` if the task cannot be retrieved, is no longer
+    ///     valid, or does not match the requested model or endpoint
     ///
-    /// This function delegates to specific calculators based on the request type:
-    /// - `calculate_chat_completion_compute_units`
-    /// - `calculate_embedding_compute_units`
-    /// - `calculate_image_generation_compute_units`
-    pub(crate) fn calculate_compute_units(
-        body_json: &Value,
-        request_type: RequestType,
+    /// # Note
+    /// Deprecated tasks without a `valid_until_epoch` are rejected, as are all deprecated
+    /// tasks until the current epoch is first fetched from the Sui network.
+    #[instrument(level = "trace", skip_all, fields(task_small_id = %task_small_id))]
+    pub(crate) async fn verify_stack_task(
         state: &AppState,
+        task_small_id: i64,
         model: &str,
-        endpoint: String,
-    ) -> Result<i64, AtomaServiceError> {
-        match request_type {
-            RequestType::ChatCompletions => {
-                calculate_chat_completion_compute_units(body_json, state, model, endpoint)
-            }
-            RequestType::Embeddings => {
-                calculate_embedding_compute_units(body_json, state, model, endpoint)
-            }
-            RequestType::ImageGenerations => {
-                calculate_image_generation_compute_units(body_json, endpoint)
-            }
-            RequestType::NonInference => Ok(0),
-        }
-    }
-
-    /// Calculates the total number of compute units required for a chat completion request.
-    ///
-    /// This function analyzes the request body to determine the total computational cost by:
-    /// 1. Counting tokens in all input messages
-    /// 2. Adding safety margins for message formatting
-    /// 3. Including the requested maximum output tokens
-    ///
-    /// # Arguments
-    /// * `body_json` - The parsed JSON body of the request containing:
-    ///   - `messages`: Array of message objects with "content" fields
-    ///   - `max_tokens`: Maximum number of tokens for the model's response
-    /// * `state` - Application state containing model configurations and tokenizers
+        request_type: &RequestType,
+        endpoint: &str,
+    ) -> Result<Task, AtomaServiceError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::GetTask {
+                task_small_id,
+                result_sender,
+            })
+            .map_err(|err| AtomaServiceError::InternalError {
+                message: format!("Failed to get stack task: {}", err),
+                endpoint: endpoint.to_string(),
+            })?;
+        let task = result_receiver
+            .await
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Failed to receive stack task, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?
+            .map_err(|err| AtomaServiceError::AuthError {
+                auth_error: format!("Failed to get stack task, with error: {err}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        let current_epoch = *state.current_epoch_receiver.borrow();
+        if !is_task_valid(&task, current_epoch) {
+            return Err(AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Stack task {} is deprecated, and no longer valid",
+                    task.task_small_id
+                ),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        if task.model_name.as_deref() != Some(model) {
+            return Err(AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Stack task {} does not serve model {model}",
+                    task.task_small_id
+                ),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        if let Some(role) = request_type.task_role(&state.task_roles) {
+            if task.role != role {
+                return Err(AtomaServiceError::AuthError {
+                    auth_error: format!(
+                        "Stack task {} has role {}, but the endpoint requires role {role}",
+                        task.task_small_id, task.role
+                    ),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+        }
+        Ok(task)
+    }
+
+    /// Returns whether a task can still serve requests in the current epoch, if known.
+    ///
+    /// Deprecated tasks remain valid until the end of their `valid_until_epoch`.
+    fn is_task_valid(task: &Task, current_epoch: Option<u64>) -> bool {
+        if !task.is_deprecated {
+            return true;
+        }
+        match (task.valid_until_epoch, current_epoch) {
+            (Some(valid_until_epoch), Some(current_epoch)) => {
+                u64::try_from(valid_until_epoch).is_ok_and(|epoch| current_epoch <= epoch)
+            }
+            _ => false,
+        }
+    }
+
+    /// Verifies that the request satisfies the security level required by the stack's task.
+    ///
+    /// Stacks bought for a confidential compute task can only be used through the
+    /// `/v1/confidential/*` endpoints, and only while the node's current public key is
+    /// backed by a TEE remote attestation. Other security levels are not enforced here.
+    ///
+    /// # Arguments
+    /// * `state` - Application state containing the TEE attestation status
+    /// * `task` - The stack's task
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the request satisfies the task's security level
+    /// * `Err(AtomaServiceError::InsufficientSecurityLevel)` - If the task requires confidential
+    ///   compute, but the endpoint is not confidential or the node has no valid TEE attestation
+    #[instrument(level = "trace", skip_all, fields(task_small_id = %task.task_small_id))]
+    pub(crate) fn verify_task_security_level(
+        state: &AppState,
+        task: &Task,
+        endpoint: &str,
+    ) -> Result<(), AtomaServiceError> {
+        if task.security_level != CONFIDENTIAL_COMPUTE_SECURITY_LEVEL {
+            return Ok(());
+        }
+        if !matches!(
+            endpoint,
+            CONFIDENTIAL_CHAT_COMPLETIONS_PATH
+                | CONFIDENTIAL_COMPLETIONS_PATH
+                | CONFIDENTIAL_EMBEDDINGS_PATH
+                | CONFIDENTIAL_RERANK_PATH
+                | CONFIDENTIAL_IMAGE_GENERATIONS_PATH
+                | CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH
+                | CONFIDENTIAL_SPEECH_PATH
+        ) {
+            return Err(AtomaServiceError::InsufficientSecurityLevel {
+                message: format!(
+                    "Stack task {} requires confidential compute, use the /v1/confidential endpoints instead",
+                    task.task_small_id
+                ),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        if !*state.tee_attestation_receiver.borrow() {
+            return Err(AtomaServiceError::InsufficientSecurityLevel {
+                message: format!(
+                    "Stack task {} requires confidential compute, but the node has no valid TEE attestation",
+                    task.task_small_id
+                ),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the signer of a signed request, from its signature header.
+    ///
+    /// For multisig and zkLogin signatures, the address is derived from the multisig public key
+    /// and the zkLogin inputs, respectively.
+    ///
+    /// # Errors
+    /// * `AtomaServiceError::MissingHeader` if the signature is missing
+    /// * `AtomaServiceError::InvalidHeader` if the signature cannot be parsed
+    pub(crate) fn request_signer(
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<SuiAddress, AtomaServiceError> {
+        let base64_signature = headers
+            .get(atoma_utils::constants::SIGNATURE)
+            .ok_or_else(|| AtomaServiceError::MissingHeader {
+                header: atoma_utils::constants::SIGNATURE.to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_str()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Failed to convert signature to string, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        signer_address(base64_signature).map_err(|e| AtomaServiceError::InvalidHeader {
+            message: format!("Failed to derive signer address, with error: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+    }
+
+    /// Converts an error of the rate limiters into the error returned for the request
+    pub(crate) fn rate_limit_error(error: RateLimitError, endpoint: &str) -> AtomaServiceError {
+        match error {
+            RateLimitError::Exceeded {
+                message,
+                retry_after,
+                status,
+            } => AtomaServiceError::RateLimitExceeded {
+                message,
+                retry_after,
+                rate_limit_status: status,
+                endpoint: endpoint.to_string(),
+            },
+            e => AtomaServiceError::InternalError {
+                message: format!("Failed to apply rate limits: {e}"),
+                endpoint: endpoint.to_string(),
+            },
+        }
+    }
+
+    /// Extracts the stack and the signer of a signed request from its headers.
+    ///
+    /// The owner of the stacks is the signer's address, which for multisig and zkLogin
+    /// signatures is derived from the multisig public key and the zkLogin inputs, respectively.
+    /// Requests signed by a session key carry a grant signed by the stack's owner, on whose
+    /// behalf the request is then served.
+    ///
+    /// # Returns
+    /// * `Ok((i64, SuiAddress, Option<SessionGrant>))` - The stack small id, the address on
+    ///   whose behalf the request is served, and the verified session grant, if any
+    /// * `Err(AtomaServiceError)` - One of:
+    ///   - `AtomaServiceError::MissingHeader` if the signature or stack small id is missing
+    ///   - `AtomaServiceError::InvalidHeader` if a header cannot be parsed
+    ///   - `AtomaServiceError::AuthError` if the session grant is invalid
+    pub(crate) fn stack_request_signer(
+        state: &AppState,
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<(i64, SuiAddress, Option<SessionGrant>), AtomaServiceError> {
+        let signer = request_signer(headers, endpoint)?;
+        let stack_small_id = headers
+            .get(atoma_utils::constants::STACK_SMALL_ID)
+            .ok_or_else(|| AtomaServiceError::MissingHeader {
+                header: atoma_utils::constants::STACK_SMALL_ID.to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_str()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Stack small ID cannot be converted to a string, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?
+            .parse::<i64>()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Stack small ID is not a valid integer, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        let session_grant = headers
+            .get(atoma_utils::constants::SESSION_GRANT)
+            .map(|value| {
+                let value = value
+                    .to_str()
+                    .map_err(|e| AtomaServiceError::InvalidHeader {
+                        message: format!(
+                            "Session grant cannot be converted to a string, with error: {e}"
+                        ),
+                        endpoint: endpoint.to_string(),
+                    })?;
+                SessionGrant::from_header(value)
+                    .and_then(|grant| {
+                        grant.verify(signer, stack_small_id, state.zklogin_verifier.as_deref())
+                    })
+                    .map_err(|e| AtomaServiceError::AuthError {
+                        auth_error: format!("Invalid session grant, with error: {e}"),
+                        endpoint: endpoint.to_string(),
+                    })
+            })
+            .transpose()?;
+        let sui_address = session_grant
+            .as_ref()
+            .map_or(signer, |grant| grant.owner_address);
+        Ok((stack_small_id, sui_address, session_grant))
+    }
+
+    /// Returns the model of a request, and the total number of compute units it requires.
+    ///
+    /// This checks that the model is served by the node, before calculating the compute units
+    /// of the request with `calculate_compute_units`, which may update its body.
+    ///
+    /// # Errors
+    /// Returns `AtomaServiceError::InvalidBody` if the model is missing or not supported, or
+    /// any of the errors of `calculate_compute_units`.
+    pub(crate) fn calculate_request_compute_units(
+        body_json: &mut Value,
+        request_type: &RequestType,
+        state: &AppState,
+        endpoint: &str,
+    ) -> Result<(String, i64), AtomaServiceError> {
+        let model = body_json
+            .get(MODEL)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Model not found in body".to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .as_str()
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Model is not a string".to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_string();
+        if !state.models.contains(&model) {
+            return Err(AtomaServiceError::InvalidBody {
+                message: format!("Model not supported, supported models: {:?}", state.models),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        let total_num_compute_units = calculate_compute_units(
+            body_json,
+            request_type.clone(),
+            state,
+            &model,
+            endpoint.to_string(),
+        )?;
+        Ok((model, total_num_compute_units))
+    }
+
+    /// Calculates the total number of compute units required for a request based on its type and content.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request containing model-specific parameters
+    /// * `request_type` - The type of request (ChatCompletions, Completions, Embeddings, Rerank,
+    ///   ImageGenerations, AudioTranscriptions, Speech, or NonInference)
+    /// * `state` - Application state containing model configurations and tokenizers
+    /// * `model` - The name of the AI model being used
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The total number of compute units required
+    /// * `Err(AtomaServiceError)` - If there's an error calculating the units, returns an appropriate HTTP status code
+    ///
+    /// # Compute Unit Calculation
+    /// The calculation varies by request type:
+    /// - ChatCompletions: Based on input tokens + max output tokens
+    /// - Embeddings: Based on input text length
+    /// - Rerank: Based on the query tokens times the number of documents, plus the documents' tokens
+    /// - ImageGenerations: Based on image dimensions and quantity
+    /// - AudioTranscriptions: Based on the duration of the audio file
+    /// - Speech: Based on the number of characters of the input text
+    /// - NonInference: Returns 0 (no compute units required)
+    ///
+    /// This function delegates to specific calculators based on the request type:
+    /// - `calculate_chat_completion_compute_units`
+    /// - `calculate_embedding_compute_units`
+    /// - `calculate_rerank_compute_units`
+    /// - `calculate_image_generation_compute_units`
+    /// - `calculate_audio_transcription_compute_units`
+    /// - `calculate_speech_compute_units`
+    pub(crate) fn calculate_compute_units(
+        body_json: &mut Value,
+        request_type: RequestType,
+        state: &AppState,
+        model: &str,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        match request_type {
+            RequestType::ChatCompletions => {
+                calculate_chat_completion_compute_units(body_json, state, model, endpoint)
+            }
+            RequestType::Completions => {
+                calculate_completion_compute_units(body_json, state, model, endpoint)
+            }
+            RequestType::Embeddings => {
+                calculate_embedding_compute_units(body_json, state, model, endpoint)
+            }
+            RequestType::Rerank => {
+                calculate_rerank_compute_units(body_json, state, model, endpoint)
+            }
+            RequestType::ImageGenerations => {
+                calculate_image_generation_compute_units(body_json, endpoint)
+            }
+            RequestType::AudioTranscriptions => {
+                calculate_audio_transcription_compute_units(body_json, endpoint)
+            }
+            RequestType::Speech => calculate_speech_compute_units(body_json, endpoint),
+            RequestType::NonInference => Ok(0),
+        }
+    }
+
+    /// Calculates the total number of compute units required for a chat completion request.
+    ///
+    /// This function analyzes the request body to determine the total computational cost by:
+    /// 1. Counting tokens in all input messages, including content parts and tool calls
+    /// 2. Counting tokens in the tool definitions, if any
+    /// 3. Adding safety margins for message formatting
+    /// 4. Including the maximum output tokens, resolved against the model's token limits
+    ///    (see `resolve_max_tokens`)
+    ///
+    /// The resolved maximum output tokens are written back to the body as `max_tokens`
+    /// (replacing `max_completion_tokens`, if any), so that the request forwarded to the
+    /// inference service matches the reserved compute units.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request containing:
+    ///   - `messages`: Array of message objects with optional "content" and "tool_calls" fields
+    ///   - `tools`: Optional array of tool definitions
+    ///   - `max_tokens` or `max_completion_tokens`: Optional maximum number of tokens for the
+    ///     model's response
+    /// * `state` - Application state containing model configurations and tokenizers
+    /// * `model` - The name of the AI model being used
+    /// * `endpoint` - The endpoint that the request was made to
+    /// # Returns
+    /// * `Ok(i64)` - The total number of compute units required
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if:
+    ///   - The model is not supported
+    ///   - Required fields are missing
+    ///   - Message content, tool calls or tools are invalid
+    ///   - Tokenization fails
+    ///
+    ///   Or any of the errors of `resolve_max_tokens`, if the maximum output tokens are invalid
+    ///
+    /// # Token Calculation
+    /// For each message:
+    /// - Base tokens: Number of tokens in the message content, which is either a string or an
+    ///   array of content parts. Text parts are tokenized, while each image part costs a fixed
+    ///   number of tokens (`AppState::num_tokens_per_image`)
+    /// - Tool call tokens: Number of tokens in the name and arguments of each tool call
+    /// - +2 tokens: Safety margin for message delimiters
+    /// - +1 token: Safety margin for role name
+    ///
+    /// Plus the number of tokens in the JSON definition of each tool.
+    ///
+    /// If the model has a chat template, this per-message estimate is replaced by the exact
+    /// number of tokens of the prompt rendered by the template (see `count_chat_template_tokens`),
+    /// so that the reserved compute units match the usage later reported by the inference service.
+    ///
+    /// Finally, the resolved max_tokens for the response are added
+    ///
+    /// # Example JSON Structure
+    /// ```json
+    /// {
+    ///     "messages": [
+    ///         {"role": "user", "content": [
+    ///             {"type": "text", "text": "What is in this image?"},
+    ///             {"type": "image_url", "image_url": {"url": "https://example.com/image.png"}}
+    ///         ]},
+    ///         {"role": "assistant", "content": "I'm doing well, thank you!"}
+    ///     ],
+    ///     "max_tokens": 100
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) fn calculate_chat_completion_compute_units(
+        body_json: &mut Value,
+        state: &AppState,
+        model: &str,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        let tokenizer_index = state
+            .models
+            .iter()
+            .position(|m| m == model)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Model not supported".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        let tokenizer = &state.tokenizers[tokenizer_index];
+
+        let messages = body_json
+            .get(MESSAGES)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Messages not found in body".to_string(),
+                endpoint: endpoint.clone(),
+            })?
+            .as_array()
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Messages is not an array".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+
+        // NOTE: The per-message estimate also validates the messages, so it is always computed,
+        // even if it is superseded by the exact count from the model's chat template
+        let mut total_num_compute_units = 0;
+        for message in messages {
+            total_num_compute_units +=
+                count_message_content_tokens(message, tokenizer, state, &endpoint)?;
+            total_num_compute_units +=
+                count_message_tool_calls_tokens(message, tokenizer, &endpoint)?;
+            // add 2 tokens as a safety margin, for start and end message delimiters
+            total_num_compute_units += 2;
+            // add 1 token as a safety margin, for the role name of the message
+            total_num_compute_units += 1;
+        }
+
+        let tools = body_json.get(TOOLS).filter(|tools| !tools.is_null());
+        if let Some(tools) = tools {
+            let tools = tools
+                .as_array()
+                .ok_or_else(|| AtomaServiceError::InvalidBody {
+                    message: "Tools is not an array".to_string(),
+                    endpoint: endpoint.clone(),
+                })?;
+            for tool in tools {
+                // NOTE: Chat templates render tool definitions as JSON in the prompt
+                total_num_compute_units += count_tokens(tokenizer, &tool.to_string(), &endpoint)?;
+            }
+        }
+
+        if let Some(chat_template) = state
+            .chat_templates
+            .get(tokenizer_index)
+            .and_then(Option::as_ref)
+        {
+            match count_chat_template_tokens(chat_template, messages, tools, tokenizer, state) {
+                Ok(num_prompt_tokens) => total_num_compute_units = num_prompt_tokens,
+                Err(e) => {
+                    // NOTE: Fall back to the per-message estimate, as templates relying on
+                    // Python features that the renderer does not support might fail to render
+                    warn!(
+                        target = "atoma-service",
+                        level = "warn",
+                        endpoint = endpoint,
+                        "Failed to count prompt tokens with the chat template of model {model}, with error: {e}"
+                    );
+                }
+            }
+        }
+
+        let model_metadata = state.model_metadata.iter().find(|m| m.model == model);
+        let max_tokens = resolve_max_tokens(
+            body_json,
+            total_num_compute_units,
+            MESSAGES,
+            model_metadata,
+            &endpoint,
+        )?;
+        if let Some(body) = body_json.as_object_mut() {
+            body.remove(MAX_COMPLETION_TOKENS);
+            body.insert(MAX_TOKENS.to_string(), max_tokens.into());
+        }
+        total_num_compute_units += max_tokens;
+
+        Ok(total_num_compute_units)
+    }
+
+    /// Resolves the maximum number of output tokens of a chat completion or completion request,
+    /// against the token limits of the requested model.
+    ///
+    /// The requested number of tokens is read from `max_completion_tokens`, or from the legacy
+    /// `max_tokens` otherwise. Requests specifying neither get the model's `default_max_tokens`,
+    /// bounded by its `max_tokens` and the context length left after the prompt.
+    ///
+    /// Requests explicitly asking for more tokens than the model's `max_tokens`, or than the
+    /// context length left after the prompt, are rejected with OpenAI-style errors, unless the
+    /// model is configured with `clamp_max_tokens`, in which case they are clamped.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request
+    /// * `num_prompt_tokens` - The number of prompt tokens of the request
+    /// * `prompt_param` - The request parameter holding the prompt, either `messages` or `prompt`
+    /// * `model_metadata` - The token limits of the requested model, if any
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The maximum number of output tokens to reserve compute units for
+    /// * `Err(AtomaServiceError)` - If:
+    ///   - `AtomaServiceError::InvalidParameter` if the requested number of tokens is not a
+    ///     positive integer, or exceeds the model's `max_tokens`
+    ///   - `AtomaServiceError::ContextLengthExceeded` if the prompt and the requested number
+    ///     of tokens do not fit in the model's context window
+    ///   - `AtomaServiceError::InvalidBody` if the request specifies no number of tokens, and
+    ///     the model has no limits to derive a default from
+    fn resolve_max_tokens(
+        body_json: &Value,
+        num_prompt_tokens: i64,
+        prompt_param: &str,
+        model_metadata: Option<&ModelMetadata>,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        let default_model_metadata = ModelMetadata::default();
+        let model_metadata = model_metadata.unwrap_or(&default_model_metadata);
+
+        let requested_max_tokens = [MAX_COMPLETION_TOKENS, MAX_TOKENS]
+            .into_iter()
+            .find_map(|param| {
+                body_json
+                    .get(param)
+                    .filter(|value| !value.is_null())
+                    .map(|value| (param, value))
+            })
+            .map(|(param, value)| {
+                value
+                    .as_i64()
+                    .filter(|max_tokens| *max_tokens > 0)
+                    .map(|max_tokens| (param, max_tokens))
+                    .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                        message: format!(
+                            "Invalid value for '{param}': {value}. It must be a positive integer."
+                        ),
+                        param: param.to_string(),
+                        endpoint: endpoint.to_string(),
+                    })
+            })
+            .transpose()?;
+
+        let remaining_context_length = match model_metadata.context_length {
+            Some(context_length) if num_prompt_tokens >= context_length => {
+                return Err(AtomaServiceError::ContextLengthExceeded {
+                    message: format!(
+                        "This model's maximum context length is {context_length} tokens. However, your {prompt_param} resulted in {num_prompt_tokens} tokens. Please reduce the length of the {prompt_param}."
+                    ),
+                    param: prompt_param.to_string(),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+            Some(context_length) => Some(context_length - num_prompt_tokens),
+            None => None,
+        };
+
+        let Some((param, mut max_tokens)) = requested_max_tokens else {
+            // NOTE: Defaults are always bounded by the model's limits, as the request did not
+            // ask for a specific number of tokens
+            return model_metadata
+                .default_max_tokens
+                .into_iter()
+                .chain(model_metadata.max_tokens)
+                .chain(remaining_context_length)
+                .min()
+                .ok_or_else(|| AtomaServiceError::InvalidBody {
+                    message: "Max tokens not found in body".to_string(),
+                    endpoint: endpoint.to_string(),
+                });
+        };
+
+        if let Some(limit) = model_metadata
+            .max_tokens
+            .filter(|limit| max_tokens > *limit)
+        {
+            if !model_metadata.clamp_max_tokens {
+                return Err(AtomaServiceError::InvalidParameter {
+                    message: format!(
+                        "'{param}' is too large: {max_tokens}. This model supports at most {limit} completion tokens, whereas you provided {max_tokens}."
+                    ),
+                    param: param.to_string(),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+            max_tokens = limit;
+        }
+
+        if let Some(remaining_context_length) =
+            remaining_context_length.filter(|remaining| max_tokens > *remaining)
+        {
+            if !model_metadata.clamp_max_tokens {
+                return Err(AtomaServiceError::ContextLengthExceeded {
+                    message: format!(
+                        "This model's maximum context length is {} tokens. However, you requested {} tokens ({num_prompt_tokens} in the {prompt_param}, {max_tokens} in the completion). Please reduce the length of the {prompt_param} or completion.",
+                        num_prompt_tokens + remaining_context_length,
+                        num_prompt_tokens + max_tokens,
+                    ),
+                    param: param.to_string(),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+            max_tokens = remaining_context_length;
+        }
+
+        Ok(max_tokens)
+    }
+
+    /// Calculates the total number of compute units required for a (legacy) completion request.
+    ///
+    /// The prompt can be a string, an array of strings, an array of token IDs, or an array of
+    /// arrays of token IDs, in which case each string or token array is a separate prompt that
+    /// gets its own completion. String prompts are tokenized, while token arrays are counted
+    /// as is. The tokens of the `suffix`, if any, are added to each prompt.
+    ///
+    /// Each prompt gets `max(n, best_of)` completions generated, each of which is charged the
+    /// maximum number of output tokens.
+    ///
+    /// The maximum number of output tokens is resolved against the longest prompt, as for chat
+    /// completions (see `resolve_max_tokens`), defaulting to 16 tokens as in OpenAI's API if
+    /// neither the request nor the model specify it. It is written back to the body as
+    /// `max_tokens`, so that the request forwarded to the inference service matches the
+    /// reserved compute units.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request containing:
+    ///   - `prompt`: The prompt(s) to complete
+    ///   - `suffix`: Optional text following the completion
+    ///   - `max_tokens`: Optional maximum number of tokens of each completion
+    ///   - `n`: Optional number of completions returned for each prompt (defaults to 1)
+    ///   - `best_of`: Optional number of completions generated for each prompt, at least `n`
+    /// * `state` - Application state containing model configurations and tokenizers
     /// * `model` - The name of the AI model being used
     /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The total number of compute units required, that is the number of tokens
+    ///   of all prompts, plus the maximum number of output tokens of each of their completions
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if:
+    ///   - The model is not supported
+    ///   - The prompt is missing, empty or malformed
+    ///   - Tokenization fails
+    ///
+    ///   AtomaServiceError::InvalidParameter if `n` or `best_of` is not a positive integer,
+    ///   or `best_of` is less than `n`
+    ///
+    ///   Or any of the errors of `resolve_max_tokens`, if the maximum output tokens are invalid
+    ///
+    /// # Example JSON Structure
+    /// ```json
+    /// {
+    ///     "prompt": ["def fibonacci(n):", [1, 2, 3]],
+    ///     "max_tokens": 100
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip_all)]
+    fn calculate_completion_compute_units(
+        body_json: &mut Value,
+        state: &AppState,
+        model: &str,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        let tokenizer_index = state
+            .models
+            .iter()
+            .position(|m| m == model)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Model not supported".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        let tokenizer = &state.tokenizers[tokenizer_index];
+
+        let prompt = body_json
+            .get(PROMPT)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Prompt not found in body".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        let prompts_num_tokens = count_prompts_tokens(prompt, tokenizer, &endpoint)?;
+        let num_suffix_tokens = match body_json.get(SUFFIX) {
+            None | Some(Value::Null) => 0,
+            Some(Value::String(suffix)) => count_tokens(tokenizer, suffix, &endpoint)?,
+            Some(_) => {
+                return Err(AtomaServiceError::InvalidBody {
+                    message: "Suffix is not a string".to_string(),
+                    endpoint,
+                })
+            }
+        };
+        let num_prompts = prompts_num_tokens.len() as i64;
+        let num_completions = resolve_num_completions(body_json, &endpoint)?;
+        let max_num_prompt_tokens =
+            prompts_num_tokens.iter().max().copied().unwrap_or_default() + num_suffix_tokens;
+
+        let model_metadata = state
+            .model_metadata
+            .iter()
+            .find(|m| m.model == model)
+            .cloned()
+            .unwrap_or_default();
+        let model_metadata = ModelMetadata {
+            default_max_tokens: model_metadata
+                .default_max_tokens
+                .or(Some(DEFAULT_COMPLETIONS_MAX_TOKENS)),
+            ..model_metadata
+        };
+        let max_tokens = resolve_max_tokens(
+            body_json,
+            max_num_prompt_tokens,
+            PROMPT,
+            Some(&model_metadata),
+            &endpoint,
+        )?;
+        if let Some(body) = body_json.as_object_mut() {
+            body.insert(MAX_TOKENS.to_string(), max_tokens.into());
+        }
+
+        // NOTE: Large `n` or `best_of` saturate the compute units, so that no stack can serve
+        // the request
+        Ok(prompts_num_tokens
+            .iter()
+            .sum::<i64>()
+            .saturating_add(num_prompts.saturating_mul(
+                num_suffix_tokens.saturating_add(num_completions.saturating_mul(max_tokens)),
+            )))
+    }
+
+    /// Resolves the number of completions generated for each prompt of a completion request,
+    /// that is `best_of` if set, or else `n`.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The number of completions generated for each prompt, 1 by default
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidParameter if `n` or `best_of`
+    ///   is not a positive integer, or `best_of` is less than `n`
+    fn resolve_num_completions(
+        body_json: &Value,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        let positive_param = |param: &str| {
+            body_json
+                .get(param)
+                .filter(|value| !value.is_null())
+                .map(|value| {
+                    value.as_i64().filter(|value| *value > 0).ok_or_else(|| {
+                        AtomaServiceError::InvalidParameter {
+                            message: format!(
+                                "Invalid value for '{param}': {value}. It must be a positive integer."
+                            ),
+                            param: param.to_string(),
+                            endpoint: endpoint.to_string(),
+                        }
+                    })
+                })
+                .transpose()
+        };
+        let n = positive_param(COMPLETIONS_N)?.unwrap_or(1);
+        let best_of = positive_param(BEST_OF)?;
+        if let Some(best_of) = best_of.filter(|best_of| *best_of < n) {
+            return Err(AtomaServiceError::InvalidParameter {
+                message: format!(
+                    "Invalid value for '{BEST_OF}': {best_of}. It must be greater than or equal to '{COMPLETIONS_N}' ({n})."
+                ),
+                param: BEST_OF.to_string(),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        Ok(best_of.unwrap_or(n))
+    }
+
+    /// Counts the number of tokens of each prompt of a completion request.
+    ///
+    /// # Arguments
+    /// * `prompt` - The `prompt` of the request body, either a string, an array of strings, an
+    ///   array of token IDs, or an array of arrays of token IDs
+    /// * `tokenizer` - The tokenizer of the requested model
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(Vec<i64>)` - The number of tokens of each prompt
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if the prompt is empty or
+    ///   malformed, or tokenization fails
+    fn count_prompts_tokens(
+        prompt: &Value,
+        tokenizer: &Tokenizer,
+        endpoint: &str,
+    ) -> Result<Vec<i64>, AtomaServiceError> {
+        let invalid_prompt = || AtomaServiceError::InvalidBody {
+            message: "Prompt must be a string, a token array, or a non-empty array of either"
+                .to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        let is_token_array = |tokens: &[Value]| tokens.iter().all(|token| token.as_u64().is_some());
+        let prompts_num_tokens = match prompt {
+            Value::String(text) => vec![count_tokens(tokenizer, text, endpoint)?],
+            Value::Array(tokens) if !tokens.is_empty() && is_token_array(tokens) => {
+                vec![tokens.len() as i64]
+            }
+            Value::Array(prompts) => prompts
+                .iter()
+                .map(|prompt| match prompt {
+                    Value::String(text) => count_tokens(tokenizer, text, endpoint),
+                    Value::Array(tokens) if !tokens.is_empty() && is_token_array(tokens) => {
+                        Ok(tokens.len() as i64)
+                    }
+                    _ => Err(invalid_prompt()),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(invalid_prompt()),
+        };
+        if prompts_num_tokens.is_empty() {
+            return Err(invalid_prompt());
+        }
+        Ok(prompts_num_tokens)
+    }
+
+    /// Counts the number of tokens in the content of a chat completion message.
+    ///
+    /// The content can be missing or `null` (e.g. for assistant messages with tool calls),
+    /// a string, or an array of `text` and `image_url` content parts.
+    ///
+    /// # Arguments
+    /// * `message` - The message object from the request body
+    /// * `tokenizer` - The tokenizer of the requested model
+    /// * `state` - Application state containing the number of tokens per image
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
     /// # Returns
-    /// * `Ok(i64)` - The total number of compute units required
-    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if:
-    ///   - The model is not supported
-    ///   - Required fields are missing
-    ///   - Message content is invalid
-    ///   - Tokenization fails
+    /// * `Ok(i64)` - The number of tokens in the message content
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if the content or one of
+    ///   its parts is malformed, or tokenization fails
+    fn count_message_content_tokens(
+        message: &Value,
+        tokenizer: &Tokenizer,
+        state: &AppState,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        let parts = match message.get(CONTENT) {
+            None | Some(Value::Null) => return Ok(0),
+            Some(Value::String(text)) => return count_tokens(tokenizer, text, endpoint),
+            Some(Value::Array(parts)) => parts,
+            Some(_) => {
+                return Err(AtomaServiceError::InvalidBody {
+                    message: "Message content is not a string or an array of content parts"
+                        .to_string(),
+                    endpoint: endpoint.to_string(),
+                })
+            }
+        };
+        let mut num_tokens = 0;
+        for part in parts {
+            match part.get(CONTENT_PART_TYPE).and_then(|t| t.as_str()) {
+                Some(TEXT_CONTENT_PART_TYPE) => {
+                    let text = part
+                        .get(TEXT)
+                        .and_then(|text| text.as_str())
+                        .ok_or_else(|| AtomaServiceError::InvalidBody {
+                            message: "Text content part has no text".to_string(),
+                            endpoint: endpoint.to_string(),
+                        })?;
+                    num_tokens += count_tokens(tokenizer, text, endpoint)?;
+                }
+                Some(IMAGE_URL_CONTENT_PART_TYPE) => {
+                    if part.get(IMAGE_URL).is_none() {
+                        return Err(AtomaServiceError::InvalidBody {
+                            message: "Image content part has no image url".to_string(),
+                            endpoint: endpoint.to_string(),
+                        });
+                    }
+                    num_tokens += state.num_tokens_per_image;
+                }
+                Some(part_type) => {
+                    return Err(AtomaServiceError::InvalidBody {
+                        message: format!("Unsupported message content part type: {part_type}"),
+                        endpoint: endpoint.to_string(),
+                    })
+                }
+                None => {
+                    return Err(AtomaServiceError::InvalidBody {
+                        message: "Message content part has no type".to_string(),
+                        endpoint: endpoint.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(num_tokens)
+    }
+
+    /// Counts the exact number of prompt tokens of a chat completion request, by rendering
+    /// the model's chat template over the conversation, as the inference service does.
     ///
-    /// # Token Calculation
-    /// For each message:
-    /// - Base tokens: Number of tokens in the message content
-    /// - +2 tokens: Safety margin for message delimiters
-    /// - +1 token: Safety margin for role name
-    ///     Plus the requested max_tokens for the response
+    /// Content part arrays are flattened into their text parts, joined by newlines, while
+    /// images are charged `AppState::num_tokens_per_image` tokens each, on top of the
+    /// rendered prompt, as the inference service expands image placeholders itself.
     ///
-    /// # Example JSON Structure
-    /// ```json
-    /// {
-    ///     "messages": [
-    ///         {"role": "user", "content": "Hello, how are you?"},
-    ///         {"role": "assistant", "content": "I'm doing well, thank you!"}
-    ///     ],
-    ///     "max_tokens": 100
-    /// }
-    /// ```
-    #[instrument(level = "trace", skip_all)]
-    pub(crate) fn calculate_chat_completion_compute_units(
-        body_json: &Value,
+    /// # Arguments
+    /// * `chat_template` - The chat template of the requested model
+    /// * `messages` - The (already validated) messages from the request body
+    /// * `tools` - The tool definitions from the request body, if any
+    /// * `tokenizer` - The tokenizer of the requested model
+    /// * `state` - Application state containing the number of tokens per image
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The number of prompt tokens
+    /// * `Err(anyhow::Error)` - If the template fails to render, or tokenization fails
+    fn count_chat_template_tokens(
+        chat_template: &ChatTemplate,
+        messages: &[Value],
+        tools: Option<&Value>,
+        tokenizer: &Tokenizer,
         state: &AppState,
-        model: &str,
-        endpoint: String,
-    ) -> Result<i64, AtomaServiceError> {
-        let tokenizer_index = state
-            .models
+    ) -> anyhow::Result<i64> {
+        let mut num_images = 0;
+        let template_messages = messages
             .iter()
-            .position(|m| m == model)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Model not supported".to_string(),
-                endpoint: endpoint.clone(),
-            })?;
-
-        let messages = body_json
-            .get(MESSAGES)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Messages not found in body".to_string(),
-                endpoint: endpoint.clone(),
-            })?
-            .as_array()
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Messages is not an array".to_string(),
-                endpoint: endpoint.clone(),
-            })?;
+            .map(|message| {
+                let mut message = message.clone();
+                if let Some(Value::Array(parts)) = message.get(CONTENT) {
+                    let mut texts = vec![];
+                    for part in parts {
+                        match part.get(CONTENT_PART_TYPE).and_then(|t| t.as_str()) {
+                            Some(TEXT_CONTENT_PART_TYPE) => {
+                                texts.extend(
+                                    part.get(TEXT)
+                                        .and_then(|text| text.as_str())
+                                        .map(str::to_string),
+                                );
+                            }
+                            Some(IMAGE_URL_CONTENT_PART_TYPE) => num_images += 1,
+                            _ => {}
+                        }
+                    }
+                    message[CONTENT] = Value::String(texts.join("\n"));
+                }
+                message
+            })
+            .collect::<Vec<_>>();
+        let prompt = chat_template.render(&template_messages, tools)?;
+        // NOTE: The rendered prompt already contains the special tokens of the template
+        let num_prompt_tokens = tokenizer
+            .encode(prompt, false)
+            .map_err(|e| anyhow::anyhow!(e))?
+            .get_ids()
+            .len() as i64;
+        Ok(num_prompt_tokens + num_images * state.num_tokens_per_image)
+    }
 
-        let mut total_num_compute_units = 0;
-        for message in messages {
-            let content = message
-                .get("content")
-                .ok_or_else(|| AtomaServiceError::InvalidBody {
-                    message: "Message content not found".to_string(),
-                    endpoint: endpoint.clone(),
-                })?;
-            let content_str = content
-                .as_str()
-                .ok_or_else(|| AtomaServiceError::InvalidBody {
-                    message: "Message content is not a string".to_string(),
-                    endpoint: endpoint.clone(),
-                })?;
-            let num_tokens = state.tokenizers[tokenizer_index]
-                .encode(content_str, true)
-                .map_err(|_| AtomaServiceError::InvalidBody {
-                    message: "Failed to encode message content".to_string(),
-                    endpoint: endpoint.clone(),
-                })?
-                .get_ids()
-                .len() as i64;
-            total_num_compute_units += num_tokens;
-            // add 2 tokens as a safety margin, for start and end message delimiters
-            total_num_compute_units += 2;
-            // add 1 token as a safety margin, for the role name of the message
-            total_num_compute_units += 1;
+    /// Counts the number of tokens in the tool calls of a chat completion message,
+    /// that is, in the function name and arguments of each tool call.
+    ///
+    /// # Arguments
+    /// * `message` - The message object from the request body
+    /// * `tokenizer` - The tokenizer of the requested model
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The number of tokens in the message tool calls (0 if there are none)
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if the tool calls are
+    ///   malformed, or tokenization fails
+    fn count_message_tool_calls_tokens(
+        message: &Value,
+        tokenizer: &Tokenizer,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        let tool_calls = match message.get(TOOL_CALLS) {
+            None | Some(Value::Null) => return Ok(0),
+            Some(Value::Array(tool_calls)) => tool_calls,
+            Some(_) => {
+                return Err(AtomaServiceError::InvalidBody {
+                    message: "Tool calls is not an array".to_string(),
+                    endpoint: endpoint.to_string(),
+                })
+            }
+        };
+        let mut num_tokens = 0;
+        for tool_call in tool_calls {
+            let function =
+                tool_call
+                    .get(FUNCTION)
+                    .ok_or_else(|| AtomaServiceError::InvalidBody {
+                        message: "Tool call has no function".to_string(),
+                        endpoint: endpoint.to_string(),
+                    })?;
+            if let Some(name) = function.get(FUNCTION_NAME).and_then(|name| name.as_str()) {
+                num_tokens += count_tokens(tokenizer, name, endpoint)?;
+            }
+            // NOTE: Arguments are a JSON encoded string, but some clients send a JSON object
+            match function.get(FUNCTION_ARGUMENTS) {
+                Some(Value::String(arguments)) => {
+                    num_tokens += count_tokens(tokenizer, arguments, endpoint)?;
+                }
+                Some(arguments) if !arguments.is_null() => {
+                    num_tokens += count_tokens(tokenizer, &arguments.to_string(), endpoint)?;
+                }
+                _ => {}
+            }
         }
+        Ok(num_tokens)
+    }
 
-        total_num_compute_units += body_json
-            .get(MAX_TOKENS)
-            .and_then(|value| value.as_i64())
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Max tokens not found in body".to_string(),
-                endpoint: endpoint.clone(),
-            })?;
-
-        Ok(total_num_compute_units)
+    /// Counts the number of tokens in a text, with the given tokenizer.
+    fn count_tokens(
+        tokenizer: &Tokenizer,
+        text: &str,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        Ok(tokenizer
+            .encode(text, true)
+            .map_err(|_| AtomaServiceError::InvalidBody {
+                message: "Failed to encode message content".to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .get_ids()
+            .len() as i64)
     }
 
     /// Calculates the total number of compute units required for an embedding request.
@@ -957,6 +2268,83 @@ pub(crate) mod utils {
         Ok(total_units)
     }
 
+    /// Calculates the total number of compute units required for a rerank request.
+    ///
+    /// Cross-encoder models score each document by encoding it along with the query, so the
+    /// query is charged once per document, along with the tokens of each document.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request containing:
+    ///   - `query`: The query to rank the documents against
+    ///   - `documents` (or TEI's `texts`): The documents to rank, each either a string or an
+    ///     object with a `text` field. As the body is forwarded as is, requests carrying both
+    ///     fields are rejected, so that the documents charged are the ones that are ranked
+    /// * `state` - Application state containing model configurations and tokenizers
+    /// * `model` - The name of the AI model being used
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The number of tokens of the query times the number of documents, plus the
+    ///   number of tokens of all documents
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if:
+    ///   - The model is not supported
+    ///   - The query is missing or not a string
+    ///   - The documents are missing, empty, or not strings (nor objects with a `text` field)
+    ///   - Both `documents` and `texts` are set
+    #[instrument(level = "trace", skip_all)]
+    fn calculate_rerank_compute_units(
+        body_json: &Value,
+        state: &AppState,
+        model: &str,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        let tokenizer_index = state
+            .models
+            .iter()
+            .position(|m| m == model)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Model not supported".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        let tokenizer = &state.tokenizers[tokenizer_index];
+
+        let query = body_json
+            .get(QUERY)
+            .and_then(|query| query.as_str())
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Query must be a string".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        if body_json.get(DOCUMENTS).is_some() && body_json.get(TEXTS).is_some() {
+            return Err(AtomaServiceError::InvalidBody {
+                message: format!("Only one of {DOCUMENTS} and {TEXTS} can be set"),
+                endpoint: endpoint.clone(),
+            });
+        }
+        let documents = body_json
+            .get(DOCUMENTS)
+            .or_else(|| body_json.get(TEXTS))
+            .and_then(|documents| documents.as_array())
+            .filter(|documents| !documents.is_empty())
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Documents must be a non-empty array".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+
+        let num_query_tokens = count_tokens(tokenizer, query, &endpoint)?;
+        let mut total_units = num_query_tokens * documents.len() as i64;
+        for document in documents {
+            let text = document
+                .as_str()
+                .or_else(|| document.get(TEXT).and_then(|text| text.as_str()))
+                .ok_or_else(|| AtomaServiceError::InvalidBody {
+                    message: "Document must be a string or an object with a text field".to_string(),
+                    endpoint: endpoint.clone(),
+                })?;
+            total_units += count_tokens(tokenizer, text, &endpoint)?;
+        }
+        Ok(total_units)
+    }
+
     /// Calculates the total number of compute units required for an image generation request.
     ///
     /// This function analyzes the request body to determine the computational cost based on:
@@ -1025,19 +2413,152 @@ pub(crate) mod utils {
         Ok(width * height * n)
     }
 
+    /// Calculates the total number of compute units required for an audio transcription request.
+    ///
+    /// Audio transcriptions are charged one compute unit per second of audio, from the
+    /// duration of the audio file estimated when parsing the request's multipart form body
+    /// (see `form_data_to_json`).
+    ///
+    /// # Arguments
+    /// * `body_json` - The request's form, as a JSON object containing:
+    ///   - `file`: The audio file, with its estimated `duration` in seconds
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The total number of compute units required
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if the audio file is missing
+    ///   or is not a file
+    #[instrument(level = "trace", skip_all)]
+    fn calculate_audio_transcription_compute_units(
+        body_json: &Value,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        let duration = body_json
+            .get(AUDIO_FILE)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Audio file not found in body".to_string(),
+                endpoint: endpoint.clone(),
+            })?
+            .get(AUDIO_DURATION)
+            .and_then(Value::as_f64)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Audio file is not a file".to_string(),
+                endpoint: endpoint.clone(),
+            })?;
+        Ok(audio_duration_compute_units(duration))
+    }
+
+    /// Calculates the total number of compute units required for a speech request.
+    ///
+    /// Speech requests are charged one compute unit per character of their input text, as
+    /// the duration of the generated audio is proportional to it.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request containing:
+    ///   - `input`: The text to generate audio for
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The number of characters of the input text
+    /// * `Err(AtomaServiceError)` - AtomaServiceError::InvalidBody if the input is missing,
+    ///   empty or not a string
+    #[instrument(level = "trace", skip_all)]
+    fn calculate_speech_compute_units(
+        body_json: &Value,
+        endpoint: String,
+    ) -> Result<i64, AtomaServiceError> {
+        let input = body_json
+            .get(INPUT)
+            .and_then(Value::as_str)
+            .filter(|input| !input.is_empty())
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Input must be a non-empty string".to_string(),
+                endpoint,
+            })?;
+        Ok(input.chars().count() as i64)
+    }
+
+    /// Returns the body size limit of requests to the given endpoint.
+    ///
+    /// Audio transcriptions requests carry an audio file, so they are allowed larger bodies
+    /// than the other requests, whose bodies are at most `MAX_BODY_SIZE` bytes.
+    pub(crate) fn max_body_size(endpoint: &str) -> usize {
+        match endpoint {
+            AUDIO_TRANSCRIPTIONS_PATH => MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE,
+            // NOTE: Confidential requests carry the base64 encoded ciphertext of their
+            // plaintext body, which is 4/3 times larger, along with the other fields
+            CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH => {
+                MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE / 3 * 4 + MAX_BODY_SIZE
+            }
+            _ => MAX_BODY_SIZE,
+        }
+    }
+
+    /// Returns the boundary of the request body, if it is a `multipart/form-data` body.
+    pub(crate) fn form_data_boundary_from_headers(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(form_data_boundary)
+    }
+
+    /// Parses a `multipart/form-data` body into a JSON object, so that it can be processed
+    /// like JSON bodies.
+    ///
+    /// Text fields are read as string values. File fields are read as objects holding their
+    /// `filename`, `content_type` and estimated `duration` in seconds (as audio files are the
+    /// only files accepted by the node), without their data.
+    ///
+    /// # Errors
+    /// Returns `AtomaServiceError::InvalidBody` if the body is not a valid multipart form, or
+    /// if one of its text fields is not valid UTF-8.
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) fn form_data_to_json(
+        body: &[u8],
+        boundary: &str,
+        endpoint: &str,
+    ) -> Result<Value, AtomaServiceError> {
+        let fields =
+            parse_form_data(body, boundary).map_err(|e| AtomaServiceError::InvalidBody {
+                message: format!("Failed to parse body as a multipart form, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        let mut form = serde_json::Map::with_capacity(fields.len());
+        for field in fields {
+            let value = match &field.filename {
+                Some(filename) => json!({
+                    "filename": filename,
+                    "content_type": field.content_type,
+                    AUDIO_DURATION: estimate_audio_duration(field.data),
+                }),
+                None => Value::String(
+                    field
+                        .text()
+                        .ok_or_else(|| AtomaServiceError::InvalidBody {
+                            message: format!("Form field {} is not valid UTF-8", field.name),
+                            endpoint: endpoint.to_string(),
+                        })?
+                        .to_string(),
+                ),
+            };
+            form.insert(field.name, value);
+        }
+        Ok(Value::Object(form))
+    }
+
     /// Verifies a plaintext body hash against a provided signature.
     ///
     /// This function performs signature verification for confidential compute requests by:
     /// 1. Retrieving and validating the signature from request headers
-    /// 2. Verifying the signature against the provided plaintext body hash
+    /// 2. Verifying the signature against the provided plaintext body hash, or the digest of
+    ///    the hash and the request's timestamp and nonce (see `signed_request_digest`)
     ///
     /// # Arguments
-    /// * `plaintext_body_hash` - The 32-byte plaintext body hash to verify
+    /// * `plaintext_body_hash` - The 32-byte digest signed by the client
     /// * `headers` - HTTP headers containing the signature for verification
+    /// * `zklogin_verifier` - The verifier of zkLogin signatures, if they are accepted
     /// * `endpoint` - The API endpoint path being accessed (used for error context)
     ///
     /// # Returns
-    /// * `Ok(())` - If signature verification succeeds
+    /// * `Ok(SuiAddress)` - The address of the signer, if signature verification succeeds
     /// * `Err(AtomaServiceError)` - If any step fails, with variants:
     ///   - `InvalidHeader` - If the signature is malformed
     ///   - `MissingHeader` - If the signature header is missing
@@ -1056,8 +2577,8 @@ pub(crate) mod utils {
     /// let headers = HeaderMap::new(); // Headers with signature
     /// let endpoint = "/v1/chat/completions";
     ///
-    /// match verify_plaintext_body_hash(&plaintext_body_hash, &headers, endpoint) {
-    ///     Ok(()) => println!("Signature verification successful"),
+    /// match verify_plaintext_body_hash(&plaintext_body_hash, &headers, None, endpoint) {
+    ///     Ok(signer) => println!("Signature of {signer} verified"),
     ///     Err(e) => eprintln!("Verification failed: {}", e),
     /// }
     /// ```
@@ -1065,8 +2586,9 @@ pub(crate) mod utils {
     pub(crate) fn verify_plaintext_body_hash(
         plaintext_body_hash: &[u8; PAYLOAD_HASH_SIZE],
         headers: &HeaderMap,
+        zklogin_verifier: Option<&ZkLoginVerifier>,
         endpoint: &str,
-    ) -> Result<(), AtomaServiceError> {
+    ) -> Result<SuiAddress, AtomaServiceError> {
         let base64_signature = headers
             .get(atoma_utils::constants::SIGNATURE)
             .ok_or_else(|| AtomaServiceError::MissingHeader {
@@ -1080,7 +2602,7 @@ pub(crate) mod utils {
                     message: format!("Signature cannot be converted to a string, with error: {e}"),
                     endpoint: endpoint.to_string(),
                 })?;
-        verify_signature(base64_signature, plaintext_body_hash).map_err(|e| {
+        verify_signature(base64_signature, plaintext_body_hash, zklogin_verifier).map_err(|e| {
             AtomaServiceError::InvalidHeader {
                 message: format!("Failed to verify signature, with error: {e}"),
                 endpoint: endpoint.to_string(),
@@ -1287,4 +2809,131 @@ pub(crate) mod utils {
         }
         Ok(())
     }
+
+    /// Extracts the timestamp and nonce of a signed request from its headers, if any.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers, possibly containing `X-Request-Timestamp` and
+    ///   `X-Request-Nonce`
+    /// * `endpoint` - The API endpoint path being accessed (used for error context)
+    ///
+    /// # Returns
+    /// * `Ok(RequestFreshness)` - The timestamp and nonce found in the headers
+    /// * `Err(AtomaServiceError::InvalidHeader)` - If the timestamp is not a number of seconds,
+    ///   or either header is not a valid, non-empty string
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) fn request_freshness_from_headers(
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<RequestFreshness, AtomaServiceError> {
+        let header_str = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .ok()
+                        .filter(|value| !value.is_empty())
+                        .ok_or_else(|| AtomaServiceError::InvalidHeader {
+                            message: format!("{name} must be a non-empty string"),
+                            endpoint: endpoint.to_string(),
+                        })
+                })
+                .transpose()
+        };
+        let timestamp = header_str(atoma_utils::constants::REQUEST_TIMESTAMP)?
+            .map(|timestamp| {
+                timestamp
+                    .parse::<u64>()
+                    .map_err(|e| AtomaServiceError::InvalidHeader {
+                        message: format!(
+                            "{} must be a Unix time in seconds, with error: {e}",
+                            atoma_utils::constants::REQUEST_TIMESTAMP
+                        ),
+                        endpoint: endpoint.to_string(),
+                    })
+            })
+            .transpose()?;
+        let nonce = header_str(atoma_utils::constants::REQUEST_NONCE)?.map(str::to_string);
+        Ok(RequestFreshness { timestamp, nonce })
+    }
+
+    /// Removes the timestamp and nonce of a signed request from its body, if any.
+    ///
+    /// The fields are removed so that the body forwarded to the inference service only
+    /// contains the fields it expects.
+    ///
+    /// # Arguments
+    /// * `body_json` - The request body, possibly containing `request_timestamp` and
+    ///   `request_nonce` fields
+    /// * `endpoint` - The API endpoint path being accessed (used for error context)
+    ///
+    /// # Returns
+    /// * `Ok(RequestFreshness)` - The timestamp and nonce found in the body
+    /// * `Err(AtomaServiceError::InvalidBody)` - If the timestamp is not a number of seconds,
+    ///   or the nonce is not a non-empty string
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) fn take_request_freshness_from_body(
+        body_json: &mut Value,
+        endpoint: &str,
+    ) -> Result<RequestFreshness, AtomaServiceError> {
+        let Some(body) = body_json.as_object_mut() else {
+            return Ok(RequestFreshness::default());
+        };
+        let timestamp = body
+            .remove(REQUEST_TIMESTAMP)
+            .map(|timestamp| {
+                timestamp
+                    .as_u64()
+                    .ok_or_else(|| AtomaServiceError::InvalidBody {
+                        message: format!("{REQUEST_TIMESTAMP} must be a Unix time in seconds"),
+                        endpoint: endpoint.to_string(),
+                    })
+            })
+            .transpose()?;
+        let nonce = body
+            .remove(REQUEST_NONCE)
+            .map(|nonce| match nonce {
+                Value::String(nonce) if !nonce.is_empty() => Ok(nonce),
+                _ => Err(AtomaServiceError::InvalidBody {
+                    message: format!("{REQUEST_NONCE} must be a non-empty string"),
+                    endpoint: endpoint.to_string(),
+                }),
+            })
+            .transpose()?;
+        Ok(RequestFreshness { timestamp, nonce })
+    }
+
+    /// Extracts the JSON canonicalization of a request's hashes from its headers.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers, possibly containing `X-Json-Canonicalization`
+    /// * `endpoint` - The API endpoint path being accessed (used for error context)
+    ///
+    /// # Returns
+    /// * `Ok(JsonCanonicalization)` - The requested canonicalization, defaulting to the compact
+    ///   `serde_json` serialization if the header is absent
+    /// * `Err(AtomaServiceError::InvalidHeader)` - If the canonicalization is not supported
+    #[instrument(level = "trace", skip_all)]
+    pub(crate) fn json_canonicalization_from_headers(
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<JsonCanonicalization, AtomaServiceError> {
+        let Some(json_canonicalization) =
+            headers.get(atoma_utils::constants::JSON_CANONICALIZATION)
+        else {
+            return Ok(JsonCanonicalization::default());
+        };
+        json_canonicalization
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(str::parse)
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!(
+                    "Invalid {} header, with error: {e}",
+                    atoma_utils::constants::JSON_CANONICALIZATION
+                ),
+                endpoint: endpoint.to_string(),
+            })
+    }
 }
@@ -0,0 +1,414 @@
+//! A mock inference service, for testing the node end to end without GPU-backed inference
+//! services.
+//!
+//! The mock serves the OpenAI-compatible chat completions (streaming and non-streaming),
+//! embeddings and image generations endpoints of vLLM, TEI and diffusion services. Its outputs
+//! only depend on the request, and its responses report a realistic `usage`, counting one token
+//! per whitespace-separated word. It can be configured to be slow, to fail, to disconnect
+//! mid-stream or to send malformed chunks.
+
+use std::{io, sync::Arc, time::Duration};
+
+use atoma_utils::hashing::blake2b_hash;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{stream, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use crate::handlers::{
+    chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
+    image_generations::IMAGE_GENERATIONS_PATH,
+};
+
+/// The path of the health endpoint of the mock inference service
+const HEALTH_PATH: &str = "/health";
+
+/// The creation timestamp of all responses, so that they are deterministic
+const CREATED: u64 = 1_700_000_000;
+
+/// The words generated by chat completions, in order and cycling, one token each
+const WORDS: [&str; 9] = [
+    "The", " quick", " brown", " fox", " jumps", " over", " the", " lazy", " dog.",
+];
+
+/// The data of a malformed chunk, which is not valid JSON
+const MALFORMED_CHUNK: &str = "{\"choices\": [{\"delta\": ";
+
+/// Configuration of the mock inference service.
+#[derive(Clone, Debug)]
+pub struct MockInferenceConfig {
+    /// Delay before responding to each request, which is the time to the first chunk of
+    /// streamed responses
+    pub latency: Duration,
+    /// Delay between the chunks of streamed responses
+    pub chunk_interval: Duration,
+    /// Number of tokens generated by chat completions, unless `max_tokens` is lower
+    pub num_completion_tokens: u64,
+    /// Number of dimensions of the generated embeddings
+    pub embedding_dimensions: usize,
+    /// Status code of the error returned to every inference request, if any
+    pub error_status: Option<StatusCode>,
+    /// Number of chunks after which streamed responses are aborted, without reporting their
+    /// usage, if any
+    pub disconnect_after_chunks: Option<usize>,
+    /// Number of chunks after which a malformed chunk is sent in streamed responses, if any
+    pub malformed_chunk_after: Option<usize>,
+}
+
+impl Default for MockInferenceConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            chunk_interval: Duration::ZERO,
+            num_completion_tokens: 16,
+            embedding_dimensions: 16,
+            error_status: None,
+            disconnect_after_chunks: None,
+            malformed_chunk_after: None,
+        }
+    }
+}
+
+/// Returns the router of the mock inference service.
+pub fn router(config: MockInferenceConfig) -> Router {
+    Router::new()
+        .route(CHAT_COMPLETIONS_PATH, post(chat_completions_handler))
+        .route(EMBEDDINGS_PATH, post(embeddings_handler))
+        .route(IMAGE_GENERATIONS_PATH, post(image_generations_handler))
+        .route(HEALTH_PATH, get(|| async { StatusCode::OK }))
+        .with_state(Arc::new(config))
+}
+
+/// Spawns the mock inference service on a random local port, returning its base URL.
+///
+/// # Errors
+///
+/// Returns an error if no local port can be bound.
+pub async fn spawn(config: MockInferenceConfig) -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, router(config)).await });
+    Ok(url)
+}
+
+/// Waits for the configured latency, then returns the configured error response, if any
+async fn respond_with_error(config: &MockInferenceConfig) -> Option<Response> {
+    tokio::time::sleep(config.latency).await;
+    let status = config.error_status?;
+    let error = json!({
+        "object": "error",
+        "message": format!("Mock inference service error: {status}"),
+        "type": "mock_error",
+        "code": status.as_u16()
+    });
+    Some((status, Json(error)).into_response())
+}
+
+/// Handles chat completion requests, generating [`WORDS`] in a loop
+async fn chat_completions_handler(
+    State(config): State<Arc<MockInferenceConfig>>,
+    Json(payload): Json<Value>,
+) -> Response {
+    if let Some(response) = respond_with_error(&config).await {
+        return response;
+    }
+    let id = format!("chatcmpl-{}", request_id(&payload));
+    let model = payload.get("model").cloned().unwrap_or(Value::Null);
+    let prompt_tokens = payload
+        .get("messages")
+        .and_then(Value::as_array)
+        .map_or(0, |messages| {
+            messages
+                .iter()
+                .filter_map(|message| message.get("content"))
+                .map(count_content_tokens)
+                .sum()
+        })
+        .max(1);
+    let max_tokens = payload
+        .get("max_completion_tokens")
+        .or_else(|| payload.get("max_tokens"))
+        .and_then(Value::as_u64);
+    let (completion_tokens, finish_reason) = match max_tokens {
+        Some(max_tokens) if max_tokens < config.num_completion_tokens => (max_tokens, "length"),
+        _ => (config.num_completion_tokens, "stop"),
+    };
+    let words = WORDS
+        .iter()
+        .cycle()
+        .take(completion_tokens as usize)
+        .copied();
+    let usage = usage(prompt_tokens, completion_tokens);
+
+    let is_stream = payload
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default();
+    if !is_stream {
+        return Json(json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": CREATED,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": words.collect::<String>()},
+                "logprobs": null,
+                "finish_reason": finish_reason
+            }],
+            "usage": usage
+        }))
+        .into_response();
+    }
+
+    let include_usage = payload
+        .pointer("/stream_options/include_usage")
+        .and_then(Value::as_bool)
+        .unwrap_or_default();
+    let chunk = |delta: Value, finish_reason: Option<&str>| {
+        let mut chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": CREATED,
+            "model": model,
+            "choices": [{"index": 0, "delta": delta, "logprobs": null, "finish_reason": finish_reason}]
+        });
+        if include_usage {
+            chunk["usage"] = Value::Null;
+        }
+        chunk.to_string()
+    };
+    let mut content_chunks =
+        std::iter::once(chunk(json!({"role": "assistant", "content": ""}), None))
+            .chain(words.map(|word| chunk(json!({"content": word}), None)))
+            .chain(std::iter::once(chunk(json!({}), Some(finish_reason))));
+
+    let mut events = Vec::new();
+    for num_chunks in 0.. {
+        if config.disconnect_after_chunks == Some(num_chunks) {
+            events.push(Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "Mock inference service disconnected",
+            )));
+            return sse_response(events, config.chunk_interval);
+        }
+        if config.malformed_chunk_after == Some(num_chunks) {
+            events.push(Ok(MALFORMED_CHUNK.to_string()));
+        }
+        match content_chunks.next() {
+            Some(chunk) => events.push(Ok(chunk)),
+            None => break,
+        }
+    }
+    if include_usage {
+        events.push(Ok(json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": CREATED,
+            "model": model,
+            "choices": [],
+            "usage": usage
+        })
+        .to_string()));
+    }
+    events.push(Ok("[DONE]".to_string()));
+    sse_response(events, config.chunk_interval)
+}
+
+/// Handles embeddings requests, generating a unit vector from the hash of each input
+async fn embeddings_handler(
+    State(config): State<Arc<MockInferenceConfig>>,
+    Json(payload): Json<Value>,
+) -> Response {
+    if let Some(response) = respond_with_error(&config).await {
+        return response;
+    }
+    let inputs = match payload.get("input") {
+        Some(Value::String(input)) => vec![Value::String(input.clone())],
+        // NOTE: An array of token ids is a single input, an array of arrays several ones
+        Some(Value::Array(inputs)) if inputs.iter().all(Value::is_number) => {
+            vec![Value::Array(inputs.clone())]
+        }
+        Some(Value::Array(inputs)) => inputs.clone(),
+        _ => vec![],
+    };
+    let prompt_tokens = inputs
+        .iter()
+        .map(|input| match input {
+            Value::Array(token_ids) => token_ids.len() as u64,
+            input => count_content_tokens(input),
+        })
+        .sum::<u64>();
+    let data = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            json!({
+                "object": "embedding",
+                "index": index,
+                "embedding": embedding(&input.to_string(), config.embedding_dimensions)
+            })
+        })
+        .collect::<Vec<_>>();
+    Json(json!({
+        "id": format!("embd-{}", request_id(&payload)),
+        "object": "list",
+        "created": CREATED,
+        "model": payload.get("model").cloned().unwrap_or(Value::Null),
+        "data": data,
+        "usage": {"prompt_tokens": prompt_tokens, "total_tokens": prompt_tokens}
+    }))
+    .into_response()
+}
+
+/// Handles image generation requests, generating `n` images from the hash of the request
+async fn image_generations_handler(
+    State(config): State<Arc<MockInferenceConfig>>,
+    Json(payload): Json<Value>,
+) -> Response {
+    if let Some(response) = respond_with_error(&config).await {
+        return response;
+    }
+    let id = request_id(&payload);
+    let is_b64_json = payload.get("response_format").and_then(Value::as_str) == Some("b64_json");
+    let n = payload.get("n").and_then(Value::as_u64).unwrap_or(1);
+    let data = (0..n)
+        .map(|index| {
+            let image = blake2b_hash(format!("{id}-{index}").as_bytes());
+            if is_b64_json {
+                json!({"b64_json": STANDARD.encode(image)})
+            } else {
+                json!({"url": format!("https://mock.atoma.invalid/images/{}.png", hex::encode(image))})
+            }
+        })
+        .collect::<Vec<_>>();
+    Json(json!({"created": CREATED, "data": data})).into_response()
+}
+
+/// Returns a server-sent events response streaming the data of `events`, one every
+/// `chunk_interval`, aborting the response at the first error
+fn sse_response(events: Vec<io::Result<String>>, chunk_interval: Duration) -> Response {
+    let stream = stream::iter(events).then(move |event| async move {
+        tokio::time::sleep(chunk_interval).await;
+        event.map(|data| format!("data: {data}\n\n"))
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// Returns a deterministic identifier of a request, from the hash of its body
+fn request_id(payload: &Value) -> String {
+    hex::encode(&blake2b_hash(payload.to_string().as_bytes())[..12])
+}
+
+/// Returns the OpenAI usage object of a request
+fn usage(prompt_tokens: u64, completion_tokens: u64) -> Value {
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens
+    })
+}
+
+/// Counts the tokens of a message content, either a string or an array of content parts, of
+/// which only the text parts are counted
+fn count_content_tokens(content: &Value) -> u64 {
+    match content {
+        Value::String(text) => text.split_whitespace().count() as u64,
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text"))
+            .map(count_content_tokens)
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Returns a deterministic unit vector of `dimensions` dimensions for `input`
+fn embedding(input: &str, dimensions: usize) -> Vec<f64> {
+    let mut embedding = (0..dimensions.div_ceil(32))
+        .flat_map(|block| blake2b_hash(format!("{block}:{input}").as_bytes()).to_vec())
+        .take(dimensions)
+        .map(|byte| byte as f64 / 127.5 - 1.0)
+        .collect::<Vec<_>>();
+    let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+    embedding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn post(url: &str, path: &str, payload: Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{url}{path}"))
+            .json(&payload)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_chat_completions_are_deterministic() {
+        let url = spawn(MockInferenceConfig::default()).await.unwrap();
+        let payload = json!({
+            "model": "llama",
+            "messages": [{"role": "user", "content": "Hello there"}],
+            "max_tokens": 4
+        });
+
+        let response = post(&url, CHAT_COMPLETIONS_PATH, payload.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = response.json().await.unwrap();
+        assert_eq!(
+            response["choices"][0]["message"]["content"],
+            "The quick brown fox"
+        );
+        assert_eq!(response["choices"][0]["finish_reason"], "length");
+        assert_eq!(response["usage"], usage(2, 4));
+        let same_response: Value = post(&url, CHAT_COMPLETIONS_PATH, payload)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response, same_response);
+    }
+
+    #[tokio::test]
+    async fn test_mock_errors_and_embeddings() {
+        let url = spawn(MockInferenceConfig {
+            error_status: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let response = post(&url, EMBEDDINGS_PATH, json!({"input": "a b"})).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let url = spawn(MockInferenceConfig::default()).await.unwrap();
+        let response: Value = post(&url, EMBEDDINGS_PATH, json!({"input": ["a b", "c"]}))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["data"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            response["data"][0]["embedding"].as_array().unwrap().len(),
+            16
+        );
+        assert_eq!(response["usage"]["prompt_tokens"], 3);
+    }
+}
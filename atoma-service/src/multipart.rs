@@ -0,0 +1,231 @@
+use thiserror::Error;
+
+/// The media type of multipart form bodies
+const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+
+/// The line break separating the headers and delimiters of multipart bodies
+const CRLF: &[u8] = b"\r\n";
+
+/// A field of a `multipart/form-data` body, borrowing its data from the body
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormField<'a> {
+    /// The name of the field, from its `Content-Disposition` header
+    pub name: String,
+    /// The file name of the field, for file fields
+    pub filename: Option<String>,
+    /// The content type of the field, if specified
+    pub content_type: Option<String>,
+    /// The data of the field
+    pub data: &'a [u8],
+}
+
+impl FormField<'_> {
+    /// Returns the data of the field as text, for text fields
+    pub fn text(&self) -> Option<&str> {
+        if self.filename.is_some() {
+            return None;
+        }
+        std::str::from_utf8(self.data).ok()
+    }
+}
+
+/// Returns the boundary of a `multipart/form-data` body, from the value of its `Content-Type`
+/// header, or `None` if the body is not a multipart form.
+pub fn form_data_boundary(content_type: &str) -> Option<String> {
+    let (media_type, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !media_type.trim().eq_ignore_ascii_case(MULTIPART_FORM_DATA) {
+        return None;
+    }
+    header_params(params)
+        .into_iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, boundary)| boundary)
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Parses a buffered `multipart/form-data` body (RFC 7578) into its fields, in order.
+///
+/// # Errors
+///
+/// Returns an error if the body does not start with (after an optional preamble) nor end with
+/// a delimiter of `boundary`, or if a part has malformed headers or no field name.
+pub fn parse_form_data<'a>(
+    body: &'a [u8],
+    boundary: &str,
+) -> Result<Vec<FormField<'a>>, MultipartError> {
+    let delimiter = [b"--", boundary.as_bytes()].concat();
+    let separator = [CRLF, &delimiter].concat();
+
+    let mut position = if body.starts_with(&delimiter) {
+        delimiter.len()
+    } else {
+        find(body, &separator, 0).ok_or(MultipartError::MissingDelimiter)? + separator.len()
+    };
+    let mut fields = Vec::new();
+    loop {
+        let rest = &body[position..];
+        if rest.starts_with(b"--") {
+            return Ok(fields);
+        }
+        // NOTE: Delimiters may be followed by linear whitespace, before their line break
+        let line_end = find(body, CRLF, position).ok_or(MultipartError::MissingDelimiter)?;
+        if body[position..line_end]
+            .iter()
+            .any(|byte| !matches!(byte, b' ' | b'\t'))
+        {
+            return Err(MultipartError::MissingDelimiter);
+        }
+        let part_start = line_end + CRLF.len();
+        let part_end =
+            find(body, &separator, part_start).ok_or(MultipartError::MissingDelimiter)?;
+        fields.push(parse_part(&body[part_start..part_end])?);
+        position = part_end + separator.len();
+    }
+}
+
+/// Parses a part of a multipart form body, made of its headers and its data
+fn parse_part(part: &[u8]) -> Result<FormField<'_>, MultipartError> {
+    let (headers, data) = if part.starts_with(CRLF) {
+        (&part[..0], &part[CRLF.len()..])
+    } else {
+        let headers_end = find(part, b"\r\n\r\n", 0).ok_or(MultipartError::MalformedHeaders)?;
+        (&part[..headers_end], &part[headers_end + 4..])
+    };
+    let headers = std::str::from_utf8(headers).map_err(|_| MultipartError::MalformedHeaders)?;
+
+    let mut content_disposition = None;
+    let mut content_type = None;
+    for header in headers.split("\r\n").filter(|header| !header.is_empty()) {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or(MultipartError::MalformedHeaders)?;
+        if name.trim().eq_ignore_ascii_case("content-disposition") {
+            content_disposition = Some(value.trim());
+        } else if name.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    let (disposition, params) = content_disposition
+        .ok_or(MultipartError::MissingName)?
+        .split_once(';')
+        .ok_or(MultipartError::MissingName)?;
+    if !disposition.trim().eq_ignore_ascii_case("form-data") {
+        return Err(MultipartError::MalformedHeaders);
+    }
+    let params = header_params(params);
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.clone())
+    };
+    Ok(FormField {
+        name: param("name").ok_or(MultipartError::MissingName)?,
+        filename: param("filename"),
+        content_type,
+        data,
+    })
+}
+
+/// Parses the `;`-separated `key=value` parameters of a header value, whose values may be
+/// quoted strings (possibly containing `;` and escaped quotes)
+fn header_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    let mut chars = params.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+        let key =
+            std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ';')).collect::<String>();
+        if key.is_empty() {
+            return parsed;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+            }
+            value.extend(std::iter::from_fn(|| chars.next_if(|c| *c != ';')));
+        }
+        parsed.push((key.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`, from `start`
+fn find(haystack: &[u8], needle: &[u8], start: usize) -> Option<usize> {
+    haystack
+        .get(start..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| start + position)
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MultipartError {
+    #[error("Multipart body is not delimited by its boundary")]
+    MissingDelimiter,
+    #[error("Multipart body has a part with malformed headers")]
+    MalformedHeaders,
+    #[error("Multipart body has a part without a form field name")]
+    MissingName,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_data_boundary() {
+        assert_eq!(
+            form_data_boundary("multipart/form-data; boundary=abc123").as_deref(),
+            Some("abc123")
+        );
+        assert_eq!(
+            form_data_boundary("Multipart/Form-Data;boundary=\"a;b c\"").as_deref(),
+            Some("a;b c")
+        );
+        assert_eq!(form_data_boundary("application/json"), None);
+        assert_eq!(form_data_boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_parse_form_data() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+            openai/whisper-large-v3\r\n\
+            --XyZ  \r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a;\\\"b\\\".wav\"\r\n\
+            Content-Type: audio/wav\r\n\r\n\
+            RIFF\r\n--Xy\r\n\
+            --XyZ--\r\nepilogue";
+        let fields = parse_form_data(body, "XyZ").unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name, "model");
+        assert_eq!(fields[0].text(), Some("openai/whisper-large-v3"));
+        assert_eq!(fields[1].name, "file");
+        assert_eq!(fields[1].filename.as_deref(), Some("a;\"b\".wav"));
+        assert_eq!(fields[1].content_type.as_deref(), Some("audio/wav"));
+        assert_eq!(fields[1].data, b"RIFF\r\n--Xy");
+        assert_eq!(fields[1].text(), None);
+
+        assert_eq!(
+            parse_form_data(
+                b"--XyZ\r\nContent-Disposition: form-data\r\n\r\nx\r\n--XyZ--",
+                "XyZ"
+            ),
+            Err(MultipartError::MissingName)
+        );
+        assert_eq!(
+            parse_form_data(
+                b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nx",
+                "XyZ"
+            ),
+            Err(MultipartError::MissingDelimiter)
+        );
+    }
+}
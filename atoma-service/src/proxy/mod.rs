@@ -1,4 +1,5 @@
 use atoma_utils::constants::SIGNATURE;
+use atoma_utils::hashing::JsonCanonicalization;
 use config::ProxyConfig;
 use reqwest::Client;
 use serde_json::json;
@@ -37,7 +38,12 @@ pub async fn register_on_proxy(
       "country": config.country,
     });
 
-    let (_, signature) = sign_response_body(&body, keystore, address_index)?;
+    let (_, signature) = sign_response_body(
+        &body,
+        JsonCanonicalization::SerdeJson,
+        keystore,
+        address_index,
+    )?;
 
     let res = client
         .post(&url)
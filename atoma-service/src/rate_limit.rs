@@ -0,0 +1,648 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderName, HeaderValue},
+    response::Response,
+};
+use futures::StreamExt;
+use thiserror::Error;
+
+use crate::config::{RateLimitConfig, RateLimits};
+
+/// Header with the maximum number of requests per second of the most restrictive limit
+pub const RATE_LIMIT_LIMIT_REQUESTS: &str = "x-ratelimit-limit-requests";
+
+/// Header with the number of requests remaining in the most restrictive limit
+pub const RATE_LIMIT_REMAINING_REQUESTS: &str = "x-ratelimit-remaining-requests";
+
+/// Header with the number of seconds until the most restrictive requests limit is fully reset
+pub const RATE_LIMIT_RESET_REQUESTS: &str = "x-ratelimit-reset-requests";
+
+/// Header with the maximum number of tokens per minute of the most restrictive limit
+pub const RATE_LIMIT_LIMIT_TOKENS: &str = "x-ratelimit-limit-tokens";
+
+/// Header with the number of tokens remaining in the most restrictive limit
+pub const RATE_LIMIT_REMAINING_TOKENS: &str = "x-ratelimit-remaining-tokens";
+
+/// Header with the number of seconds until the most restrictive tokens limit is fully reset
+pub const RATE_LIMIT_RESET_TOKENS: &str = "x-ratelimit-reset-tokens";
+
+/// The scope of the limits of the requests of each Sui address
+const PER_ADDRESS_SCOPE: &str = "Sui address";
+
+/// The scope of the limits of the requests on each stack
+const PER_STACK_SCOPE: &str = "stack";
+
+/// The delay after which requests rejected for too many concurrent requests can be retried
+const CONCURRENT_REQUESTS_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// The minimum number of tracked keys before idle ones are pruned
+const MIN_PRUNE_THRESHOLD: usize = 1_024;
+
+/// A token bucket, continuously refilled at a rate of `capacity` tokens per `refill_period`
+#[derive(Debug)]
+struct TokenBucket {
+    /// Maximum number of tokens in the bucket
+    capacity: u64,
+    /// Duration to refill the bucket from empty to full
+    refill_period: Duration,
+    /// Number of tokens currently in the bucket
+    available: f64,
+    /// The instant the bucket was last refilled
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full token bucket
+    fn new(capacity: u64, refill_period: Duration, now: Instant) -> Self {
+        Self {
+            capacity,
+            refill_period,
+            available: capacity as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Adds the tokens refilled since the last refill
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled =
+            elapsed.as_secs_f64() / self.refill_period.as_secs_f64() * self.capacity as f64;
+        self.available = (self.available + refilled).min(self.capacity as f64);
+        self.last_refill = now;
+    }
+
+    /// Returns the duration until `cost` tokens are available.
+    ///
+    /// Costs above the bucket's capacity are capped to it, so that large requests can still
+    /// be served when the bucket is full.
+    fn wait_time(&self, cost: u64) -> Duration {
+        let missing = (cost.min(self.capacity) as f64 - self.available).max(0.0);
+        Duration::from_secs_f64(missing / self.capacity as f64 * self.refill_period.as_secs_f64())
+    }
+
+    /// Removes `cost` tokens from the bucket, capped to its capacity
+    fn consume(&mut self, cost: u64) {
+        self.available -= cost.min(self.capacity) as f64;
+    }
+
+    /// Returns whether the bucket is full
+    fn is_full(&self) -> bool {
+        self.available >= self.capacity as f64
+    }
+
+    /// Returns the current status of the bucket, for rate limit headers
+    fn status(&self) -> BucketStatus {
+        BucketStatus {
+            limit: self.capacity,
+            remaining: self.available.floor() as u64,
+            reset: self.wait_time(self.capacity),
+        }
+    }
+}
+
+/// The rate limiting state of a single key (a Sui address or a stack)
+#[derive(Debug)]
+struct KeyState {
+    /// Bucket of the requests per second, if limited
+    requests: Option<TokenBucket>,
+    /// Bucket of the tokens per minute, if limited
+    tokens: Option<TokenBucket>,
+    /// Number of requests currently being served
+    concurrent_requests: usize,
+}
+
+impl KeyState {
+    fn new(limits: &RateLimits, now: Instant) -> Self {
+        Self {
+            requests: limits
+                .requests_per_second
+                .map(|capacity| TokenBucket::new(capacity, Duration::from_secs(1), now)),
+            tokens: limits
+                .tokens_per_minute
+                .map(|capacity| TokenBucket::new(capacity, Duration::from_secs(60), now)),
+            concurrent_requests: 0,
+        }
+    }
+
+    /// Refills the key's buckets
+    fn refill(&mut self, now: Instant) {
+        self.requests
+            .iter_mut()
+            .chain(self.tokens.iter_mut())
+            .for_each(|bucket| bucket.refill(now));
+    }
+
+    /// Returns whether the key has no request being served and full buckets, in which case
+    /// its state is the same as a new one's
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.concurrent_requests == 0
+            && self
+                .requests
+                .iter()
+                .chain(self.tokens.iter())
+                .all(TokenBucket::is_full)
+    }
+
+    /// Returns the current status of the key's buckets, for rate limit headers
+    fn status(&self) -> RateLimitStatus {
+        RateLimitStatus {
+            requests: self.requests.as_ref().map(TokenBucket::status),
+            tokens: self.tokens.as_ref().map(TokenBucket::status),
+        }
+    }
+}
+
+/// The tracked keys of a rate limiter
+#[derive(Debug)]
+struct KeyedState<K> {
+    /// The rate limiting state of each key
+    keys: HashMap<K, KeyState>,
+    /// The number of tracked keys above which idle keys are pruned
+    prune_threshold: usize,
+}
+
+/// A rate limiter enforcing the same limits on the requests of each key.
+#[derive(Debug)]
+struct KeyedRateLimiter<K> {
+    /// The scope of the limits, for error messages
+    scope: &'static str,
+    /// The limits of each key
+    limits: RateLimits,
+    /// The tracked keys
+    state: Mutex<KeyedState<K>>,
+}
+
+impl<K: Clone + Debug + Eq + Hash> KeyedRateLimiter<K> {
+    fn new(scope: &'static str, limits: RateLimits) -> Result<Self, RateLimitError> {
+        let zero_limit = [
+            (limits.requests_per_second == Some(0)).then_some("requests_per_second"),
+            (limits.max_concurrent_requests == Some(0)).then_some("max_concurrent_requests"),
+            (limits.tokens_per_minute == Some(0)).then_some("tokens_per_minute"),
+        ];
+        if let Some(limit) = zero_limit.into_iter().flatten().next() {
+            return Err(RateLimitError::ZeroLimit { scope, limit });
+        }
+        Ok(Self {
+            scope,
+            limits,
+            state: Mutex::new(KeyedState {
+                keys: HashMap::new(),
+                prune_threshold: MIN_PRUNE_THRESHOLD,
+            }),
+        })
+    }
+
+    /// Admits a request of `key`, estimated to consume `num_tokens` tokens, if it is within
+    /// the key's limits.
+    ///
+    /// # Returns
+    ///
+    /// Returns the guard counting the request as concurrent until dropped, along with the
+    /// status of the key's limits.
+    fn try_acquire(
+        self: &Arc<Self>,
+        key: K,
+        num_tokens: u64,
+    ) -> Result<(ConcurrencyGuard<K>, RateLimitStatus), RateLimitError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        // NOTE: Idle keys are indistinguishable from untracked ones, so pruning them is
+        // lossless. The threshold grows with the number of busy keys, to amortize pruning.
+        if state.keys.len() >= state.prune_threshold {
+            state.keys.retain(|_, key_state| !key_state.is_idle(now));
+            state.prune_threshold = (2 * state.keys.len()).max(MIN_PRUNE_THRESHOLD);
+        }
+        let key_state = state
+            .keys
+            .entry(key.clone())
+            .or_insert_with(|| KeyState::new(&self.limits, now));
+        key_state.refill(now);
+
+        if let Some(max_concurrent_requests) = self.limits.max_concurrent_requests {
+            if key_state.concurrent_requests >= max_concurrent_requests {
+                return Err(RateLimitError::Exceeded {
+                    message: format!(
+                        "Limit of {max_concurrent_requests} concurrent requests per {} reached",
+                        self.scope
+                    ),
+                    retry_after: CONCURRENT_REQUESTS_RETRY_AFTER,
+                    status: key_state.status(),
+                });
+            }
+        }
+        if let Some(requests) = &key_state.requests {
+            let retry_after = requests.wait_time(1);
+            if !retry_after.is_zero() {
+                return Err(RateLimitError::Exceeded {
+                    message: format!(
+                        "Limit of {} requests per second per {} exceeded",
+                        requests.capacity, self.scope
+                    ),
+                    retry_after,
+                    status: key_state.status(),
+                });
+            }
+        }
+        if let Some(tokens) = &key_state.tokens {
+            let retry_after = tokens.wait_time(num_tokens);
+            if !retry_after.is_zero() {
+                return Err(RateLimitError::Exceeded {
+                    message: format!(
+                        "Limit of {} tokens per minute per {} exceeded",
+                        tokens.capacity, self.scope
+                    ),
+                    retry_after,
+                    status: key_state.status(),
+                });
+            }
+        }
+
+        if let Some(requests) = &mut key_state.requests {
+            requests.consume(1);
+        }
+        if let Some(tokens) = &mut key_state.tokens {
+            tokens.consume(num_tokens);
+        }
+        key_state.concurrent_requests += 1;
+        let status = key_state.status();
+        Ok((
+            ConcurrencyGuard {
+                limiter: self.clone(),
+                key,
+            },
+            status,
+        ))
+    }
+}
+
+/// Counts a request as concurrent for its key, until dropped
+#[derive(Debug)]
+struct ConcurrencyGuard<K: Clone + Debug + Eq + Hash> {
+    limiter: Arc<KeyedRateLimiter<K>>,
+    key: K,
+}
+
+impl<K: Clone + Debug + Eq + Hash> Drop for ConcurrencyGuard<K> {
+    fn drop(&mut self) {
+        if let Some(key_state) = self.limiter.state.lock().unwrap().keys.get_mut(&self.key) {
+            key_state.concurrent_requests = key_state.concurrent_requests.saturating_sub(1);
+        }
+    }
+}
+
+/// Status of a token bucket, as reported in rate limit headers
+#[derive(Clone, Copy, Debug)]
+struct BucketStatus {
+    /// Capacity of the bucket
+    limit: u64,
+    /// Number of whole tokens remaining in the bucket
+    remaining: u64,
+    /// Duration until the bucket is full again
+    reset: Duration,
+}
+
+/// Status of the rate limits of a request, as reported in its response headers.
+///
+/// When a request is subject to the limits of several keys, the most restrictive ones (that
+/// is, with the fewest remaining requests or tokens) are reported.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitStatus {
+    /// Status of the requests per second limit, if any
+    requests: Option<BucketStatus>,
+    /// Status of the tokens per minute limit, if any
+    tokens: Option<BucketStatus>,
+}
+
+impl RateLimitStatus {
+    /// Merges the status of the limits of another key, keeping the most restrictive ones
+    fn merge(self, other: Self) -> Self {
+        let most_restrictive = |a: Option<BucketStatus>, b: Option<BucketStatus>| match (a, b) {
+            (Some(a), Some(b)) if b.remaining < a.remaining => Some(b),
+            (a, b) => a.or(b),
+        };
+        Self {
+            requests: most_restrictive(self.requests, other.requests),
+            tokens: most_restrictive(self.tokens, other.tokens),
+        }
+    }
+
+    /// Inserts the rate limit headers into a response's headers
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        let buckets = [
+            (
+                self.requests,
+                [
+                    RATE_LIMIT_LIMIT_REQUESTS,
+                    RATE_LIMIT_REMAINING_REQUESTS,
+                    RATE_LIMIT_RESET_REQUESTS,
+                ],
+            ),
+            (
+                self.tokens,
+                [
+                    RATE_LIMIT_LIMIT_TOKENS,
+                    RATE_LIMIT_REMAINING_TOKENS,
+                    RATE_LIMIT_RESET_TOKENS,
+                ],
+            ),
+        ];
+        for (status, [limit, remaining, reset]) in buckets {
+            if let Some(status) = status {
+                let reset_secs =
+                    status.reset.as_secs() + u64::from(status.reset.subsec_nanos() > 0);
+                headers.insert(
+                    HeaderName::from_static(limit),
+                    HeaderValue::from(status.limit),
+                );
+                headers.insert(
+                    HeaderName::from_static(remaining),
+                    HeaderValue::from(status.remaining),
+                );
+                headers.insert(
+                    HeaderName::from_static(reset),
+                    HeaderValue::from(reset_secs),
+                );
+            }
+        }
+    }
+}
+
+/// Permit of a request admitted by the rate limiters.
+///
+/// The request counts against the concurrent requests limits until this permit is dropped.
+#[derive(Debug)]
+pub struct RateLimitPermit {
+    /// The guard of the request for its Sui address, if limited
+    per_address: Option<ConcurrencyGuard<String>>,
+    /// The guard of the request for its stack, if limited
+    per_stack: Option<ConcurrencyGuard<i64>>,
+    /// The status of the request's limits
+    status: RateLimitStatus,
+}
+
+impl RateLimitPermit {
+    /// Adds the rate limit headers to the response of the request, and keeps the request
+    /// counted as concurrent until its response body, possibly streamed, is dropped.
+    pub fn bind(self, response: Response) -> Response {
+        let (mut parts, body) = response.into_parts();
+        self.status.insert_headers(&mut parts.headers);
+        if self.per_address.is_none() && self.per_stack.is_none() {
+            return Response::from_parts(parts, body);
+        }
+        let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _permit = &self;
+            chunk
+        }));
+        Response::from_parts(parts, body)
+    }
+}
+
+/// Rate limiters of the requests of each Sui address, and on each stack.
+#[derive(Debug, Default)]
+pub struct RateLimiters {
+    /// The rate limiter of the requests of each Sui address, if limited
+    per_address: Option<Arc<KeyedRateLimiter<String>>>,
+    /// The rate limiter of the requests on each stack, if limited
+    per_stack: Option<Arc<KeyedRateLimiter<i64>>>,
+}
+
+impl RateLimiters {
+    /// Creates the rate limiters from the service configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any configured limit is zero.
+    pub fn from_config(config: &RateLimitConfig) -> Result<Self, RateLimitError> {
+        Ok(Self {
+            per_address: config
+                .per_address
+                .clone()
+                .map(|limits| KeyedRateLimiter::new(PER_ADDRESS_SCOPE, limits).map(Arc::new))
+                .transpose()?,
+            per_stack: config
+                .per_stack
+                .clone()
+                .map(|limits| KeyedRateLimiter::new(PER_STACK_SCOPE, limits).map(Arc::new))
+                .transpose()?,
+        })
+    }
+
+    /// Admits a request signed by `sui_address`, estimated to consume `num_tokens` tokens, if
+    /// it is within the limits of the address.
+    ///
+    /// The limits of the request's stack are enforced separately by `acquire_stack`, once the
+    /// signer is known to be allowed to use the stack, so that requests on stacks of other
+    /// owners never consume their limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RateLimitError::Exceeded` if the request exceeds any limit of the address.
+    pub fn acquire(
+        &self,
+        sui_address: &str,
+        num_tokens: i64,
+    ) -> Result<RateLimitPermit, RateLimitError> {
+        let num_tokens = u64::try_from(num_tokens).unwrap_or_default();
+        let (per_address, status) = match &self.per_address {
+            Some(limiter) => {
+                let (guard, status) = limiter.try_acquire(sui_address.to_string(), num_tokens)?;
+                (Some(guard), status)
+            }
+            None => (None, RateLimitStatus::default()),
+        };
+        Ok(RateLimitPermit {
+            per_address,
+            per_stack: None,
+            status,
+        })
+    }
+
+    /// Admits a request on stack `stack_small_id`, already admitted for its Sui address with
+    /// `permit`, and estimated to consume `num_tokens` tokens, if it is within the limits of
+    /// the stack.
+    ///
+    /// NOTE: A request rejected by the stack's limits still counts against its address'
+    /// requests and tokens, as retried requests would otherwise be free for the address.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RateLimitError::Exceeded` if the request exceeds any limit of the stack.
+    pub fn acquire_stack(
+        &self,
+        permit: RateLimitPermit,
+        stack_small_id: i64,
+        num_tokens: i64,
+    ) -> Result<RateLimitPermit, RateLimitError> {
+        let Some(limiter) = &self.per_stack else {
+            return Ok(permit);
+        };
+        let num_tokens = u64::try_from(num_tokens).unwrap_or_default();
+        match limiter.try_acquire(stack_small_id, num_tokens) {
+            Ok((guard, stack_status)) => Ok(RateLimitPermit {
+                per_stack: Some(guard),
+                status: permit.status.merge(stack_status),
+                ..permit
+            }),
+            Err(RateLimitError::Exceeded {
+                message,
+                retry_after,
+                status: stack_status,
+            }) => Err(RateLimitError::Exceeded {
+                message,
+                retry_after,
+                status: permit.status.merge(stack_status),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    #[error("Rate limit {limit} per {scope} must be greater than zero")]
+    ZeroLimit {
+        scope: &'static str,
+        limit: &'static str,
+    },
+    #[error("{message}")]
+    Exceeded {
+        message: String,
+        retry_after: Duration,
+        status: RateLimitStatus,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUI_ADDRESS: &str = "0x1";
+
+    fn rate_limiters(per_address: RateLimits, per_stack: RateLimits) -> RateLimiters {
+        RateLimiters::from_config(&RateLimitConfig {
+            per_address: Some(per_address),
+            per_stack: Some(per_stack),
+        })
+        .unwrap()
+    }
+
+    /// Admits a request within the limits of both its address and its stack
+    fn acquire(
+        rate_limiters: &RateLimiters,
+        sui_address: &str,
+        stack_small_id: i64,
+        num_tokens: i64,
+    ) -> Result<RateLimitPermit, RateLimitError> {
+        rate_limiters
+            .acquire(sui_address, num_tokens)
+            .and_then(|permit| rate_limiters.acquire_stack(permit, stack_small_id, num_tokens))
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(60, Duration::from_secs(60), now);
+        assert!(bucket.wait_time(60).is_zero());
+        bucket.consume(100);
+        assert_eq!(bucket.wait_time(1), Duration::from_secs(1));
+        // NOTE: Costs above the capacity only require a full bucket
+        assert_eq!(bucket.wait_time(100), Duration::from_secs(60));
+
+        bucket.refill(now + Duration::from_secs(30));
+        assert_eq!(bucket.status().remaining, 30);
+        bucket.refill(now + Duration::from_secs(120));
+        assert!(bucket.is_full());
+    }
+
+    #[test]
+    fn test_requests_per_second_limit() {
+        let rate_limiters = rate_limiters(
+            RateLimits {
+                requests_per_second: Some(2),
+                ..Default::default()
+            },
+            RateLimits::default(),
+        );
+        let _first = acquire(&rate_limiters, SUI_ADDRESS, 1, 10).unwrap();
+        let _second = acquire(&rate_limiters, SUI_ADDRESS, 2, 10).unwrap();
+        assert!(matches!(
+            acquire(&rate_limiters, SUI_ADDRESS, 3, 10),
+            Err(RateLimitError::Exceeded { retry_after, .. }) if !retry_after.is_zero()
+        ));
+        // NOTE: Other addresses are limited independently
+        assert!(acquire(&rate_limiters, "0x2", 1, 10).is_ok());
+    }
+
+    #[test]
+    fn test_concurrent_requests_limit() {
+        let rate_limiters = rate_limiters(
+            RateLimits::default(),
+            RateLimits {
+                max_concurrent_requests: Some(1),
+                ..Default::default()
+            },
+        );
+        let permit = acquire(&rate_limiters, SUI_ADDRESS, 1, 10).unwrap();
+        assert!(matches!(
+            acquire(&rate_limiters, "0x2", 1, 10),
+            Err(RateLimitError::Exceeded { retry_after, .. }) if retry_after == CONCURRENT_REQUESTS_RETRY_AFTER
+        ));
+        drop(permit);
+        assert!(acquire(&rate_limiters, "0x2", 1, 10).is_ok());
+    }
+
+    #[test]
+    fn test_tokens_per_minute_limit() {
+        let rate_limiters = rate_limiters(
+            RateLimits::default(),
+            RateLimits {
+                tokens_per_minute: Some(1_000),
+                ..Default::default()
+            },
+        );
+        let permit = acquire(&rate_limiters, SUI_ADDRESS, 1, 600).unwrap();
+        let mut headers = HeaderMap::new();
+        permit.status.insert_headers(&mut headers);
+        assert_eq!(headers[RATE_LIMIT_LIMIT_TOKENS], "1000");
+        assert_eq!(headers[RATE_LIMIT_REMAINING_TOKENS], "400");
+        assert!(headers.get(RATE_LIMIT_LIMIT_REQUESTS).is_none());
+
+        let Err(RateLimitError::Exceeded {
+            retry_after,
+            status,
+            ..
+        }) = acquire(&rate_limiters, SUI_ADDRESS, 1, 600)
+        else {
+            panic!("Request should exceed the tokens per minute limit");
+        };
+        // NOTE: 200 more tokens are refilled in 12 seconds
+        assert!(retry_after > Duration::from_secs(11) && retry_after <= Duration::from_secs(12));
+        assert_eq!(status.tokens.unwrap().remaining, 400);
+    }
+
+    #[test]
+    fn test_zero_limit() {
+        assert!(matches!(
+            RateLimiters::from_config(&RateLimitConfig {
+                per_address: None,
+                per_stack: Some(RateLimits {
+                    requests_per_second: Some(0),
+                    ..Default::default()
+                }),
+            }),
+            Err(RateLimitError::ZeroLimit {
+                scope: PER_STACK_SCOPE,
+                limit: "requests_per_second"
+            })
+        ));
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_utils::{constants::PAYLOAD_HASH_SIZE, hashing::blake2b_hash};
+use flume::Sender as FlumeSender;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::{error, warn};
+
+use crate::config::ReplayProtectionConfig;
+
+/// Freshness fields of a signed request, covered by its signature
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestFreshness {
+    /// Unix time, in seconds, at which the request was signed
+    pub timestamp: Option<u64>,
+    /// Client-chosen value making the request unique
+    pub nonce: Option<String>,
+}
+
+impl RequestFreshness {
+    /// Returns whether the request carries neither a timestamp nor a nonce
+    pub fn is_empty(&self) -> bool {
+        self.timestamp.is_none() && self.nonce.is_none()
+    }
+}
+
+/// Computes the digest signed by clients sending the freshness fields of their request as
+/// the `X-Request-Timestamp` and `X-Request-Nonce` headers.
+///
+/// The digest is the Blake2b hash of the request body's hash, followed by the timestamp as
+/// 8 big-endian bytes (zero if absent), and by the bytes of the nonce (empty if absent).
+/// Clients sending them as body fields instead sign the body's hash, as usual.
+pub fn signed_request_digest(
+    body_hash: &[u8; PAYLOAD_HASH_SIZE],
+    freshness: &RequestFreshness,
+) -> [u8; PAYLOAD_HASH_SIZE] {
+    let timestamp = freshness.timestamp.unwrap_or_default().to_be_bytes();
+    let nonce = freshness.nonce.as_deref().unwrap_or_default().as_bytes();
+    blake2b_hash(&[body_hash.as_slice(), timestamp.as_slice(), nonce].concat()).into()
+}
+
+/// A request whose signature was verified by the signature verification middleware
+#[derive(Clone, Debug)]
+pub struct SignedRequest {
+    /// Key identifying the request, hashing its signer's address and the signed digest.
+    ///
+    /// NOTE: The signature itself is not used, as ECDSA signatures are malleable, so a
+    /// replayed request could carry a different, yet valid, signature.
+    pub signature_key: [u8; PAYLOAD_HASH_SIZE],
+    /// Freshness fields of the request
+    pub freshness: RequestFreshness,
+}
+
+impl SignedRequest {
+    /// Creates a signed request from the signer's address, and the digest it signed
+    pub fn new(signer: &[u8], signed_digest: &[u8], freshness: RequestFreshness) -> Self {
+        Self {
+            signature_key: blake2b_hash(&[signer, signed_digest].concat()).into(),
+            freshness,
+        }
+    }
+}
+
+/// Signed requests recently served by the node, kept in memory in front of the database.
+///
+/// Once full, the oldest entries are evicted first. Evicted requests are still rejected if
+/// replayed, as the database remembers them until they expire.
+#[derive(Debug, Default)]
+struct SeenSignatures {
+    /// Unix time, in seconds, at which each remembered request expires
+    expiries: HashMap<[u8; PAYLOAD_HASH_SIZE], u64>,
+    /// Remembered requests, in insertion order
+    order: VecDeque<[u8; PAYLOAD_HASH_SIZE]>,
+}
+
+impl SeenSignatures {
+    /// Returns whether a request is remembered, and has not expired
+    fn contains(&self, signature_key: &[u8; PAYLOAD_HASH_SIZE], now: u64) -> bool {
+        self.expiries
+            .get(signature_key)
+            .is_some_and(|expires_at| *expires_at >= now)
+    }
+
+    /// Remembers a request, evicting the oldest ones beyond `capacity`
+    fn insert(&mut self, signature_key: [u8; PAYLOAD_HASH_SIZE], expires_at: u64, capacity: usize) {
+        if self.expiries.insert(signature_key, expires_at).is_none() {
+            self.order.push_back(signature_key);
+        }
+        while self.order.len() > capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.expiries.remove(&evicted);
+            }
+        }
+    }
+
+    /// Forgets a request
+    fn remove(&mut self, signature_key: &[u8; PAYLOAD_HASH_SIZE]) {
+        if self.expiries.remove(signature_key).is_some() {
+            self.order.retain(|key| key != signature_key);
+        }
+    }
+}
+
+/// Replay protection of the signed requests to the Atoma Service.
+///
+/// Requests carrying a timestamp are only served while it is within the freshness window of
+/// the node's clock, and at most once during that window. Requests carrying a nonce must also
+/// carry a timestamp, as the node only remembers requests for as long as they are fresh.
+/// Requests carrying neither are served as is, unless timestamps are required.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    /// Whether signed requests without a timestamp are rejected
+    require_timestamp: bool,
+    /// Maximum difference, in seconds, between a request's timestamp and the node's clock
+    freshness_window: u64,
+    /// Maximum number of seen requests remembered in memory
+    max_cached_signatures: usize,
+    /// Seen requests remembered in memory
+    seen: Mutex<SeenSignatures>,
+    /// Last time expired requests were deleted from the database
+    last_prune: Mutex<Instant>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::from_config(&ReplayProtectionConfig::default())
+            .expect("Default replay protection configuration is valid")
+    }
+}
+
+impl ReplayGuard {
+    /// Creates the replay protection of signed requests, from its configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the freshness window is shorter than a second, the resolution of
+    /// request timestamps.
+    pub fn from_config(config: &ReplayProtectionConfig) -> Result<Self, ReplayError> {
+        let freshness_window = config.freshness_window.as_secs();
+        if freshness_window == 0 {
+            return Err(ReplayError::ZeroFreshnessWindow);
+        }
+        Ok(Self {
+            require_timestamp: config.require_timestamp,
+            freshness_window,
+            max_cached_signatures: config.max_cached_signatures,
+            seen: Mutex::new(SeenSignatures::default()),
+            last_prune: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Checks that a signed request is fresh, and records it as seen so that its replays are
+    /// rejected, including by other instances of the node sharing its database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request's timestamp is missing while required or along with a
+    /// nonce, or outside of the freshness window, if the request was already seen, or if it
+    /// cannot be recorded.
+    pub async fn check(
+        &self,
+        request: &SignedRequest,
+        state_manager_sender: &FlumeSender<AtomaAtomaStateManagerEvent>,
+    ) -> Result<(), ReplayError> {
+        self.check_at(request, unix_now(), state_manager_sender)
+            .await
+    }
+
+    /// Checks a signed request as `check` does, at the given Unix time, in seconds
+    async fn check_at(
+        &self,
+        request: &SignedRequest,
+        now: u64,
+        state_manager_sender: &FlumeSender<AtomaAtomaStateManagerEvent>,
+    ) -> Result<(), ReplayError> {
+        let expires_at = match request.freshness.timestamp {
+            Some(timestamp) => {
+                if timestamp.abs_diff(now) > self.freshness_window {
+                    return Err(ReplayError::StaleTimestamp {
+                        timestamp,
+                        now,
+                        freshness_window: Duration::from_secs(self.freshness_window),
+                    });
+                }
+                timestamp.saturating_add(self.freshness_window)
+            }
+            None if self.require_timestamp => return Err(ReplayError::MissingTimestamp),
+            // NOTE: Without a timestamp, a nonce would only be remembered for a while, after
+            // which the same signed request would be served again
+            None if request.freshness.nonce.is_some() => {
+                return Err(ReplayError::NonceWithoutTimestamp)
+            }
+            None => return Ok(()),
+        };
+
+        if self
+            .seen
+            .lock()
+            .unwrap()
+            .contains(&request.signature_key, now)
+        {
+            return Err(self.reject());
+        }
+        let (result_sender, result_receiver) = oneshot::channel();
+        state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::RecordSeenRequestSignature {
+                signature_key: request.signature_key,
+                expires_at: i64::try_from(expires_at).unwrap_or(i64::MAX),
+                now: i64::try_from(now).unwrap_or(i64::MAX),
+                result_sender,
+            })
+            .map_err(|e| ReplayError::StateManager(e.to_string()))?;
+        let is_new_request = result_receiver
+            .await
+            .map_err(|e| ReplayError::StateManager(e.to_string()))?
+            .map_err(|e| ReplayError::StateManager(e.to_string()))?;
+        if !is_new_request {
+            return Err(self.reject());
+        }
+        self.seen.lock().unwrap().insert(
+            request.signature_key,
+            expires_at,
+            self.max_cached_signatures,
+        );
+
+        self.prune_expired(now, state_manager_sender);
+        Ok(())
+    }
+
+    /// Forgets a signed request recorded as seen by `check`, as it was rejected before being
+    /// served (e.g. because of rate limits), so that it can be retried.
+    pub fn release(
+        &self,
+        request: &SignedRequest,
+        state_manager_sender: &FlumeSender<AtomaAtomaStateManagerEvent>,
+    ) {
+        if request.freshness.timestamp.is_none() {
+            return;
+        }
+        self.seen.lock().unwrap().remove(&request.signature_key);
+        if let Err(e) =
+            state_manager_sender.send(AtomaAtomaStateManagerEvent::ReleaseSeenRequestSignature {
+                signature_key: request.signature_key,
+            })
+        {
+            error!(
+                target = "atoma-service",
+                event = "replay_release_error",
+                error = %e,
+                "Failed to release the seen request signature"
+            );
+        }
+    }
+
+    /// Records the rejection of a replayed request, and returns the corresponding error
+    fn reject(&self) -> ReplayError {
+        warn!(
+            target = "atoma-service",
+            event = "replay_rejected",
+            "Rejecting request, as it was already served"
+        );
+        ReplayError::Replayed
+    }
+
+    /// Deletes the expired requests from the database, at most once per freshness window
+    fn prune_expired(
+        &self,
+        now: u64,
+        state_manager_sender: &FlumeSender<AtomaAtomaStateManagerEvent>,
+    ) {
+        {
+            let mut last_prune = self.last_prune.lock().unwrap();
+            if last_prune.elapsed() < Duration::from_secs(self.freshness_window) {
+                return;
+            }
+            *last_prune = Instant::now();
+        }
+        if let Err(e) =
+            state_manager_sender.send(AtomaAtomaStateManagerEvent::PruneSeenRequestSignatures {
+                now: i64::try_from(now).unwrap_or(i64::MAX),
+            })
+        {
+            error!(
+                target = "atoma-service",
+                event = "replay_prune_error",
+                error = %e,
+                "Failed to prune the expired seen request signatures"
+            );
+        }
+    }
+}
+
+/// Returns the current Unix time, in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Replay protection freshness window must be at least one second")]
+    ZeroFreshnessWindow,
+    #[error("Signed requests must carry a timestamp")]
+    MissingTimestamp,
+    #[error("Signed requests carrying a nonce must carry a timestamp")]
+    NonceWithoutTimestamp,
+    #[error(
+        "Request timestamp {timestamp} is not within {freshness_window:?} of the node's clock ({now})"
+    )]
+    StaleTimestamp {
+        timestamp: u64,
+        now: u64,
+        freshness_window: Duration,
+    },
+    #[error("Request was already served")]
+    Replayed,
+    #[error("Failed to record the request as seen: {0}")]
+    StateManager(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spawns a state manager recording seen requests in memory, as the database would
+    fn state_manager() -> FlumeSender<AtomaAtomaStateManagerEvent> {
+        let (sender, receiver) = flume::unbounded();
+        tokio::spawn(async move {
+            let mut seen = HashMap::new();
+            while let Ok(event) = receiver.recv_async().await {
+                match event {
+                    AtomaAtomaStateManagerEvent::RecordSeenRequestSignature {
+                        signature_key,
+                        expires_at,
+                        now,
+                        result_sender,
+                    } => {
+                        let is_new_request = !matches!(
+                            seen.get(&signature_key),
+                            Some(seen_expires_at) if *seen_expires_at >= now
+                        );
+                        if is_new_request {
+                            seen.insert(signature_key, expires_at);
+                        }
+                        let _ = result_sender.send(Ok(is_new_request));
+                    }
+                    AtomaAtomaStateManagerEvent::ReleaseSeenRequestSignature { signature_key } => {
+                        seen.remove(&signature_key);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        sender
+    }
+
+    fn request(timestamp: Option<u64>, nonce: Option<&str>) -> SignedRequest {
+        SignedRequest::new(
+            &[1u8; 32],
+            &[2u8; 32],
+            RequestFreshness {
+                timestamp,
+                nonce: nonce.map(str::to_string),
+            },
+        )
+    }
+
+    fn replay_guard(require_timestamp: bool, max_cached_signatures: usize) -> ReplayGuard {
+        ReplayGuard::from_config(&ReplayProtectionConfig {
+            require_timestamp,
+            freshness_window: Duration::from_secs(60),
+            max_cached_signatures,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replayed_request_is_rejected() {
+        let state_manager = state_manager();
+        let replay_guard = replay_guard(false, 10);
+        let request = request(Some(unix_now()), None);
+        assert!(replay_guard.check(&request, &state_manager).await.is_ok());
+        assert!(matches!(
+            replay_guard.check(&request, &state_manager).await,
+            Err(ReplayError::Replayed)
+        ));
+
+        // NOTE: Without the in-memory entry, the database still rejects the replay
+        let replay_guard = self::replay_guard(false, 0);
+        assert!(matches!(
+            replay_guard.check(&request, &state_manager).await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stale_timestamp_is_rejected() {
+        let state_manager = state_manager();
+        let replay_guard = replay_guard(false, 10);
+        for timestamp in [unix_now() - 120, unix_now() + 120] {
+            assert!(matches!(
+                replay_guard
+                    .check(&request(Some(timestamp), None), &state_manager)
+                    .await,
+                Err(ReplayError::StaleTimestamp { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_timestamp() {
+        let state_manager = state_manager();
+        let replay_guard = replay_guard(false, 10);
+        let unprotected = request(None, None);
+        for _ in 0..2 {
+            assert!(replay_guard
+                .check(&unprotected, &state_manager)
+                .await
+                .is_ok());
+        }
+        let with_nonce = request(None, Some("nonce"));
+        assert!(matches!(
+            replay_guard.check(&with_nonce, &state_manager).await,
+            Err(ReplayError::NonceWithoutTimestamp)
+        ));
+
+        let replay_guard = self::replay_guard(true, 10);
+        assert!(matches!(
+            replay_guard
+                .check(&request(None, Some("other nonce")), &state_manager)
+                .await,
+            Err(ReplayError::MissingTimestamp)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_nonce_only_request_is_not_replayable_after_window() {
+        let state_manager = state_manager();
+        let replay_guard = replay_guard(false, 10);
+        let now = unix_now();
+        // NOTE: A request carrying only a nonce would be forgotten once the freshness window
+        // elapsed, so it is never served
+        let nonce_only = request(None, Some("nonce"));
+        for now in [now, now + 61] {
+            assert!(matches!(
+                replay_guard
+                    .check_at(&nonce_only, now, &state_manager)
+                    .await,
+                Err(ReplayError::NonceWithoutTimestamp)
+            ));
+        }
+        // NOTE: Once its timestamp is no longer fresh, a request is rejected as stale instead
+        let with_nonce = request(Some(now), Some("nonce"));
+        assert!(replay_guard
+            .check_at(&with_nonce, now, &state_manager)
+            .await
+            .is_ok());
+        assert!(matches!(
+            replay_guard
+                .check_at(&with_nonce, now + 61, &state_manager)
+                .await,
+            Err(ReplayError::StaleTimestamp { .. })
+        ));
+        let replay_guard = self::replay_guard(false, 0);
+        assert!(matches!(
+            replay_guard
+                .check_at(&with_nonce, now + 61, &state_manager)
+                .await,
+            Err(ReplayError::StaleTimestamp { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_released_request_can_be_retried() {
+        let state_manager = state_manager();
+        let replay_guard = replay_guard(false, 10);
+        let request = request(Some(unix_now()), Some("nonce"));
+        assert!(replay_guard.check(&request, &state_manager).await.is_ok());
+        replay_guard.release(&request, &state_manager);
+        assert!(replay_guard.check(&request, &state_manager).await.is_ok());
+        assert!(matches!(
+            replay_guard.check(&request, &state_manager).await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[test]
+    fn test_seen_signatures_are_bounded() {
+        let mut seen = SeenSignatures::default();
+        for key in 0..5u8 {
+            seen.insert([key; 32], 100, 3);
+        }
+        assert_eq!(seen.expiries.len(), 3);
+        assert!(!seen.contains(&[1; 32], 0));
+        assert!(seen.contains(&[4; 32], 0));
+        assert!(!seen.contains(&[4; 32], 101));
+        seen.remove(&[4; 32]);
+        assert!(!seen.contains(&[4; 32], 0));
+        assert_eq!(seen.order.len(), 2);
+    }
+
+    #[test]
+    fn test_signed_request_digest() {
+        let body_hash = [3u8; 32];
+        let timestamp = RequestFreshness {
+            timestamp: Some(1),
+            nonce: None,
+        };
+        let nonce = RequestFreshness {
+            timestamp: None,
+            nonce: Some("1".to_string()),
+        };
+        assert_ne!(
+            signed_request_digest(&body_hash, &timestamp),
+            signed_request_digest(&body_hash, &nonce)
+        );
+        assert_ne!(signed_request_digest(&body_hash, &timestamp), body_hash);
+    }
+}
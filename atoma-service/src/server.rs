@@ -6,12 +6,14 @@ use atoma_confidential::types::{
     ConfidentialComputeSharedSecretRequest, ConfidentialComputeSharedSecretResponse,
 };
 use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_utils::signature::ZkLoginVerifier;
 use axum::{
     body::Body,
+    extract::{DefaultBodyLimit, State},
     middleware::{from_fn, from_fn_with_state},
     response::{IntoResponse, Response},
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 use flume::Sender as FlumeSender;
 use hyper::StatusCode;
@@ -33,12 +35,26 @@ use tracing::error;
 use utoipa::OpenApi;
 
 use crate::{
+    admission::AdmissionController,
+    backends::ModelBackends,
+    chat_template::ChatTemplate,
     components::openapi::openapi_routes,
+    config::{ModelMetadata, TaskRolesConfig, UsageReconciliationPolicy},
     handlers::{
+        audio::{
+            audio_transcriptions_handler, confidential_audio_transcriptions_handler,
+            confidential_speech_handler, speech_handler, AUDIO_TRANSCRIPTIONS_PATH,
+            CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH, CONFIDENTIAL_SPEECH_PATH,
+            MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE, SPEECH_PATH,
+        },
         chat_completions::{
             chat_completions_handler, confidential_chat_completions_handler, CHAT_COMPLETIONS_PATH,
             CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
         },
+        completions::{
+            completions_handler, confidential_completions_handler, COMPLETIONS_PATH,
+            CONFIDENTIAL_COMPLETIONS_PATH,
+        },
         embeddings::{
             confidential_embeddings_handler, embeddings_handler, CONFIDENTIAL_EMBEDDINGS_PATH,
             EMBEDDINGS_PATH,
@@ -47,11 +63,19 @@ use crate::{
             confidential_image_generations_handler, image_generations_handler,
             CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_GENERATIONS_PATH,
         },
+        models::{model_handler, models_handler, MODELS_PATH, MODEL_PATH},
+        quote::{quote_handler, QUOTE_PATH},
+        rerank::{
+            confidential_rerank_handler, rerank_handler, CONFIDENTIAL_RERANK_PATH, RERANK_PATH,
+        },
+        stacks::{stack_usage_handler, STACK_USAGE_PATH},
     },
     middleware::{
-        confidential_compute_middleware, signature_verification_middleware,
-        verify_stack_permissions,
+        confidential_compute_middleware, replay_protection_middleware,
+        signature_verification_middleware, verify_stack_permissions,
     },
+    rate_limit::RateLimiters,
+    replay::ReplayGuard,
 };
 
 /// The path for the health check endpoint.
@@ -66,8 +90,15 @@ type StackSmallId = u64;
 /// Represents the number of compute units available, stored as a 64-bit unsigned integer.
 type ComputeUnits = u64;
 
+/// A small identifier for a Task, represented as a 64-bit unsigned integer.
+type TaskSmallId = u64;
+
 /// Represents the result of a blockchain query for stack information.
-type StackQueryResult = (Option<StackSmallId>, Option<ComputeUnits>);
+type StackQueryResult = (
+    Option<StackSmallId>,
+    Option<ComputeUnits>,
+    Option<TaskSmallId>,
+);
 
 /// Represents a request for confidential compute decryption.
 type DecryptionRequest = (
@@ -121,6 +152,19 @@ pub struct AppState {
     /// shared secret computation across different components.
     pub compute_shared_secret_sender: UnboundedSender<SharedSecretRequest>,
 
+    /// Channel receiver for the node's TEE attestation status.
+    ///
+    /// Holds `true` only while the node's current public key is backed by a TEE
+    /// remote attestation submitted on-chain, and is required to serve stacks
+    /// whose task demands confidential compute.
+    pub tee_attestation_receiver: Receiver<bool>,
+
+    /// Channel receiver for the current Sui epoch.
+    ///
+    /// Holds `None` until the epoch is first fetched, and is used to keep serving stacks of
+    /// deprecated tasks until their `valid_until_epoch`.
+    pub current_epoch_receiver: Receiver<Option<u64>>,
+
     /// Channel sender for requesting compute units from the blockchain.
     pub stack_retrieve_sender:
         mpsc::UnboundedSender<(TransactionDigest, i64, oneshot::Sender<StackQueryResult>)>,
@@ -140,24 +184,81 @@ pub struct AppState {
     /// models as needed.
     pub models: Arc<Vec<String>>,
 
-    /// URL of the chat completions service.
+    /// Number of input tokens charged for each image in a chat completion request.
     ///
-    /// This URL points to the external service responsible for performing
-    /// AI model chat completions. The application forwards requests to this
-    /// service to obtain AI-generated responses.
-    pub chat_completions_service_url: String,
+    /// Images are not tokenized by the node, so each `image_url` content part
+    /// is estimated at this fixed cost when reserving compute units.
+    pub num_tokens_per_image: i64,
 
-    /// URL for the embeddings service.
+    /// Policy for the token usage charged to stacks.
     ///
-    /// This is an optional field that, if provided, specifies the endpoint
-    /// for the embeddings service used by the Atoma Service.
-    pub embeddings_service_url: String,
+    /// Selects whether the token counts reported by the inference services,
+    /// the ones counted with the models' tokenizers, or the maximum of both,
+    /// are charged for chat completion and completion requests.
+    pub usage_reconciliation: UsageReconciliationPolicy,
 
-    /// URL for the image generations service.
+    /// Roles of the tasks serving each type of inference request.
     ///
-    /// This is an optional field that, if provided, specifies the endpoint
-    /// for the image generations service used by the Atoma Service.
-    pub image_generations_service_url: String,
+    /// Requests are only served on stacks whose task has the role of the
+    /// requested endpoint, if configured.
+    pub task_roles: TaskRolesConfig,
+
+    /// Chat templates of the available AI models.
+    ///
+    /// Each entry holds the chat template of the model at the same index in
+    /// `models`, if the model's HuggingFace repository provides one. Templates
+    /// are used to count the exact number of prompt tokens of chat completion
+    /// requests.
+    pub chat_templates: Arc<Vec<Option<ChatTemplate>>>,
+
+    /// Token limits of the available AI models.
+    ///
+    /// Each entry holds the context length and `max_tokens` bounds of a model
+    /// in `models`, which are enforced on chat completion requests before
+    /// compute units are reserved for them. Models without an entry are
+    /// not limited.
+    pub model_metadata: Arc<Vec<ModelMetadata>>,
+
+    /// Small IDs of the node badges held by the node.
+    ///
+    /// The tasks these nodes are subscribed to are listed, for each
+    /// model, by the models endpoint.
+    pub node_small_ids: Arc<Vec<i64>>,
+
+    /// Inference backends of the available AI models.
+    ///
+    /// These backends point to the external services responsible for
+    /// performing chat completions, embeddings and image generations for
+    /// each model. The application forwards requests to the backend of the
+    /// requested model to obtain AI-generated responses.
+    pub backends: Arc<ModelBackends>,
+
+    /// Admission control of the available AI models.
+    ///
+    /// Requests are admitted to the inference backend of their model only
+    /// while it serves fewer requests than it is configured for, and wait
+    /// in the model's admission queue otherwise.
+    pub admission: Arc<AdmissionController>,
+
+    /// Rate limiters of the requests of each Sui address, and on each stack.
+    ///
+    /// These limiters bound the requests per second, concurrent requests
+    /// and tokens per minute that a single client can consume, and are
+    /// enforced before compute units are reserved for a request.
+    pub rate_limiters: Arc<RateLimiters>,
+
+    /// Replay protection of the signed requests.
+    ///
+    /// Remembers the signed requests carrying a timestamp or a nonce
+    /// that were already served, backed by the state manager's database,
+    /// so that their replays are rejected even across restarts.
+    pub replay_guard: Arc<ReplayGuard>,
+
+    /// Verifier of the zkLogin signatures, if they are accepted.
+    ///
+    /// Holds the cached JWKs of the OpenID providers, and the current
+    /// Sui epoch, against which zkLogin signatures are verified offline.
+    pub zklogin_verifier: Option<Arc<ZkLoginVerifier>>,
 
     /// The Sui keystore of the node.
     ///
@@ -201,20 +302,37 @@ pub fn create_router(app_state: AppState) -> Router {
             CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
             post(confidential_chat_completions_handler),
         )
+        .route(
+            CONFIDENTIAL_COMPLETIONS_PATH,
+            post(confidential_completions_handler),
+        )
         .route(
             CONFIDENTIAL_EMBEDDINGS_PATH,
             post(confidential_embeddings_handler),
         )
+        .route(CONFIDENTIAL_RERANK_PATH, post(confidential_rerank_handler))
         .route(
             CONFIDENTIAL_IMAGE_GENERATIONS_PATH,
             post(confidential_image_generations_handler),
         )
+        .route(
+            CONFIDENTIAL_AUDIO_TRANSCRIPTIONS_PATH,
+            post(confidential_audio_transcriptions_handler)
+                .layer(DefaultBodyLimit::max(MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE)),
+        )
+        .route(CONFIDENTIAL_SPEECH_PATH, post(confidential_speech_handler))
         .layer(
             ServiceBuilder::new()
                 .layer(from_fn_with_state(
                     app_state.clone(),
                     confidential_compute_middleware,
                 ))
+                // NOTE: The signature of confidential requests is verified when decrypting
+                // them, so replays are only detected after the confidential compute middleware
+                .layer(from_fn_with_state(
+                    app_state.clone(),
+                    replay_protection_middleware,
+                ))
                 .layer(from_fn_with_state(
                     app_state.clone(),
                     verify_stack_permissions,
@@ -222,23 +340,66 @@ pub fn create_router(app_state: AppState) -> Router {
                 .into_inner(),
         )
         .with_state(app_state.clone());
+    // NOTE: Quotes do not reserve compute units, so they skip the stack permissions and
+    // replay protection middlewares
+    let quote_routes = Router::new()
+        .route(QUOTE_PATH, post(quote_handler))
+        .layer(
+            ServiceBuilder::new()
+                .option_layer(app_state.zklogin_verifier.clone().map(Extension))
+                .layer(from_fn(signature_verification_middleware))
+                .into_inner(),
+        )
+        .with_state(app_state.clone());
+    // NOTE: Stack usage requests are only signed by the stack's owner, and do not reserve
+    // compute units, so they skip the stack permissions middleware
+    let stack_routes = Router::new()
+        .route(STACK_USAGE_PATH, get(stack_usage_handler))
+        .layer(
+            ServiceBuilder::new()
+                .option_layer(app_state.zklogin_verifier.clone().map(Extension))
+                .layer(from_fn(signature_verification_middleware))
+                .layer(from_fn_with_state(
+                    app_state.clone(),
+                    replay_protection_middleware,
+                ))
+                .into_inner(),
+        )
+        .with_state(app_state.clone());
     Router::new()
         .route(CHAT_COMPLETIONS_PATH, post(chat_completions_handler))
+        .route(COMPLETIONS_PATH, post(completions_handler))
         .route(EMBEDDINGS_PATH, post(embeddings_handler))
+        .route(RERANK_PATH, post(rerank_handler))
         .route(IMAGE_GENERATIONS_PATH, post(image_generations_handler))
+        .route(
+            AUDIO_TRANSCRIPTIONS_PATH,
+            post(audio_transcriptions_handler)
+                .layer(DefaultBodyLimit::max(MAX_AUDIO_TRANSCRIPTIONS_BODY_SIZE)),
+        )
+        .route(SPEECH_PATH, post(speech_handler))
         .layer(
             ServiceBuilder::new()
+                .option_layer(app_state.zklogin_verifier.clone().map(Extension))
                 .layer(from_fn(signature_verification_middleware))
+                .layer(from_fn_with_state(
+                    app_state.clone(),
+                    replay_protection_middleware,
+                ))
                 .layer(from_fn_with_state(
                     app_state.clone(),
                     verify_stack_permissions,
                 ))
                 .into_inner(),
         )
-        .with_state(app_state)
         .route(HEALTH_PATH, get(health))
+        .route(MODELS_PATH, get(models_handler))
+        .route(MODEL_PATH, get(model_handler))
+        .with_state(app_state)
         .route(METRICS_PATH, get(metrics_handler))
         .merge(confidential_routes)
+        .merge(quote_routes)
+        .merge(stack_routes)
         .merge(openapi_routes())
 }
 
@@ -305,8 +466,12 @@ pub(crate) struct HealthOpenApi;
 ///
 /// # Returns
 ///
-/// Returns a static string "OK" to indicate that the server is healthy and
-/// functioning properly.
+/// Returns the health of each inference backend and of its replicas, along with
+/// an overall status:
+/// - `ok` if every backend has an available replica
+/// - `degraded` if some backends have no available replica
+/// - `unavailable`, with a `503 Service Unavailable` status code, if no backend
+///   has an available replica
 ///
 /// # Examples
 ///
@@ -320,11 +485,27 @@ pub(crate) struct HealthOpenApi;
     path = "",
     tag = "health",
     responses(
-        (status = OK, description = "Service is healthy", body = Value)
+        (status = OK, description = "Service is healthy, or degraded", body = Value),
+        (status = SERVICE_UNAVAILABLE, description = "No inference backend is available", body = Value)
     )
 )]
-async fn health() -> impl IntoResponse {
-    Json(json!({ "status": "ok" }))
+async fn health(State(state): State<AppState>) -> impl IntoResponse {
+    let backends = state.backends.health();
+    let num_available_backends = backends
+        .iter()
+        .filter(|backend| backend.is_available)
+        .count();
+    let (status_code, status) = if num_available_backends == backends.len() {
+        (StatusCode::OK, "ok")
+    } else if num_available_backends > 0 {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (
+        status_code,
+        Json(json!({ "status": status, "backends": backends })),
+    )
 }
 
 /// OpenAPI documentation for the metrics endpoint.
@@ -374,18 +555,19 @@ async fn metrics_handler() -> Result<impl IntoResponse, StatusCode> {
 pub(crate) mod utils {
     use super::*;
 
-    use atoma_utils::hashing::blake2b_hash;
+    use atoma_utils::hashing::{blake2b_hash, JsonCanonicalization};
     use sui_keys::keystore::AccountKeystore;
     use sui_sdk::types::crypto::EncodeDecodeBase64;
 
     /// Signs a JSON response body using the node's Sui keystore.
     ///
-    /// This function takes a JSON response body, converts it to bytes, creates a SHA-256 hash,
-    /// and signs it using the Sui keystore with the specified address.
+    /// This function takes a JSON response body, serializes it with the given canonicalization,
+    /// creates a Blake2b hash, and signs it using the Sui keystore with the specified address.
     ///
     /// # Arguments
     ///
     /// * `response_body` - The JSON response body to be signed
+    /// * `json_canonicalization` - The JSON canonicalization used to serialize the response body
     /// * `keystore` - The Sui keystore containing the signing keys
     /// * `address_index` - The index of the address to use for signing within the keystore
     ///
@@ -402,17 +584,41 @@ pub(crate) mod utils {
     /// * The SHA-256 hash cannot be converted to a 32-byte array
     pub(crate) fn sign_response_body(
         response_body: &Value,
+        json_canonicalization: JsonCanonicalization,
         keystore: &FileBasedKeystore,
         address_index: usize,
     ) -> anyhow::Result<([u8; 32], String)> {
         let address = keystore.addresses()[address_index];
-        let response_body_str = response_body.to_string();
-        let response_body_bytes = response_body_str.as_bytes();
-        let blake2b_hash = blake2b_hash(response_body_bytes);
+        let blake2b_hash = json_canonicalization.hash(response_body);
         let signature = keystore.sign_hashed(&address, blake2b_hash.as_slice())?;
         Ok((
             blake2b_hash.as_slice().try_into()?,
             signature.encode_base64(),
         ))
     }
+
+    /// Signs a response body that is not JSON (e.g. generated audio) using the node's Sui
+    /// keystore, from the Blake2b hash of its bytes.
+    ///
+    /// # Returns
+    ///
+    /// Returns a tuple containing the 32-byte Blake2b hash of the response body, and the
+    /// base64-encoded signature of the hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore fails to sign the hash.
+    pub(crate) fn sign_response_bytes(
+        response_body: &[u8],
+        keystore: &FileBasedKeystore,
+        address_index: usize,
+    ) -> anyhow::Result<([u8; 32], String)> {
+        let address = keystore.addresses()[address_index];
+        let response_hash = blake2b_hash(response_body);
+        let signature = keystore.sign_hashed(&address, response_hash.as_slice())?;
+        Ok((
+            response_hash.as_slice().try_into()?,
+            signature.encode_base64(),
+        ))
+    }
 }
@@ -0,0 +1,335 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_utils::{
+    constants::PAYLOAD_HASH_SIZE,
+    hashing::blake2b_hash,
+    signature::{SignatureError, ZkLoginVerifier},
+    verify_signature,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flume::Sender as FlumeSender;
+use serde::{Deserialize, Serialize};
+use sui_sdk::types::{
+    base_types::SuiAddress,
+    crypto::{EncodeDecodeBase64, PublicKey},
+};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// Domain separator of the digests signed by stack owners to grant a session key access
+/// to one of their stacks, so that grants cannot be confused with signed request bodies
+const SESSION_GRANT_DOMAIN: &[u8] = b"atoma-session-grant";
+
+/// Maximum lifetime of a session grant, so that leaked session keys expire quickly
+pub const MAX_SESSION_GRANT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A grant, signed by a stack's owner, allowing a short-lived session key to spend up to
+/// a number of compute units of the stack.
+///
+/// Grants are sent in the `X-Session-Grant` header, as the base64 encoding of their JSON
+/// serialization, alongside a request signed by the session key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionGrant {
+    /// Base64-encoded Sui public key of the session key, prefixed by its scheme flag
+    pub session_public_key: String,
+    /// Small ID of the stack the session key may spend compute units of
+    pub stack_small_id: i64,
+    /// Maximum number of compute units the session key may spend over the grant's lifetime
+    pub max_compute_units: i64,
+    /// Unix time, in seconds, after which the grant is no longer accepted
+    pub expires_at: u64,
+    /// Base64-encoded Sui signature of the grant's digest by the stack's owner
+    pub signature: String,
+}
+
+/// A session grant whose owner signature and caps were checked against a request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedSessionGrant {
+    /// Digest of the grant, identifying it
+    pub grant_id: [u8; PAYLOAD_HASH_SIZE],
+    /// Address of the stack's owner, who signed the grant
+    pub owner_address: SuiAddress,
+    /// Small ID of the stack the session key may spend compute units of
+    pub stack_small_id: i64,
+    /// Maximum number of compute units the session key may spend
+    pub max_compute_units: i64,
+    /// Unix time, in seconds, after which the grant is no longer accepted
+    pub expires_at: u64,
+}
+
+/// Computes the digest signed by a stack's owner to grant a session key access to the stack.
+///
+/// The digest is the Blake2b hash of the `atoma-session-grant` domain separator, followed by
+/// the session public key (its scheme flag, then its bytes), and by the stack small ID, the
+/// maximum number of compute units and the expiry, each as 8 big-endian bytes.
+pub fn session_grant_digest(
+    session_public_key: &[u8],
+    stack_small_id: i64,
+    max_compute_units: i64,
+    expires_at: u64,
+) -> [u8; PAYLOAD_HASH_SIZE] {
+    blake2b_hash(
+        &[
+            SESSION_GRANT_DOMAIN,
+            session_public_key,
+            &stack_small_id.to_be_bytes(),
+            &max_compute_units.to_be_bytes(),
+            &expires_at.to_be_bytes(),
+        ]
+        .concat(),
+    )
+    .into()
+}
+
+impl SessionGrant {
+    /// Parses a session grant from the value of the `X-Session-Grant` header
+    pub fn from_header(value: &str) -> Result<Self, SessionGrantError> {
+        let json = STANDARD
+            .decode(value)
+            .map_err(|e| SessionGrantError::InvalidEncoding(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| SessionGrantError::InvalidEncoding(e.to_string()))
+    }
+
+    /// Encodes the session grant as the value of the `X-Session-Grant` header
+    pub fn to_header(&self) -> String {
+        STANDARD.encode(serde_json::to_vec(self).expect("Session grants serialize to JSON"))
+    }
+
+    /// Returns the digest signed by the stack's owner
+    pub fn digest(&self) -> Result<[u8; PAYLOAD_HASH_SIZE], SessionGrantError> {
+        let session_public_key = STANDARD
+            .decode(&self.session_public_key)
+            .map_err(|e| SessionGrantError::InvalidSessionKey(e.to_string()))?;
+        Ok(session_grant_digest(
+            &session_public_key,
+            self.stack_small_id,
+            self.max_compute_units,
+            self.expires_at,
+        ))
+    }
+
+    /// Returns the address of the session key
+    pub fn session_address(&self) -> Result<SuiAddress, SessionGrantError> {
+        let public_key = PublicKey::decode_base64(&self.session_public_key)
+            .map_err(|e| SessionGrantError::InvalidSessionKey(e.to_string()))?;
+        Ok(SuiAddress::from(&public_key))
+    }
+
+    /// Checks that the grant was signed by the stack's owner, that it has not expired, and
+    /// that it allows the request's signer to spend compute units of the requested stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grant's signature is invalid, if it expired or outlives
+    /// [`MAX_SESSION_GRANT_LIFETIME`], or if it was granted for another stack or session key.
+    pub fn verify(
+        &self,
+        request_signer: SuiAddress,
+        stack_small_id: i64,
+        zklogin_verifier: Option<&ZkLoginVerifier>,
+    ) -> Result<VerifiedSessionGrant, SessionGrantError> {
+        self.verify_at(request_signer, stack_small_id, zklogin_verifier, unix_now())
+    }
+
+    /// Checks the grant, as [`SessionGrant::verify`], at the given Unix time, in seconds
+    fn verify_at(
+        &self,
+        request_signer: SuiAddress,
+        stack_small_id: i64,
+        zklogin_verifier: Option<&ZkLoginVerifier>,
+        now: u64,
+    ) -> Result<VerifiedSessionGrant, SessionGrantError> {
+        let grant_id = self.digest()?;
+        let owner_address = verify_signature(&self.signature, &grant_id, zklogin_verifier)?;
+        if self.expires_at < now {
+            return Err(SessionGrantError::Expired {
+                expires_at: self.expires_at,
+                now,
+            });
+        }
+        if self.expires_at - now > MAX_SESSION_GRANT_LIFETIME.as_secs() {
+            return Err(SessionGrantError::LifetimeTooLong {
+                expires_at: self.expires_at,
+                max_lifetime: MAX_SESSION_GRANT_LIFETIME,
+            });
+        }
+        if self.max_compute_units <= 0 {
+            return Err(SessionGrantError::InvalidComputeUnitCap(
+                self.max_compute_units,
+            ));
+        }
+        if self.stack_small_id != stack_small_id {
+            return Err(SessionGrantError::StackMismatch {
+                granted: self.stack_small_id,
+                requested: stack_small_id,
+            });
+        }
+        if self.session_address()? != request_signer {
+            return Err(SessionGrantError::SignerMismatch);
+        }
+        Ok(VerifiedSessionGrant {
+            grant_id,
+            owner_address,
+            stack_small_id: self.stack_small_id,
+            max_compute_units: self.max_compute_units,
+            expires_at: self.expires_at,
+        })
+    }
+}
+
+impl VerifiedSessionGrant {
+    /// Reserves compute units of the grant for a request, recording the grant's usage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reservation would exceed the grant's compute unit cap, or if
+    /// the usage cannot be recorded.
+    pub async fn reserve_compute_units(
+        &self,
+        num_compute_units: i64,
+        state_manager_sender: &FlumeSender<AtomaAtomaStateManagerEvent>,
+    ) -> Result<(), SessionGrantError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state_manager_sender
+            .send(
+                AtomaAtomaStateManagerEvent::ReserveSessionGrantComputeUnits {
+                    grant_id: self.grant_id,
+                    owner_address: self.owner_address.to_string(),
+                    stack_small_id: self.stack_small_id,
+                    max_compute_units: self.max_compute_units,
+                    expires_at: i64::try_from(self.expires_at).unwrap_or(i64::MAX),
+                    num_compute_units,
+                    result_sender,
+                },
+            )
+            .map_err(|e| SessionGrantError::StateManager(e.to_string()))?;
+        let is_reserved = result_receiver
+            .await
+            .map_err(|e| SessionGrantError::StateManager(e.to_string()))?
+            .map_err(|e| SessionGrantError::StateManager(e.to_string()))?;
+        if !is_reserved {
+            return Err(SessionGrantError::CapExceeded {
+                max_compute_units: self.max_compute_units,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current Unix time, in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Error)]
+pub enum SessionGrantError {
+    #[error("Failed to decode session grant, with error: `{0}`")]
+    InvalidEncoding(String),
+    #[error("Invalid session public key, with error: `{0}`")]
+    InvalidSessionKey(String),
+    #[error("Invalid session grant signature, with error: `{0}`")]
+    Signature(#[from] SignatureError),
+    #[error("Session grant expired at {expires_at} (now: {now})")]
+    Expired { expires_at: u64, now: u64 },
+    #[error("Session grant expiry {expires_at} is further than {max_lifetime:?} away")]
+    LifetimeTooLong {
+        expires_at: u64,
+        max_lifetime: Duration,
+    },
+    #[error("Session grant compute unit cap must be positive, got {0}")]
+    InvalidComputeUnitCap(i64),
+    #[error("Session grant is for stack {granted}, but stack {requested} was requested")]
+    StackMismatch { granted: i64, requested: i64 },
+    #[error("Request was not signed by the session key of the grant")]
+    SignerMismatch,
+    #[error("Session grant compute unit cap of {max_compute_units} would be exceeded")]
+    CapExceeded { max_compute_units: i64 },
+    #[error("Failed to record the session grant usage: {0}")]
+    StateManager(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sui_sdk::types::crypto::{get_key_pair, AccountKeyPair, Signature, SuiKeyPair};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn key_pair() -> SuiKeyPair {
+        let (_, key_pair): (_, AccountKeyPair) = get_key_pair();
+        SuiKeyPair::Ed25519(key_pair)
+    }
+
+    fn grant(owner: &SuiKeyPair, session: &SuiKeyPair, expires_at: u64) -> SessionGrant {
+        let session_public_key = session.public();
+        let session_public_key_bytes =
+            [&[session_public_key.flag()], session_public_key.as_ref()].concat();
+        let digest = session_grant_digest(&session_public_key_bytes, 1, 1000, expires_at);
+        SessionGrant {
+            session_public_key: session_public_key.encode_base64(),
+            stack_small_id: 1,
+            max_compute_units: 1000,
+            expires_at,
+            signature: Signature::new_hashed(&digest, owner).encode_base64(),
+        }
+    }
+
+    #[test]
+    fn test_verify_session_grant() {
+        let (owner, session) = (key_pair(), key_pair());
+        let grant =
+            SessionGrant::from_header(&grant(&owner, &session, NOW + 60).to_header()).unwrap();
+        let session_address = SuiAddress::from(&session.public());
+
+        let verified = grant.verify_at(session_address, 1, None, NOW).unwrap();
+        assert_eq!(verified.owner_address, SuiAddress::from(&owner.public()));
+        assert_eq!(verified.grant_id, grant.digest().unwrap());
+        assert_eq!(verified.max_compute_units, 1000);
+    }
+
+    #[test]
+    fn test_reject_invalid_session_grant() {
+        let (owner, session) = (key_pair(), key_pair());
+        let session_address = SuiAddress::from(&session.public());
+        let grant = grant(&owner, &session, NOW + 60);
+
+        assert!(matches!(
+            grant.verify_at(session_address, 1, None, NOW + 61),
+            Err(SessionGrantError::Expired { .. })
+        ));
+        assert!(matches!(
+            grant.verify_at(session_address, 2, None, NOW),
+            Err(SessionGrantError::StackMismatch {
+                granted: 1,
+                requested: 2
+            })
+        ));
+        assert!(matches!(
+            grant.verify_at(SuiAddress::from(&owner.public()), 1, None, NOW),
+            Err(SessionGrantError::SignerMismatch)
+        ));
+
+        let mut tampered = grant.clone();
+        tampered.max_compute_units = 2000;
+        assert!(matches!(
+            tampered.verify_at(session_address, 1, None, NOW),
+            Err(SessionGrantError::Signature(
+                SignatureError::VerificationFailed
+            ))
+        ));
+
+        let long_lived = self::grant(
+            &owner,
+            &session,
+            NOW + MAX_SESSION_GRANT_LIFETIME.as_secs() + 1,
+        );
+        assert!(matches!(
+            long_lived.verify_at(session_address, 1, None, NOW),
+            Err(SessionGrantError::LifetimeTooLong { .. })
+        ));
+    }
+}
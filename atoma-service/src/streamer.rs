@@ -1,4 +1,7 @@
+mod sse;
+
 use std::{
+    collections::VecDeque,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -7,8 +10,8 @@ use std::{
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use atoma_utils::{
     constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE, SALT_SIZE},
-    encryption::encrypt_plaintext,
-    hashing::blake2b_hash,
+    encryption::encrypt_stream_chunk,
+    hashing::{blake2b_hash, JsonCanonicalization},
 };
 use axum::body::Bytes;
 use axum::{response::sse::Event, Error};
@@ -17,32 +20,34 @@ use flume::Sender as FlumeSender;
 use futures::Stream;
 use prometheus::HistogramTimer;
 use serde_json::{json, Value};
+use sse::{SseEvent, SseParser};
 use sui_keys::keystore::FileBasedKeystore;
-use tracing::{error, instrument};
+use tokenizers::Tokenizer;
+use tracing::{debug, error, instrument};
 use x25519_dalek::SharedSecret;
 
 use crate::{
+    backends::InferenceBackend,
+    config::UsageReconciliationPolicy,
     handlers::{
         prometheus::{
             CHAT_COMPLETIONS_DECODING_TIME, CHAT_COMPLETIONS_INPUT_TOKENS_METRICS,
             CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS,
         },
-        update_stack_num_compute_units, USAGE_KEY,
+        record_stack_receipt, update_stack_num_compute_units, USAGE_KEY,
     },
     server::utils,
+    usage::{choice_texts, count_tokens, reconcile_usage, TokenUsage},
 };
 
 /// The chunk that indicates the end of a streaming response
 const DONE_CHUNK: &str = "[DONE]";
 
-/// The prefix for the data chunk
-const DATA_PREFIX: &str = "data: ";
-
-/// The keep-alive chunk
-const KEEP_ALIVE_CHUNK: &[u8] = b": keep-alive\n\n";
+/// The default event type of server-sent events
+const MESSAGE_EVENT: &str = "message";
 
-/// The keep-alive-text chunk (used by mistralrs)
-const KEEP_ALIVE_TEXT_CHUNK: &[u8] = b"keep-alive-text\n";
+/// The event type used by inference services to report errors mid-stream
+const ERROR_EVENT: &str = "error";
 
 /// The choices key
 const CHOICES: &str = "choices";
@@ -59,6 +64,9 @@ const RESPONSE_HASH_KEY: &str = "response_hash";
 /// The signature key
 const SIGNATURE_KEY: &str = "signature";
 
+/// The final chunk marker key, set on the last encrypted chunk of a stream
+const IS_FINAL_KEY: &str = "is_final";
+
 /// Metadata required for encrypting streaming responses to clients.
 ///
 /// This structure contains the cryptographic elements needed to establish
@@ -66,7 +74,7 @@ const SIGNATURE_KEY: &str = "signature";
 pub struct StreamingEncryptionMetadata {
     /// The shared secret key derived from ECDH key exchange
     pub shared_secret: SharedSecret,
-    /// A unique nonce value to prevent replay attacks
+    /// The base nonce from which the nonce of each encrypted chunk is derived
     pub nonce: [u8; NONCE_SIZE],
     /// Additional randomness used in the encryption process
     pub salt: [u8; SALT_SIZE],
@@ -76,16 +84,34 @@ pub struct StreamingEncryptionMetadata {
 pub struct Streamer {
     /// The stream of bytes from the inference service
     stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
+    /// The parser for the server-sent events framing of the inference service stream
+    sse_parser: SseParser,
+    /// The adapter normalizing the chunks of the inference service stream
+    adapter: &'static dyn InferenceBackend,
+    /// The normalized chunks not yet sent back to the client, as a single chunk of the
+    /// inference service stream can be normalized into several chunks
+    pending_chunks: VecDeque<Value>,
+    /// The policy for the token usage charged to the stack, when the usage reported by the
+    /// inference service differs from the one counted locally
+    usage_reconciliation: UsageReconciliationPolicy,
     /// The accumulated response for final processing
     accumulated_response: Vec<Value>,
+    /// The latest usage reported by the inference service on a non-final chunk, if any
+    last_usage: Option<Value>,
+    /// Whether the usage of the request was already committed to the stack
+    is_usage_committed: bool,
     /// Current status of the stream
     status: StreamStatus,
     /// The stack small id for the request
     stack_small_id: i64,
+    /// The session key grant the request is charged to, if any
+    session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
     /// The estimated total compute units for the request
     estimated_total_compute_units: i64,
     /// The request payload hash
     payload_hash: [u8; PAYLOAD_HASH_SIZE],
+    /// The JSON canonicalization used to hash the accumulated response
+    json_canonicalization: JsonCanonicalization,
     /// The sender for the state manager
     state_manager_sender: FlumeSender<AtomaAtomaStateManagerEvent>,
     /// The keystore
@@ -94,6 +120,10 @@ pub struct Streamer {
     address_index: usize,
     /// The model for the inference request
     model: String,
+    /// The tokenizer for the model, used to count the output tokens of partial responses
+    tokenizer: Option<Arc<Tokenizer>>,
+    /// The estimated number of input tokens for the request
+    estimated_input_tokens: i64,
     /// The first token generation (prefill phase) timer for the request.
     /// We need store it as an option because we need to consume its value
     /// once the first token is generated
@@ -102,6 +132,8 @@ pub struct Streamer {
     decoding_phase_timer: Option<HistogramTimer>,
     /// The client encryption metadata for the request
     streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
+    /// The number of chunks encrypted so far, used to derive the nonce of the next chunk
+    num_encrypted_chunks: u32,
     /// The endpoint for the request
     endpoint: String,
 }
@@ -126,29 +158,46 @@ impl Streamer {
         stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
         state_manager_sender: FlumeSender<AtomaAtomaStateManagerEvent>,
         stack_small_id: i64,
+        session_grant_id: Option<[u8; PAYLOAD_HASH_SIZE]>,
         estimated_total_compute_units: i64,
         payload_hash: [u8; PAYLOAD_HASH_SIZE],
+        json_canonicalization: JsonCanonicalization,
         keystore: Arc<FileBasedKeystore>,
         address_index: usize,
         model: String,
+        tokenizer: Option<Arc<Tokenizer>>,
+        estimated_input_tokens: i64,
+        adapter: &'static dyn InferenceBackend,
+        usage_reconciliation: UsageReconciliationPolicy,
         streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
         endpoint: String,
         first_token_generation_timer: HistogramTimer,
     ) -> Self {
         Self {
             stream: Box::pin(stream),
+            sse_parser: SseParser::default(),
+            adapter,
+            pending_chunks: VecDeque::new(),
+            usage_reconciliation,
             accumulated_response: Vec::new(),
+            last_usage: None,
+            is_usage_committed: false,
             status: StreamStatus::NotStarted,
             stack_small_id,
+            session_grant_id,
             estimated_total_compute_units,
             payload_hash,
+            json_canonicalization,
             state_manager_sender,
             keystore,
             address_index,
             model,
+            tokenizer,
+            estimated_input_tokens,
             first_token_generation_timer: Some(first_token_generation_timer),
             decoding_phase_timer: None,
             streaming_encryption_metadata,
+            num_encrypted_chunks: 0,
             endpoint,
         }
     }
@@ -158,7 +207,8 @@ impl Streamer {
     ///
     /// This method:
     /// 1. Signs the accumulated response data
-    /// 2. Extracts and validates token usage information
+    /// 2. Extracts and validates token usage information, and reconciles it with the tokens
+    ///    counted locally, according to the node's `UsageReconciliationPolicy`
     /// 3. Updates the state manager with token counts
     /// 4. Calculates a total hash combining payload and response hashes
     /// 5. Updates the state manager with the total hash
@@ -180,9 +230,10 @@ impl Streamer {
     ///
     /// # State Updates
     ///
-    /// This method sends two events to the state manager:
+    /// This method sends three events to the state manager:
     /// * `UpdateStackNumTokens` - Updates the token count for the stack
     /// * `UpdateStackTotalHash` - Updates the combined hash of payload and response
+    /// * `RecordStackReceipt` - Records the receipt of the response
     #[instrument(
         level = "info",
         skip(self, usage),
@@ -204,27 +255,31 @@ impl Streamer {
         }
 
         // Get total tokens
-        let mut total_compute_units = 0;
-        if let Some(prompt_tokens) = usage.get("prompt_tokens") {
-            let prompt_tokens = prompt_tokens.as_u64().unwrap_or(0);
-            CHAT_COMPLETIONS_INPUT_TOKENS_METRICS
-                .with_label_values(&[&self.model])
-                .inc_by(prompt_tokens as f64);
-            total_compute_units += prompt_tokens;
-        } else {
-            error!("Error getting prompt tokens from usage");
-            return Err(Error::new("Error getting prompt tokens from usage"));
-        }
-        if let Some(completion_tokens) = usage.get("completion_tokens") {
-            let completion_tokens = completion_tokens.as_u64().unwrap_or(0);
-            CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS
-                .with_label_values(&[&self.model])
-                .inc_by(completion_tokens as f64);
-            total_compute_units += completion_tokens;
-        } else {
-            error!("Error getting completion tokens from usage");
-            return Err(Error::new("Error getting completion tokens from usage"));
-        }
+        let Some(reported_usage) = TokenUsage::from_usage(usage) else {
+            error!("Error getting prompt and completion tokens from usage");
+            return Err(Error::new(
+                "Error getting prompt and completion tokens from usage",
+            ));
+        };
+        let local_usage = self
+            .count_local_output_tokens()
+            .map(|output_tokens| TokenUsage {
+                input_tokens: self.estimated_input_tokens,
+                output_tokens,
+            });
+        let usage = reconcile_usage(
+            self.usage_reconciliation,
+            &self.model,
+            reported_usage,
+            local_usage,
+        );
+        CHAT_COMPLETIONS_INPUT_TOKENS_METRICS
+            .with_label_values(&[&self.model])
+            .inc_by(usage.input_tokens as f64);
+        CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS
+            .with_label_values(&[&self.model])
+            .inc_by(usage.output_tokens as f64);
+        let total_compute_units = usage.total();
 
         tracing::info!(
             target = "atoma-service",
@@ -237,6 +292,29 @@ impl Streamer {
             total_compute_units,
         );
 
+        self.commit_stack_usage(total_compute_units, response_hash);
+
+        Ok(())
+    }
+
+    /// Commits the usage of the request to the stack, by updating the stack's total hash
+    /// with the response hash, recording the receipt of the response, and releasing the
+    /// estimated compute units in favor of the compute units actually used.
+    ///
+    /// This is done at most once per request, either when the final chunk is received, or
+    /// when the stream is dropped before completion (see [`Streamer::handle_partial_response`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `total_compute_units` - The number of compute units actually used by the request
+    /// * `response_hash` - The hash of the (possibly partial) response
+    fn commit_stack_usage(
+        &mut self,
+        total_compute_units: i64,
+        response_hash: [u8; PAYLOAD_HASH_SIZE],
+    ) {
+        self.is_usage_committed = true;
+
         // Calculate and update total hash
         let total_hash = blake2b_hash(&[self.payload_hash, response_hash].concat());
         let total_hash_bytes: [u8; 32] = total_hash
@@ -260,19 +338,124 @@ impl Streamer {
                 e
             );
         }
+        if let Err(e) = record_stack_receipt(
+            &self.state_manager_sender,
+            self.stack_small_id,
+            self.payload_hash,
+            response_hash,
+            total_compute_units,
+            &self.endpoint,
+        ) {
+            error!(
+                target = "atoma-service",
+                level = "error",
+                endpoint = self.endpoint,
+                "Error recording stack receipt: {}",
+                e
+            );
+        }
 
         // Update stack num tokens
         if let Err(e) = update_stack_num_compute_units(
             &self.state_manager_sender,
             self.stack_small_id,
+            self.session_grant_id,
             self.estimated_total_compute_units,
-            total_compute_units as i64,
+            total_compute_units,
             &self.endpoint,
         ) {
             error!("Error updating stack num tokens: {}", e);
         }
+    }
 
-        Ok(())
+    /// Handles a response that did not complete, because the client disconnected, the
+    /// inference service failed, or the stream ended without a final usage chunk.
+    ///
+    /// The compute units actually used so far are committed to the stack, instead of keeping
+    /// the full estimate reserved, and the hash of the partial response (the chunks received
+    /// so far) is recorded in the stack's total hash, as it would be for a complete response.
+    #[instrument(
+        level = "info",
+        skip(self),
+        fields(
+            endpoint = self.endpoint,
+            stack_small_id = self.stack_small_id,
+            estimated_total_compute_units = self.estimated_total_compute_units,
+            payload_hash = hex::encode(self.payload_hash)
+        )
+    )]
+    fn handle_partial_response(&mut self) {
+        let (input_tokens, output_tokens) = self.count_partial_usage();
+        CHAT_COMPLETIONS_INPUT_TOKENS_METRICS
+            .with_label_values(&[&self.model])
+            .inc_by(input_tokens as f64);
+        CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS
+            .with_label_values(&[&self.model])
+            .inc_by(output_tokens as f64);
+        // NOTE: Local token counts are only an approximation of the inference service's,
+        // so we never charge more than the compute units reserved for the request
+        let total_compute_units =
+            (input_tokens + output_tokens).min(self.estimated_total_compute_units);
+
+        let response_hash: [u8; PAYLOAD_HASH_SIZE] = self
+            .json_canonicalization
+            .hash(&json!(self.accumulated_response))
+            .into();
+
+        tracing::info!(
+            target = "atoma-service",
+            level = "info",
+            endpoint = self.endpoint,
+            stack_small_id = self.stack_small_id,
+            status = ?self.status,
+            "Handle partial response: Total compute units: {}",
+            total_compute_units,
+        );
+
+        self.commit_stack_usage(total_compute_units, response_hash);
+    }
+
+    /// Counts the input and output tokens of a response that did not complete.
+    ///
+    /// The latest usage reported by the inference service is used, if chunks carry it
+    /// (e.g. vLLM's `continuous_usage_stats`). Otherwise, the output tokens are counted by
+    /// tokenizing the content generated so far (the delta content of chat completion chunks,
+    /// or the text of completion chunks), while the input tokens are the estimated number
+    /// of prompt tokens.
+    fn count_partial_usage(&self) -> (i64, i64) {
+        if let Some(usage) = self.last_usage.as_ref() {
+            let prompt_tokens = usage.get("prompt_tokens").and_then(|t| t.as_i64());
+            let completion_tokens = usage.get("completion_tokens").and_then(|t| t.as_i64());
+            if let (Some(prompt_tokens), Some(completion_tokens)) =
+                (prompt_tokens, completion_tokens)
+            {
+                return (prompt_tokens, completion_tokens);
+            }
+        }
+        // NOTE: Inference services stream (about) one token per chunk, which is our best guess
+        // if the content cannot be tokenized
+        let output_tokens = self
+            .count_local_output_tokens()
+            .unwrap_or_else(|| self.accumulated_contents().len() as i64);
+        (self.estimated_input_tokens, output_tokens)
+    }
+
+    /// Returns the non-empty contents generated so far, which are the texts of the choices of
+    /// the chunks (see [`choice_texts`]), including the tool calls of chat completion deltas
+    fn accumulated_contents(&self) -> Vec<&str> {
+        self.accumulated_response
+            .iter()
+            .filter_map(|chunk| chunk.get(CHOICES).and_then(|choices| choices.as_array()))
+            .flatten()
+            .flat_map(choice_texts)
+            .filter(|content| !content.is_empty())
+            .collect()
+    }
+
+    /// Counts the output tokens generated so far with the model's tokenizer, or returns `None`
+    /// if the model has no tokenizer
+    fn count_local_output_tokens(&self) -> Option<i64> {
+        count_tokens(self.tokenizer.as_ref()?, &self.accumulated_contents())
     }
 
     /// Signs the accumulated response  
@@ -289,6 +472,7 @@ impl Streamer {
         // Sign the accumulated response
         let (response_hash, signature) = utils::sign_response_body(
             &json!(self.accumulated_response),
+            self.json_canonicalization,
             &self.keystore,
             self.address_index,
         )
@@ -302,57 +486,59 @@ impl Streamer {
 
     /// Handles the encryption request for a chunk of streaming data.
     ///
-    /// This method initiates the encryption process for a given chunk by:
-    /// 1. Creating a oneshot channel for receiving the encryption response
-    /// 2. Sending the encryption request with the chunk data to the confidential compute service
-    /// 3. Setting up the streamer to wait for the encrypted response
+    /// Every chunk is encrypted with a distinct nonce, derived from the stream's base nonce
+    /// and the chunk's position in the stream, while the last chunk is additionally marked
+    /// as final. This way, clients can verify that no chunk was dropped, reordered or replayed,
+    /// and that the stream was not truncated (see `atoma_utils::encryption::StreamDecryptor`).
+    ///
+    /// NOTE: Only the usage-only chunk (with empty `choices`) that ends the stream is marked as
+    /// final, as requested with `stream_options.include_usage`. Streams ending with `[DONE]`
+    /// without such a chunk never get a final chunk, so clients report them as truncated.
     ///
     /// # Arguments
     ///
     /// * `chunk` - The JSON value containing the data to be encrypted
-    /// * `proxy_x25519_public_key` - The X25519 public key of the proxy (32 bytes)
-    /// * `salt` - The salt value used in the encryption process
+    /// * `usage` - The usage of the request, only present for the final chunk
+    /// * `streaming_encryption_metadata` - The shared secret, base nonce and salt for the stream
+    /// * `counter` - The position of the chunk in the stream
     ///
     /// # Returns
     ///
-    /// Returns a `Result<(), Error>` where:
-    /// * `Ok(())` - The encryption request was successfully sent
-    /// * `Err(Error)` - An error occurred while sending the encryption request
-    ///
-    /// # State Changes
-    ///
-    /// * Sets `waiting_for_encrypted_chunk` to `true`
-    /// * Updates `encryption_response_receiver` with the new receiver
-    #[instrument(level = "debug", skip_all)]
+    /// Returns a `Result<Value, Error>` where:
+    /// * `Ok(Value)` - The encrypted chunk, with its ciphertext and nonce (and for the final chunk,
+    ///   the usage and final chunk marker)
+    /// * `Err(Error)` - An error occurred while encrypting the chunk
+    #[instrument(level = "debug", skip(chunk, usage, streaming_encryption_metadata))]
     fn handle_encryption_request(
         chunk: &Value,
         usage: Option<&Value>,
         streaming_encryption_metadata: &StreamingEncryptionMetadata,
+        counter: u32,
     ) -> Result<Value, Error> {
         let StreamingEncryptionMetadata {
             shared_secret,
             nonce,
             salt,
         } = streaming_encryption_metadata;
+        // NOTE: The final chunk of the stream is the only one carrying usage
+        let is_final = usage.is_some();
         // NOTE: We remove the usage key from the chunk before encryption
         // because we need to send the usage key back to the client in the final chunk
-        let (encrypted_chunk, nonce) = if usage.is_some() {
+        let plaintext = if is_final {
             let mut chunk = chunk.clone();
             chunk.as_object_mut().map(|obj| obj.remove(USAGE_KEY));
-            encrypt_plaintext(
-                chunk.to_string().as_bytes(),
-                shared_secret,
-                salt,
-                Some(*nonce),
-            )
+            chunk.to_string()
         } else {
-            encrypt_plaintext(
-                chunk.to_string().as_bytes(),
-                shared_secret,
-                salt,
-                Some(*nonce),
-            )
-        }
+            chunk.to_string()
+        };
+        let (encrypted_chunk, nonce) = encrypt_stream_chunk(
+            plaintext.as_bytes(),
+            shared_secret,
+            salt,
+            nonce,
+            counter,
+            is_final,
+        )
         .map_err(|e| {
             error!(
                 target = "atoma-service",
@@ -367,6 +553,7 @@ impl Streamer {
                 CIPHERTEXT_KEY: encrypted_chunk,
                 NONCE_KEY: nonce,
                 USAGE_KEY: usage.clone(),
+                IS_FINAL_KEY: true,
             }))
         } else {
             Ok(json!({
@@ -375,140 +562,577 @@ impl Streamer {
             }))
         }
     }
+
+    /// Encrypts a chunk of streaming data, if the request is confidential, and advances
+    /// the stream's chunk counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The JSON value containing the data to be encrypted
+    /// * `usage` - The usage of the request, only present for the final chunk
+    ///
+    /// # Returns
+    ///
+    /// Returns the encrypted chunk for confidential requests, or `None` otherwise.
+    fn encrypt_chunk(
+        &mut self,
+        chunk: &Value,
+        usage: Option<&Value>,
+    ) -> Result<Option<Value>, Error> {
+        let Some(streaming_encryption_metadata) = self.streaming_encryption_metadata.as_ref()
+        else {
+            return Ok(None);
+        };
+        let encrypted_chunk = Self::handle_encryption_request(
+            chunk,
+            usage,
+            streaming_encryption_metadata,
+            self.num_encrypted_chunks,
+        )?;
+        self.num_encrypted_chunks = self
+            .num_encrypted_chunks
+            .checked_add(1)
+            .ok_or_else(|| Error::new("Too many chunks in confidential stream"))?;
+        Ok(Some(encrypted_chunk))
+    }
+
+    /// Handles a single server-sent event from the inference service stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `sse_event` - The event, as parsed from the inference service stream
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<Option<Event>, Error>` where:
+    /// * `Ok(Some(Event))` - The event to send back to the client
+    /// * `Ok(None)` - If there is nothing to send back to the client, either because the event
+    ///   is not a `message` event, or because it marks the end of the stream
+    /// * `Err(Error)` - If the event reports an error, or its data is not a valid chunk
+    fn handle_sse_event(&mut self, sse_event: SseEvent) -> Result<Option<Event>, Error> {
+        match sse_event.event.as_deref() {
+            None | Some(MESSAGE_EVENT) => {}
+            Some(ERROR_EVENT) => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    endpoint = self.endpoint,
+                    event_id = ?sse_event.id,
+                    "Inference service stream error: {}",
+                    sse_event.data
+                );
+                return Err(Error::new(format!(
+                    "Inference service stream error: {}",
+                    sse_event.data
+                )));
+            }
+            Some(event) => {
+                debug!(
+                    target = "atoma-service",
+                    level = "debug",
+                    endpoint = self.endpoint,
+                    "Skipping inference service stream event of type {event}"
+                );
+                return Ok(None);
+            }
+        }
+        let chunk_str = sse_event.data.as_str();
+
+        if chunk_str.starts_with(DONE_CHUNK) {
+            // This is the last chunk, meaning the inference streaming is complete
+            self.status = StreamStatus::Completed;
+            return Ok(None);
+        }
+        let chunk = serde_json::from_str::<Value>(chunk_str).map_err(|e| {
+            error!(
+                target = "atoma-service",
+                level = "error",
+                endpoint = self.endpoint,
+                "Error parsing chunk {chunk_str}: {}",
+                e
+            );
+            Error::new(format!("Error parsing chunk {chunk_str}: {}", e))
+        })?;
+
+        // Observe the first token generation timer
+        if let Some(timer) = self.first_token_generation_timer.take() {
+            timer.observe_duration();
+            let timer = CHAT_COMPLETIONS_DECODING_TIME
+                .with_label_values(&[&self.model])
+                .start_timer();
+            self.decoding_phase_timer = Some(timer);
+        }
+
+        self.pending_chunks
+            .extend(self.adapter.normalize_chunk(chunk));
+        self.handle_pending_chunk()
+    }
+
+    /// Handles the next normalized chunk not yet sent back to the client, if any.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<Option<Event>, Error>` where:
+    /// * `Ok(Some(Event))` - The event to send back to the client
+    /// * `Ok(None)` - If there is no pending chunk
+    /// * `Err(Error)` - If the chunk has no choices, or no usage while its choices are empty
+    fn handle_pending_chunk(&mut self) -> Result<Option<Event>, Error> {
+        let Some(chunk) = self.pending_chunks.pop_front() else {
+            return Ok(None);
+        };
+        let choices = match chunk.get(CHOICES).and_then(|choices| choices.as_array()) {
+            Some(choices) => choices,
+            None => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    endpoint = self.endpoint,
+                    "Error getting choices from chunk"
+                );
+                return Err(Error::new("Error getting choices from chunk"));
+            }
+        };
+
+        if choices.is_empty() {
+            // Check if this is a final chunk with usage info
+            if let Some(usage) = chunk.get(USAGE_KEY) {
+                self.status = StreamStatus::Completed;
+                // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
+                let mut chunk = self
+                    .encrypt_chunk(&chunk, Some(usage))?
+                    .unwrap_or_else(|| chunk.clone());
+                let (signature, response_hash) = self.sign_final_chunk()?;
+                chunk[SIGNATURE_KEY] = json!(signature);
+                chunk[RESPONSE_HASH_KEY] = json!(STANDARD.encode(response_hash));
+                self.handle_final_chunk(usage, response_hash)?;
+                Ok(Some(Event::default().json_data(&chunk)?))
+            } else {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    endpoint = self.endpoint,
+                    "Error getting usage from chunk"
+                );
+                Err(Error::new("Error getting usage from chunk"))
+            }
+        } else {
+            // NOTE: Some inference services report the usage so far on every chunk
+            if let Some(usage) = chunk.get(USAGE_KEY).filter(|usage| usage.is_object()) {
+                self.last_usage = Some(usage.clone());
+            }
+            // Accumulate regular chunks
+            self.accumulated_response.push(chunk.clone());
+            // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
+            let chunk = self.encrypt_chunk(&chunk, None)?.unwrap_or(chunk);
+            Ok(Some(Event::default().json_data(&chunk)?))
+        }
+    }
 }
 
 impl Stream for Streamer {
     type Item = Result<Event, Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.status == StreamStatus::Completed {
-            return Poll::Ready(None);
-        }
-
-        match self.stream.as_mut().poll_next(cx) {
-            Poll::Ready(Some(Ok(chunk))) => {
-                if self.status != StreamStatus::Started {
-                    self.status = StreamStatus::Started;
-                }
-
-                if chunk.as_ref() == KEEP_ALIVE_CHUNK || chunk.as_ref() == KEEP_ALIVE_TEXT_CHUNK {
-                    return Poll::Pending;
-                }
+        // NOTE: A single read from the inference service can contain partial events, several
+        // events or only keep-alive comments, so we keep polling the inner stream until there is
+        // an event to send back to the client. Only returning `Poll::Pending` when the inner stream
+        // does ensures that the task is woken up once more bytes are available.
+        loop {
+            if self.status == StreamStatus::Completed {
+                return Poll::Ready(None);
+            }
 
-                let chunk_str = match std::str::from_utf8(&chunk) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!(
-                            target = "atoma-service",
-                            level = "error",
-                            "Invalid UTF-8 sequence: {}",
-                            e
-                        );
-                        return Poll::Ready(Some(Err(Error::new(format!(
-                            "Invalid UTF-8 sequence: {}",
-                            e
-                        )))));
-                    }
-                };
-                let chunk_str = chunk_str.strip_prefix(DATA_PREFIX).unwrap_or(chunk_str);
+            if let Some(event) = self.handle_pending_chunk()? {
+                return Poll::Ready(Some(Ok(event)));
+            }
 
-                if chunk_str.starts_with(DONE_CHUNK) {
-                    // This is the last chunk, meaning the inference streaming is complete
-                    self.status = StreamStatus::Completed;
-                    return Poll::Ready(None);
-                }
-                let chunk = serde_json::from_str::<Value>(chunk_str).map_err(|e| {
+            if let Some(sse_event) = self.sse_parser.next_event() {
+                let sse_event = sse_event.map_err(|e| {
                     error!(
                         target = "atoma-service",
                         level = "error",
                         endpoint = self.endpoint,
-                        "Error parsing chunk {chunk_str}: {}",
+                        "Invalid UTF-8 sequence: {}",
                         e
                     );
-                    Error::new(format!("Error parsing chunk {chunk_str}: {}", e))
+                    Error::new(format!("Invalid UTF-8 sequence: {}", e))
                 })?;
-
-                // Observe the first token generation timer
-                if let Some(timer) = self.first_token_generation_timer.take() {
-                    timer.observe_duration();
-                    let timer = CHAT_COMPLETIONS_DECODING_TIME
-                        .with_label_values(&[&self.model])
-                        .start_timer();
-                    self.decoding_phase_timer = Some(timer);
+                match self.handle_sse_event(sse_event)? {
+                    Some(event) => return Poll::Ready(Some(Ok(event))),
+                    None => continue,
                 }
+            }
 
-                let choices = match chunk.get(CHOICES).and_then(|choices| choices.as_array()) {
-                    Some(choices) => choices,
-                    None => {
-                        error!(
-                            target = "atoma-service",
-                            level = "error",
-                            endpoint = self.endpoint,
-                            "Error getting choices from chunk"
-                        );
-                        return Poll::Ready(Some(Err(Error::new(
-                            "Error getting choices from chunk",
-                        ))));
-                    }
-                };
-
-                if choices.is_empty() {
-                    // Check if this is a final chunk with usage info
-                    if let Some(usage) = chunk.get(USAGE_KEY) {
-                        self.status = StreamStatus::Completed;
-                        let mut chunk = if let Some(streaming_encryption_metadata) =
-                            self.streaming_encryption_metadata.as_ref()
-                        {
-                            // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
-                            Self::handle_encryption_request(
-                                &chunk,
-                                Some(usage),
-                                streaming_encryption_metadata,
-                            )?
-                        } else {
-                            chunk.clone()
-                        };
-                        let (signature, response_hash) = self.sign_final_chunk()?;
-                        chunk[SIGNATURE_KEY] = json!(signature);
-                        chunk[RESPONSE_HASH_KEY] = json!(STANDARD.encode(response_hash));
-                        self.handle_final_chunk(usage, response_hash)?;
-                        Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)))
-                    } else {
-                        error!(
-                            target = "atoma-service",
-                            level = "error",
-                            endpoint = self.endpoint,
-                            "Error getting usage from chunk"
-                        );
-                        Poll::Ready(Some(Err(Error::new("Error getting usage from chunk"))))
+            if self.sse_parser.is_finished() {
+                self.status = StreamStatus::Completed;
+                return Poll::Ready(None);
+            }
+
+            match self.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if self.status != StreamStatus::Started {
+                        self.status = StreamStatus::Started;
                     }
-                } else {
-                    // Accumulate regular chunks
-                    self.accumulated_response.push(chunk.clone());
-                    let chunk = if let Some(streaming_encryption_metadata) =
-                        self.streaming_encryption_metadata.as_ref()
-                    {
-                        // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
-                        Self::handle_encryption_request(
-                            &chunk,
-                            None,
-                            streaming_encryption_metadata,
-                        )?
-                    } else {
-                        chunk
-                    };
-                    Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)))
+                    self.sse_parser.push(&chunk);
                 }
+                Poll::Ready(Some(Err(e))) => {
+                    self.status = StreamStatus::Failed(e.to_string());
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(None) => self.sse_parser.finish(),
+                Poll::Pending => return Poll::Pending,
             }
-            Poll::Ready(Some(Err(e))) => {
-                self.status = StreamStatus::Failed(e.to_string());
-                Poll::Ready(None)
-            }
-            Poll::Ready(None) => {
-                self.status = StreamStatus::Completed;
-                Poll::Ready(None)
-            }
-            Poll::Pending => Poll::Pending,
         }
     }
 }
+
+impl Drop for Streamer {
+    fn drop(&mut self) {
+        if self.is_usage_committed {
+            return;
+        }
+        // NOTE: Dropping the inference service stream closes the underlying connection,
+        // which aborts the request on the inference service, so it stops generating tokens
+        // for a client that is no longer listening
+        self.stream = Box::pin(futures::stream::empty());
+        self.handle_partial_response();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backends::adapters::{LlamaCppBackend, VllmBackend},
+        handlers::{
+            chat_completions::CHAT_COMPLETIONS_PATH,
+            prometheus::CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN,
+        },
+        mock::{self, MockInferenceConfig},
+    };
+    use futures::{stream, StreamExt};
+    use sui_keys::keystore::AccountKeystore;
+    use sui_sdk::types::crypto::SignatureScheme;
+    use tempfile::tempdir;
+
+    const MODEL: &str = "meta-llama/Llama-3.1-70B-Instruct";
+    const STACK_SMALL_ID: i64 = 1;
+    const ESTIMATED_TOTAL_COMPUTE_UNITS: i64 = 100;
+    const ESTIMATED_INPUT_TOKENS: i64 = 20;
+    const PAYLOAD_HASH: [u8; PAYLOAD_HASH_SIZE] = [7; PAYLOAD_HASH_SIZE];
+
+    fn content_chunk(content: &str) -> Value {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "model": MODEL,
+            "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": null}],
+            "usage": null
+        })
+    }
+
+    fn sse_body(chunks: &[Value]) -> String {
+        chunks
+            .iter()
+            .map(|chunk| format!("data: {chunk}\n\n"))
+            .collect()
+    }
+
+    /// Creates a streamer over the given inference service body, which never ends
+    /// if `is_open` is set, as if the inference service was still generating tokens
+    fn setup_streamer(
+        body: String,
+        is_open: bool,
+    ) -> (Streamer, flume::Receiver<AtomaAtomaStateManagerEvent>) {
+        setup_streamer_with_adapter(body, is_open, &VllmBackend)
+    }
+
+    /// Creates a streamer over the given body of an inference service served by the engine
+    /// of `adapter`
+    fn setup_streamer_with_adapter(
+        body: String,
+        is_open: bool,
+        adapter: &'static dyn InferenceBackend,
+    ) -> (Streamer, flume::Receiver<AtomaAtomaStateManagerEvent>) {
+        let body = stream::iter(vec![Ok::<_, reqwest::Error>(Bytes::from(body))]);
+        let body: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>> = if is_open {
+            Box::pin(body.chain(stream::pending()))
+        } else {
+            Box::pin(body)
+        };
+        new_streamer(body, adapter)
+    }
+
+    /// Creates a streamer over a streamed chat completion of a mock inference service, for a
+    /// prompt of 2 tokens and at most 4 output tokens
+    async fn setup_mock_streamer(
+        config: MockInferenceConfig,
+    ) -> (Streamer, flume::Receiver<AtomaAtomaStateManagerEvent>) {
+        let url = mock::spawn(config).await.unwrap();
+        let mut payload = json!({
+            "model": MODEL,
+            "messages": [{"role": "user", "content": "Hello there"}],
+            "max_tokens": 4,
+            "stream": true
+        });
+        VllmBackend.prepare_streaming_request(&mut payload);
+        let response = reqwest::Client::new()
+            .post(format!("{url}{CHAT_COMPLETIONS_PATH}"))
+            .json(&payload)
+            .send()
+            .await
+            .unwrap();
+        new_streamer(response.bytes_stream(), &VllmBackend)
+    }
+
+    /// Creates a streamer over the given inference service body, with a new keystore
+    fn new_streamer(
+        body: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+        adapter: &'static dyn InferenceBackend,
+    ) -> (Streamer, flume::Receiver<AtomaAtomaStateManagerEvent>) {
+        let temp_dir = tempdir().unwrap();
+        let mut keystore = FileBasedKeystore::new(&temp_dir.path().join("keystore")).unwrap();
+        keystore
+            .generate_and_add_new_key(SignatureScheme::ED25519, None, None, None)
+            .unwrap();
+        let (state_manager_sender, state_manager_receiver) = flume::unbounded();
+        let streamer = Streamer::new(
+            body,
+            state_manager_sender,
+            STACK_SMALL_ID,
+            None,
+            ESTIMATED_TOTAL_COMPUTE_UNITS,
+            PAYLOAD_HASH,
+            JsonCanonicalization::default(),
+            Arc::new(keystore),
+            0,
+            MODEL.to_string(),
+            None,
+            ESTIMATED_INPUT_TOKENS,
+            adapter,
+            UsageReconciliationPolicy::default(),
+            None,
+            "/v1/chat/completions".to_string(),
+            CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN
+                .with_label_values(&[MODEL])
+                .start_timer(),
+        );
+        (streamer, state_manager_receiver)
+    }
+
+    /// Receives the stack updates committed by the streamer, as (total hash, total compute units)
+    fn receive_stack_updates(
+        receiver: &flume::Receiver<AtomaAtomaStateManagerEvent>,
+    ) -> ([u8; 32], i64) {
+        let Ok(AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
+            stack_small_id,
+            total_hash,
+        }) = receiver.try_recv()
+        else {
+            panic!("Expected stack total hash update");
+        };
+        assert_eq!(stack_small_id, STACK_SMALL_ID);
+        let Ok(AtomaAtomaStateManagerEvent::RecordStackReceipt { receipt }) = receiver.try_recv()
+        else {
+            panic!("Expected stack receipt");
+        };
+        assert_eq!(receipt.stack_small_id, STACK_SMALL_ID);
+        assert_eq!(receipt.payload_hash, PAYLOAD_HASH);
+        assert_eq!(
+            blake2b_hash(&[receipt.payload_hash, receipt.response_hash].concat()).as_slice(),
+            total_hash
+        );
+        let Ok(AtomaAtomaStateManagerEvent::UpdateStackNumComputeUnits {
+            stack_small_id,
+            estimated_total_compute_units,
+            total_compute_units,
+        }) = receiver.try_recv()
+        else {
+            panic!("Expected stack compute units update");
+        };
+        assert_eq!(stack_small_id, STACK_SMALL_ID);
+        assert_eq!(estimated_total_compute_units, ESTIMATED_TOTAL_COMPUTE_UNITS);
+        assert_eq!(receipt.num_compute_units, total_compute_units);
+        assert!(receiver.try_recv().is_err(), "Expected a single commit");
+        (total_hash, total_compute_units)
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_commits_partial_usage() {
+        let chunks = vec![content_chunk("Hello"), content_chunk(" world")];
+        let (mut streamer, receiver) = setup_streamer(sse_body(&chunks), true);
+
+        assert!(streamer.next().await.unwrap().is_ok());
+        assert!(streamer.next().await.unwrap().is_ok());
+        assert!(receiver.is_empty());
+
+        // NOTE: Dropping the streamer is what axum does when the client disconnects
+        drop(streamer);
+
+        let (total_hash, total_compute_units) = receive_stack_updates(&receiver);
+        let response_hash: [u8; 32] = blake2b_hash(json!(chunks).to_string().as_bytes()).into();
+        let expected_total_hash: [u8; 32] =
+            blake2b_hash(&[PAYLOAD_HASH, response_hash].concat()).into();
+        assert_eq!(total_hash, expected_total_hash);
+        // NOTE: Without a tokenizer, each content chunk counts as a single output token
+        assert_eq!(total_compute_units, ESTIMATED_INPUT_TOKENS + 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_counts_completion_text() {
+        let chunks = ["def", " fibonacci", ""]
+            .into_iter()
+            .map(|text| {
+                json!({
+                    "id": "cmpl-1",
+                    "object": "text_completion",
+                    "model": MODEL,
+                    "choices": [{"index": 0, "text": text, "finish_reason": null}],
+                    "usage": null
+                })
+            })
+            .collect::<Vec<_>>();
+        let (mut streamer, receiver) = setup_streamer(sse_body(&chunks), true);
+
+        for _ in 0..chunks.len() {
+            assert!(streamer.next().await.unwrap().is_ok());
+        }
+        drop(streamer);
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        // NOTE: Empty texts are not counted as output tokens
+        assert_eq!(total_compute_units, ESTIMATED_INPUT_TOKENS + 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_counts_tool_calls() {
+        let tool_call_chunk = |function: Value| {
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "model": MODEL,
+                "choices": [{
+                    "index": 0,
+                    "delta": {"tool_calls": [{"index": 0, "function": function}]},
+                    "finish_reason": null
+                }],
+                "usage": null
+            })
+        };
+        let chunks = vec![
+            tool_call_chunk(json!({"name": "get_weather", "arguments": ""})),
+            tool_call_chunk(json!({"arguments": "{\"city\":"})),
+            tool_call_chunk(json!({"arguments": "\"Paris\"}"})),
+        ];
+        let (mut streamer, receiver) = setup_streamer(sse_body(&chunks), true);
+
+        for _ in 0..chunks.len() {
+            assert!(streamer.next().await.unwrap().is_ok());
+        }
+        drop(streamer);
+
+        // NOTE: The function name and each non-empty arguments fragment count as output tokens
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, ESTIMATED_INPUT_TOKENS + 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_uses_reported_usage() {
+        let mut chunk = content_chunk("Hello");
+        chunk[USAGE_KEY] = json!({"prompt_tokens": 12, "completion_tokens": 1, "total_tokens": 13});
+        let (mut streamer, receiver) = setup_streamer(sse_body(&[chunk]), true);
+
+        assert!(streamer.next().await.unwrap().is_ok());
+        drop(streamer);
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, 13);
+    }
+
+    #[tokio::test]
+    async fn test_completed_stream_commits_usage_once() {
+        let chunks = vec![
+            content_chunk("Hello"),
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "model": MODEL,
+                "choices": [],
+                "usage": {"prompt_tokens": 12, "completion_tokens": 1, "total_tokens": 13}
+            }),
+        ];
+        let body = sse_body(&chunks) + "data: [DONE]\n\n";
+        let (streamer, receiver) = setup_streamer(body, false);
+
+        let events = streamer.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.is_ok()));
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, 13);
+    }
+
+    #[tokio::test]
+    async fn test_llama_cpp_stream_commits_timings_usage() {
+        let mut final_chunk = content_chunk("!");
+        final_chunk[CHOICES][0]["finish_reason"] = json!("stop");
+        final_chunk["timings"] = json!({"prompt_n": 12, "predicted_n": 2});
+        let body = sse_body(&[content_chunk("Hello"), final_chunk]) + "data: [DONE]\n\n";
+        let (streamer, receiver) = setup_streamer_with_adapter(body, false, &LlamaCppBackend);
+
+        // NOTE: The timings of the final chunk are reported in a separate usage chunk
+        let events = streamer.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.is_ok()));
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, 14);
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_commits_reported_usage() {
+        let (streamer, receiver) = setup_mock_streamer(MockInferenceConfig::default()).await;
+
+        // NOTE: The role chunk, 4 content chunks, the finish reason chunk and the usage chunk
+        let events = streamer.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 7);
+        assert!(events.iter().all(|event| event.is_ok()));
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, 6);
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_disconnect_commits_partial_usage() {
+        let (streamer, receiver) = setup_mock_streamer(MockInferenceConfig {
+            disconnect_after_chunks: Some(3),
+            ..Default::default()
+        })
+        .await;
+
+        let events = streamer.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.is_ok()));
+
+        // NOTE: The role chunk has no content, so only 2 output tokens are counted
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, ESTIMATED_INPUT_TOKENS + 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_malformed_chunk_fails() {
+        let (mut streamer, receiver) = setup_mock_streamer(MockInferenceConfig {
+            malformed_chunk_after: Some(2),
+            ..Default::default()
+        })
+        .await;
+
+        assert!(streamer.next().await.unwrap().is_ok());
+        assert!(streamer.next().await.unwrap().is_ok());
+        assert!(streamer.next().await.unwrap().is_err());
+        drop(streamer);
+
+        let (_, total_compute_units) = receive_stack_updates(&receiver);
+        assert_eq!(total_compute_units, ESTIMATED_INPUT_TOKENS + 1);
+    }
+}
@@ -0,0 +1,318 @@
+use std::str::Utf8Error;
+
+/// The byte order mark, which may prefix the first line of an event stream
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+
+/// The data field name
+const DATA_FIELD: &str = "data";
+
+/// The event type field name
+const EVENT_FIELD: &str = "event";
+
+/// The event id field name
+const ID_FIELD: &str = "id";
+
+/// A single server-sent event, as dispatched by the [`SseParser`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SseEvent {
+    /// The event type, if set by an `event:` line (`None` means the default `message` type)
+    pub event: Option<String>,
+    /// The last event id seen on the stream, if any
+    pub id: Option<String>,
+    /// The event data, with multiple `data:` lines joined by newlines
+    pub data: String,
+}
+
+/// An incremental parser for `text/event-stream` bodies.
+///
+/// Bytes are pushed into the parser as they are read from the inference service, regardless
+/// of how the service (or the network) chunks them: a single read can contain a partial event,
+/// or several events at once. Complete events are then pulled with [`SseParser::next_event`].
+///
+/// The parser follows the WHATWG server-sent events specification: lines may be terminated
+/// by `\n`, `\r\n` or `\r`, comment lines (e.g. `: keep-alive`) and unknown fields are ignored,
+/// and events are dispatched on empty lines, unless they carry no data.
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    /// Bytes received that do not form a complete line yet
+    buffer: Vec<u8>,
+    /// Position in `buffer` up to which the bytes have already been parsed
+    position: usize,
+    /// Whether the byte order mark check at the start of the stream was already performed
+    started: bool,
+    /// Whether the end of the stream has been reached
+    finished: bool,
+    /// The event type of the event being parsed
+    event: Option<String>,
+    /// The data of the event being parsed, with a trailing newline per `data:` line
+    data: String,
+    /// The last event id seen on the stream, which persists across events
+    last_event_id: Option<String>,
+}
+
+impl SseParser {
+    /// Appends bytes read from the stream to the parser's buffer.
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Marks the end of the stream.
+    ///
+    /// Any trailing line left in the buffer is then treated as terminated, and the event
+    /// being parsed is dispatched, even if the stream did not end with an empty line.
+    pub(crate) fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns `true` once the end of the stream was reached and all events were pulled.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished && self.position == self.buffer.len() && self.data.is_empty()
+    }
+
+    /// Pulls the next complete event out of the buffered bytes.
+    ///
+    /// # Returns
+    ///
+    /// * `None` - If the buffered bytes do not contain a complete event yet
+    /// * `Some(Ok(SseEvent))` - The next complete event
+    /// * `Some(Err(Utf8Error))` - If a line of the stream is not valid UTF-8
+    pub(crate) fn next_event(&mut self) -> Option<Result<SseEvent, Utf8Error>> {
+        if !self.started {
+            if self.buffer.len() < BYTE_ORDER_MARK.len()
+                && BYTE_ORDER_MARK.starts_with(&self.buffer)
+                && !self.finished
+            {
+                return None;
+            }
+            if self.buffer.starts_with(BYTE_ORDER_MARK) {
+                self.position = BYTE_ORDER_MARK.len();
+            }
+            self.started = true;
+        }
+        loop {
+            let Some((line_end, next_line_start)) = self.next_line_bounds() else {
+                if self.finished {
+                    // NOTE: The stream ended without a final empty line, so we dispatch
+                    // whatever was left of the last event
+                    self.position = self.buffer.len();
+                    return self.dispatch().map(Ok);
+                }
+                return None;
+            };
+            let line = match std::str::from_utf8(&self.buffer[self.position..line_end]) {
+                Ok(line) => line.to_string(),
+                Err(e) => {
+                    self.position = next_line_start;
+                    return Some(Err(e));
+                }
+            };
+            self.position = next_line_start;
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    return Some(Ok(event));
+                }
+                continue;
+            }
+            self.process_line(&line);
+        }
+    }
+
+    /// Finds the end of the next line in the buffer, and the start of the line after it.
+    ///
+    /// A trailing `\r` is only treated as a line terminator once the next byte is known,
+    /// as it might be the first half of a `\r\n` split across two reads.
+    fn next_line_bounds(&self) -> Option<(usize, usize)> {
+        let remaining = &self.buffer[self.position..];
+        let Some(offset) = remaining.iter().position(|&b| b == b'\n' || b == b'\r') else {
+            // NOTE: At the end of the stream, an unterminated trailing line is still a line
+            return (self.finished && !remaining.is_empty())
+                .then_some((self.buffer.len(), self.buffer.len()));
+        };
+        let end = self.position + offset;
+        match (self.buffer[end], self.buffer.get(end + 1)) {
+            (b'\r', Some(b'\n')) => Some((end, end + 2)),
+            (b'\r', None) if !self.finished => None,
+            _ => Some((end, end + 1)),
+        }
+    }
+
+    /// Processes a single non-empty line of the stream.
+    fn process_line(&mut self, line: &str) {
+        if line.starts_with(':') {
+            // NOTE: Comment lines, such as `: keep-alive`, are ignored
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            DATA_FIELD => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            EVENT_FIELD => self.event = Some(value.to_string()),
+            ID_FIELD if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            // NOTE: `retry` and unknown fields (e.g. mistralrs' `keep-alive-text`) are ignored
+            _ => {}
+        }
+    }
+
+    /// Dispatches the event being parsed, if it carries any data.
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+        Some(SseEvent {
+            event,
+            id: self.last_event_id.clone(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A chat completions stream recorded from vLLM, with usage reporting enabled
+    const VLLM_CHAT_COMPLETIONS_STREAM: &str = concat!(
+        "data: {\"id\":\"chatcmpl-7f3c\",\"object\":\"chat.completion.chunk\",\"created\":1733150000,\"model\":\"meta-llama/Llama-3.1-70B-Instruct\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+        "data: {\"id\":\"chatcmpl-7f3c\",\"object\":\"chat.completion.chunk\",\"created\":1733150000,\"model\":\"meta-llama/Llama-3.1-70B-Instruct\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+        ": keep-alive\n\n",
+        "data: {\"id\":\"chatcmpl-7f3c\",\"object\":\"chat.completion.chunk\",\"created\":1733150000,\"model\":\"meta-llama/Llama-3.1-70B-Instruct\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"! How can I help\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+        "data: {\"id\":\"chatcmpl-7f3c\",\"object\":\"chat.completion.chunk\",\"created\":1733150000,\"model\":\"meta-llama/Llama-3.1-70B-Instruct\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" you today? \u{1F44B}\"},\"logprobs\":null,\"finish_reason\":\"stop\",\"stop_reason\":null}],\"usage\":null}\n\n",
+        "data: {\"id\":\"chatcmpl-7f3c\",\"object\":\"chat.completion.chunk\",\"created\":1733150000,\"model\":\"meta-llama/Llama-3.1-70B-Instruct\",\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"total_tokens\":23,\"completion_tokens\":11}}\n\n",
+        "data: [DONE]\n\n",
+    );
+
+    /// Parses the given reads, in order, and returns the parsed events
+    fn parse(reads: &[&[u8]]) -> Vec<SseEvent> {
+        let mut parser = SseParser::default();
+        let mut events = vec![];
+        for read in reads {
+            parser.push(read);
+            while let Some(event) = parser.next_event() {
+                events.push(event.expect("Invalid UTF-8 in stream"));
+            }
+        }
+        parser.finish();
+        while let Some(event) = parser.next_event() {
+            events.push(event.expect("Invalid UTF-8 in stream"));
+        }
+        assert!(parser.is_finished());
+        events
+    }
+
+    /// Splits the stream at the given (unordered, possibly repeated) byte offsets
+    fn rechunk(stream: &[u8], mut offsets: Vec<usize>) -> Vec<&[u8]> {
+        offsets
+            .iter_mut()
+            .for_each(|offset| *offset %= stream.len() + 1);
+        offsets.sort_unstable();
+        let mut chunks = vec![];
+        let mut start = 0;
+        for offset in offsets {
+            chunks.push(&stream[start..offset]);
+            start = offset;
+        }
+        chunks.push(&stream[start..]);
+        chunks
+    }
+
+    fn data_event(data: &str) -> SseEvent {
+        SseEvent {
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_recorded_vllm_stream() {
+        let events = parse(&[VLLM_CHAT_COMPLETIONS_STREAM.as_bytes()]);
+        assert_eq!(events.len(), 6);
+        assert!(events.iter().all(|e| e.event.is_none() && e.id.is_none()));
+        for event in &events[..5] {
+            serde_json::from_str::<serde_json::Value>(&event.data).expect("Invalid JSON chunk");
+        }
+        assert_eq!(events[5], data_event("[DONE]"));
+    }
+
+    #[test]
+    fn test_parse_event_and_id_fields() {
+        let stream = b"id: 1\nevent: error\ndata: {\"message\":\ndata: \"overloaded\"}\n\nretry: 10\ndata: next\n\n";
+        let events = parse(&[stream]);
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("error".to_string()),
+                    id: Some("1".to_string()),
+                    data: "{\"message\":\n\"overloaded\"}".to_string(),
+                },
+                SseEvent {
+                    event: None,
+                    id: Some("1".to_string()),
+                    data: "next".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_line_terminators_and_keep_alives() {
+        let stream =
+            b"\xEF\xBB\xBF: keep-alive\r\n\r\nkeep-alive-text\ndata:a\r\rdata: b\r\n\r\ndata: c";
+        let events = parse(&[stream]);
+        assert_eq!(
+            events,
+            vec![data_event("a"), data_event("b"), data_event("c")]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_utf8_line() {
+        let mut parser = SseParser::default();
+        parser.push(b"data: \xFF\n\ndata: ok\n\n");
+        assert!(matches!(parser.next_event(), Some(Err(_))));
+        assert_eq!(parser.next_event(), Some(Ok(data_event("ok"))));
+        assert_eq!(parser.next_event(), None);
+    }
+
+    #[test]
+    fn test_parse_single_byte_reads() {
+        let stream = VLLM_CHAT_COMPLETIONS_STREAM.as_bytes();
+        let chunks: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(parse(&chunks), parse(&[stream]));
+    }
+
+    proptest! {
+        #[test]
+        fn test_rechunked_vllm_stream_parses_identically(
+            offsets in proptest::collection::vec(any::<usize>(), 0..64)
+        ) {
+            let stream = VLLM_CHAT_COMPLETIONS_STREAM.as_bytes();
+            let expected = parse(&[stream]);
+            let chunks = rechunk(stream, offsets);
+            prop_assert_eq!(parse(&chunks), expected);
+        }
+
+        #[test]
+        fn test_rechunked_crlf_vllm_stream_parses_identically(
+            offsets in proptest::collection::vec(any::<usize>(), 0..64)
+        ) {
+            let crlf_stream = VLLM_CHAT_COMPLETIONS_STREAM.replace('\n', "\r\n");
+            let expected = parse(&[VLLM_CHAT_COMPLETIONS_STREAM.as_bytes()]);
+            let chunks = rechunk(crlf_stream.as_bytes(), offsets);
+            prop_assert_eq!(parse(&chunks), expected);
+        }
+    }
+}
@@ -8,22 +8,34 @@ mod middleware {
     use atoma_utils::{
         constants::{self, SALT_SIZE},
         encryption::encrypt_plaintext,
-        hashing::blake2b_hash,
+        hashing::{blake2b_hash, JsonCanonicalization},
         test::POSTGRES_TEST_DB_URL,
     };
     use axum::{
-        body::Body, extract::Request, http::StatusCode, response::Response, routing::post, Router,
+        body::Body,
+        extract::{Path, Query, Request, State},
+        http::{header::RETRY_AFTER, HeaderMap, StatusCode},
+        response::Response,
+        routing::{get, post},
+        Extension, Json, Router,
     };
     use base64::{engine::general_purpose::STANDARD, prelude::BASE64_STANDARD, Engine};
     use flume::Sender;
     use serde_json::{json, Value};
     use serial_test::serial;
     use sqlx::PgPool;
-    use std::{str::FromStr, sync::Arc};
+    use std::{
+        str::FromStr,
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    };
     use sui_keys::keystore::{AccountKeystore, FileBasedKeystore};
     use sui_sdk::types::{
         base_types::{ObjectID, SuiAddress},
-        crypto::{EncodeDecodeBase64, PublicKey, Signature, SignatureScheme},
+        crypto::{
+            get_key_pair, AccountKeyPair, EncodeDecodeBase64, PublicKey, Signature,
+            SignatureScheme, SuiKeyPair,
+        },
     };
     use tempfile::tempdir;
     use tokenizers::Tokenizer;
@@ -31,19 +43,57 @@ mod middleware {
     use tower::Service;
 
     use crate::{
+        admission::AdmissionController,
+        backends::ModelBackends,
+        chat_template::ChatTemplate,
+        config::{
+            AtomaServiceConfig, BackendHealthConfig, ModelMetadata, RateLimitConfig, RateLimits,
+            TaskRolesConfig, UsageReconciliationPolicy,
+        },
+        error::AtomaServiceError,
         handlers::{
-            chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
+            audio::AUDIO_TRANSCRIPTIONS_PATH,
+            chat_completions::{
+                chat_completions_handler, CHAT_COMPLETIONS_PATH, CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+            },
+            completions::COMPLETIONS_PATH,
+            embeddings::EMBEDDINGS_PATH,
             image_generations::IMAGE_GENERATIONS_PATH,
+            models::{model_handler, models_handler, MODELS_PATH, MODEL_PATH},
+            quote::quote_handler,
+            record_stack_receipt,
+            rerank::RERANK_PATH,
+            stacks::{stack_usage_handler, StackUsageQuery},
         },
         middleware::{
-            confidential_compute_middleware, signature_verification_middleware,
-            verify_stack_permissions, RequestMetadata, RequestType,
+            confidential_compute_middleware, replay_protection_middleware,
+            signature_verification_middleware, verify_stack_permissions, RequestMetadata,
+            RequestType,
         },
+        mock::{self, MockInferenceConfig},
+        rate_limit::{RateLimiters, RATE_LIMIT_REMAINING_REQUESTS},
+        replay::{signed_request_digest, ReplayGuard, RequestFreshness},
         server::AppState,
+        session::{session_grant_digest, SessionGrant},
     };
 
     const TEST_MESSAGE: &str = "Test message";
 
+    /// Number of input tokens charged for each image in the tests
+    const NUM_TOKENS_PER_IMAGE: i64 = 100;
+
+    /// Small id of a deprecated chat completions task (and of its stack)
+    const DEPRECATED_TASK_SMALL_ID: i64 = 4;
+
+    /// Small id of a confidential compute chat completions task (and of its stack)
+    const CONFIDENTIAL_TASK_SMALL_ID: i64 = 5;
+
+    /// Small id of an audio transcriptions task (and of its stack)
+    const AUDIO_TRANSCRIPTIONS_TASK_SMALL_ID: i64 = 6;
+
+    /// Small id of a rerank task (and of its stack)
+    const RERANK_TASK_SMALL_ID: i64 = 7;
+
     #[allow(dead_code)]
     fn setup_subscriber() {
         tracing_subscriber::fmt()
@@ -105,7 +155,10 @@ mod middleware {
                 node_subscriptions,
                 stacks,
                 stack_settlement_tickets,
-                stack_attestation_disputes
+                stack_attestation_disputes,
+                seen_request_signatures,
+                session_grant_usage,
+                stack_receipts
             CASCADE",
         )
         .execute(&db)
@@ -131,38 +184,68 @@ mod middleware {
         )
         .await
         .expect("Failed to create state manager");
-        let task = Task {
-            task_small_id: 1,
-            task_id: "1".to_string(),
-            role: 0,
-            model_name: Some("meta-llama/Llama-3.1-70B-Instruct".to_string()),
-            security_level: 0,
-            minimum_reputation_score: Some(100),
-            is_deprecated: false,
-            valid_until_epoch: Some(1),
-            deprecated_at_epoch: Some(1),
-        };
-        state_manager.state.insert_new_task(task).await.unwrap();
-        state_manager
-            .state
-            .subscribe_node_to_task(1, 1, 100, 1000)
-            .await
-            .unwrap();
+        // NOTE: Each stack is bought for a task, with the stack small id matching the task small id
+        let tasks = [
+            (1, 0, "meta-llama/Llama-3.1-70B-Instruct", false, 0),
+            (2, 1, "intfloat/multilingual-e5-large-instruct", false, 0),
+            (3, 2, "black-forest-labs/FLUX.1-schnell", false, 0),
+            (
+                DEPRECATED_TASK_SMALL_ID,
+                0,
+                "meta-llama/Llama-3.1-70B-Instruct",
+                true,
+                0,
+            ),
+            (
+                CONFIDENTIAL_TASK_SMALL_ID,
+                0,
+                "meta-llama/Llama-3.1-70B-Instruct",
+                false,
+                2,
+            ),
+            (
+                AUDIO_TRANSCRIPTIONS_TASK_SMALL_ID,
+                3,
+                "openai/whisper-large-v3",
+                false,
+                0,
+            ),
+            (RERANK_TASK_SMALL_ID, 5, "BAAI/bge-reranker-v2-m3", false, 0),
+        ];
         let sui_address = SuiAddress::from(&public_key);
-        let stack = Stack {
-            owner_address: sui_address.to_string(),
-            stack_small_id: 1,
-            stack_id: "1".to_string(),
-            task_small_id: 1,
-            selected_node_id: 1,
-            num_compute_units: 600,
-            price_per_one_million_compute_units: 1,
-            already_computed_units: 0,
-            in_settle_period: false,
-            total_hash: vec![],
-            num_total_messages: 1,
-        };
-        state_manager.state.insert_new_stack(stack).await.unwrap();
+        for (task_small_id, role, model_name, is_deprecated, security_level) in tasks {
+            let task = Task {
+                task_small_id,
+                task_id: task_small_id.to_string(),
+                role,
+                model_name: Some(model_name.to_string()),
+                security_level,
+                minimum_reputation_score: Some(100),
+                is_deprecated,
+                valid_until_epoch: Some(1),
+                deprecated_at_epoch: Some(1),
+            };
+            state_manager.state.insert_new_task(task).await.unwrap();
+            state_manager
+                .state
+                .subscribe_node_to_task(1, task_small_id, 100, 1000)
+                .await
+                .unwrap();
+            let stack = Stack {
+                owner_address: sui_address.to_string(),
+                stack_small_id: task_small_id,
+                stack_id: task_small_id.to_string(),
+                task_small_id,
+                selected_node_id: 1,
+                num_compute_units: 600,
+                price_per_one_million_compute_units: 1,
+                already_computed_units: 0,
+                in_settle_period: false,
+                total_hash: vec![],
+                num_total_messages: 1,
+            };
+            state_manager.state.insert_new_stack(stack).await.unwrap();
+        }
         let (shutdown_sender, shutdown_signal) = tokio::sync::watch::channel(false);
         let shutdown_signal_clone = shutdown_signal.clone();
         let state_manager_handle = tokio::spawn(async move {
@@ -194,6 +277,8 @@ mod middleware {
             "meta-llama/Llama-3.1-70B-Instruct",
             "intfloat/multilingual-e5-large-instruct",
             "black-forest-labs/FLUX.1-schnell",
+            "openai/whisper-large-v3",
+            "BAAI/bge-reranker-v2-m3",
         ];
         let public_key = keystore.key_pairs().first().unwrap().public();
         let blake2b_hash = blake2b_hash(TEST_MESSAGE.as_bytes());
@@ -258,6 +343,7 @@ mod middleware {
         );
         let (compute_shared_secret_sender, compute_shared_secret_receiver) =
             tokio::sync::mpsc::unbounded_channel();
+        let (tee_attestation_sender, tee_attestation_receiver) = tokio::sync::watch::channel(false);
         let _join_handle = tokio::spawn(async move {
             let confidential_compute_service = AtomaConfidentialComputeService::new(
                 Arc::new(RwLock::new(
@@ -269,6 +355,7 @@ mod middleware {
                 decryption_receiver,
                 encryption_receiver,
                 compute_shared_secret_receiver,
+                tee_attestation_sender,
                 shutdown_receiver,
             )
             .expect("Failed to create confidential compute service");
@@ -287,14 +374,29 @@ mod middleware {
         (
             AppState {
                 models: Arc::new(models.into_iter().map(|s| s.to_string()).collect()),
-                tokenizers: Arc::new(vec![Arc::new(tokenizer.clone()), Arc::new(tokenizer)]),
+                tokenizers: Arc::new(vec![Arc::new(tokenizer); 5]),
+                num_tokens_per_image: NUM_TOKENS_PER_IMAGE,
+                usage_reconciliation: UsageReconciliationPolicy::default(),
+                task_roles: TaskRolesConfig {
+                    audio_transcriptions: Some(3),
+                    speech: Some(4),
+                    rerank: Some(5),
+                    ..Default::default()
+                },
+                chat_templates: Arc::new(vec![None, None, None, None, None]),
+                model_metadata: Arc::new(vec![]),
+                node_small_ids: Arc::new(vec![1]),
                 state_manager_sender,
                 decryption_sender,
                 encryption_sender,
                 compute_shared_secret_sender,
-                chat_completions_service_url: "".to_string(),
-                embeddings_service_url: "".to_string(),
-                image_generations_service_url: "".to_string(),
+                tee_attestation_receiver,
+                current_epoch_receiver: tokio::sync::watch::channel(None).1,
+                backends: Arc::new(ModelBackends::default()),
+                admission: Arc::new(AdmissionController::default()),
+                rate_limiters: Arc::new(RateLimiters::default()),
+                replay_guard: Arc::new(ReplayGuard::default()),
+                zklogin_verifier: None,
                 keystore: Arc::new(keystore),
                 address_index: 0,
                 stack_retrieve_sender,
@@ -711,140 +813,981 @@ mod middleware {
 
     #[tokio::test]
     #[serial]
-    async fn test_signature_verification_success() {
-        let signature = get_signature().await;
-        let message = json!({
-            "message": TEST_MESSAGE
+    async fn test_verify_stack_permissions_multimodal_and_tools_token_counting() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "What is the weather like in this city?"},
+                        {"type": "image_url", "image_url": {"url": "https://example.com/city.png"}}
+                    ]
+                },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\": \"Lisbon\"}"}
+                    }]
+                },
+                {
+                    "role": "tool",
+                    "tool_call_id": "call_1",
+                    "content": "Sunny, 24C"
+                }
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Get the current weather in a city",
+                    "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+                }
+            }],
+            "max_tokens": 50,
         });
 
         let req = Request::builder()
             .method("POST")
-            .uri("/")
-            .header(constants::SIGNATURE, signature)
-            .body(Body::from(message.to_string()))
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
             .unwrap();
 
-        async fn check_metadata_handler(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+        async fn verify_token_count(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
             let metadata = req
                 .extensions()
                 .get::<RequestMetadata>()
                 .expect("Metadata should be set");
-            assert_ne!(metadata.payload_hash, [0u8; 32]); // Hash should be set
+
+            // Should include max_tokens (50), the image cost, the text, tool call and
+            // tool definition tokens, and safety margins (3 tokens per message)
+            assert!(metadata.estimated_total_compute_units > 50 + NUM_TOKENS_PER_IMAGE + 3 * 3);
+
             Ok(Response::new(Body::empty()))
         }
 
         let mut app = Router::new()
-            .route("/", post(check_metadata_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+            .route(CHAT_COMPLETIONS_PATH, post(verify_token_count))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
 
         let response = app.call(req).await.expect("Failed to get response");
         assert_eq!(response.status(), StatusCode::OK);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
     }
 
     #[tokio::test]
     #[serial]
-    async fn test_signature_verification_missing_header() {
+    async fn test_verify_stack_permissions_chat_template_token_counting() {
+        let (
+            mut app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let chat_template = ChatTemplate::new(
+            "{% for message in messages %}{{'<|im_start|>' + message['role'] + '\n' + message['content'] + '<|im_end|>' + '\n'}}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\n' }}{% endif %}".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        // NOTE: Content parts are flattened into their text, while images are charged separately
+        let prompt = chat_template
+            .render(
+                &[
+                    json!({"role": "system", "content": "You are a helpful assistant"}),
+                    json!({"role": "user", "content": "Describe this image"}),
+                ],
+                None,
+            )
+            .unwrap();
+        let expected_total_compute_units = app_state.tokenizers[0]
+            .encode(prompt, false)
+            .unwrap()
+            .get_ids()
+            .len() as i64
+            + NUM_TOKENS_PER_IMAGE
+            + 50;
+        app_state.chat_templates = Arc::new(vec![Some(chat_template), None, None]);
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [
+                {"role": "system", "content": "You are a helpful assistant"},
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "Describe this image"},
+                        {"type": "image_url", "image_url": {"url": "https://example.com/image.png"}}
+                    ]
+                }
+            ],
+            "max_tokens": 50,
+        });
+
         let req = Request::builder()
             .method("POST")
-            .uri("/")
-            // Intentionally omitting X-Signature header
-            .body(Body::from("Test message"))
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
             .unwrap();
 
+        let verify_token_count = move |req: Request<Body>| async move {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set");
+            assert_eq!(
+                metadata.estimated_total_compute_units,
+                expected_total_compute_units
+            );
+            Response::new(Body::empty())
+        };
+
         let mut app = Router::new()
-            .route("/", post(test_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+            .route(CHAT_COMPLETIONS_PATH, post(verify_token_count))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
 
         let response = app.call(req).await.expect("Failed to get response");
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::OK);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
     }
 
     #[tokio::test]
     #[serial]
-    async fn test_signature_verification_invalid_base64() {
+    async fn test_verify_stack_permissions_unsupported_content_part() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": [{"type": "input_audio", "input_audio": {"data": "", "format": "wav"}}]
+            }],
+            "max_tokens": 50,
+        });
+
         let req = Request::builder()
             .method("POST")
-            .uri("/")
-            .header(constants::SIGNATURE, "not-valid-base64!")
-            .body(Body::from("Test message"))
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
             .unwrap();
 
         let mut app = Router::new()
-            .route("/", post(test_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+            .route(CHAT_COMPLETIONS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
 
         let response = app.call(req).await.expect("Failed to get response");
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
     }
 
-    #[tokio::test]
-    #[serial]
-    async fn test_signature_verification_invalid_signature() {
-        let keystore = setup_keystore();
-        let address = keystore.addresses()[0];
-        let message = json!({
-            "message": "Test message"
-        });
-
-        // Sign a different message than what we'll send
-        let different_message = json!({
-            "message": "Different message"
-        });
-        let blake2b_hash = blake2b_hash(different_message.to_string().as_bytes());
-        let signature = keystore
-            .sign_hashed(&address, blake2b_hash.as_slice())
-            .expect("Failed to sign message");
-        let signature_b64 = BASE64_STANDARD.encode(signature.as_ref());
+    /// Sends a chat completions request with `body`, for a chat model limited by `model_metadata`,
+    /// and returns the response status code and JSON body, which is either the error, or the
+    /// request body as forwarded by the middleware
+    async fn max_tokens_request(
+        mut app_state: AppState,
+        signature: &Signature,
+        model_metadata: ModelMetadata,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        app_state.model_metadata = Arc::new(vec![model_metadata]);
 
         let req = Request::builder()
             .method("POST")
-            .uri("/")
-            .header(constants::SIGNATURE, signature_b64)
-            .body(Body::from(message.to_string())) // Send original message with signature for different message
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
             .unwrap();
 
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
+        }
+
         let mut app = Router::new()
-            .route("/", post(test_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+            .route(CHAT_COMPLETIONS_PATH, post(echo_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
 
         let response = app.call(req).await.expect("Failed to get response");
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        (status, serde_json::from_slice(&body).unwrap())
     }
 
     #[tokio::test]
     #[serial]
-    async fn test_signature_verification_empty_body() {
-        let keystore = setup_keystore();
-        let address = keystore.addresses()[0];
-        let message = json!({});
+    async fn test_verify_stack_permissions_default_max_tokens() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
 
-        // Sign empty message
-        let blake2b_hash = blake2b_hash(message.to_string().as_bytes());
+        let (status, forwarded_body) = max_tokens_request(
+            app_state,
+            &signature,
+            ModelMetadata {
+                model: "meta-llama/Llama-3.1-70B-Instruct".to_string(),
+                context_length: Some(4_096),
+                default_max_tokens: Some(256),
+                ..Default::default()
+            },
+            json!({
+                "model": "meta-llama/Llama-3.1-70B-Instruct",
+                "messages": [{"role": "user", "content": "Hello"}],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // NOTE: The default is forwarded, so the inference service generates at most the reserved tokens
+        assert_eq!(forwarded_body["max_tokens"], 256);
 
-        let signature = keystore
-            .sign_hashed(&address, blake2b_hash.as_slice())
-            .expect("Failed to sign message");
-        let signature_b64 = BASE64_STANDARD.encode(signature.as_ref());
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
 
-        let req = Request::builder()
-            .method("POST")
-            .uri("/")
-            .header(constants::SIGNATURE, signature_b64)
-            .body(Body::from(message.to_string()))
-            .unwrap();
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_max_tokens_too_large() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
 
-        let mut app = Router::new()
-            .route("/", post(test_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+        let (status, error_body) = max_tokens_request(
+            app_state,
+            &signature,
+            ModelMetadata {
+                model: "meta-llama/Llama-3.1-70B-Instruct".to_string(),
+                max_tokens: Some(100),
+                ..Default::default()
+            },
+            json!({
+                "model": "meta-llama/Llama-3.1-70B-Instruct",
+                "messages": [{"role": "user", "content": "Hello"}],
+                "max_completion_tokens": 200,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error_body["error"]["type"], "invalid_request_error");
+        assert_eq!(error_body["error"]["code"], "invalid_value");
+        assert_eq!(error_body["error"]["param"], "max_completion_tokens");
 
-        let response = app.call(req).await.expect("Failed to get response");
-        assert_eq!(response.status(), StatusCode::OK);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
     }
 
     #[tokio::test]
     #[serial]
-    async fn test_signature_verification_large_body() {
-        let keystore = setup_keystore();
+    async fn test_verify_stack_permissions_context_length_exceeded() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let model_metadata = ModelMetadata {
+            model: "meta-llama/Llama-3.1-70B-Instruct".to_string(),
+            context_length: Some(64),
+            ..Default::default()
+        };
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "max_tokens": 100,
+        });
+
+        let (status, error_body) = max_tokens_request(
+            app_state.clone(),
+            &signature,
+            model_metadata.clone(),
+            body.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error_body["error"]["type"], "invalid_request_error");
+        assert_eq!(error_body["error"]["code"], "context_length_exceeded");
+        assert_eq!(error_body["error"]["param"], "max_tokens");
+
+        // NOTE: With clamping enabled, the request is forwarded with the remaining context length
+        let num_prompt_tokens = app_state.tokenizers[0]
+            .encode("Hello", true)
+            .unwrap()
+            .get_ids()
+            .len() as i64
+            + 3;
+        let (status, forwarded_body) = max_tokens_request(
+            app_state,
+            &signature,
+            ModelMetadata {
+                clamp_max_tokens: true,
+                ..model_metadata
+            },
+            body,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(forwarded_body["max_tokens"], 64 - num_prompt_tokens);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_completions_token_counting() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        async fn metadata_handler(req: Request<Body>) -> Json<Value> {
+            let estimated_total_compute_units = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set")
+                .estimated_total_compute_units;
+            let body = axum::body::to_bytes(req.into_body(), usize::MAX)
+                .await
+                .expect("Failed to read request body");
+            Json(json!({
+                "estimated_total_compute_units": estimated_total_compute_units,
+                "body": serde_json::from_slice::<Value>(&body).unwrap(),
+            }))
+        }
+
+        let mut app = Router::new()
+            .route(COMPLETIONS_PATH, post(metadata_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+        let mut completions_request = |body: Value| {
+            let req = Request::builder()
+                .method("POST")
+                .uri(COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::STACK_SMALL_ID, "1")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.call(req);
+            async move {
+                let response = response.await.expect("Failed to get response");
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("Failed to read response body");
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        // Each prompt of a batch of token arrays is charged its tokens plus `max_tokens`
+        let (status, response) = completions_request(json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "prompt": [[1, 2, 3], [4, 5]],
+            "max_tokens": 10,
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["estimated_total_compute_units"], 25);
+
+        // A string prompt without `max_tokens` generates at most 16 tokens, which is forwarded
+        // to the inference service
+        let (status, response) = completions_request(json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "prompt": "def fibonacci(n):",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["body"]["max_tokens"], 16);
+        assert!(response["estimated_total_compute_units"].as_i64().unwrap() > 16);
+
+        // Each prompt is charged `max_tokens` for each of its `max(n, best_of)` completions
+        for (n, best_of, expected_compute_units) in [
+            (json!(4), Value::Null, 5 + 2 * 4 * 10),
+            (json!(2), json!(8), 5 + 2 * 8 * 10),
+            (Value::Null, json!(3), 5 + 2 * 3 * 10),
+        ] {
+            let (status, response) = completions_request(json!({
+                "model": "meta-llama/Llama-3.1-70B-Instruct",
+                "prompt": [[1, 2, 3], [4, 5]],
+                "max_tokens": 10,
+                "n": n,
+                "best_of": best_of,
+            }))
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                response["estimated_total_compute_units"],
+                expected_compute_units
+            );
+        }
+
+        // Non-positive counts of completions, and `best_of` less than `n`, are rejected
+        for (n, best_of, param) in [
+            (json!(0), Value::Null, "n"),
+            (Value::Null, json!(-1), "best_of"),
+            (json!(4), json!(2), "best_of"),
+        ] {
+            let (status, response) = completions_request(json!({
+                "model": "meta-llama/Llama-3.1-70B-Instruct",
+                "prompt": [[1, 2, 3], [4, 5]],
+                "max_tokens": 10,
+                "n": n,
+                "best_of": best_of,
+            }))
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(response["error"]["param"], param);
+        }
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_rerank_token_counting() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        let tokenizer = app_state.tokenizers[4].clone();
+        let num_tokens = |text: &str| tokenizer.encode(text, true).unwrap().get_ids().len() as i64;
+
+        async fn verify_rerank_compute_units(req: Request<Body>) -> Json<Value> {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set");
+            assert_eq!(metadata.request_type, RequestType::Rerank);
+            Json(json!(metadata.estimated_total_compute_units))
+        }
+
+        let mut app = Router::new()
+            .route(RERANK_PATH, post(verify_rerank_compute_units))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+        let mut rerank_request = |body: Value| {
+            let req = Request::builder()
+                .method("POST")
+                .uri(RERANK_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::STACK_SMALL_ID, RERANK_TASK_SMALL_ID.to_string())
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.call(req)
+        };
+
+        // The query is charged once per document, along with the tokens of each document,
+        // which can be strings or objects with a `text` field
+        let response = rerank_request(json!({
+            "model": "BAAI/bge-reranker-v2-m3",
+            "query": "What is the capital of France?",
+            "documents": ["Paris is the capital of France.", {"text": "Berlin is in Germany."}],
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<i64>(&body).unwrap(),
+            2 * num_tokens("What is the capital of France?")
+                + num_tokens("Paris is the capital of France.")
+                + num_tokens("Berlin is in Germany.")
+        );
+
+        // A request without documents is rejected
+        let response = rerank_request(json!({
+            "model": "BAAI/bge-reranker-v2-m3",
+            "query": "What is the capital of France?",
+            "documents": [],
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // A request with both `documents` and TEI's `texts` is rejected, as it would be
+        // charged for the `documents` while the backend may rank the `texts`
+        let response = rerank_request(json!({
+            "model": "BAAI/bge-reranker-v2-m3",
+            "query": "What is the capital of France?",
+            "documents": ["Paris is the capital of France."],
+            "texts": ["Paris is the capital of France.", "Berlin is in Germany."],
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_audio_transcriptions_multipart_body() {
+        let (app_state, _, _, shutdown_sender, state_manager_handle, _event_subscriber_sender, _) =
+            setup_app_state().await;
+
+        // A 2.5 seconds WAV file, of 16 kHz mono 16-bit audio
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend_from_slice(&16_000u32.to_le_bytes());
+        wav.extend_from_slice(&32_000u32.to_le_bytes());
+        wav.extend_from_slice(b"\x02\0\x10\0data");
+        wav.extend_from_slice(&80_000u32.to_le_bytes());
+        wav.extend(std::iter::repeat(0).take(80_000));
+        let form = |fields: &[u8]| {
+            [
+                b"--boundary\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\n\
+                openai/whisper-large-v3\r\n"
+                    .as_slice(),
+                fields,
+                b"--boundary--\r\n",
+            ]
+            .concat()
+        };
+        let body = form(
+            &[
+                b"--boundary\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"audio.wav\"\r\n\
+                Content-Type: audio/wav\r\n\r\n"
+                    .as_slice(),
+                &wav,
+                b"\r\n",
+            ]
+            .concat(),
+        );
+
+        async fn verify_audio_transcriptions_handler(
+            req: Request<Body>,
+        ) -> Result<Response<Body>, StatusCode> {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .cloned()
+                .expect("Metadata should be set");
+            let body = axum::body::to_bytes(req.into_body(), usize::MAX)
+                .await
+                .expect("Failed to read request body");
+
+            // The multipart body is forwarded as is, and its raw bytes are signed
+            assert_eq!(metadata.payload_hash, blake2b_hash(&body).as_slice());
+            // A 2.5 seconds audio file is charged 3 compute units
+            assert_eq!(metadata.estimated_total_compute_units, 3);
+            assert_eq!(metadata.request_type, RequestType::AudioTranscriptions);
+
+            Ok(Response::new(Body::empty()))
+        }
+
+        let keystore = app_state.keystore.clone();
+        let mut app = Router::new()
+            .route(
+                AUDIO_TRANSCRIPTIONS_PATH,
+                post(verify_audio_transcriptions_handler),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+        let mut transcriptions_request = |body: Vec<u8>| {
+            let signature = keystore
+                .sign_hashed(&keystore.addresses()[0], blake2b_hash(&body).as_slice())
+                .expect("Failed to sign message");
+            let req = Request::builder()
+                .method("POST")
+                .uri(AUDIO_TRANSCRIPTIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(
+                    constants::STACK_SMALL_ID,
+                    AUDIO_TRANSCRIPTIONS_TASK_SMALL_ID.to_string(),
+                )
+                .header("Content-Type", "multipart/form-data; boundary=boundary")
+                .body(Body::from(body))
+                .unwrap();
+            app.call(req)
+        };
+
+        let response = transcriptions_request(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A form without an audio file is rejected
+        let response = transcriptions_request(form(b"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_rate_limited() {
+        let (
+            mut app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        app_state.rate_limiters = Arc::new(
+            RateLimiters::from_config(&RateLimitConfig {
+                per_address: None,
+                per_stack: Some(RateLimits {
+                    requests_per_second: Some(1),
+                    ..Default::default()
+                }),
+            })
+            .unwrap(),
+        );
+
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
+        }
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(echo_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri(CHAT_COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::STACK_SMALL_ID, "1")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    json!({
+                        "model": "meta-llama/Llama-3.1-70B-Instruct",
+                        "messages": [{"role": "user", "content": "Hello"}],
+                        "max_tokens": 100,
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = app.call(request()).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[RATE_LIMIT_REMAINING_REQUESTS], "0");
+
+        let response = app.call(request()).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "RATE_LIMIT_EXCEEDED");
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_non_owner_keeps_stack_rate_limits() {
+        let (
+            mut app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        app_state.rate_limiters = Arc::new(
+            RateLimiters::from_config(&RateLimitConfig {
+                per_address: None,
+                per_stack: Some(RateLimits {
+                    requests_per_second: Some(1),
+                    ..Default::default()
+                }),
+            })
+            .unwrap(),
+        );
+        let (_, key_pair): (_, AccountKeyPair) = get_key_pair();
+        let key_pair = SuiKeyPair::Ed25519(key_pair);
+
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
+        }
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(echo_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "max_tokens": 100,
+        });
+        let request = |signature: String| {
+            Request::builder()
+                .method("POST")
+                .uri(CHAT_COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature)
+                .header(constants::STACK_SMALL_ID, "1")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // NOTE: Stack 1 is not owned by the signer, so the request is rejected without
+        // consuming the stack's limits
+        let body_hash: [u8; 32] = blake2b_hash(body.to_string().as_bytes()).into();
+        let response = app
+            .call(request(
+                Signature::new_hashed(&body_hash, &key_pair).encode_base64(),
+            ))
+            .await
+            .expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .call(request(signature.encode_base64()))
+            .await
+            .expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[RATE_LIMIT_REMAINING_REQUESTS], "0");
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_success() {
+        let signature = get_signature().await;
+        let message = json!({
+            "message": TEST_MESSAGE
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(constants::SIGNATURE, signature)
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        async fn check_metadata_handler(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set");
+            assert_ne!(metadata.payload_hash, [0u8; 32]); // Hash should be set
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new()
+            .route("/", post(check_metadata_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_missing_header() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            // Intentionally omitting X-Signature header
+            .body(Body::from("Test message"))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route("/", post(test_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_invalid_base64() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(constants::SIGNATURE, "not-valid-base64!")
+            .body(Body::from("Test message"))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route("/", post(test_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_invalid_signature() {
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let message = json!({
+            "message": "Test message"
+        });
+
+        // Sign a different message than what we'll send
+        let different_message = json!({
+            "message": "Different message"
+        });
+        let blake2b_hash = blake2b_hash(different_message.to_string().as_bytes());
+        let signature = keystore
+            .sign_hashed(&address, blake2b_hash.as_slice())
+            .expect("Failed to sign message");
+        let signature_b64 = BASE64_STANDARD.encode(signature.as_ref());
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(constants::SIGNATURE, signature_b64)
+            .body(Body::from(message.to_string())) // Send original message with signature for different message
+            .unwrap();
+
+        let mut app = Router::new()
+            .route("/", post(test_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_empty_body() {
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let message = json!({});
+
+        // Sign empty message
+        let blake2b_hash = blake2b_hash(message.to_string().as_bytes());
+
+        let signature = keystore
+            .sign_hashed(&address, blake2b_hash.as_slice())
+            .expect("Failed to sign message");
+        let signature_b64 = BASE64_STANDARD.encode(signature.as_ref());
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(constants::SIGNATURE, signature_b64)
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route("/", post(test_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_large_body() {
+        let keystore = setup_keystore();
         let address = keystore.addresses()[0];
 
         // Create a body larger than MAX_BODY_SIZE
@@ -948,10 +1891,12 @@ mod middleware {
         let initial_metadata = RequestMetadata {
             stack_small_id: 42,
             estimated_total_compute_units: 100,
+            session_grant_id: None,
             payload_hash: [0u8; 32],
             request_type: RequestType::ChatCompletions,
             endpoint_path: "/".to_string(),
             client_encryption_metadata: None,
+            json_canonicalization: JsonCanonicalization::default(),
         };
 
         let mut req = Request::builder()
@@ -988,15 +1933,154 @@ mod middleware {
                 ]
             );
 
-            Ok(Response::new(Body::empty()))
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new()
+            .route("/", post(verify_extensions_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_signature_verification_with_canonical_json() {
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let body = r#"{"model": "test", "max_tokens": 1.0E2, "message": "\u0054est"}"#;
+        let canonical_hash = blake2b_hash(br#"{"max_tokens":100,"message":"Test","model":"test"}"#);
+
+        let signature = keystore
+            .sign_hashed(&address, canonical_hash.as_slice())
+            .expect("Failed to sign message");
+        let signature_b64 = BASE64_STANDARD.encode(signature.as_ref());
+
+        async fn verify_canonicalization_handler(
+            req: Request<Body>,
+        ) -> Result<Response<Body>, StatusCode> {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set");
+            assert_eq!(metadata.json_canonicalization, JsonCanonicalization::Jcs);
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new()
+            .route("/", post(verify_canonicalization_handler))
+            .layer(axum::middleware::from_fn(signature_verification_middleware));
+
+        let request = |canonicalization: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature_b64.clone())
+                .header(constants::JSON_CANONICALIZATION, canonicalization)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let response = app.call(request("jcs")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.call(request("unknown")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Without the header, the signature is checked against the compact serialization
+        let response = app
+            .call(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header(constants::SIGNATURE, signature_b64.clone())
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_replay_protection_rejects_replayed_request() {
+        let (app_state, _, _, shutdown_sender, state_manager_handle, _event_subscriber_sender, _) =
+            setup_app_state().await;
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
         }
 
-        let mut app = Router::new()
-            .route("/", post(verify_extensions_handler))
-            .layer(axum::middleware::from_fn(signature_verification_middleware));
+        let mut app = Router::new().route("/", post(echo_handler)).layer(
+            tower::ServiceBuilder::new()
+                .layer(axum::middleware::from_fn(signature_verification_middleware))
+                .layer(axum::middleware::from_fn_with_state(
+                    app_state,
+                    replay_protection_middleware,
+                )),
+        );
 
-        let response = app.call(req).await.expect("Failed to get response");
+        // Timestamp and nonce sent as headers, covered by the signed digest
+        let message = json!({ "message": TEST_MESSAGE });
+        let header_request = |timestamp: u64| {
+            let body_hash: [u8; 32] = blake2b_hash(message.to_string().as_bytes()).into();
+            let freshness = RequestFreshness {
+                timestamp: Some(timestamp),
+                nonce: Some("nonce".to_string()),
+            };
+            let signature = keystore
+                .sign_hashed(&address, &signed_request_digest(&body_hash, &freshness))
+                .expect("Failed to sign message");
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::REQUEST_TIMESTAMP, timestamp.to_string())
+                .header(constants::REQUEST_NONCE, "nonce")
+                .body(Body::from(message.to_string()))
+                .unwrap()
+        };
+        let response = app.call(header_request(now)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.call(header_request(now)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.call(header_request(now - 3_600)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Timestamp sent as a body field, which is not forwarded
+        let signed_message = json!({ "message": TEST_MESSAGE, "request_timestamp": now });
+        let signature = keystore
+            .sign_hashed(
+                &address,
+                blake2b_hash(signed_message.to_string().as_bytes()).as_slice(),
+            )
+            .expect("Failed to sign message");
+        let body_request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .body(Body::from(signed_message.to_string()))
+                .unwrap()
+        };
+        let response = app.call(body_request()).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        assert_eq!(body, message.to_string().as_bytes());
+        let response = app.call(body_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
     }
 
     #[tokio::test]
@@ -1022,7 +2106,7 @@ mod middleware {
             .method("POST")
             .uri(EMBEDDINGS_PATH)
             .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::STACK_SMALL_ID, "2")
             .header("Content-Type", "application/json")
             .body(Body::from(body.to_string()))
             .unwrap();
@@ -1062,7 +2146,7 @@ mod middleware {
             .method("POST")
             .uri(EMBEDDINGS_PATH)
             .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::STACK_SMALL_ID, "2")
             .header("Content-Type", "application/json")
             .body(Body::from(body.to_string()))
             .unwrap();
@@ -1099,7 +2183,7 @@ mod middleware {
             .method("POST")
             .uri(IMAGE_GENERATIONS_PATH)
             .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::STACK_SMALL_ID, "3")
             .header("Content-Type", "application/json")
             .body(Body::from(body.to_string()))
             .unwrap();
@@ -1144,7 +2228,7 @@ mod middleware {
             .method("POST")
             .uri(IMAGE_GENERATIONS_PATH)
             .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::STACK_SMALL_ID, "3")
             .header("Content-Type", "application/json")
             .body(Body::from(body.to_string()))
             .unwrap();
@@ -1164,11 +2248,54 @@ mod middleware {
             .method("POST")
             .uri(IMAGE_GENERATIONS_PATH)
             .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::STACK_SMALL_ID, "3")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_invalid_embeddings_input() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        // Test with invalid input type (number instead of string or array)
+        let body = json!({
+            "model": "intfloat/multilingual-e5-large-instruct",
+            "input": 123
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(EMBEDDINGS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "2")
             .header("Content-Type", "application/json")
             .body(Body::from(body.to_string()))
             .unwrap();
 
+        let mut app = Router::new()
+            .route(EMBEDDINGS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+
         let response = app.call(req).await.expect("Failed to get response");
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
@@ -1179,9 +2306,246 @@ mod middleware {
 
     #[tokio::test]
     #[serial]
-    async fn test_verify_stack_permissions_invalid_embeddings_input() {
+    async fn test_verify_stack_permissions_task_model_mismatch() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        // Stack 2 was bought for the embeddings model, not for the chat model
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": "Hello"
+            }],
+            "max_tokens": 100,
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "2")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_task_role_mismatch() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        // Stack 1 was bought for chat completions, so it cannot be drained by image generations
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "prompt": "A beautiful sunset over mountains",
+            "size": "4x4",
+            "n": 1
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(IMAGE_GENERATIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route(IMAGE_GENERATIONS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_deprecated_task() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": "Hello"
+            }],
+            "max_tokens": 100,
+        });
+
+        // NOTE: The deprecated task is valid until epoch 1, and rejected while the current
+        // epoch is unknown
+        for (current_epoch, expected_status) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some(1), StatusCode::OK),
+            (Some(2), StatusCode::UNAUTHORIZED),
+        ] {
+            let mut app_state = app_state.clone();
+            app_state.current_epoch_receiver = tokio::sync::watch::channel(current_epoch).1;
+            let req = Request::builder()
+                .method("POST")
+                .uri(CHAT_COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(
+                    constants::STACK_SMALL_ID,
+                    DEPRECATED_TASK_SMALL_ID.to_string(),
+                )
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+
+            let mut app = Router::new()
+                .route(CHAT_COMPLETIONS_PATH, post(test_handler))
+                .layer(axum::middleware::from_fn_with_state(
+                    app_state,
+                    verify_stack_permissions,
+                ));
+
+            let response = app.call(req).await.expect("Failed to get response");
+            assert_eq!(response.status(), expected_status);
+        }
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    /// Sends a chat completions request for the confidential compute stack through `path`
+    /// and returns the response status code
+    async fn confidential_task_request_status(
+        app_state: AppState,
+        signature: &Signature,
+        path: &'static str,
+    ) -> StatusCode {
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": "Hello"
+            }],
+            "max_tokens": 100,
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(path)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(
+                constants::STACK_SMALL_ID,
+                CONFIDENTIAL_TASK_SMALL_ID.to_string(),
+            )
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let mut app = Router::new().route(path, post(test_handler)).layer(
+            axum::middleware::from_fn_with_state(app_state, verify_stack_permissions),
+        );
+
+        let response = app.call(req).await.expect("Failed to get response");
+        response.status()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_confidential_task_plaintext_path() {
+        let (
+            mut app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        // NOTE: Even with a valid TEE attestation, the plaintext route is rejected
+        app_state.tee_attestation_receiver = tokio::sync::watch::channel(true).1;
+
+        let status =
+            confidential_task_request_status(app_state, &signature, CHAT_COMPLETIONS_PATH).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_confidential_task_without_attestation() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+
+        let status = confidential_task_request_status(
+            app_state,
+            &signature,
+            CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_confidential_task_with_attestation() {
         let (
-            app_state,
+            mut app_state,
             _,
             signature,
             shutdown_sender,
@@ -1189,31 +2553,15 @@ mod middleware {
             _event_subscriber_sender,
             _,
         ) = setup_app_state().await;
+        app_state.tee_attestation_receiver = tokio::sync::watch::channel(true).1;
 
-        // Test with invalid input type (number instead of string or array)
-        let body = json!({
-            "model": "intfloat/multilingual-e5-large-instruct",
-            "input": 123
-        });
-
-        let req = Request::builder()
-            .method("POST")
-            .uri(EMBEDDINGS_PATH)
-            .header(constants::SIGNATURE, signature.encode_base64())
-            .header(constants::STACK_SMALL_ID, "1")
-            .header("Content-Type", "application/json")
-            .body(Body::from(body.to_string()))
-            .unwrap();
-
-        let mut app = Router::new()
-            .route(EMBEDDINGS_PATH, post(test_handler))
-            .layer(axum::middleware::from_fn_with_state(
-                app_state,
-                verify_stack_permissions,
-            ));
-
-        let response = app.call(req).await.expect("Failed to get response");
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let status = confidential_task_request_status(
+            app_state,
+            &signature,
+            CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
 
         shutdown_sender.send(true).unwrap();
         state_manager_handle.await.unwrap();
@@ -1283,6 +2631,179 @@ mod middleware {
         truncate_tables().await;
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_replay_protection_allows_retry_after_rate_limit() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        app_state.rate_limiters = Arc::new(
+            RateLimiters::from_config(&RateLimitConfig {
+                per_address: None,
+                per_stack: Some(RateLimits {
+                    requests_per_second: Some(1),
+                    ..Default::default()
+                }),
+            })
+            .unwrap(),
+        );
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
+        }
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(echo_handler))
+            .layer(
+                tower::ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn(signature_verification_middleware))
+                    .layer(axum::middleware::from_fn_with_state(
+                        app_state.clone(),
+                        replay_protection_middleware,
+                    ))
+                    .layer(axum::middleware::from_fn_with_state(
+                        app_state,
+                        verify_stack_permissions,
+                    )),
+            );
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "max_tokens": 100,
+        });
+        let request = |nonce: &str| {
+            let body_hash: [u8; 32] = blake2b_hash(body.to_string().as_bytes()).into();
+            let freshness = RequestFreshness {
+                timestamp: Some(now),
+                nonce: Some(nonce.to_string()),
+            };
+            let signature = keystore
+                .sign_hashed(&address, &signed_request_digest(&body_hash, &freshness))
+                .expect("Failed to sign message");
+            Request::builder()
+                .method("POST")
+                .uri(CHAT_COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::STACK_SMALL_ID, "1")
+                .header(constants::REQUEST_TIMESTAMP, now.to_string())
+                .header(constants::REQUEST_NONCE, nonce)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = app.call(request("first")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.call(request("second")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // NOTE: The rate limited request was not served, so it can be retried as is
+        tokio::time::sleep(std::time::Duration::from_millis(1_100)).await;
+        let response = app.call(request("second")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.call(request("second")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_replay_protection_rejects_replayed_confidential_request() {
+        let (app_state, _, _, shutdown_sender, state_manager_handle, _, server_dh_public_key) =
+            setup_app_state().await;
+        let keystore = setup_keystore();
+        let address = keystore.addresses()[0];
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let salt = rand::random::<[u8; SALT_SIZE]>();
+        let client_dh_private_key = x25519_dalek::StaticSecret::random_from_rng(rand::thread_rng());
+        let client_dh_public_key = x25519_dalek::PublicKey::from(&client_dh_private_key);
+        let plaintext_body_hash: [u8; 32] = blake2b_hash(TEST_MESSAGE.as_bytes()).into();
+        let shared_secret = client_dh_private_key.diffie_hellman(&server_dh_public_key);
+        let (encrypted_data, nonce) =
+            encrypt_plaintext(TEST_MESSAGE.as_bytes(), &shared_secret, &salt, None)
+                .expect("Failed to encrypt plaintext data");
+        let encrypted_body_json = json!({
+            "ciphertext": STANDARD.encode(encrypted_data),
+            "salt": STANDARD.encode(salt),
+            "nonce": STANDARD.encode(nonce.as_slice()),
+            "node_dh_public_key": STANDARD.encode(server_dh_public_key.as_ref()),
+            "client_dh_public_key": STANDARD.encode(client_dh_public_key.as_ref()),
+            "plaintext_body_hash": STANDARD.encode(plaintext_body_hash.as_slice()),
+            "model_name": "test_model",
+            "num_compute_units": 100,
+            "stream": false,
+            "stack_small_id": 1
+        });
+        let freshness = RequestFreshness {
+            timestamp: Some(now),
+            nonce: Some("nonce".to_string()),
+        };
+        let signature = keystore
+            .sign_hashed(
+                &address,
+                &signed_request_digest(&plaintext_body_hash, &freshness),
+            )
+            .expect("Failed to sign message");
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::REQUEST_TIMESTAMP, now.to_string())
+                .header(constants::REQUEST_NONCE, "nonce")
+                .body(Body::from(encrypted_body_json.to_string()))
+                .unwrap()
+        };
+
+        async fn echo_handler(req: Request<Body>) -> Response<Body> {
+            Response::new(req.into_body())
+        }
+
+        let mut app = Router::new().route("/", post(echo_handler)).layer(
+            tower::ServiceBuilder::new()
+                .layer(axum::middleware::from_fn_with_state(
+                    app_state.clone(),
+                    confidential_compute_middleware,
+                ))
+                .layer(axum::middleware::from_fn_with_state(
+                    app_state,
+                    replay_protection_middleware,
+                )),
+        );
+
+        let response = app.call(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        assert_eq!(body, TEST_MESSAGE.as_bytes());
+        let response = app.call(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_confidential_compute_middleware_missing_headers() {
@@ -1550,10 +3071,12 @@ mod middleware {
         let initial_metadata = RequestMetadata {
             stack_small_id: 42,
             estimated_total_compute_units: 100,
+            session_grant_id: None,
             payload_hash: [0u8; 32],
             request_type: RequestType::ChatCompletions,
             endpoint_path: "/".to_string(),
             client_encryption_metadata: None,
+            json_canonicalization: JsonCanonicalization::default(),
         };
 
         let mut req = Request::builder()
@@ -1622,4 +3145,398 @@ mod middleware {
         state_manager_handle.await.unwrap();
         truncate_tables().await;
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_models_endpoint() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        app_state.model_metadata = Arc::new(vec![ModelMetadata {
+            model: "meta-llama/Llama-3.1-70B-Instruct".to_string(),
+            context_length: Some(8192),
+            ..Default::default()
+        }]);
+        let mut app = Router::new()
+            .route(MODELS_PATH, get(models_handler))
+            .route(MODEL_PATH, get(model_handler))
+            .with_state(app_state);
+
+        let req = Request::builder()
+            .uri(MODELS_PATH)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let models: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(models["object"], "list");
+        let data = models["data"].as_array().unwrap();
+        assert_eq!(data.len(), 5);
+        assert_eq!(data[0]["id"], "meta-llama/Llama-3.1-70B-Instruct");
+        assert_eq!(data[0]["object"], "model");
+        assert_eq!(data[0]["owned_by"], "meta-llama");
+        assert_eq!(data[0]["context_length"], 8192);
+        assert_eq!(
+            data[0]["endpoints"],
+            json!([CHAT_COMPLETIONS_PATH, COMPLETIONS_PATH])
+        );
+        // NOTE: The deprecated task is not listed
+        assert_eq!(
+            data[0]["task_small_ids"],
+            json!([1, CONFIDENTIAL_TASK_SMALL_ID])
+        );
+        assert_eq!(data[1]["context_length"], Value::Null);
+        assert_eq!(data[1]["endpoints"], json!([EMBEDDINGS_PATH]));
+        assert_eq!(data[1]["task_small_ids"], json!([2]));
+        assert_eq!(data[2]["endpoints"], json!([IMAGE_GENERATIONS_PATH]));
+        assert_eq!(data[3]["endpoints"], json!([AUDIO_TRANSCRIPTIONS_PATH]));
+        assert_eq!(data[4]["endpoints"], json!([RERANK_PATH]));
+
+        let req = Request::builder()
+            .uri(format!(
+                "{MODELS_PATH}/intfloat/multilingual-e5-large-instruct"
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let model: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(model, data[1]);
+
+        let req = Request::builder()
+            .uri(format!("{MODELS_PATH}/unknown-model"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["code"], "model_not_found");
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_chat_completions_handler_with_mock_inference_service() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        let url = mock::spawn(MockInferenceConfig::default()).await.unwrap();
+        app_state.backends = Arc::new(
+            ModelBackends::from_config(&AtomaServiceConfig {
+                chat_completions_service_url: Some(url),
+                embeddings_service_url: None,
+                image_generations_service_url: None,
+                audio_transcriptions_service_url: None,
+                speech_service_url: None,
+                backends: vec![],
+                backend_health: BackendHealthConfig::default(),
+                admission_control: vec![],
+                rate_limits: Default::default(),
+                replay_protection: Default::default(),
+                zklogin: None,
+                models: app_state.models.to_vec(),
+                revisions: vec!["main".to_string(); app_state.models.len()],
+                num_tokens_per_image: NUM_TOKENS_PER_IMAGE,
+                usage_reconciliation: UsageReconciliationPolicy::default(),
+                model_metadata: vec![],
+                task_roles: TaskRolesConfig::default(),
+                service_bind_address: "0.0.0.0:3000".to_string(),
+            })
+            .unwrap(),
+        );
+        let request_metadata = RequestMetadata::default()
+            .with_stack_info(1, 100)
+            .with_payload_hash([1u8; 32]);
+        let request_metadata = RequestMetadata {
+            endpoint_path: CHAT_COMPLETIONS_PATH.to_string(),
+            ..request_metadata
+        };
+        let payload = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{"role": "user", "content": "Hello there"}],
+            "max_tokens": 4
+        });
+
+        let response =
+            chat_completions_handler(Extension(request_metadata), State(app_state), Json(payload))
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "The quick brown fox"
+        );
+        assert_eq!(body["usage"]["total_tokens"], 6);
+        assert!(body["signature"].is_string());
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_failed_request_does_not_consume_session_grant() {
+        let (app_state, _, _, shutdown_sender, state_manager_handle, _event_subscriber_sender, _) =
+            setup_app_state().await;
+        let keystore = setup_keystore();
+        let owner_address = keystore.addresses()[0];
+        let (_, session_key_pair): (_, AccountKeyPair) = get_key_pair();
+        let session_key_pair = SuiKeyPair::Ed25519(session_key_pair);
+        let session_public_key = session_key_pair.public();
+        let expires_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let grant_id = session_grant_digest(
+            &[&[session_public_key.flag()], session_public_key.as_ref()].concat(),
+            1,
+            1_000,
+            expires_at,
+        );
+        let grant = SessionGrant {
+            session_public_key: session_public_key.encode_base64(),
+            stack_small_id: 1,
+            max_compute_units: 1_000,
+            expires_at,
+            signature: keystore
+                .sign_hashed(&owner_address, &grant_id)
+                .expect("Failed to sign session grant")
+                .encode_base64(),
+        };
+
+        // NOTE: No inference backend is configured, so the request fails once admitted
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(chat_completions_handler))
+            .layer(
+                tower::ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn(signature_verification_middleware))
+                    .layer(axum::middleware::from_fn_with_state(
+                        app_state.clone(),
+                        verify_stack_permissions,
+                    )),
+            )
+            .with_state(app_state.clone());
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "max_tokens": 100,
+        });
+        let body_hash: [u8; 32] = blake2b_hash(body.to_string().as_bytes()).into();
+        let request = Request::builder()
+            .method("POST")
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(
+                constants::SIGNATURE,
+                Signature::new_hashed(&body_hash, &session_key_pair).encode_base64(),
+            )
+            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::SESSION_GRANT, grant.to_header())
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // NOTE: State manager events are handled in order, so the compute units of the
+        // failed request are released once its stack can be retrieved
+        let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
+        app_state
+            .state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::GetStack {
+                stack_small_id: 1,
+                result_sender,
+            })
+            .unwrap();
+        let stack = result_receiver.await.unwrap().unwrap().unwrap();
+        assert_eq!(stack.already_computed_units, 0);
+        let db = PgPool::connect(POSTGRES_TEST_DB_URL)
+            .await
+            .expect("Failed to connect to database");
+        let (used_compute_units, num_requests): (i64, i64) = sqlx::query_as(
+            "SELECT used_compute_units, num_requests FROM session_grant_usage WHERE grant_id = $1",
+        )
+        .bind(grant_id.as_slice())
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(used_compute_units, 0);
+        assert_eq!(num_requests, 1);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_quote_handler() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        let quote = |stack_small_id: &str, endpoint: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                constants::SIGNATURE,
+                signature.encode_base64().parse().unwrap(),
+            );
+            headers.insert(constants::STACK_SMALL_ID, stack_small_id.parse().unwrap());
+            let payload = json!({
+                "endpoint": endpoint,
+                "request": {
+                    "model": "meta-llama/Llama-3.1-70B-Instruct",
+                    "messages": [{"role": "user", "content": "What is the capital of Mars?"}],
+                    "max_tokens": 100
+                }
+            });
+            quote_handler(
+                Extension(RequestMetadata::default()),
+                State(app_state.clone()),
+                headers,
+                Json(payload),
+            )
+        };
+
+        let Json(body) = quote("1", CHAT_COMPLETIONS_PATH).await.unwrap();
+        let estimated_compute_units = body["estimated_compute_units"].as_i64().unwrap();
+        assert!(estimated_compute_units > 100);
+        assert_eq!(body["stack_small_id"], 1);
+        assert_eq!(body["model"], "meta-llama/Llama-3.1-70B-Instruct");
+        assert_eq!(body["remaining_compute_units"], 600);
+        assert_eq!(body["price_per_one_million_compute_units"], 1);
+        assert_eq!(body["estimated_price"], 1);
+        assert!(body["signature"].is_string());
+        assert!(body["response_hash"].is_string());
+
+        // NOTE: Quotes do not reserve compute units on the stack
+        let Json(body) = quote("1", CHAT_COMPLETIONS_PATH).await.unwrap();
+        assert_eq!(body["remaining_compute_units"], 600);
+        assert_eq!(body["estimated_compute_units"], estimated_compute_units);
+
+        assert!(matches!(
+            quote("1", MODELS_PATH).await,
+            Err(AtomaServiceError::InvalidBody { .. })
+        ));
+        assert!(matches!(
+            quote("99", CHAT_COMPLETIONS_PATH).await,
+            Err(AtomaServiceError::AuthError { .. })
+        ));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_stack_usage_handler() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _,
+        ) = setup_app_state().await;
+        for num_compute_units in [10, 20, 30] {
+            record_stack_receipt(
+                &app_state.state_manager_sender,
+                1,
+                [num_compute_units as u8; 32],
+                [1; 32],
+                num_compute_units,
+                "test",
+            )
+            .unwrap();
+        }
+        let stack_usage = |stack_small_id: i64, limit: Option<i64>, offset: Option<i64>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                constants::SIGNATURE,
+                signature.encode_base64().parse().unwrap(),
+            );
+            stack_usage_handler(
+                Extension(RequestMetadata::default()),
+                State(app_state.clone()),
+                Path(stack_small_id),
+                Query(StackUsageQuery { limit, offset }),
+                headers,
+            )
+        };
+
+        let Json(body) = stack_usage(1, Some(2), None).await.unwrap();
+        assert_eq!(body["stack_small_id"], 1);
+        assert_eq!(body["num_compute_units"], 600);
+        assert_eq!(body["already_computed_units"], 0);
+        assert_eq!(body["num_total_messages"], 1);
+        assert_eq!(body["settlement_state"], "active");
+        assert_eq!(body["next_offset"], 2);
+        assert!(body["signature"].is_string());
+        assert!(body["response_hash"].is_string());
+        let receipts = body["receipts"].as_array().unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0]["payload_hash"], STANDARD.encode([10; 32]));
+        assert_eq!(receipts[0]["response_hash"], STANDARD.encode([1; 32]));
+        assert_eq!(receipts[0]["num_compute_units"], 10);
+        assert_eq!(receipts[1]["num_compute_units"], 20);
+
+        let Json(body) = stack_usage(1, Some(2), Some(2)).await.unwrap();
+        assert_eq!(body["receipts"].as_array().unwrap().len(), 1);
+        assert_eq!(body["receipts"][0]["num_compute_units"], 30);
+        assert!(body["next_offset"].is_null());
+
+        // NOTE: Out of range offsets are clamped, instead of overflowing the next page's offset
+        let Json(body) = stack_usage(1, None, Some(i64::MAX)).await.unwrap();
+        assert_eq!(body["receipts"], json!([]));
+        assert!(body["next_offset"].is_null());
+
+        // NOTE: Receipts are only recorded for the stack they were served for
+        let Json(body) = stack_usage(2, None, None).await.unwrap();
+        assert_eq!(body["receipts"], json!([]));
+
+        assert!(matches!(
+            stack_usage(99, None, None).await,
+            Err(AtomaServiceError::AuthError { .. })
+        ));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
 }
@@ -36,6 +36,10 @@ pub struct ConfidentialComputeRequest {
     /// Number of compute units to be used for the request, for image generations,
     /// as this value is known in advance (the number of pixels to generate)
     pub num_compute_units: Option<u64>,
+
+    /// Content type of the plaintext body, if it is not JSON (e.g. the `multipart/form-data`
+    /// body, with its boundary, of audio transcriptions requests)
+    pub plaintext_content_type: Option<String>,
 }
 
 /// Represents a response from a confidential compute request
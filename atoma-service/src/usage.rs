@@ -0,0 +1,257 @@
+use serde_json::Value;
+use tokenizers::Tokenizer;
+use tracing::debug;
+
+use crate::{config::UsageReconciliationPolicy, handlers::prometheus::USAGE_DISCREPANCY_RATIO};
+
+/// The key for the number of input tokens of an OpenAI usage object
+const PROMPT_TOKENS_KEY: &str = "prompt_tokens";
+
+/// The key for the number of output tokens of an OpenAI usage object
+const COMPLETION_TOKENS_KEY: &str = "completion_tokens";
+
+/// The key for the choices of a response
+const CHOICES_KEY: &str = "choices";
+
+/// The key for the message of a chat completion choice
+const MESSAGE_KEY: &str = "message";
+
+/// The key for the delta of a streamed chat completion choice
+const DELTA_KEY: &str = "delta";
+
+/// The key for the content of a chat completion message
+const CONTENT_KEY: &str = "content";
+
+/// The key for the reasoning content of a chat completion message, for reasoning models
+const REASONING_CONTENT_KEY: &str = "reasoning_content";
+
+/// The key for the tool calls of a chat completion message
+const TOOL_CALLS_KEY: &str = "tool_calls";
+
+/// The JSON pointer to the name of the function called by a tool call
+const FUNCTION_NAME_POINTER: &str = "/function/name";
+
+/// The JSON pointer to the arguments of the function called by a tool call
+const FUNCTION_ARGUMENTS_POINTER: &str = "/function/arguments";
+
+/// The key for the text of a (legacy) completion choice
+const TEXT_KEY: &str = "text";
+
+/// The number of input and output tokens of a request
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    /// The number of input (prompt) tokens
+    pub input_tokens: i64,
+    /// The number of output (completion) tokens
+    pub output_tokens: i64,
+}
+
+impl TokenUsage {
+    /// Returns the token usage of an OpenAI usage object, or `None` if it does not report both
+    /// its `prompt_tokens` and `completion_tokens`
+    pub fn from_usage(usage: &Value) -> Option<Self> {
+        let count = |key: &str| usage.get(key).map(|tokens| tokens.as_i64().unwrap_or(0));
+        Some(Self {
+            input_tokens: count(PROMPT_TOKENS_KEY)?,
+            output_tokens: count(COMPLETION_TOKENS_KEY)?,
+        })
+    }
+
+    /// Returns the total number of tokens, which is the number of compute units of the request
+    pub fn total(&self) -> i64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Reconciles the token usage reported by an inference service with the one counted locally
+/// by the node, and returns the token usage to charge to the stack, according to `policy`.
+///
+/// Only the output tokens are counted from the response: the local input tokens are the
+/// prompt tokens estimated when the request was admitted, by tokenizing the forwarded prompt
+/// (see `utils::calculate_chat_completion_compute_units`), so that the local input usage is
+/// the one reserved on the stack.
+///
+/// The relative discrepancy of the reported input and output tokens from the local counts is
+/// recorded in the `atoma_usage_discrepancy_ratio` histogram. Without local counts (e.g. if
+/// the model has no tokenizer), the reported usage is charged.
+pub fn reconcile_usage(
+    policy: UsageReconciliationPolicy,
+    model: &str,
+    reported: TokenUsage,
+    local: Option<TokenUsage>,
+) -> TokenUsage {
+    let Some(local) = local else {
+        return reported;
+    };
+    for (token_type, reported_tokens, local_tokens) in [
+        ("input", reported.input_tokens, local.input_tokens),
+        ("output", reported.output_tokens, local.output_tokens),
+    ] {
+        USAGE_DISCREPANCY_RATIO
+            .with_label_values(&[model, token_type])
+            .observe(discrepancy_ratio(reported_tokens, local_tokens));
+    }
+    if reported != local {
+        debug!(
+            target = "atoma-service",
+            level = "debug",
+            model,
+            ?policy,
+            "Reported usage {reported:?} differs from local usage {local:?}"
+        );
+    }
+    match policy {
+        UsageReconciliationPolicy::Backend => reported,
+        UsageReconciliationPolicy::Local => local,
+        UsageReconciliationPolicy::Max => TokenUsage {
+            input_tokens: reported.input_tokens.max(local.input_tokens),
+            output_tokens: reported.output_tokens.max(local.output_tokens),
+        },
+    }
+}
+
+/// Counts the tokens of the concatenation of `texts`, or returns `None` if they cannot be
+/// tokenized
+pub fn count_tokens(tokenizer: &Tokenizer, texts: &[&str]) -> Option<i64> {
+    tokenizer
+        .encode(texts.concat(), false)
+        .ok()
+        .map(|encoding| encoding.get_ids().len() as i64)
+}
+
+/// Returns the texts generated in a non-streaming response, which are the texts of its
+/// choices (see [`choice_texts`])
+pub fn response_texts(response: &Value) -> Vec<&str> {
+    response
+        .get(CHOICES_KEY)
+        .and_then(|choices| choices.as_array())
+        .into_iter()
+        .flatten()
+        .flat_map(choice_texts)
+        .collect()
+}
+
+/// Returns the texts generated in a choice, or in a streamed chunk of a choice.
+///
+/// These are the reasoning content, the content, and the names and arguments of the tool
+/// calls of chat completion messages (or deltas), or the texts of completion choices.
+pub fn choice_texts(choice: &Value) -> Vec<&str> {
+    let Some(message) = choice.get(MESSAGE_KEY).or_else(|| choice.get(DELTA_KEY)) else {
+        return choice
+            .get(TEXT_KEY)
+            .and_then(Value::as_str)
+            .into_iter()
+            .collect();
+    };
+    let tool_calls = message
+        .get(TOOL_CALLS_KEY)
+        .and_then(|tool_calls| tool_calls.as_array())
+        .into_iter()
+        .flatten()
+        .flat_map(|tool_call| {
+            [
+                tool_call.pointer(FUNCTION_NAME_POINTER),
+                tool_call.pointer(FUNCTION_ARGUMENTS_POINTER),
+            ]
+        });
+    [message.get(REASONING_CONTENT_KEY), message.get(CONTENT_KEY)]
+        .into_iter()
+        .chain(tool_calls)
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+/// Returns the discrepancy of the reported number of tokens from the local one, relative to
+/// the local one (e.g. `0.1` if the inference service reports 10% more tokens)
+fn discrepancy_ratio(reported_tokens: i64, local_tokens: i64) -> f64 {
+    (reported_tokens - local_tokens) as f64 / local_tokens.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reconcile_usage() {
+        let reported = TokenUsage {
+            input_tokens: 10,
+            output_tokens: 20,
+        };
+        let local = TokenUsage {
+            input_tokens: 12,
+            output_tokens: 15,
+        };
+        let reconcile = |policy, local| reconcile_usage(policy, "llama", reported, local);
+
+        assert_eq!(
+            reconcile(UsageReconciliationPolicy::Backend, Some(local)),
+            reported
+        );
+        assert_eq!(
+            reconcile(UsageReconciliationPolicy::Local, Some(local)),
+            local
+        );
+        assert_eq!(
+            reconcile(UsageReconciliationPolicy::Max, Some(local)),
+            TokenUsage {
+                input_tokens: 12,
+                output_tokens: 20
+            }
+        );
+        // NOTE: Without local counts, the reported usage is trusted
+        assert_eq!(reconcile(UsageReconciliationPolicy::Local, None), reported);
+        assert_eq!(discrepancy_ratio(20, 15), 5.0 / 15.0);
+    }
+
+    #[test]
+    fn test_token_usage_and_response_texts() {
+        let response = json!({
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": "Hello"}},
+                {"index": 1, "text": " world"}
+            ],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5}
+        });
+        assert_eq!(response_texts(&response), vec!["Hello", " world"]);
+        assert_eq!(
+            TokenUsage::from_usage(&response["usage"]).map(|usage| usage.total()),
+            Some(5)
+        );
+        assert_eq!(TokenUsage::from_usage(&json!({"prompt_tokens": 3})), None);
+    }
+
+    #[test]
+    fn test_response_texts_with_tool_calls() {
+        let response = json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "reasoning_content": "The user asks for the weather",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                    }]
+                }
+            }]
+        });
+        assert_eq!(
+            response_texts(&response),
+            vec![
+                "The user asks for the weather",
+                "get_weather",
+                "{\"city\":\"Paris\"}"
+            ]
+        );
+
+        let chunk_choice = json!({
+            "index": 0,
+            "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\""}}]}
+        });
+        assert_eq!(choice_texts(&chunk_choice), vec!["{\"city\""]);
+    }
+}
@@ -627,8 +627,8 @@ pub(crate) async fn handle_stack_attestation_dispute_event(
 /// Handles events related to the state manager.
 ///
 /// This function processes various events that are sent to the state manager,
-/// including requests to get available stacks with compute units, update the number
-/// of compute units for a stack, and update the total hash of a stack.
+/// including requests to get available stacks with compute units, get a task, update
+/// the number of compute units for a stack, and update the total hash of a stack.
 ///
 /// # Arguments
 ///
@@ -643,15 +643,37 @@ pub(crate) async fn handle_stack_attestation_dispute_event(
 ///
 /// This function may return an error if:
 /// * The database operations for updating compute units or hashes fail.
-/// * The result sender fails to send the result for the `GetAvailableStackWithComputeUnits` event.
+/// * The result sender fails to send the result for the `GetAvailableStackWithComputeUnits`,
+///   `GetStack`, `GetStackReceipts`, `GetStackSettlementTicket`, `GetTask`,
+///   `GetSubscribedTasks`, `RecordSeenRequestSignature` or `ReserveSessionGrantComputeUnits`
+///   events.
 ///
 /// # Behavior
 ///
 /// The function performs the following steps:
 /// 1. Matches the incoming event to determine the type of operation to perform.
 /// 2. For `GetAvailableStackWithComputeUnits`, it retrieves the available stack and sends the result.
-/// 3. For `UpdateStackNumComputeUnits`, it updates the number of compute units for the specified stack.
-/// 4. For `UpdateStackTotalHash`, it updates the total hash for the specified stack.
+/// 3. For `GetStack`, it retrieves the stack with the specified small id, if any, and sends
+///    the result.
+/// 4. For `GetStackReceipts`, it retrieves a page of the receipts of the specified stack and
+///    sends the result.
+/// 5. For `GetStackSettlementTicket`, it retrieves the settlement ticket of the specified
+///    stack, if any, and sends the result.
+/// 6. For `GetTask`, it retrieves the task with the specified small id and sends the result.
+/// 7. For `GetSubscribedTasks`, it retrieves the tasks the specified node is subscribed to
+///    and sends the result.
+/// 8. For `RecordSeenRequestSignature`, it records the signed request and sends whether it
+///    was not seen before.
+/// 9. For `ReleaseSeenRequestSignature`, it forgets the signed request, so that it can be
+///    retried.
+/// 10. For `PruneSeenRequestSignatures`, it deletes the expired seen signed requests.
+/// 11. For `ReserveSessionGrantComputeUnits`, it reserves compute units of a session key grant
+///     and sends whether they fit within its cap.
+/// 12. For `UpdateSessionGrantComputeUnits`, it reconciles the compute units reserved on a
+///     session key grant with the ones actually used.
+/// 13. For `RecordStackReceipt`, it records the receipt of a response served for a stack.
+/// 14. For `UpdateStackNumComputeUnits`, it updates the number of compute units for the specified stack.
+/// 15. For `UpdateStackTotalHash`, it updates the total hash for the specified stack.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_state_manager_event(
     state_manager: &AtomaStateManager,
@@ -681,6 +703,143 @@ pub(crate) async fn handle_state_manager_event(
                 .send(result)
                 .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
         }
+        AtomaAtomaStateManagerEvent::GetStack {
+            stack_small_id,
+            result_sender,
+        } => {
+            let result = match state_manager.state.get_stack(stack_small_id).await {
+                Err(AtomaStateManagerError::DatabaseConnectionError(sqlx::Error::RowNotFound)) => {
+                    Ok(None)
+                }
+                result => result.map(Some),
+            };
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetStackReceipts {
+            stack_small_id,
+            limit,
+            offset,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .get_stack_receipts(stack_small_id, limit, offset)
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetStackSettlementTicket {
+            stack_small_id,
+            result_sender,
+        } => {
+            let result = match state_manager
+                .state
+                .get_stack_settlement_ticket(stack_small_id)
+                .await
+            {
+                Err(AtomaStateManagerError::DatabaseConnectionError(sqlx::Error::RowNotFound)) => {
+                    Ok(None)
+                }
+                result => result.map(Some),
+            };
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetTask {
+            task_small_id,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .get_task_by_small_id(task_small_id)
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetSubscribedTasks {
+            node_small_id,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .get_subscribed_tasks(node_small_id)
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::RecordSeenRequestSignature {
+            signature_key,
+            expires_at,
+            now,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .insert_seen_request_signature(&signature_key, expires_at, now)
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::ReleaseSeenRequestSignature { signature_key } => {
+            state_manager
+                .state
+                .delete_seen_request_signature(&signature_key)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::PruneSeenRequestSignatures { now } => {
+            state_manager
+                .state
+                .delete_expired_request_signatures(now)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::ReserveSessionGrantComputeUnits {
+            grant_id,
+            owner_address,
+            stack_small_id,
+            max_compute_units,
+            expires_at,
+            num_compute_units,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .reserve_session_grant_compute_units(
+                    &grant_id,
+                    &owner_address,
+                    stack_small_id,
+                    max_compute_units,
+                    expires_at,
+                    num_compute_units,
+                )
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::UpdateSessionGrantComputeUnits {
+            grant_id,
+            estimated_total_compute_units,
+            total_compute_units,
+        } => {
+            state_manager
+                .state
+                .update_session_grant_compute_units(
+                    &grant_id,
+                    estimated_total_compute_units,
+                    total_compute_units,
+                )
+                .await?
+        }
+        AtomaAtomaStateManagerEvent::RecordStackReceipt { receipt } => {
+            state_manager.state.insert_stack_receipt(&receipt).await?
+        }
         AtomaAtomaStateManagerEvent::UpdateStackNumComputeUnits {
             stack_small_id,
             estimated_total_compute_units,
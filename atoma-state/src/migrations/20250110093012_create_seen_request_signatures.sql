@@ -0,0 +1,7 @@
+-- Create seen request signatures table, used to reject replayed signed requests
+CREATE TABLE IF NOT EXISTS seen_request_signatures (
+    signature_key            BYTEA   PRIMARY KEY,
+    expires_at               BIGINT  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_seen_request_signatures_expires_at ON seen_request_signatures (expires_at);
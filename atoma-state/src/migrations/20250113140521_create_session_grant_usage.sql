@@ -0,0 +1,12 @@
+-- Create session grant usage table, recording the compute units used by each delegated session key grant
+CREATE TABLE IF NOT EXISTS session_grant_usage (
+    grant_id                 BYTEA   PRIMARY KEY,
+    owner_address            TEXT    NOT NULL,
+    stack_small_id           BIGINT  NOT NULL,
+    max_compute_units        BIGINT  NOT NULL,
+    used_compute_units       BIGINT  NOT NULL,
+    num_requests             BIGINT  NOT NULL,
+    expires_at               BIGINT  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_grant_usage_stack_small_id ON session_grant_usage (stack_small_id);
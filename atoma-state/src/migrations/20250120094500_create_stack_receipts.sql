@@ -0,0 +1,11 @@
+-- Create stack receipts table, recording the responses served for each stack and the compute units charged for them
+CREATE TABLE IF NOT EXISTS stack_receipts (
+    receipt_id               BIGSERIAL PRIMARY KEY,
+    stack_small_id           BIGINT  NOT NULL,
+    payload_hash             BYTEA   NOT NULL,
+    response_hash            BYTEA   NOT NULL,
+    num_compute_units        BIGINT  NOT NULL,
+    timestamp                BIGINT  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_stack_receipts_stack_small_id ON stack_receipts (stack_small_id, receipt_id);
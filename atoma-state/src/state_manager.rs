@@ -1,8 +1,8 @@
 use crate::build_query_with_in;
 use crate::handlers::{handle_atoma_event, handle_state_manager_event};
 use crate::types::{
-    AtomaAtomaStateManagerEvent, NodeSubscription, Stack, StackAttestationDispute,
-    StackSettlementTicket, Task,
+    AtomaAtomaStateManagerEvent, NodeSubscription, SessionGrantUsage, Stack,
+    StackAttestationDispute, StackReceipt, StackSettlementTicket, Task,
 };
 
 use atoma_sui::events::AtomaEvent;
@@ -2149,6 +2149,412 @@ impl AtomaState {
 
         Ok(())
     }
+
+    /// Records a signed request as seen, unless it was already seen and has not expired.
+    ///
+    /// This method inserts the request's signature key into the `seen_request_signatures` table.
+    /// If the key is already present with an expiry that has passed, the entry is renewed, as
+    /// if the key had never been seen.
+    ///
+    /// # Arguments
+    ///
+    /// * `signature_key` - The key identifying the signed request (its signer and signed digest).
+    /// * `expires_at` - The Unix time, in seconds, after which the entry can be forgotten.
+    /// * `now` - The current Unix time, in seconds.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<bool>`: A result containing either:
+    ///   - `Ok(true)`: If the request was not seen before, and is now recorded.
+    ///   - `Ok(false)`: If the request was already seen, i.e. it is a replay.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn is_fresh_request(state_manager: &AtomaStateManager, signature_key: [u8; 32], expires_at: i64, now: i64) -> Result<bool, AtomaStateManagerError> {
+    ///     state_manager.insert_seen_request_signature(&signature_key, expires_at, now).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all, fields(expires_at = %expires_at))]
+    pub async fn insert_seen_request_signature(
+        &self,
+        signature_key: &[u8],
+        expires_at: i64,
+        now: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO seen_request_signatures (signature_key, expires_at)
+                VALUES ($1, $2)
+                ON CONFLICT (signature_key) DO UPDATE SET expires_at = EXCLUDED.expires_at
+                WHERE seen_request_signatures.expires_at < $3",
+        )
+        .bind(signature_key)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Deletes a signed request from the `seen_request_signatures` table, so that it can be
+    /// retried, e.g. because it was rejected before being served.
+    ///
+    /// # Arguments
+    ///
+    /// * `signature_key` - The key identifying the signed request (its signer and signed digest).
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn release(state_manager: &AtomaStateManager, signature_key: [u8; 32]) -> Result<(), AtomaStateManagerError> {
+    ///     state_manager.delete_seen_request_signature(&signature_key).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn delete_seen_request_signature(&self, signature_key: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM seen_request_signatures WHERE signature_key = $1")
+            .bind(signature_key)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the expired entries of the `seen_request_signatures` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current Unix time, in seconds. Entries expiring before it are deleted.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<u64>`: A result containing either:
+    ///   - `Ok(u64)`: The number of deleted entries.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn prune(state_manager: &AtomaStateManager, now: i64) -> Result<u64, AtomaStateManagerError> {
+    ///     state_manager.delete_expired_request_signatures(now).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all, fields(now = %now))]
+    pub async fn delete_expired_request_signatures(&self, now: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM seen_request_signatures WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.db)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Reserves compute units of a session key grant, unless they exceed its cap.
+    ///
+    /// This method records the grant in the `session_grant_usage` table on its first use, and
+    /// adds the reserved compute units to its usage, as long as the usage stays within the
+    /// grant's `max_compute_units`.
+    ///
+    /// # Arguments
+    ///
+    /// * `grant_id` - The identifier of the grant (the digest signed by the stack owner).
+    /// * `owner_address` - The address of the owner of the stack, who signed the grant.
+    /// * `stack_small_id` - The small ID of the stack the grant delegates.
+    /// * `max_compute_units` - The maximum number of compute units the session key can use.
+    /// * `expires_at` - The Unix time, in seconds, at which the grant expires.
+    /// * `num_compute_units` - The number of compute units to reserve.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<bool>`: A result containing either:
+    ///   - `Ok(true)`: If the compute units were reserved.
+    ///   - `Ok(false)`: If reserving the compute units would exceed the grant's cap.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn reserve(state_manager: &AtomaStateManager, grant_id: [u8; 32]) -> Result<bool, AtomaStateManagerError> {
+    ///     state_manager.reserve_session_grant_compute_units(&grant_id, "0x123", 1, 1_000, 1_700_000_000, 100).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(stack_small_id = %stack_small_id, num_compute_units = %num_compute_units)
+    )]
+    pub async fn reserve_session_grant_compute_units(
+        &self,
+        grant_id: &[u8],
+        owner_address: &str,
+        stack_small_id: i64,
+        max_compute_units: i64,
+        expires_at: i64,
+        num_compute_units: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO session_grant_usage
+                (grant_id, owner_address, stack_small_id, max_compute_units, used_compute_units, num_requests, expires_at)
+                SELECT $1, $2, $3, $4, $5, 1, $6
+                WHERE $5 <= $4
+                ON CONFLICT (grant_id) DO UPDATE SET
+                    used_compute_units = session_grant_usage.used_compute_units + EXCLUDED.used_compute_units,
+                    num_requests = session_grant_usage.num_requests + 1
+                WHERE session_grant_usage.used_compute_units + EXCLUDED.used_compute_units <= session_grant_usage.max_compute_units",
+        )
+        .bind(grant_id)
+        .bind(owner_address)
+        .bind(stack_small_id)
+        .bind(max_compute_units)
+        .bind(num_compute_units)
+        .bind(expires_at)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Reconciles the compute units reserved on a session key grant with the ones actually used.
+    ///
+    /// This method replaces the compute units reserved on the grant for a request, when it was
+    /// admitted, with the number of compute units the request actually used. A request that
+    /// failed before being served uses no compute units, so that its reservation is released.
+    ///
+    /// # Arguments
+    ///
+    /// * `grant_id` - The identifier of the grant (the digest signed by the stack owner).
+    /// * `estimated_total_compute_units` - The number of compute units reserved for the request.
+    /// * `total_compute_units` - The number of compute units actually used by the request.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn release(state_manager: &AtomaStateManager, grant_id: [u8; 32]) -> Result<(), AtomaStateManagerError> {
+    ///     state_manager.update_session_grant_compute_units(&grant_id, 100, 0).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(
+            estimated_total_compute_units = %estimated_total_compute_units,
+            total_compute_units = %total_compute_units
+        )
+    )]
+    pub async fn update_session_grant_compute_units(
+        &self,
+        grant_id: &[u8],
+        estimated_total_compute_units: i64,
+        total_compute_units: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE session_grant_usage
+                SET used_compute_units = GREATEST(used_compute_units - $2 + $3, 0)
+                WHERE grant_id = $1",
+        )
+        .bind(grant_id)
+        .bind(estimated_total_compute_units)
+        .bind(total_compute_units)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the usage of a session key grant.
+    ///
+    /// # Arguments
+    ///
+    /// * `grant_id` - The identifier of the grant (the digest signed by the stack owner).
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<SessionGrantUsage>>`: A result containing either:
+    ///   - `Ok(Some(SessionGrantUsage))`: The usage of the grant, if it was used.
+    ///   - `Ok(None)`: If the grant was never used.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn usage(state_manager: &AtomaStateManager, grant_id: [u8; 32]) -> Result<Option<SessionGrantUsage>, AtomaStateManagerError> {
+    ///     state_manager.get_session_grant_usage(&grant_id).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn get_session_grant_usage(
+        &self,
+        grant_id: &[u8],
+    ) -> Result<Option<SessionGrantUsage>> {
+        let usage = sqlx::query_as::<_, SessionGrantUsage>(
+            "SELECT * FROM session_grant_usage WHERE grant_id = $1",
+        )
+        .bind(grant_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(usage)
+    }
+
+    /// Inserts the receipt of a response served for a stack.
+    ///
+    /// This method inserts a new entry into the `stack_receipts` table, recording the hashes of
+    /// the request payload and of its response, and the compute units charged for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `receipt` - The `StackReceipt` object to be inserted into the database.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, StackReceipt};
+    ///
+    /// async fn record(state_manager: &AtomaStateManager, receipt: StackReceipt) -> Result<(), AtomaStateManagerError> {
+    ///     state_manager.insert_stack_receipt(&receipt).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(
+            stack_small_id = %receipt.stack_small_id,
+            num_compute_units = %receipt.num_compute_units
+        )
+    )]
+    pub async fn insert_stack_receipt(&self, receipt: &StackReceipt) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO stack_receipts
+                (stack_small_id, payload_hash, response_hash, num_compute_units, timestamp)
+                VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(receipt.stack_small_id)
+        .bind(&receipt.payload_hash)
+        .bind(&receipt.response_hash)
+        .bind(receipt.num_compute_units)
+        .bind(receipt.timestamp)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the receipts of the responses served for a stack, in the order they were
+    /// recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_small_id` - The unique small identifier of the stack.
+    /// * `limit` - The maximum number of receipts to retrieve.
+    /// * `offset` - The number of receipts to skip.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<StackReceipt>>`: A result containing either:
+    ///   - `Ok(Vec<StackReceipt>)`: The receipts of the stack, which may be empty.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `StackReceipt` objects.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, StackReceipt};
+    ///
+    /// async fn first_page(state_manager: &AtomaStateManager, stack_small_id: i64) -> Result<Vec<StackReceipt>, AtomaStateManagerError> {
+    ///     state_manager.get_stack_receipts(stack_small_id, 100, 0).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(stack_small_id = %stack_small_id, limit = %limit, offset = %offset)
+    )]
+    pub async fn get_stack_receipts(
+        &self,
+        stack_small_id: i64,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<StackReceipt>> {
+        let receipts = sqlx::query_as::<_, StackReceipt>(
+            "SELECT stack_small_id, payload_hash, response_hash, num_compute_units, timestamp
+                FROM stack_receipts
+                WHERE stack_small_id = $1
+                ORDER BY receipt_id
+                LIMIT $2 OFFSET $3",
+        )
+        .bind(stack_small_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(receipts)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -2195,7 +2601,10 @@ mod tests {
                 stacks,
                 stack_settlement_tickets,
                 stack_attestation_disputes,
-                node_public_key_rotations
+                node_public_key_rotations,
+                seen_request_signatures,
+                session_grant_usage,
+                stack_receipts
             CASCADE",
         )
         .execute(db)
@@ -4597,4 +5006,156 @@ mod tests {
         truncate_tables(&state_manager.db).await;
         Ok(())
     }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_insert_seen_request_signature() -> Result<()> {
+        let state_manager = setup_test_db().await;
+        let signature_key = [7u8; 32];
+
+        // First sighting is recorded, replays within the expiry are rejected
+        assert!(
+            state_manager
+                .insert_seen_request_signature(&signature_key, 100, 0)
+                .await?
+        );
+        assert!(
+            !state_manager
+                .insert_seen_request_signature(&signature_key, 150, 50)
+                .await?
+        );
+
+        // Once expired, the entry is renewed
+        assert!(
+            state_manager
+                .insert_seen_request_signature(&signature_key, 300, 200)
+                .await?
+        );
+        assert!(
+            !state_manager
+                .insert_seen_request_signature(&signature_key, 350, 250)
+                .await?
+        );
+
+        // Once released, the request can be retried
+        state_manager
+            .delete_seen_request_signature(&signature_key)
+            .await?;
+        assert!(
+            state_manager
+                .insert_seen_request_signature(&signature_key, 350, 250)
+                .await?
+        );
+
+        assert!(
+            state_manager
+                .insert_seen_request_signature(&[8u8; 32], 100, 0)
+                .await?
+        );
+        assert_eq!(
+            state_manager.delete_expired_request_signatures(250).await?,
+            1
+        );
+        let count = sqlx::query("SELECT COUNT(*) as count FROM seen_request_signatures")
+            .fetch_one(&state_manager.db)
+            .await?;
+        assert_eq!(count.get::<i64, _>("count"), 1);
+
+        truncate_tables(&state_manager.db).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_reserve_session_grant_compute_units() -> Result<()> {
+        let state_manager = setup_test_db().await;
+        let grant_id = [3u8; 32];
+        let reserve = |num_compute_units| {
+            state_manager.reserve_session_grant_compute_units(
+                &grant_id,
+                "0x123",
+                1,
+                100,
+                1_000,
+                num_compute_units,
+            )
+        };
+
+        assert!(state_manager
+            .get_session_grant_usage(&grant_id)
+            .await?
+            .is_none());
+        assert!(!reserve(150).await?);
+        assert!(reserve(60).await?);
+        assert!(reserve(40).await?);
+        assert!(!reserve(1).await?);
+
+        let usage = state_manager
+            .get_session_grant_usage(&grant_id)
+            .await?
+            .expect("Grant usage should be recorded");
+        assert_eq!(
+            usage,
+            SessionGrantUsage {
+                grant_id: grant_id.to_vec(),
+                owner_address: "0x123".to_string(),
+                stack_small_id: 1,
+                max_compute_units: 100,
+                used_compute_units: 100,
+                num_requests: 2,
+                expires_at: 1_000,
+            }
+        );
+
+        // A failed request releases its reservation, and a served one is charged what it used
+        state_manager
+            .update_session_grant_compute_units(&grant_id, 60, 0)
+            .await?;
+        state_manager
+            .update_session_grant_compute_units(&grant_id, 40, 25)
+            .await?;
+        let usage = state_manager
+            .get_session_grant_usage(&grant_id)
+            .await?
+            .expect("Grant usage should be recorded");
+        assert_eq!(usage.used_compute_units, 25);
+        assert!(reserve(75).await?);
+        assert!(!reserve(1).await?);
+
+        truncate_tables(&state_manager.db).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_insert_and_get_stack_receipts() -> Result<()> {
+        let state_manager = setup_test_db().await;
+        let receipt = |stack_small_id, index: u8| StackReceipt {
+            stack_small_id,
+            payload_hash: vec![index; 32],
+            response_hash: vec![index + 1; 32],
+            num_compute_units: i64::from(index) * 10,
+            timestamp: 1_000 + i64::from(index),
+        };
+        for index in 0..3 {
+            state_manager
+                .insert_stack_receipt(&receipt(1, index))
+                .await?;
+        }
+        state_manager.insert_stack_receipt(&receipt(2, 9)).await?;
+
+        assert_eq!(
+            state_manager.get_stack_receipts(1, 10, 0).await?,
+            vec![receipt(1, 0), receipt(1, 1), receipt(1, 2)]
+        );
+        assert_eq!(
+            state_manager.get_stack_receipts(1, 1, 1).await?,
+            vec![receipt(1, 1)]
+        );
+        assert!(state_manager.get_stack_receipts(1, 10, 3).await?.is_empty());
+        assert!(state_manager.get_stack_receipts(3, 10, 0).await?.is_empty());
+
+        truncate_tables(&state_manager.db).await;
+        Ok(())
+    }
 }
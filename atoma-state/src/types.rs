@@ -214,6 +214,40 @@ pub struct NodeSubscription {
     pub valid: bool,
 }
 
+/// Represents the usage of a session key grant, delegating the use of a stack
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SessionGrantUsage {
+    /// Identifier of the grant (the digest signed by the stack owner)
+    pub grant_id: Vec<u8>,
+    /// Address of the owner of the stack, who signed the grant
+    pub owner_address: String,
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// Maximum number of compute units the session key can use
+    pub max_compute_units: i64,
+    /// Number of compute units reserved by requests signed with the session key
+    pub used_compute_units: i64,
+    /// Number of requests signed with the session key
+    pub num_requests: i64,
+    /// Unix time, in seconds, at which the grant expires
+    pub expires_at: i64,
+}
+
+/// Represents the receipt of a response served by the node for a stack
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct StackReceipt {
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// Blake2b hash of the request payload
+    pub payload_hash: Vec<u8>,
+    /// Blake2b hash of the response, as signed by the node
+    pub response_hash: Vec<u8>,
+    /// Number of compute units charged to the stack for the response
+    pub num_compute_units: i64,
+    /// Unix time, in seconds, at which the response was signed
+    pub timestamp: i64,
+}
+
 pub enum AtomaAtomaStateManagerEvent {
     /// Represents an update to the number of compute units in a stack
     UpdateStackNumComputeUnits {
@@ -231,6 +265,29 @@ pub enum AtomaAtomaStateManagerEvent {
         /// Total hash of the stack
         total_hash: [u8; 32],
     },
+    /// Records the receipt of a response served for a stack
+    RecordStackReceipt {
+        /// The receipt of the response
+        receipt: StackReceipt,
+    },
+    /// Gets the receipts of the responses served for a stack, in the order they were served
+    GetStackReceipts {
+        /// Unique small integer identifier for the stack
+        stack_small_id: i64,
+        /// Maximum number of receipts to get
+        limit: i64,
+        /// Number of receipts to skip
+        offset: i64,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<StackReceipt>>>,
+    },
+    /// Gets the settlement ticket of a stack
+    GetStackSettlementTicket {
+        /// Unique small integer identifier for the stack
+        stack_small_id: i64,
+        /// Oneshot channel to send the ticket back, or `None` if the stack is not being settled
+        result_sender: oneshot::Sender<Result<Option<StackSettlementTicket>>>,
+    },
     /// Gets an available stack with enough compute units for a given stack and public key
     GetAvailableStackWithComputeUnits {
         /// Unique small integer identifier for the stack
@@ -242,4 +299,73 @@ pub enum AtomaAtomaStateManagerEvent {
         /// Oneshot channel to send the result back to the sender channel
         result_sender: oneshot::Sender<Result<Option<Stack>>>,
     },
+    /// Gets a stack by its small id, without reserving any of its compute units
+    GetStack {
+        /// Unique small integer identifier for the stack
+        stack_small_id: i64,
+        /// Oneshot channel to send the stack back, or `None` if it does not exist
+        result_sender: oneshot::Sender<Result<Option<Stack>>>,
+    },
+    /// Records a signed request as seen, to reject its replays
+    RecordSeenRequestSignature {
+        /// Key identifying the signed request
+        signature_key: [u8; 32],
+        /// Unix time, in seconds, after which the request can be forgotten
+        expires_at: i64,
+        /// Current Unix time, in seconds
+        now: i64,
+        /// Oneshot channel to send back whether the request was not seen before
+        result_sender: oneshot::Sender<Result<bool>>,
+    },
+    /// Forgets a signed request recorded as seen, as it was rejected before being served, so
+    /// that it can be retried
+    ReleaseSeenRequestSignature {
+        /// Key identifying the signed request
+        signature_key: [u8; 32],
+    },
+    /// Deletes the seen signed requests that have expired
+    PruneSeenRequestSignatures {
+        /// Current Unix time, in seconds
+        now: i64,
+    },
+    /// Reserves compute units of a session key grant, within its cap
+    ReserveSessionGrantComputeUnits {
+        /// Identifier of the grant
+        grant_id: [u8; 32],
+        /// Address of the owner of the stack, who signed the grant
+        owner_address: String,
+        /// Unique small integer identifier for the stack
+        stack_small_id: i64,
+        /// Maximum number of compute units the session key can use
+        max_compute_units: i64,
+        /// Unix time, in seconds, at which the grant expires
+        expires_at: i64,
+        /// Number of compute units to reserve
+        num_compute_units: i64,
+        /// Oneshot channel to send back whether the compute units were reserved
+        result_sender: oneshot::Sender<Result<bool>>,
+    },
+    /// Reconciles the compute units reserved on a session key grant with the ones actually used
+    UpdateSessionGrantComputeUnits {
+        /// Identifier of the grant
+        grant_id: [u8; 32],
+        /// Number of compute units reserved on the grant for the request
+        estimated_total_compute_units: i64,
+        /// Number of compute units actually used by the request
+        total_compute_units: i64,
+    },
+    /// Gets a task by its small id
+    GetTask {
+        /// Unique small integer identifier for the task
+        task_small_id: i64,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Task>>,
+    },
+    /// Gets the tasks a node is subscribed to
+    GetSubscribedTasks {
+        /// Unique small integer identifier for the node
+        node_small_id: i64,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<Task>>>,
+    },
 }
@@ -1,6 +1,8 @@
 use std::path::Path;
 use sui_sdk::{
-    json::SuiJsonValue, rpc_types::SuiData, types::base_types::ObjectID,
+    json::SuiJsonValue,
+    rpc_types::SuiData,
+    types::base_types::{EpochId, ObjectID},
     wallet_context::WalletContext,
 };
 use thiserror::Error;
@@ -1141,6 +1143,25 @@ impl AtomaSuiClient {
         Err(AtomaSuiClientError::FailedToFindNewKeyRotationEvent)
     }
 
+    /// Get the current Sui epoch
+    ///
+    /// # Returns
+    ///
+    /// Returns the epoch of the latest Sui system state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Sui RPC node cannot be reached.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn get_current_epoch(&self) -> Result<EpochId> {
+        let client = self.wallet_ctx.get_client().await?;
+        let system_state = client
+            .governance_api()
+            .get_latest_sui_system_state()
+            .await?;
+        Ok(system_state.epoch)
+    }
+
     /// Get or load the USDC wallet object ID
     ///
     /// This method checks if the USDC wallet object ID is already loaded and returns it if so.
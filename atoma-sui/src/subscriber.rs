@@ -32,7 +32,7 @@ pub(crate) type Result<T> = std::result::Result<T, SuiEventSubscriberError>;
 type StackRetrieveReceiver = mpsc::UnboundedReceiver<(
     TransactionDigest,
     i64,
-    oneshot::Sender<(Option<u64>, Option<u64>)>,
+    oneshot::Sender<(Option<u64>, Option<u64>, Option<u64>)>,
 )>;
 
 /// A subscriber for Sui blockchain events.
@@ -214,6 +214,7 @@ impl SuiEventSubscriber {
                             .events;
                         let mut compute_units = None;
                         let mut stack_small_id = None;
+                        let mut task_small_id = None;
                         if let Some(tx_events) = tx_events {
                             for event in tx_events.data.iter() {
                                 let event_identifier = AtomaEventIdentifier::from_str(event.type_.name.as_str())?;
@@ -228,6 +229,7 @@ impl SuiEventSubscriber {
                                     // right away.
                                     compute_units = Some(event.num_compute_units);
                                     stack_small_id = Some(event.stack_small_id.inner);
+                                    task_small_id = Some(event.task_small_id.inner);
                                     self.state_manager_sender
                                         .send(AtomaEvent::StackCreateAndUpdateEvent(event))
                                         .map_err(Box::new)?;
@@ -236,10 +238,10 @@ impl SuiEventSubscriber {
                                 }
                             }
                         }
-                        // Send the compute units to the Atoma service, so it can be used to validate the
-                        // request.
+                        // Send the compute units and the stack's task to the Atoma service, so it can be
+                        // used to validate the request.
                         result_sender
-                            .send((stack_small_id, compute_units))
+                            .send((stack_small_id, compute_units, task_small_id))
                             .map_err(|_| SuiEventSubscriberError::SendComputeUnitsError)?;
                     }
                     page = client.event_api().query_events(self.filter.clone(), cursor, limit, false) => {
@@ -1,4 +1,7 @@
-use aes_gcm::{aead::Aead, Aes256Gcm, Error as AesError, KeyInit};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, Error as AesError, KeyInit,
+};
 use hkdf::Hkdf;
 use sha2::Sha256;
 use thiserror::Error;
@@ -6,6 +9,16 @@ use x25519_dalek::SharedSecret;
 
 pub const NONCE_BYTE_SIZE: usize = 12;
 
+/// Size of the big-endian chunk counter mixed into the nonce of each streamed chunk
+pub const STREAM_COUNTER_BYTE_SIZE: usize = 4;
+
+/// Flag mixed into the nonce (and associated data) of the last chunk of a stream
+const STREAM_FINAL_CHUNK_FLAG: u8 = 0x01;
+
+/// Size of the associated data authenticated alongside each streamed chunk,
+/// that is, the chunk counter followed by the final chunk flag
+const STREAM_ASSOCIATED_DATA_SIZE: usize = STREAM_COUNTER_BYTE_SIZE + 1;
+
 type Result<T> = std::result::Result<T, EncryptionError>;
 
 /// Decrypts ciphertext using AES-256-GCM with a derived key from a shared secret.
@@ -59,11 +72,7 @@ pub fn decrypt_ciphertext(
     salt: &[u8],
     nonce: &[u8],
 ) -> Result<Vec<u8>> {
-    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret.as_bytes());
-    let mut symmetric_key = [0u8; 32];
-    hkdf.expand(b"", &mut symmetric_key)
-        .map_err(EncryptionError::KeyExpansionFailed)?;
-
+    let symmetric_key = derive_symmetric_key(shared_secret, salt)?;
     let cipher = Aes256Gcm::new(&symmetric_key.into());
     cipher
         .decrypt(nonce.into(), ciphertext)
@@ -119,11 +128,7 @@ pub fn encrypt_plaintext(
     salt: &[u8],
     nonce: Option<[u8; NONCE_BYTE_SIZE]>,
 ) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
-    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret.as_bytes());
-    let mut symmetric_key = [0u8; 32];
-    hkdf.expand(b"", &mut symmetric_key)
-        .map_err(EncryptionError::KeyExpansionFailed)?;
-
+    let symmetric_key = derive_symmetric_key(shared_secret, salt)?;
     let cipher = Aes256Gcm::new(&symmetric_key.into());
     let nonce = nonce.unwrap_or(rand::random::<[u8; NONCE_BYTE_SIZE]>());
     let ciphertext = cipher
@@ -133,6 +138,235 @@ pub fn encrypt_plaintext(
     Ok((ciphertext, nonce))
 }
 
+/// Derives the nonce used to encrypt a single chunk of a streaming response.
+///
+/// Every chunk of a stream is encrypted under the same HKDF-derived key, so each chunk
+/// must use a distinct nonce. Following the STREAM construction, the nonce for a chunk
+/// is obtained from the stream's base nonce by XOR-ing:
+/// 1. The big-endian chunk `counter` into bytes `7..11`
+/// 2. A final chunk flag into the last byte, if `is_final` is set
+///
+/// As XOR is a bijection on disjoint bytes, every `(counter, is_final)` pair yields a
+/// distinct nonce for a given base nonce, and the last chunk of a stream can never share
+/// a nonce with a non-final chunk at the same position.
+///
+/// # Arguments
+///
+/// * `base_nonce` - The random nonce negotiated for the whole stream
+/// * `counter` - The zero-based index of the chunk within the stream
+/// * `is_final` - Whether this is the last chunk of the stream
+///
+/// # Returns
+///
+/// The 12-byte nonce to use for AES-GCM when encrypting (or decrypting) the chunk.
+pub fn derive_stream_chunk_nonce(
+    base_nonce: &[u8; NONCE_BYTE_SIZE],
+    counter: u32,
+    is_final: bool,
+) -> [u8; NONCE_BYTE_SIZE] {
+    let mut nonce = *base_nonce;
+    let counter_start = NONCE_BYTE_SIZE - 1 - STREAM_COUNTER_BYTE_SIZE;
+    for (byte, counter_byte) in nonce[counter_start..NONCE_BYTE_SIZE - 1]
+        .iter_mut()
+        .zip(counter.to_be_bytes())
+    {
+        *byte ^= counter_byte;
+    }
+    if is_final {
+        nonce[NONCE_BYTE_SIZE - 1] ^= STREAM_FINAL_CHUNK_FLAG;
+    }
+    nonce
+}
+
+/// Encrypts a single chunk of a streaming response using AES-256-GCM.
+///
+/// The nonce is derived from the stream's base nonce with [`derive_stream_chunk_nonce`], and
+/// the chunk counter together with the final chunk flag are authenticated as associated data.
+/// This binds every ciphertext to its position in the stream, so that a client can detect
+/// reordered, dropped or replayed chunks, as well as a stream truncated before its last chunk.
+///
+/// # Arguments
+///
+/// * `plaintext` - The chunk data to encrypt
+/// * `shared_secret` - The shared secret derived from X25519 key exchange
+/// * `salt` - Salt value used in the key derivation process
+/// * `base_nonce` - The random nonce negotiated for the whole stream
+/// * `counter` - The zero-based index of the chunk within the stream
+/// * `is_final` - Whether this is the last chunk of the stream
+///
+/// # Returns
+///
+/// Returns a tuple containing the encrypted chunk and the derived nonce used to encrypt it,
+/// or an `EncryptionError` if the operation fails.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use atoma_utils::encryption::encrypt_stream_chunk;
+///
+/// let (ciphertext, nonce) = encrypt_stream_chunk(
+///     b"first chunk",
+///     &shared_secret,
+///     &salt,
+///     &base_nonce,
+///     0,
+///     false,
+/// )?;
+/// ```
+pub fn encrypt_stream_chunk(
+    plaintext: &[u8],
+    shared_secret: &SharedSecret,
+    salt: &[u8],
+    base_nonce: &[u8; NONCE_BYTE_SIZE],
+    counter: u32,
+    is_final: bool,
+) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
+    let symmetric_key = derive_symmetric_key(shared_secret, salt)?;
+    let cipher = Aes256Gcm::new(&symmetric_key.into());
+    let nonce = derive_stream_chunk_nonce(base_nonce, counter, is_final);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce.into(),
+            Payload {
+                msg: plaintext,
+                aad: &stream_associated_data(counter, is_final),
+            },
+        )
+        .map_err(EncryptionError::EncryptionFailed)?;
+    Ok((ciphertext, nonce))
+}
+
+/// Client-side decryptor and verifier for confidential streaming responses.
+///
+/// Chunks must be fed in the order in which they were received. For each chunk, the
+/// decryptor checks that the nonce sent alongside it is the one expected for the chunk's
+/// position in the stream, and authenticates the chunk counter and final chunk flag as
+/// associated data. Once the stream ends, [`StreamDecryptor::finish`] must be called to
+/// make sure the final chunk was received, that is, the stream was not truncated.
+///
+/// The base nonce is recovered from the first chunk, so clients do not need to know it
+/// in advance. A first chunk that is not the real first chunk of the stream fails to
+/// authenticate, as its associated data was produced for a different counter.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use atoma_utils::encryption::StreamDecryptor;
+///
+/// let mut decryptor = StreamDecryptor::new(&shared_secret, &salt)?;
+/// for (ciphertext, nonce, is_final) in chunks {
+///     let plaintext = decryptor.decrypt_chunk(&ciphertext, &nonce, is_final)?;
+///     // process plaintext
+/// }
+/// decryptor.finish()?;
+/// ```
+pub struct StreamDecryptor {
+    /// The AES-256-GCM cipher, keyed with the HKDF-derived symmetric key
+    cipher: Aes256Gcm,
+    /// The base nonce of the stream, recovered from the first chunk
+    base_nonce: Option<[u8; NONCE_BYTE_SIZE]>,
+    /// The index of the next chunk expected in the stream
+    counter: u32,
+    /// Whether the final chunk of the stream has been decrypted
+    finished: bool,
+}
+
+impl StreamDecryptor {
+    /// Constructor
+    pub fn new(shared_secret: &SharedSecret, salt: &[u8]) -> Result<Self> {
+        let symmetric_key = derive_symmetric_key(shared_secret, salt)?;
+        Ok(Self {
+            cipher: Aes256Gcm::new(&symmetric_key.into()),
+            base_nonce: None,
+            counter: 0,
+            finished: false,
+        })
+    }
+
+    /// Decrypts and verifies the next chunk of the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `ciphertext` - The encrypted chunk
+    /// * `nonce` - The nonce sent alongside the chunk
+    /// * `is_final` - Whether the chunk is marked as the last chunk of the stream
+    ///
+    /// # Returns
+    ///
+    /// Returns the decrypted chunk, or an `EncryptionError` if:
+    /// * A chunk is received after the final chunk
+    /// * The nonce has an invalid length or does not match the chunk's position
+    /// * The chunk fails to authenticate
+    pub fn decrypt_chunk(
+        &mut self,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        is_final: bool,
+    ) -> Result<Vec<u8>> {
+        if self.finished {
+            return Err(EncryptionError::StreamAlreadyFinalized);
+        }
+        let nonce: [u8; NONCE_BYTE_SIZE] = nonce
+            .try_into()
+            .map_err(|_| EncryptionError::InvalidNonceLength(nonce.len()))?;
+        // NOTE: XOR-ing the first chunk's nonce with its own counter and flag recovers the base nonce
+        let base_nonce = *self
+            .base_nonce
+            .get_or_insert_with(|| derive_stream_chunk_nonce(&nonce, 0, is_final));
+        if derive_stream_chunk_nonce(&base_nonce, self.counter, is_final) != nonce {
+            return Err(EncryptionError::UnexpectedStreamNonce(self.counter));
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(
+                &nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: &stream_associated_data(self.counter, is_final),
+                },
+            )
+            .map_err(EncryptionError::DecryptionFailed)?;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(EncryptionError::StreamCounterOverflow)?;
+        self.finished = is_final;
+        Ok(plaintext)
+    }
+
+    /// Checks that the final chunk of the stream has been received and decrypted.
+    ///
+    /// # Returns
+    ///
+    /// Returns `EncryptionError::StreamTruncated` if the stream ended before its final chunk.
+    pub fn finish(self) -> Result<()> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(EncryptionError::StreamTruncated(self.counter))
+        }
+    }
+}
+
+/// Derives the AES-256 symmetric key from the shared secret, using HKDF-SHA256.
+fn derive_symmetric_key(shared_secret: &SharedSecret, salt: &[u8]) -> Result<[u8; 32]> {
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret.as_bytes());
+    let mut symmetric_key = [0u8; 32];
+    hkdf.expand(b"", &mut symmetric_key)
+        .map_err(EncryptionError::KeyExpansionFailed)?;
+    Ok(symmetric_key)
+}
+
+/// Builds the associated data authenticated alongside a streamed chunk.
+fn stream_associated_data(counter: u32, is_final: bool) -> [u8; STREAM_ASSOCIATED_DATA_SIZE] {
+    let mut associated_data = [0u8; STREAM_ASSOCIATED_DATA_SIZE];
+    associated_data[..STREAM_COUNTER_BYTE_SIZE].copy_from_slice(&counter.to_be_bytes());
+    if is_final {
+        associated_data[STREAM_COUNTER_BYTE_SIZE] = STREAM_FINAL_CHUNK_FLAG;
+    }
+    associated_data
+}
+
 #[derive(Debug, Error)]
 pub enum EncryptionError {
     #[error("Failed to decrypt ciphertext, with error: `{0}`")]
@@ -141,4 +375,129 @@ pub enum EncryptionError {
     EncryptionFailed(AesError),
     #[error("Failed to expand key, with error: `{0}`")]
     KeyExpansionFailed(hkdf::InvalidLength),
+    #[error("Invalid nonce length: `{0}`")]
+    InvalidNonceLength(usize),
+    #[error("Unexpected nonce for stream chunk `{0}`")]
+    UnexpectedStreamNonce(u32),
+    #[error("Stream chunk counter overflowed")]
+    StreamCounterOverflow,
+    #[error("Received a stream chunk after the final chunk")]
+    StreamAlreadyFinalized,
+    #[error("Stream truncated after `{0}` chunks, final chunk missing")]
+    StreamTruncated(u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{EphemeralSecret, PublicKey};
+
+    fn shared_secret() -> SharedSecret {
+        let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+        let other = EphemeralSecret::random_from_rng(rand::thread_rng());
+        secret.diffie_hellman(&PublicKey::from(&other))
+    }
+
+    fn encrypt_stream(
+        chunks: &[&[u8]],
+        shared_secret: &SharedSecret,
+        salt: &[u8],
+    ) -> Vec<(Vec<u8>, [u8; NONCE_BYTE_SIZE], bool)> {
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let is_final = i == chunks.len() - 1;
+                let (ciphertext, nonce) = encrypt_stream_chunk(
+                    chunk,
+                    shared_secret,
+                    salt,
+                    &base_nonce,
+                    i as u32,
+                    is_final,
+                )
+                .unwrap();
+                (ciphertext, nonce, is_final)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_chunk_nonces_are_unique() {
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let mut nonces = std::collections::HashSet::new();
+        for counter in 0..1024 {
+            assert!(nonces.insert(derive_stream_chunk_nonce(&base_nonce, counter, false)));
+            assert!(nonces.insert(derive_stream_chunk_nonce(&base_nonce, counter, true)));
+        }
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let shared_secret = shared_secret();
+        let salt = rand::random::<[u8; 16]>();
+        let chunks: [&[u8]; 3] = [b"first", b"second", b"third"];
+        let encrypted = encrypt_stream(&chunks, &shared_secret, &salt);
+
+        let mut decryptor = StreamDecryptor::new(&shared_secret, &salt).unwrap();
+        for ((ciphertext, nonce, is_final), chunk) in encrypted.iter().zip(chunks) {
+            let plaintext = decryptor
+                .decrypt_chunk(ciphertext, nonce, *is_final)
+                .unwrap();
+            assert_eq!(plaintext, chunk);
+        }
+        decryptor.finish().unwrap();
+    }
+
+    #[test]
+    fn test_stream_truncation_is_detected() {
+        let shared_secret = shared_secret();
+        let salt = rand::random::<[u8; 16]>();
+        let encrypted = encrypt_stream(&[b"first", b"second", b"third"], &shared_secret, &salt);
+
+        let mut decryptor = StreamDecryptor::new(&shared_secret, &salt).unwrap();
+        for (ciphertext, nonce, is_final) in &encrypted[..2] {
+            decryptor
+                .decrypt_chunk(ciphertext, nonce, *is_final)
+                .unwrap();
+        }
+        assert!(matches!(
+            decryptor.finish(),
+            Err(EncryptionError::StreamTruncated(2))
+        ));
+
+        // Marking a non-final chunk as final must fail to authenticate
+        let mut decryptor = StreamDecryptor::new(&shared_secret, &salt).unwrap();
+        let (ciphertext, nonce, _) = &encrypted[0];
+        decryptor.decrypt_chunk(ciphertext, nonce, false).unwrap();
+        let (ciphertext, nonce, _) = &encrypted[1];
+        assert!(decryptor.decrypt_chunk(ciphertext, nonce, true).is_err());
+    }
+
+    #[test]
+    fn test_stream_reordering_and_dropping_is_detected() {
+        let shared_secret = shared_secret();
+        let salt = rand::random::<[u8; 16]>();
+        let encrypted = encrypt_stream(&[b"first", b"second", b"third"], &shared_secret, &salt);
+
+        // Dropping the first chunk
+        let mut decryptor = StreamDecryptor::new(&shared_secret, &salt).unwrap();
+        let (ciphertext, nonce, is_final) = &encrypted[1];
+        assert!(decryptor
+            .decrypt_chunk(ciphertext, nonce, *is_final)
+            .is_err());
+
+        // Swapping two chunks
+        let mut decryptor = StreamDecryptor::new(&shared_secret, &salt).unwrap();
+        let (ciphertext, nonce, is_final) = &encrypted[0];
+        decryptor
+            .decrypt_chunk(ciphertext, nonce, *is_final)
+            .unwrap();
+        let (ciphertext, nonce, is_final) = &encrypted[2];
+        assert!(matches!(
+            decryptor.decrypt_chunk(ciphertext, nonce, *is_final),
+            Err(EncryptionError::UnexpectedStreamNonce(1))
+        ));
+    }
 }
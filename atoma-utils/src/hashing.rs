@@ -2,6 +2,8 @@ use blake2::{
     digest::generic_array::{typenum::U32, GenericArray},
     Blake2b, Digest,
 };
+use serde_json::Value;
+use std::str::FromStr;
 
 /// Computes a Blake2b hash of the input data
 ///
@@ -23,3 +25,241 @@ pub fn blake2b_hash(slice: &[u8]) -> GenericArray<u8, U32> {
     hasher.update(slice);
     hasher.finalize()
 }
+
+/// Serialization of JSON values, when hashing request and response bodies
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonCanonicalization {
+    /// Compact serialization of `serde_json`, whose key order and number formatting are
+    /// specific to its implementation
+    #[default]
+    SerdeJson,
+    /// JSON Canonicalization Scheme (JCS), as specified by RFC 8785, which clients in any
+    /// language can reproduce
+    Jcs,
+}
+
+impl JsonCanonicalization {
+    /// Value of the `X-Json-Canonicalization` header selecting RFC 8785 canonicalization
+    pub const JCS: &'static str = "jcs";
+
+    /// Serializes a JSON value with this canonicalization
+    pub fn serialize(&self, value: &Value) -> String {
+        match self {
+            Self::SerdeJson => value.to_string(),
+            Self::Jcs => canonicalize_json(value),
+        }
+    }
+
+    /// Computes the Blake2b hash of a JSON value, serialized with this canonicalization
+    pub fn hash(&self, value: &Value) -> GenericArray<u8, U32> {
+        blake2b_hash(self.serialize(value).as_bytes())
+    }
+}
+
+impl FromStr for JsonCanonicalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case(Self::JCS) {
+            Ok(Self::Jcs)
+        } else {
+            Err(format!(
+                "Unsupported JSON canonicalization `{s}`, expected `{}`",
+                Self::JCS
+            ))
+        }
+    }
+}
+
+/// Serializes a JSON value following the JSON Canonicalization Scheme (RFC 8785)
+///
+/// Object members are sorted by the UTF-16 code units of their keys, strings are escaped
+/// minimally, and numbers are formatted as ECMAScript does for IEEE 754 doubles, without
+/// any whitespace.
+///
+/// # Example
+/// ```rust,ignore
+/// use atoma_utils::hashing::canonicalize_json;
+///
+/// let value = serde_json::json!({"b": 1e30, "a": [4.50, "€"]});
+/// assert_eq!(canonicalize_json(&value), r#"{"a":[4.5,"€"],"b":1e+30}"#);
+/// ```
+pub fn canonicalize_json(value: &Value) -> String {
+    let mut canonical = String::new();
+    write_canonical_json(value, &mut canonical);
+    canonical
+}
+
+/// Appends the canonical serialization of a JSON value to `out`
+fn write_canonical_json(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(boolean) => out.push_str(if *boolean { "true" } else { "false" }),
+        Value::Number(number) => {
+            // NOTE: RFC 8785 represents all numbers as IEEE 754 doubles, so integers beyond
+            // 2^53 lose precision, as they would in ECMAScript
+            out.push_str(&format_canonical_number(
+                number.as_f64().unwrap_or_default(),
+            ))
+        }
+        Value::String(string) => write_canonical_string(string, out),
+        Value::Array(array) => {
+            out.push('[');
+            for (index, element) in array.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(element, out);
+            }
+            out.push(']');
+        }
+        Value::Object(object) => {
+            let mut members = object.iter().collect::<Vec<_>>();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (index, (key, member)) in members.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical_string(key, out);
+                out.push(':');
+                write_canonical_json(member, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Appends a JSON string to `out`, escaping only the characters RFC 8785 requires
+fn write_canonical_string(string: &str, out: &mut String) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{09}' => out.push_str("\\t"),
+            '\u{0a}' => out.push_str("\\n"),
+            '\u{0c}' => out.push_str("\\f"),
+            '\u{0d}' => out.push_str("\\r"),
+            c if c < '\u{20}' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Formats a number as ECMAScript's `Number.prototype.toString` does
+fn format_canonical_number(number: f64) -> String {
+    if number == 0.0 {
+        // NOTE: Negative zero is serialized as `0`
+        return "0".to_string();
+    }
+    // NOTE: Rust formats floats with the shortest digits that round-trip, as ECMAScript does,
+    // so only their layout differs
+    let scientific = format!("{:e}", number.abs());
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("Scientific notation has an exponent");
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().expect("Exponent is an integer");
+    let num_digits = digits.len() as i32;
+    // NOTE: Position of the decimal point, relative to the first digit
+    let point = exponent + 1;
+
+    let mut formatted = String::new();
+    if number < 0.0 {
+        formatted.push('-');
+    }
+    if num_digits <= point && point <= 21 {
+        formatted.push_str(&digits);
+        formatted.push_str(&"0".repeat((point - num_digits) as usize));
+    } else if 0 < point && point <= 21 {
+        let (integer, fraction) = digits.split_at(point as usize);
+        formatted.push_str(integer);
+        formatted.push('.');
+        formatted.push_str(fraction);
+    } else if -6 < point && point <= 0 {
+        formatted.push_str("0.");
+        formatted.push_str(&"0".repeat(-point as usize));
+        formatted.push_str(&digits);
+    } else {
+        let (first, rest) = digits.split_at(1);
+        formatted.push_str(first);
+        if !rest.is_empty() {
+            formatted.push('.');
+            formatted.push_str(rest);
+        }
+        formatted.push('e');
+        formatted.push(if exponent < 0 { '-' } else { '+' });
+        formatted.push_str(&exponent.abs().to_string());
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize_json_rfc8785_example() {
+        let value: Value = serde_json::from_str(
+            r#"{
+                "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+                "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+                "literals": [null, true, false]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            canonicalize_json(&value),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_json_numbers() {
+        for (number, expected) in [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (1.0, "1"),
+            (-1.5, "-1.5"),
+            (100.0, "100"),
+            (9007199254740992.0, "9007199254740992"),
+            (295147905179352830000.0, "295147905179352830000"),
+            (1e21, "1e+21"),
+            (0.000001, "0.000001"),
+            (1e-7, "1e-7"),
+            (-1.2345e-10, "-1.2345e-10"),
+            (5e-324, "5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+        ] {
+            assert_eq!(format_canonical_number(number), expected);
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_json_sorts_keys_by_utf16_code_units() {
+        // NOTE: U+10000 is encoded as the surrogate pair D800 DC00, which sorts before U+FB33
+        let value = serde_json::json!({"\u{fb33}": 1, "\u{10000}": 2, "a": 3, "": 4});
+        assert_eq!(
+            canonicalize_json(&value),
+            "{\"\":4,\"a\":3,\"\u{10000}\":2,\"\u{fb33}\":1}"
+        );
+    }
+
+    #[test]
+    fn test_json_canonicalization() {
+        let value = serde_json::json!({"b": 1, "a": 2});
+        assert_eq!(
+            JsonCanonicalization::SerdeJson.serialize(&value),
+            value.to_string()
+        );
+        assert_eq!(
+            JsonCanonicalization::Jcs.serialize(&value),
+            r#"{"a":2,"b":1}"#
+        );
+        assert_eq!("JCS".parse(), Ok(JsonCanonicalization::Jcs));
+        assert!("rfc8785".parse::<JsonCanonicalization>().is_err());
+    }
+}
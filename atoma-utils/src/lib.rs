@@ -1,19 +1,13 @@
 pub mod encryption;
 pub mod hashing;
+pub mod signature;
 
 use anyhow::{Context, Error, Result};
-use axum::http::StatusCode;
-use fastcrypto::{
-    ed25519::{Ed25519PublicKey, Ed25519Signature},
-    secp256k1::{Secp256k1PublicKey, Secp256k1Signature},
-    secp256r1::{Secp256r1PublicKey, Secp256r1Signature},
-    traits::{ToFromBytes, VerifyingKey},
-};
-use std::str::FromStr;
-use sui_sdk::types::crypto::{PublicKey, Signature, SignatureScheme, SuiSignature};
 use tokio::sync::watch;
 use tracing::{error, instrument};
 
+pub use signature::{signer_address, verify_signature};
+
 pub mod constants {
     /// HTTP header name for the Stack Small ID.
     /// Used to identify specific stacks in the system.
@@ -27,6 +21,30 @@ pub mod constants {
     /// Contains a unique identifier for a blockchain transaction.
     pub const TX_DIGEST: &str = "X-Tx-Digest";
 
+    /// HTTP header name for the request timestamp.
+    /// Contains the Unix time, in seconds, at which the request was signed.
+    pub const REQUEST_TIMESTAMP: &str = "X-Request-Timestamp";
+
+    /// HTTP header name for the request nonce.
+    /// Contains a client-chosen value making each signed request unique.
+    pub const REQUEST_NONCE: &str = "X-Request-Nonce";
+
+    /// HTTP header name for the JSON canonicalization of request and response hashes.
+    /// Set to `jcs` to hash bodies canonicalized as specified by RFC 8785.
+    pub const JSON_CANONICALIZATION: &str = "X-Json-Canonicalization";
+
+    /// HTTP header name for the session grant of requests signed by a session key.
+    /// Contains the base64-encoded JSON grant, signed by the stack's owner.
+    pub const SESSION_GRANT: &str = "X-Session-Grant";
+
+    /// HTTP header name for the signature of a response whose body is not JSON (e.g. audio).
+    /// Contains the node's base64-encoded signature of the response hash.
+    pub const RESPONSE_SIGNATURE: &str = "X-Response-Signature";
+
+    /// HTTP header name for the hash of a response whose body is not JSON (e.g. audio).
+    /// Contains the base64-encoded Blake2b hash of the response body bytes.
+    pub const RESPONSE_HASH: &str = "X-Response-Hash";
+
     /// Field name for encrypted data in the request/response body.
     /// Contains the encrypted payload of the message.
     pub const CIPHERTEXT: &str = "ciphertext";
@@ -85,83 +103,6 @@ where
     })
 }
 
-/// Verifies the authenticity of a request by checking its signature against the provided hash.
-///
-/// # Arguments
-/// * `base64_signature` - A base64-encoded signature string that contains:
-///   - The signature itself
-///   - The public key
-///   - The signature scheme used
-/// * `body_hash` - A 32-byte Blake2b hash of the request body
-///
-/// # Returns
-/// * `Ok(())` if the signature is valid
-/// * `Err(StatusCode)` if:
-///   - The signature cannot be parsed (`BAD_REQUEST`)
-///   - The public key is invalid (`BAD_REQUEST`)
-///   - The signature scheme is unsupported (`BAD_REQUEST`)
-///   - The signature verification fails (`UNAUTHORIZED`)
-///
-/// # Supported Signature Schemes
-/// - ED25519
-/// - Secp256k1
-/// - Secp256r1
-///
-/// # Security Note
-/// This function is critical for ensuring request authenticity. It verifies that:
-/// 1. The request was signed by the owner of the public key
-/// 2. The request body hasn't been tampered with since signing
-#[instrument(level = "trace", skip_all)]
-pub fn verify_signature(
-    base64_signature: &str,
-    body_hash: &[u8; constants::PAYLOAD_HASH_SIZE],
-) -> Result<(), StatusCode> {
-    let signature = Signature::from_str(base64_signature).map_err(|_| {
-        error!("Failed to parse signature");
-        StatusCode::BAD_REQUEST
-    })?;
-    let signature_bytes = signature.signature_bytes();
-    let public_key_bytes = signature.public_key_bytes();
-    let signature_scheme = signature.scheme();
-    let public_key =
-        PublicKey::try_from_bytes(signature_scheme, public_key_bytes).map_err(|e| {
-            error!("Failed to extract public key from bytes, with error: {e}");
-            StatusCode::BAD_REQUEST
-        })?;
-
-    match signature_scheme {
-        SignatureScheme::ED25519 => {
-            let public_key = Ed25519PublicKey::from_bytes(public_key.as_ref()).unwrap();
-            let signature = Ed25519Signature::from_bytes(signature_bytes).unwrap();
-            public_key.verify(body_hash, &signature).map_err(|_| {
-                error!("Failed to verify signature");
-                StatusCode::UNAUTHORIZED
-            })?;
-        }
-        SignatureScheme::Secp256k1 => {
-            let public_key = Secp256k1PublicKey::from_bytes(public_key.as_ref()).unwrap();
-            let signature = Secp256k1Signature::from_bytes(signature_bytes).unwrap();
-            public_key.verify(body_hash, &signature).map_err(|_| {
-                error!("Failed to verify signature");
-                StatusCode::UNAUTHORIZED
-            })?;
-        }
-        SignatureScheme::Secp256r1 => {
-            let public_key = Secp256r1PublicKey::from_bytes(public_key.as_ref()).unwrap();
-            let signature = Secp256r1Signature::from_bytes(signature_bytes).unwrap();
-            public_key.verify(body_hash, &signature).map_err(|_| {
-                error!("Failed to verify signature");
-                StatusCode::UNAUTHORIZED
-            })?;
-        }
-        _ => {
-            error!("Currently unsupported signature scheme");
-            return Err(StatusCode::BAD_REQUEST);
-        }
-    }
-    Ok(())
-}
-
 /// Converts a JSON array of numbers into a vector of bytes.
 ///
 /// # Arguments
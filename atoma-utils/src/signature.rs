@@ -0,0 +1,417 @@
+use std::sync::Arc;
+
+use fastcrypto::{
+    ed25519::Ed25519PublicKey,
+    encoding::{Base64, Encoding},
+    secp256k1::Secp256k1PublicKey,
+    secp256r1::Secp256r1PublicKey,
+    traits::{ToFromBytes, VerifyingKey},
+};
+use serde::Deserialize;
+use shared_crypto::intent::{Intent, IntentMessage, PersonalMessage};
+use sui_sdk::types::{
+    base_types::{EpochId, SuiAddress},
+    crypto::{CompressedSignature, PublicKey, SignatureScheme, SuiSignature},
+    digests::ZKLoginInputsDigest,
+    multisig::MultiSig,
+    signature::{AuthenticatorTrait, GenericSignature, VerifyParams},
+    signature_verification::VerifiedDigestCache,
+    zk_login_authenticator::ZkLoginAuthenticator,
+};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::instrument;
+
+use crate::constants::PAYLOAD_HASH_SIZE;
+
+type Result<T> = std::result::Result<T, SignatureError>;
+
+/// Verifier of zkLogin signatures, against a cache of the OpenID providers' JWKs.
+///
+/// zkLogin signatures are verified offline, so the JWKs of the providers that issued the
+/// signers' JWTs must be cached beforehand, and the current Sui epoch must be kept up to
+/// date with [`ZkLoginVerifier::set_current_epoch`] to reject expired ephemeral keys.
+pub struct ZkLoginVerifier {
+    /// Verification parameters, holding the cached JWKs
+    verify_params: VerifyParams,
+    /// Cache of the zkLogin inputs whose proofs were already verified
+    zklogin_inputs_cache: Arc<VerifiedDigestCache<ZKLoginInputsDigest>>,
+    /// The current Sui epoch, if known
+    current_epoch: watch::Sender<Option<EpochId>>,
+}
+
+/// A cached JWK, along with the identifier of its OpenID provider and key.
+///
+/// Generic over the JWK types of [`VerifyParams`], which are inferred from it.
+#[derive(Deserialize)]
+struct CachedJwk<I, J> {
+    jwk_id: I,
+    jwk: J,
+}
+
+impl ZkLoginVerifier {
+    /// Creates a verifier from a JSON list of cached JWKs, each an object with a `jwk_id`
+    /// (with the `iss` and `kid` of the key) and a `jwk` (with its `kty`, `e`, `n` and `alg`).
+    pub fn from_jwks_json(jwks_json: &[u8]) -> Result<Self> {
+        let cached_jwks: Vec<CachedJwk<_, _>> = serde_json::from_slice(jwks_json)
+            .map_err(|e| SignatureError::InvalidJwks(e.to_string()))?;
+        let verify_params = VerifyParams::new(
+            cached_jwks
+                .into_iter()
+                .map(|cached_jwk| (cached_jwk.jwk_id, cached_jwk.jwk))
+                .collect(),
+            Vec::new(),
+            Default::default(),
+            false,
+            false,
+            false,
+            None,
+        );
+        Ok(Self {
+            verify_params,
+            zklogin_inputs_cache: Arc::new(VerifiedDigestCache::new_empty()),
+            current_epoch: watch::Sender::new(None),
+        })
+    }
+
+    /// Sets the current Sui epoch, against which the ephemeral keys' expiry is checked
+    pub fn set_current_epoch(&self, epoch: EpochId) {
+        self.current_epoch.send_replace(Some(epoch));
+    }
+
+    /// Verifies a zkLogin signature, returning the address of its signer.
+    ///
+    /// The ephemeral key signs the personal message intent of the body hash, as zkLogin
+    /// wallets only sign intent messages.
+    fn verify(
+        &self,
+        authenticator: &ZkLoginAuthenticator,
+        body_hash: &[u8; PAYLOAD_HASH_SIZE],
+    ) -> Result<SuiAddress> {
+        let public_key = authenticator
+            .get_pk()
+            .map_err(|e| SignatureError::InvalidPublicKey(e.to_string()))?;
+        let address = SuiAddress::from(&public_key);
+        let current_epoch = (*self.current_epoch.borrow()).ok_or(SignatureError::UnknownEpoch)?;
+        authenticator
+            .verify_user_authenticator_epoch(current_epoch, None)
+            .map_err(|e| SignatureError::ZkLoginVerificationFailed(e.to_string()))?;
+        let intent_message = IntentMessage::new(
+            Intent::personal_message(),
+            PersonalMessage {
+                message: body_hash.to_vec(),
+            },
+        );
+        authenticator
+            .verify_claims(
+                &intent_message,
+                address,
+                &self.verify_params,
+                self.zklogin_inputs_cache.clone(),
+            )
+            .map_err(|e| SignatureError::ZkLoginVerificationFailed(e.to_string()))?;
+        Ok(address)
+    }
+}
+
+/// Verifies the authenticity of a request by checking its signature against the provided hash.
+///
+/// # Arguments
+/// * `base64_signature` - A base64-encoded Sui signature, either:
+///   - A single signature, with its public key and signature scheme
+///   - A multisig, with the signatures of enough of its members to reach its threshold
+///   - A zkLogin signature, with its ephemeral signature and zero-knowledge proof
+/// * `body_hash` - A 32-byte Blake2b hash of the request body
+/// * `zklogin_verifier` - The verifier of zkLogin signatures, if they are accepted
+///
+/// # Returns
+/// * `Ok(SuiAddress)` - The address of the signer, if the signature is valid
+/// * `Err(SignatureError)` - If the signature cannot be parsed, its scheme is not supported,
+///   or its verification fails
+///
+/// # Supported Signature Schemes
+/// - ED25519
+/// - Secp256k1
+/// - Secp256r1
+/// - MultiSig, of members using any of the above schemes
+/// - zkLogin, signing the personal message intent of the body hash
+///
+/// # Security Note
+/// This function is critical for ensuring request authenticity. It verifies that:
+/// 1. The request was signed by the owner of the returned address
+/// 2. The request body hasn't been tampered with since signing
+#[instrument(level = "trace", skip_all)]
+pub fn verify_signature(
+    base64_signature: &str,
+    body_hash: &[u8; PAYLOAD_HASH_SIZE],
+    zklogin_verifier: Option<&ZkLoginVerifier>,
+) -> Result<SuiAddress> {
+    match parse_signature(base64_signature)? {
+        GenericSignature::Signature(signature) => {
+            let public_key =
+                PublicKey::try_from_bytes(signature.scheme(), signature.public_key_bytes())
+                    .map_err(|e| SignatureError::InvalidPublicKey(e.to_string()))?;
+            verify_with_scheme(
+                signature.scheme(),
+                public_key.as_ref(),
+                signature.signature_bytes(),
+                body_hash,
+            )
+            .map(|()| SuiAddress::from(&public_key))
+        }
+        GenericSignature::MultiSig(multisig) => verify_multisig(&multisig, body_hash),
+        GenericSignature::ZkLoginAuthenticator(authenticator) => zklogin_verifier
+            .ok_or(SignatureError::ZkLoginNotAccepted)?
+            .verify(&authenticator, body_hash),
+        _ => Err(SignatureError::UnsupportedScheme(
+            "only single key, multisig and zkLogin signatures are supported".to_string(),
+        )),
+    }
+}
+
+/// Returns the address of the signer of a base64-encoded Sui signature, without verifying it.
+///
+/// This is the address owning the stacks a request signed with this signature can use.
+pub fn signer_address(base64_signature: &str) -> Result<SuiAddress> {
+    match parse_signature(base64_signature)? {
+        GenericSignature::Signature(signature) => {
+            let public_key =
+                PublicKey::try_from_bytes(signature.scheme(), signature.public_key_bytes())
+                    .map_err(|e| SignatureError::InvalidPublicKey(e.to_string()))?;
+            Ok(SuiAddress::from(&public_key))
+        }
+        GenericSignature::MultiSig(multisig) => Ok(SuiAddress::from(multisig.get_pk())),
+        GenericSignature::ZkLoginAuthenticator(authenticator) => authenticator
+            .get_pk()
+            .map(|public_key| SuiAddress::from(&public_key))
+            .map_err(|e| SignatureError::InvalidPublicKey(e.to_string())),
+        _ => Err(SignatureError::UnsupportedScheme(
+            "only single key, multisig and zkLogin signatures are supported".to_string(),
+        )),
+    }
+}
+
+/// Decodes a base64-encoded Sui signature, of any of the Sui signature schemes
+fn parse_signature(base64_signature: &str) -> Result<GenericSignature> {
+    let signature_bytes = Base64::decode(base64_signature)
+        .map_err(|e| SignatureError::InvalidEncoding(e.to_string()))?;
+    <GenericSignature as sui_sdk::types::crypto::ToFromBytes>::from_bytes(&signature_bytes)
+        .map_err(|e| SignatureError::InvalidSignature(e.to_string()))
+}
+
+/// Verifies the signatures of a multisig's members, and that their weight reaches its threshold
+fn verify_multisig(multisig: &MultiSig, body_hash: &[u8; PAYLOAD_HASH_SIZE]) -> Result<SuiAddress> {
+    let multisig_public_key = multisig.get_pk();
+    let indices = multisig
+        .get_indices()
+        .map_err(|e| SignatureError::InvalidMultiSig(e.to_string()))?;
+    if indices.len() != multisig.get_sigs().len() {
+        return Err(SignatureError::InvalidMultiSig(
+            "Number of signatures does not match the members bitmap".to_string(),
+        ));
+    }
+    let mut weight: u16 = 0;
+    for (signature, index) in multisig.get_sigs().iter().zip(indices) {
+        let (public_key, member_weight) = multisig_public_key
+            .pubkeys()
+            .get(usize::from(index))
+            .ok_or_else(|| {
+                SignatureError::InvalidMultiSig(format!("Member index {index} out of bounds"))
+            })?;
+        let (scheme, signature_bytes) = match signature {
+            CompressedSignature::Ed25519(bytes) => (SignatureScheme::ED25519, bytes.0.as_slice()),
+            CompressedSignature::Secp256k1(bytes) => {
+                (SignatureScheme::Secp256k1, bytes.0.as_slice())
+            }
+            CompressedSignature::Secp256r1(bytes) => {
+                (SignatureScheme::Secp256r1, bytes.0.as_slice())
+            }
+            _ => {
+                return Err(SignatureError::UnsupportedScheme(
+                    "multisig member signatures must be ED25519, Secp256k1 or Secp256r1"
+                        .to_string(),
+                ))
+            }
+        };
+        if public_key.scheme() != scheme {
+            return Err(SignatureError::InvalidMultiSig(format!(
+                "Signature scheme of member {index} does not match its public key"
+            )));
+        }
+        verify_with_scheme(scheme, public_key.as_ref(), signature_bytes, body_hash)?;
+        weight = weight.saturating_add(u16::from(*member_weight));
+    }
+    let threshold = *multisig_public_key.threshold();
+    if weight < threshold {
+        return Err(SignatureError::InsufficientMultiSigWeight { weight, threshold });
+    }
+    Ok(SuiAddress::from(multisig_public_key))
+}
+
+/// Verifies a signature over the body hash, for a single key signature scheme
+fn verify_with_scheme(
+    scheme: SignatureScheme,
+    public_key_bytes: &[u8],
+    signature_bytes: &[u8],
+    body_hash: &[u8; PAYLOAD_HASH_SIZE],
+) -> Result<()> {
+    match scheme {
+        SignatureScheme::ED25519 => {
+            verify_with_key::<Ed25519PublicKey>(public_key_bytes, signature_bytes, body_hash)
+        }
+        SignatureScheme::Secp256k1 => {
+            verify_with_key::<Secp256k1PublicKey>(public_key_bytes, signature_bytes, body_hash)
+        }
+        SignatureScheme::Secp256r1 => {
+            verify_with_key::<Secp256r1PublicKey>(public_key_bytes, signature_bytes, body_hash)
+        }
+        scheme => Err(SignatureError::UnsupportedScheme(format!("{scheme:?}"))),
+    }
+}
+
+/// Verifies a signature over the body hash, with a public key of type `K`
+fn verify_with_key<K: VerifyingKey>(
+    public_key_bytes: &[u8],
+    signature_bytes: &[u8],
+    body_hash: &[u8; PAYLOAD_HASH_SIZE],
+) -> Result<()> {
+    let public_key = K::from_bytes(public_key_bytes)
+        .map_err(|e| SignatureError::InvalidPublicKey(e.to_string()))?;
+    let signature = <K::Sig as ToFromBytes>::from_bytes(signature_bytes)
+        .map_err(|e| SignatureError::InvalidSignature(e.to_string()))?;
+    public_key
+        .verify(body_hash, &signature)
+        .map_err(|_| SignatureError::VerificationFailed)
+}
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("Failed to decode signature, with error: `{0}`")]
+    InvalidEncoding(String),
+    #[error("Failed to parse signature, with error: `{0}`")]
+    InvalidSignature(String),
+    #[error("Failed to parse public key, with error: `{0}`")]
+    InvalidPublicKey(String),
+    #[error("Unsupported signature scheme: `{0}`")]
+    UnsupportedScheme(String),
+    #[error("Invalid multisig, with error: `{0}`")]
+    InvalidMultiSig(String),
+    #[error("Multisig signatures weight `{weight}` is below its threshold `{threshold}`")]
+    InsufficientMultiSigWeight { weight: u16, threshold: u16 },
+    #[error("zkLogin signatures are not accepted by this node")]
+    ZkLoginNotAccepted,
+    #[error("Current epoch is not known yet, cannot verify zkLogin signatures")]
+    UnknownEpoch,
+    #[error("Failed to verify zkLogin signature, with error: `{0}`")]
+    ZkLoginVerificationFailed(String),
+    #[error("Failed to parse cached JWKs, with error: `{0}`")]
+    InvalidJwks(String),
+    #[error("Signature verification failed")]
+    VerificationFailed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sui_sdk::types::{
+        crypto::{get_key_pair, AccountKeyPair, EncodeDecodeBase64, Signature, SuiKeyPair},
+        multisig::MultiSigPublicKey,
+    };
+
+    const BODY_HASH: [u8; PAYLOAD_HASH_SIZE] = [7u8; PAYLOAD_HASH_SIZE];
+
+    fn key_pair() -> SuiKeyPair {
+        let (_, key_pair): (_, AccountKeyPair) = get_key_pair();
+        SuiKeyPair::Ed25519(key_pair)
+    }
+
+    fn multisig(
+        key_pairs: &[SuiKeyPair],
+        signers: &[usize],
+        threshold: u16,
+    ) -> (SuiAddress, String) {
+        let multisig_public_key = MultiSigPublicKey::new(
+            key_pairs.iter().map(|key_pair| key_pair.public()).collect(),
+            vec![1; key_pairs.len()],
+            threshold,
+        )
+        .unwrap();
+        let signatures = signers
+            .iter()
+            .map(|&signer| Signature::new_hashed(&BODY_HASH, &key_pairs[signer]))
+            .collect();
+        let multisig = MultiSig::combine(signatures, multisig_public_key.clone()).unwrap();
+        (
+            SuiAddress::from(&multisig_public_key),
+            GenericSignature::MultiSig(multisig).encode_base64(),
+        )
+    }
+
+    #[test]
+    fn test_verify_single_signature() {
+        let key_pair = key_pair();
+        let signature = Signature::new_hashed(&BODY_HASH, &key_pair).encode_base64();
+        let address = SuiAddress::from(&key_pair.public());
+
+        assert_eq!(
+            verify_signature(&signature, &BODY_HASH, None).unwrap(),
+            address
+        );
+        assert_eq!(signer_address(&signature).unwrap(), address);
+        assert!(matches!(
+            verify_signature(&signature, &[0u8; PAYLOAD_HASH_SIZE], None),
+            Err(SignatureError::VerificationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_verify_multisig_signature() {
+        let key_pairs = [key_pair(), key_pair(), key_pair()];
+
+        let (address, signature) = multisig(&key_pairs, &[0, 2], 2);
+        assert_eq!(
+            verify_signature(&signature, &BODY_HASH, None).unwrap(),
+            address
+        );
+        assert_eq!(signer_address(&signature).unwrap(), address);
+
+        let (_, signature) = multisig(&key_pairs, &[1], 2);
+        assert!(matches!(
+            verify_signature(&signature, &BODY_HASH, None),
+            Err(SignatureError::InsufficientMultiSigWeight {
+                weight: 1,
+                threshold: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_signatures_are_rejected() {
+        assert!(matches!(
+            verify_signature("not base64!", &BODY_HASH, None),
+            Err(SignatureError::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            verify_signature(&Base64::encode([0u8; 3]), &BODY_HASH, None),
+            Err(SignatureError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            signer_address(&Base64::encode([])),
+            Err(SignatureError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_zklogin_verifier_parses_cached_jwks() {
+        let jwks = br#"[{
+            "jwk_id": { "iss": "https://accounts.google.com", "kid": "key" },
+            "jwk": { "kty": "RSA", "e": "AQAB", "n": "n", "alg": "RS256" }
+        }]"#;
+        let verifier = ZkLoginVerifier::from_jwks_json(jwks).unwrap();
+        assert_eq!(verifier.verify_params.oidc_provider_jwks.len(), 1);
+        assert!(matches!(
+            ZkLoginVerifier::from_jwks_json(b"{}"),
+            Err(SignatureError::InvalidJwks(_))
+        ));
+    }
+}